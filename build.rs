@@ -1,4 +1,15 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::compile_protos("proto/authentication.proto")?;
+    // As JSON in the traffic captures of the auth service and the output of
+    // the client
+    const SERDE: &str = "#[derive(serde::Serialize, serde::Deserialize)] #[serde(default)]";
+    tonic_build::configure()
+        .message_attribute(".authentication", SERDE)
+        .compile_protos(&["proto/authentication.proto"], &["proto"])?;
+    tonic_build::configure()
+        .message_attribute(".audit", SERDE)
+        .compile_protos(&["proto/audit.proto"], &["proto"])?;
+    tonic_build::configure()
+        .message_attribute(".admin", SERDE)
+        .compile_protos(&["proto/admin.proto"], &["proto"])?;
     Ok(())
-}
\ No newline at end of file
+}
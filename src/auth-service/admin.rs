@@ -0,0 +1,557 @@
+use std::{sync::Arc, time::Instant};
+
+use common::secrets::constant_time_eq;
+use tonic::{metadata::MetadataMap, service::Interceptor, Request, Response, Status};
+
+use crate::{
+    api_keys::{self, NewApiKey},
+    events, metrics,
+    service::AuthenticationService,
+    users,
+};
+
+pub mod proto {
+    tonic::include_proto!("admin");
+}
+
+use proto::{
+    admin_server::{self, AdminServer},
+    ApiKey, CreateApiKeyRequest, CreateApiKeyResponse, CreateServiceAccountRequest,
+    DeleteUserRequest, DeleteUserResponse, DisableUserRequest, DisableUserResponse,
+    EnableUserRequest, GetUserRequest, ListApiKeysRequest, ListApiKeysResponse, ListUsersRequest,
+    ListUsersResponse, RevokeApiKeyRequest, RevokeApiKeyResponse, User,
+};
+
+pub const DEFAULT_PAGE_SIZE: usize = 50;
+pub const MAX_PAGE_SIZE: usize = 1_000;
+
+pub type AdminService =
+    tonic::service::interceptor::InterceptedService<AdminServer<Admin>, RequireToken>;
+
+/// Serves [`Admin`] to the callers presenting `token`.
+pub fn service(authentication: Arc<AuthenticationService>, token: &str) -> AdminService {
+    AdminServer::with_interceptor(Admin { authentication }, RequireToken::new(token))
+}
+
+/// Rejects the requests without the admin token in their `authorization`
+/// metadata.
+#[derive(Clone)]
+pub struct RequireToken {
+    expected: String,
+}
+
+impl RequireToken {
+    pub fn new(token: &str) -> Self {
+        Self {
+            expected: format!("Bearer {}", token),
+        }
+    }
+
+    /// Whether the `authorization` metadata holds the admin token.
+    pub fn accepts(&self, metadata: &MetadataMap) -> bool {
+        let presented = metadata
+            .get("authorization")
+            .map(|value| value.as_bytes())
+            .unwrap_or_default();
+        constant_time_eq(presented, self.expected.as_bytes())
+    }
+}
+
+impl Interceptor for RequireToken {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        if self.accepts(request.metadata()) {
+            Ok(request)
+        } else {
+            Err(Status::unauthenticated("Invalid admin token"))
+        }
+    }
+}
+
+impl From<&users::User> for User {
+    fn from(user: &users::User) -> Self {
+        Self {
+            user_id: user.id().to_string(),
+            username: user.username().to_string(),
+            disabled: user.is_disabled(),
+            service_account: user.is_service_account(),
+        }
+    }
+}
+
+impl From<&api_keys::ApiKey> for ApiKey {
+    fn from(key: &api_keys::ApiKey) -> Self {
+        Self {
+            key_id: key.id.clone(),
+            name: key.name.clone(),
+            prefix: key.prefix(),
+            scopes: key.scopes.clone(),
+            created_at_ms: key.created_at_ms,
+            expires_at_ms: key.expires_at_ms.unwrap_or_default(),
+            last_used_at_ms: key.last_used_at_ms.unwrap_or_default(),
+        }
+    }
+}
+
+fn not_found(e: String) -> Status {
+    Status::not_found(e)
+}
+
+/// Missing users and keys are not found, anything else is the caller's
+/// mistake.
+fn rejected(e: String) -> Status {
+    if e.ends_with("not found") {
+        Status::not_found(e)
+    } else {
+        Status::failed_precondition(e)
+    }
+}
+
+/// When a key living `ttl_secs` from `now_ms` expires, never when 0.
+fn expires_at_ms(now_ms: u64, ttl_secs: u64) -> Result<Option<u64>, String> {
+    if ttl_secs == 0 {
+        return Ok(None);
+    }
+    ttl_secs
+        .checked_mul(1_000)
+        .and_then(|ttl_ms| now_ms.checked_add(ttl_ms))
+        .map(Some)
+        .ok_or_else(|| "ttl_secs is too large".to_string())
+}
+
+/// Reports an admin call to the metrics.
+fn observe<T>(rpc: &str, result: &Result<T, Status>, start: Instant) {
+    let outcome = if result.is_ok() { "success" } else { "failure" };
+    tracing::Span::current().record("outcome", outcome);
+    metrics::observe_request(rpc, outcome, start.elapsed());
+}
+
+pub struct Admin {
+    authentication: Arc<AuthenticationService>,
+}
+
+#[tonic::async_trait]
+impl admin_server::Admin for Admin {
+    async fn list_users(
+        &self,
+        request: Request<ListUsersRequest>,
+    ) -> Result<Response<ListUsersResponse>, Status> {
+        let start = Instant::now();
+        let req = request.into_inner();
+        let page_size = match req.page_size as usize {
+            0 => DEFAULT_PAGE_SIZE,
+            page_size => page_size.min(MAX_PAGE_SIZE),
+        };
+        let after = (!req.page_token.is_empty()).then_some(req.page_token.as_str());
+
+        let authenticator = self.authentication.authenticator();
+        // One more than asked tells whether there is a next page
+        let mut page = authenticator.list_users(&req.username_prefix, after, page_size + 1);
+        let next_page_token = if page.len() > page_size {
+            page.truncate(page_size);
+            page.last()
+                .map(|user| user.username().to_string())
+                .unwrap_or_default()
+        } else {
+            String::new()
+        };
+        let reply = ListUsersResponse {
+            users: page.into_iter().map(User::from).collect(),
+            next_page_token,
+        };
+        drop(authenticator);
+
+        let result = Ok(Response::new(reply));
+        observe("ListUsers", &result, start);
+        result
+    }
+
+    async fn get_user(&self, request: Request<GetUserRequest>) -> Result<Response<User>, Status> {
+        let start = Instant::now();
+        let req = request.into_inner();
+
+        let result = self
+            .authentication
+            .authenticator()
+            .get_user(&req.username)
+            .map(|user| Response::new(user.into()))
+            .ok_or_else(|| Status::not_found("User not found"));
+
+        observe("GetUser", &result, start);
+        result
+    }
+
+    async fn disable_user(
+        &self,
+        request: Request<DisableUserRequest>,
+    ) -> Result<Response<DisableUserResponse>, Status> {
+        let start = Instant::now();
+        let req = request.into_inner();
+
+        let result = self
+            .authentication
+            .authenticator()
+            .disable_user(&req.username)
+            .map(|(user, revoked)| {
+                Response::new(DisableUserResponse {
+                    user: Some((&user).into()),
+                    revoked_sessions: revoked as u32,
+                })
+            })
+            .map_err(not_found);
+
+        observe("DisableUser", &result, start);
+        result
+    }
+
+    async fn enable_user(
+        &self,
+        request: Request<EnableUserRequest>,
+    ) -> Result<Response<User>, Status> {
+        let start = Instant::now();
+        let req = request.into_inner();
+
+        let result = self
+            .authentication
+            .authenticator()
+            .enable_user(&req.username)
+            .map(|user| Response::new((&user).into()))
+            .map_err(not_found);
+
+        observe("EnableUser", &result, start);
+        result
+    }
+
+    async fn delete_user(
+        &self,
+        request: Request<DeleteUserRequest>,
+    ) -> Result<Response<DeleteUserResponse>, Status> {
+        let start = Instant::now();
+        let req = request.into_inner();
+
+        let result = self
+            .authentication
+            .authenticator()
+            .delete_user(&req.username)
+            .map(|revoked| {
+                Response::new(DeleteUserResponse {
+                    revoked_sessions: revoked as u32,
+                })
+            })
+            .map_err(not_found);
+
+        observe("DeleteUser", &result, start);
+        result
+    }
+
+    async fn create_service_account(
+        &self,
+        request: Request<CreateServiceAccountRequest>,
+    ) -> Result<Response<User>, Status> {
+        let start = Instant::now();
+        let req = request.into_inner();
+
+        let result = self
+            .authentication
+            .authenticator()
+            .create_service_account(&req.username)
+            .map(|user| Response::new((&user).into()))
+            .map_err(Status::already_exists);
+
+        observe("CreateServiceAccount", &result, start);
+        result
+    }
+
+    async fn create_api_key(
+        &self,
+        request: Request<CreateApiKeyRequest>,
+    ) -> Result<Response<CreateApiKeyResponse>, Status> {
+        let start = Instant::now();
+        let req = request.into_inner();
+
+        let result = match expires_at_ms(events::now_ms(), req.ttl_secs) {
+            Ok(expires_at_ms) => self
+                .authentication
+                .authenticator()
+                .create_api_key(
+                    &req.username,
+                    NewApiKey {
+                        name: req.name,
+                        scopes: req.scopes,
+                        expires_at_ms,
+                    },
+                )
+                .map(|(api_key, key)| {
+                    Response::new(CreateApiKeyResponse {
+                        api_key: Some((&api_key).into()),
+                        key,
+                    })
+                })
+                .map_err(rejected),
+            Err(e) => Err(Status::invalid_argument(e)),
+        };
+
+        observe("CreateApiKey", &result, start);
+        result
+    }
+
+    async fn list_api_keys(
+        &self,
+        request: Request<ListApiKeysRequest>,
+    ) -> Result<Response<ListApiKeysResponse>, Status> {
+        let start = Instant::now();
+        let req = request.into_inner();
+
+        let result = self
+            .authentication
+            .authenticator()
+            .list_api_keys(&req.username)
+            .map(|keys| {
+                Response::new(ListApiKeysResponse {
+                    api_keys: keys.into_iter().map(ApiKey::from).collect(),
+                })
+            })
+            .map_err(rejected);
+
+        observe("ListApiKeys", &result, start);
+        result
+    }
+
+    async fn revoke_api_key(
+        &self,
+        request: Request<RevokeApiKeyRequest>,
+    ) -> Result<Response<RevokeApiKeyResponse>, Status> {
+        let start = Instant::now();
+        let req = request.into_inner();
+
+        let result = self
+            .authentication
+            .authenticator()
+            .revoke_api_key(&req.username, &req.key_id)
+            .map(|()| Response::new(RevokeApiKeyResponse {}))
+            .map_err(rejected);
+
+        observe("RevokeApiKey", &result, start);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{auth::Authenticator, sessions::SessionsTranstient, users::UsersTransient};
+    use proto::admin_client::AdminClient;
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::transport::Channel;
+
+    const TOKEN: &str = "0123456789abcdef";
+
+    async fn start() -> (Arc<AuthenticationService>, AdminClient<Channel>) {
+        let authentication = Arc::new(AuthenticationService::new(Authenticator::new(
+            UsersTransient::fast(),
+            SessionsTranstient::new(),
+        )));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(service(authentication.clone(), TOKEN))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        let client = AdminClient::connect(format!("http://127.0.0.1:{}", port))
+            .await
+            .unwrap();
+        (authentication, client)
+    }
+
+    fn authorized<T>(message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        request.metadata_mut().insert(
+            "authorization",
+            format!("Bearer {}", TOKEN).parse().unwrap(),
+        );
+        request
+    }
+
+    fn sign_up(authentication: &AuthenticationService, usernames: &[&str]) {
+        for username in usernames {
+            authentication
+                .authenticator()
+                .sign_up(username, "password", "")
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn should_reject_overflowing_ttl() {
+        assert_eq!(expires_at_ms(1_000, 0).unwrap(), None);
+        assert_eq!(expires_at_ms(1_000, 60).unwrap(), Some(61_000));
+        assert!(expires_at_ms(1_000, u64::MAX / 1_000 + 1).is_err());
+        assert!(expires_at_ms(u64::MAX - 1_000, 2).is_err());
+    }
+
+    #[tokio::test]
+    async fn should_reject_calls_without_token() {
+        let (_, mut client) = start().await;
+
+        let status = client
+            .get_user(GetUserRequest {
+                username: "username".to_string(),
+            })
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+    }
+
+    #[tokio::test]
+    async fn should_list_users_in_pages() {
+        let (authentication, mut client) = start().await;
+        sign_up(&authentication, &["carol", "alice", "bob", "alina"]);
+
+        let first = client
+            .list_users(authorized(ListUsersRequest {
+                page_size: 2,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let second = client
+            .list_users(authorized(ListUsersRequest {
+                page_size: 2,
+                page_token: first.next_page_token.clone(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let prefixed = client
+            .list_users(authorized(ListUsersRequest {
+                username_prefix: "al".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let usernames = |response: &ListUsersResponse| -> Vec<String> {
+            response
+                .users
+                .iter()
+                .map(|user| user.username.clone())
+                .collect()
+        };
+        assert_eq!(usernames(&first), ["alice", "alina"]);
+        assert_eq!(usernames(&second), ["bob", "carol"]);
+        assert!(second.next_page_token.is_empty());
+        assert_eq!(usernames(&prefixed), ["alice", "alina"]);
+    }
+
+    #[tokio::test]
+    async fn should_disable_and_delete_users() {
+        let (authentication, mut client) = start().await;
+        sign_up(&authentication, &["username"]);
+        authentication
+            .authenticator()
+            .sign_in("username", "password")
+            .unwrap();
+
+        let disabled = client
+            .disable_user(authorized(DisableUserRequest {
+                username: "username".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(disabled.user.unwrap().disabled);
+        assert_eq!(disabled.revoked_sessions, 1);
+        assert!(authentication
+            .authenticator()
+            .sign_in("username", "password")
+            .is_err());
+
+        client
+            .delete_user(authorized(DeleteUserRequest {
+                username: "username".to_string(),
+            }))
+            .await
+            .unwrap();
+        let status = client
+            .get_user(authorized(GetUserRequest {
+                username: "username".to_string(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+    }
+    #[tokio::test]
+    async fn should_manage_api_keys_of_service_accounts() {
+        let (authentication, mut client) = start().await;
+        sign_up(&authentication, &["username"]);
+
+        let account = client
+            .create_service_account(authorized(CreateServiceAccountRequest {
+                username: "ci-bot".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(account.service_account);
+
+        let status = client
+            .create_api_key(authorized(CreateApiKeyRequest {
+                username: "username".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+        let status = client
+            .create_api_key(authorized(CreateApiKeyRequest {
+                username: "ci-bot".to_string(),
+                ttl_secs: u64::MAX,
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let created = client
+            .create_api_key(authorized(CreateApiKeyRequest {
+                username: "ci-bot".to_string(),
+                name: "ci".to_string(),
+                scopes: vec![api_keys::SCOPE_PROFILE_READ.to_string()],
+                ttl_secs: 60,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let api_key = created.api_key.unwrap();
+        assert!(created.key.starts_with(&api_key.prefix));
+        assert!(api_key.expires_at_ms > api_key.created_at_ms);
+
+        let listed = client
+            .list_api_keys(authorized(ListApiKeysRequest {
+                username: "ci-bot".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(listed.api_keys.len(), 1);
+        assert_eq!(listed.api_keys[0].key_id, api_key.key_id);
+
+        client
+            .revoke_api_key(authorized(RevokeApiKeyRequest {
+                username: "ci-bot".to_string(),
+                key_id: api_key.key_id,
+            }))
+            .await
+            .unwrap();
+        assert!(authentication
+            .authenticator()
+            .authorize(&created.key, api_keys::SCOPE_PROFILE_READ)
+            .is_err());
+    }
+}
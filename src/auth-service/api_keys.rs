@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+
+use common::secrets::constant_time_eq;
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use tracing::instrument;
+
+/// Starts every API key, telling them apart from session tokens.
+pub const KEY_PREFIX: &str = "ak_";
+
+/// Reading the profile of the key's owner.
+pub const SCOPE_PROFILE_READ: &str = "profile:read";
+/// Updating the profile of the key's owner.
+pub const SCOPE_PROFILE_WRITE: &str = "profile:write";
+/// Subscribing to the events about the key's owner.
+pub const SCOPE_EVENTS_READ: &str = "events:read";
+/// The RPCs managing sessions and accounts, which confirm the password,
+/// only take session tokens.
+pub const SCOPES: [&str; 3] = [SCOPE_PROFILE_READ, SCOPE_PROFILE_WRITE, SCOPE_EVENTS_READ];
+
+/// A long-lived credential of a service account. Only the hash of the key is
+/// kept, the key itself is shown once, when created.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKey {
+    pub id: String,
+    /// Id of the service account owning the key
+    pub owner: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at_ms: u64,
+    pub expires_at_ms: Option<u64>,
+    pub last_used_at_ms: Option<u64>,
+    hash: String,
+}
+
+impl ApiKey {
+    /// The start of the key, enough to recognize it in listings.
+    pub fn prefix(&self) -> String {
+        format!("{}{}", KEY_PREFIX, self.id)
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|granted| granted == scope)
+    }
+}
+
+/// What a new key is for.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NewApiKey {
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub expires_at_ms: Option<u64>,
+}
+
+pub trait ApiKeys {
+    /// Creates a key for `owner`, returning it along with the key itself.
+    fn create_key(
+        &mut self,
+        owner: &str,
+        key: NewApiKey,
+        now_ms: u64,
+    ) -> Result<(&ApiKey, String), String>;
+
+    /// Finds the key, recording its use, unless it was revoked or expired.
+    fn use_key(&mut self, key: &str, now_ms: u64) -> Result<&ApiKey, String>;
+
+    /// The keys of `owner`, oldest first.
+    fn list_keys(&self, owner: &str) -> Vec<&ApiKey>;
+
+    fn revoke_key(&mut self, owner: &str, id: &str) -> Result<(), String>;
+
+    /// Revokes every key of `owner`, returning how many there were.
+    fn revoke_owner_keys(&mut self, owner: &str) -> usize;
+}
+
+#[derive(Debug, Default)]
+pub struct ApiKeysTransient {
+    keys: HashMap<String, ApiKey>,
+}
+
+impl ApiKeysTransient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Keys carry 256 random bits, so a single round of SHA-256 is enough to
+/// keep them from being recovered.
+fn hash(key: &str) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn random_hex(bytes: usize) -> String {
+    let mut buffer = vec![0u8; bytes];
+    OsRng.fill_bytes(&mut buffer);
+    buffer.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+impl ApiKeys for ApiKeysTransient {
+    #[instrument(skip(self, key), fields(storage.backend = "InMemory"))]
+    fn create_key(
+        &mut self,
+        owner: &str,
+        key: NewApiKey,
+        now_ms: u64,
+    ) -> Result<(&ApiKey, String), String> {
+        if let Some(scope) = key
+            .scopes
+            .iter()
+            .find(|scope| !SCOPES.contains(&scope.as_str()))
+        {
+            return Err(format!("Unknown scope {}", scope));
+        }
+
+        let id = loop {
+            let id = random_hex(6);
+            if !self.keys.contains_key(&id) {
+                break id;
+            }
+        };
+        let secret = format!("{}{}_{}", KEY_PREFIX, id, random_hex(32));
+        let api_key = ApiKey {
+            id: id.clone(),
+            owner: owner.to_string(),
+            name: key.name,
+            scopes: key.scopes,
+            created_at_ms: now_ms,
+            expires_at_ms: key.expires_at_ms,
+            last_used_at_ms: None,
+            hash: hash(&secret),
+        };
+        Ok((self.keys.entry(id).or_insert(api_key), secret))
+    }
+
+    #[instrument(skip_all, fields(storage.backend = "InMemory"))]
+    fn use_key(&mut self, key: &str, now_ms: u64) -> Result<&ApiKey, String> {
+        let invalid = || "Invalid API key".to_string();
+        let id = key
+            .strip_prefix(KEY_PREFIX)
+            .and_then(|rest| rest.split_once('_'))
+            .map(|(id, _)| id)
+            .ok_or_else(invalid)?;
+        let api_key = self.keys.get_mut(id).ok_or_else(invalid)?;
+        if !constant_time_eq(hash(key).as_bytes(), api_key.hash.as_bytes()) {
+            return Err(invalid());
+        }
+        if api_key
+            .expires_at_ms
+            .is_some_and(|expires_at_ms| expires_at_ms <= now_ms)
+        {
+            return Err("API key has expired".to_string());
+        }
+
+        api_key.last_used_at_ms = Some(now_ms);
+        Ok(api_key)
+    }
+
+    fn list_keys(&self, owner: &str) -> Vec<&ApiKey> {
+        let mut keys: Vec<&ApiKey> = self
+            .keys
+            .values()
+            .filter(|key| key.owner == owner)
+            .collect();
+        keys.sort_by_key(|key| key.created_at_ms);
+        keys
+    }
+
+    #[instrument(skip(self), fields(storage.backend = "InMemory"))]
+    fn revoke_key(&mut self, owner: &str, id: &str) -> Result<(), String> {
+        match self.keys.get(id) {
+            Some(key) if key.owner == owner => {
+                self.keys.remove(id);
+                Ok(())
+            }
+            _ => Err("API key not found".to_string()),
+        }
+    }
+
+    #[instrument(skip(self), fields(storage.backend = "InMemory"))]
+    fn revoke_owner_keys(&mut self, owner: &str) -> usize {
+        let before = self.keys.len();
+        self.keys.retain(|_, key| key.owner != owner);
+        before - self.keys.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_000_000;
+
+    fn new_key(expires_at_ms: Option<u64>) -> NewApiKey {
+        NewApiKey {
+            name: "ci".to_string(),
+            scopes: vec![SCOPE_PROFILE_READ.to_string()],
+            expires_at_ms,
+        }
+    }
+
+    #[test]
+    fn should_use_created_key() {
+        let mut keys = ApiKeysTransient::new();
+        let (created, secret) = keys.create_key("1234", new_key(None), NOW).unwrap();
+        let created = created.clone();
+
+        assert!(secret.starts_with(&created.prefix()));
+        let used = keys.use_key(&secret, NOW + 1).unwrap();
+        assert_eq!(used.id, created.id);
+        assert_eq!(used.last_used_at_ms, Some(NOW + 1));
+        assert!(used.has_scope(SCOPE_PROFILE_READ));
+        assert!(!used.has_scope(SCOPE_PROFILE_WRITE));
+    }
+
+    #[test]
+    fn should_reject_wrong_or_expired_keys() {
+        let mut keys = ApiKeysTransient::new();
+        let (_, secret) = keys
+            .create_key("1234", new_key(Some(NOW + 10)), NOW)
+            .unwrap();
+        let last = if secret.ends_with('0') { '1' } else { '0' };
+        let forged = format!("{}{}", &secret[..secret.len() - 1], last);
+
+        assert!(keys.use_key(&forged, NOW).is_err());
+        assert!(keys.use_key("session-token", NOW).is_err());
+        assert_eq!(
+            keys.use_key(&secret, NOW + 10).unwrap_err(),
+            "API key has expired"
+        );
+    }
+
+    #[test]
+    fn should_reject_unknown_scope() {
+        let mut keys = ApiKeysTransient::new();
+        let key = NewApiKey {
+            scopes: vec!["everything".to_string()],
+            ..new_key(None)
+        };
+
+        assert!(keys.create_key("1234", key, NOW).is_err());
+    }
+
+    #[test]
+    fn should_revoke_keys() {
+        let mut keys = ApiKeysTransient::new();
+        let (created, secret) = keys.create_key("1234", new_key(None), NOW).unwrap();
+        let id = created.id.clone();
+        keys.create_key("1234", new_key(None), NOW).unwrap();
+        keys.create_key("5678", new_key(None), NOW).unwrap();
+
+        assert!(keys.revoke_key("5678", &id).is_err());
+        keys.revoke_key("1234", &id).unwrap();
+
+        assert!(keys.use_key(&secret, NOW).is_err());
+        assert_eq!(keys.list_keys("1234").len(), 1);
+        assert_eq!(keys.revoke_owner_keys("1234"), 1);
+        assert_eq!(keys.list_keys("5678").len(), 1);
+    }
+}
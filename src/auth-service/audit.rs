@@ -0,0 +1,469 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+    pin::Pin,
+};
+
+use clap::ValueEnum;
+use common::audit::AuditEntry;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+use tonic::{service::interceptor::InterceptedService, Request, Response, Status};
+
+use crate::{
+    admin::RequireToken,
+    config::AuditConfig,
+    events::{self, Event},
+};
+
+pub mod proto {
+    tonic::include_proto!("audit");
+}
+
+// Re-exporting
+pub use proto::audit_server::AuditServer;
+
+pub type AuditService = InterceptedService<AuditServer<AuditStream>, RequireToken>;
+
+/// Serves the [`AuditStream`] to the callers presenting the admin `token`,
+/// the entries naming every user.
+pub fn service(stream: AuditStream, token: &str) -> AuditService {
+    AuditServer::with_interceptor(stream, RequireToken::new(token))
+}
+
+/// Entries buffered for each `Tail` subscriber before it starts missing some.
+pub const DEFAULT_STREAM_CAPACITY: usize = 1024;
+
+/// Where the audit log is written.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditSinkKind {
+    /// Appended to `audit.path`, continuing the chain across restarts
+    File,
+    /// Printed to stdout, one JSON object per line
+    Stdout,
+    /// Streamed to the subscribers of the `audit.Audit/Tail` RPC
+    Grpc,
+}
+
+pub trait AuditSink: Send + Sync {
+    fn write(&mut self, entry: &AuditEntry) -> Result<(), String>;
+
+    /// The last entry written by a previous run, which the chain continues.
+    fn last(&self) -> Option<&AuditEntry> {
+        None
+    }
+
+    /// Makes the entries written so far durable.
+    fn flush(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Appends entries as JSON lines to a file.
+pub struct FileSink {
+    file: File,
+    last: Option<AuditEntry>,
+}
+
+impl FileSink {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let last = Self::read_last(path)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Failed to open audit log {}: {}", path.display(), e))?;
+        Ok(Self { file, last })
+    }
+
+    fn read_last(path: &Path) -> Result<Option<AuditEntry>, String> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(format!(
+                    "Failed to read audit log {}: {}",
+                    path.display(),
+                    e
+                ))
+            }
+        };
+
+        let mut last_line = None;
+        for line in BufReader::new(file).lines() {
+            let line =
+                line.map_err(|e| format!("Failed to read audit log {}: {}", path.display(), e))?;
+            if !line.trim().is_empty() {
+                last_line = Some(line);
+            }
+        }
+
+        last_line
+            .map(|line| {
+                serde_json::from_str(&line).map_err(|e| {
+                    format!(
+                        "Last entry of audit log {} is corrupted: {}",
+                        path.display(),
+                        e
+                    )
+                })
+            })
+            .transpose()
+    }
+}
+
+impl AuditSink for FileSink {
+    fn write(&mut self, entry: &AuditEntry) -> Result<(), String> {
+        let mut line = serde_json::to_string(entry).map_err(|e| e.to_string())?;
+        line.push('\n');
+        self.file
+            .write_all(line.as_bytes())
+            .map_err(|e| format!("Failed to write audit log: {}", e))
+    }
+
+    fn last(&self) -> Option<&AuditEntry> {
+        self.last.as_ref()
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        self.file
+            .sync_data()
+            .map_err(|e| format!("Failed to sync audit log: {}", e))
+    }
+}
+
+/// Prints entries as JSON lines to stdout, which carries nothing else since
+/// logs go to stderr.
+pub struct StdoutSink;
+
+impl AuditSink for StdoutSink {
+    fn write(&mut self, entry: &AuditEntry) -> Result<(), String> {
+        let line = serde_json::to_string(entry).map_err(|e| e.to_string())?;
+        writeln!(io::stdout().lock(), "{}", line)
+            .map_err(|e| format!("Failed to write audit log: {}", e))
+    }
+}
+
+/// Publishes entries to the subscribers of an [`AuditStream`].
+pub struct StreamSink {
+    sender: broadcast::Sender<AuditEntry>,
+}
+
+impl AuditSink for StreamSink {
+    fn write(&mut self, entry: &AuditEntry) -> Result<(), String> {
+        // Having no subscriber is not an error
+        let _ = self.sender.send(entry.clone());
+        Ok(())
+    }
+}
+
+/// The `Audit` gRPC service, streaming entries as they are written.
+pub struct AuditStream {
+    sender: broadcast::Sender<AuditEntry>,
+}
+
+impl AuditStream {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self { sender }
+    }
+
+    pub fn sink(&self) -> StreamSink {
+        StreamSink {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl From<AuditEntry> for proto::AuditEntry {
+    fn from(entry: AuditEntry) -> Self {
+        Self {
+            sequence: entry.sequence,
+            timestamp_ms: entry.timestamp_ms,
+            event: entry.event.to_string(),
+            prev_hash: entry.prev_hash,
+            hash: entry.hash,
+        }
+    }
+}
+
+type TailStream = Pin<Box<dyn Stream<Item = Result<proto::AuditEntry, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl proto::audit_server::Audit for AuditStream {
+    type TailStream = TailStream;
+
+    #[allow(clippy::result_large_err)]
+    async fn tail(
+        &self,
+        _request: Request<proto::TailRequest>,
+    ) -> Result<Response<Self::TailStream>, Status> {
+        let stream = BroadcastStream::new(self.sender.subscribe()).map(|entry| {
+            entry.map(Into::into).map_err(|e| {
+                // The subscriber fell behind, it would see a gap in the chain
+                Status::data_loss(format!("Audit stream {}", e))
+            })
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+/// Hash chained, append-only record of the security relevant [`Event`]s, written to every sink.
+/// Without sinks nothing is recorded.
+#[derive(Default)]
+pub struct AuditLog {
+    sinks: Vec<Box<dyn AuditSink>>,
+    /// Keys the hashes of the chain.
+    key: Vec<u8>,
+    last: Option<AuditEntry>,
+}
+
+impl AuditLog {
+    /// Continues the chain from the most recent entry any sink already has.
+    pub fn new(sinks: Vec<Box<dyn AuditSink>>, key: &[u8]) -> Self {
+        let last = sinks
+            .iter()
+            .filter_map(|sink| sink.last())
+            .max_by_key(|entry| entry.sequence)
+            .cloned();
+        Self {
+            sinks,
+            key: key.to_vec(),
+            last,
+        }
+    }
+
+    /// Builds the sinks listed in the configuration, along with the service
+    /// to expose when one of them is the gRPC stream.
+    pub fn from_config(config: &AuditConfig) -> Result<(Self, Option<AuditStream>), String> {
+        let mut sinks: Vec<Box<dyn AuditSink>> = Vec::new();
+        let mut stream = None;
+        if config.sinks.is_empty() {
+            return Ok((Self::default(), stream));
+        }
+        let key = config
+            .key
+            .as_deref()
+            .ok_or("The audit sinks require audit.key")?
+            .as_bytes();
+
+        for kind in &config.sinks {
+            match kind {
+                AuditSinkKind::File => {
+                    let path = config
+                        .path
+                        .as_ref()
+                        .ok_or("The file audit sink requires audit.path")?;
+                    let sink = FileSink::open(path)?;
+                    if let Some(last) = sink.last() {
+                        if last.hash != last.compute_hash(key) {
+                            return Err(format!(
+                                "Audit log {} was not written with audit.key",
+                                path.display()
+                            ));
+                        }
+                    }
+                    sinks.push(Box::new(sink));
+                }
+                AuditSinkKind::Stdout => sinks.push(Box::new(StdoutSink)),
+                AuditSinkKind::Grpc => {
+                    let audit_stream = AuditStream::new(DEFAULT_STREAM_CAPACITY);
+                    sinks.push(Box::new(audit_stream.sink()));
+                    stream = Some(audit_stream);
+                }
+            }
+        }
+
+        Ok((Self::new(sinks, key), stream))
+    }
+
+    pub fn record(&mut self, event: &Event) -> Result<(), String> {
+        if self.sinks.is_empty() {
+            return Ok(());
+        }
+
+        let event = serde_json::to_value(event).map_err(|e| e.to_string())?;
+        let entry = AuditEntry::new(&self.key, self.last.as_ref(), events::now_ms(), event);
+
+        let mut errors = Vec::new();
+        for sink in &mut self.sinks {
+            if let Err(e) = sink.write(&entry) {
+                errors.push(e);
+            }
+        }
+        self.last = Some(entry);
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("; "))
+        }
+    }
+
+    pub fn flush(&mut self) -> Result<(), String> {
+        for sink in &mut self.sinks {
+            sink.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::audit::Start;
+    use proto::audit_client::AuditClient;
+    use std::{
+        path::PathBuf,
+        sync::{Arc, Mutex},
+    };
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+
+    /// Keeps the entries in memory, shared with the test.
+    #[derive(Clone, Default)]
+    struct MemorySink(Arc<Mutex<Vec<AuditEntry>>>);
+
+    impl AuditSink for MemorySink {
+        fn write(&mut self, entry: &AuditEntry) -> Result<(), String> {
+            self.0.lock().unwrap().push(entry.clone());
+            Ok(())
+        }
+    }
+
+    fn signed_out(user_id: &str) -> Event {
+        Event::SignedOut {
+            user_id: user_id.to_string(),
+        }
+    }
+
+    const KEY: &[u8] = b"audit-key";
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}-{}.log", name, uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn should_chain_recorded_events() {
+        let sink = MemorySink::default();
+        let mut log = AuditLog::new(vec![Box::new(sink.clone())], KEY);
+
+        log.record(&signed_out("a")).unwrap();
+        log.record(&signed_out("b")).unwrap();
+
+        let entries = sink.0.lock().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].prev_hash, entries[0].hash);
+        assert_eq!(entries[1].event["type"], "signed_out");
+        assert_eq!(entries[1].event["user_id"], "b");
+    }
+
+    #[test]
+    fn should_continue_chain_after_reopening_file() {
+        let path = temp_path("audit");
+
+        let mut log = AuditLog::new(vec![Box::new(FileSink::open(&path).unwrap())], KEY);
+        log.record(&signed_out("a")).unwrap();
+        log.flush().unwrap();
+        drop(log);
+
+        let mut log = AuditLog::new(vec![Box::new(FileSink::open(&path).unwrap())], KEY);
+        log.record(&signed_out("b")).unwrap();
+        drop(log);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let verification = common::audit::verify(contents.as_bytes(), KEY, Start::Genesis);
+        assert!(verification.is_valid(), "{:?}", verification.problems);
+        assert_eq!(verification.entries, 2);
+    }
+
+    #[test]
+    fn should_refuse_corrupted_file() {
+        let path = temp_path("audit-corrupted");
+        std::fs::write(&path, "not json\n").unwrap();
+
+        let result = FileSink::open(&path);
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn should_require_path_for_file_sink() {
+        let config = AuditConfig {
+            sinks: vec![AuditSinkKind::File],
+            path: None,
+            key: Some("audit-key".to_string()),
+        };
+
+        assert!(AuditLog::from_config(&config).is_err());
+    }
+
+    #[test]
+    fn should_refuse_file_written_with_another_key() {
+        let path = temp_path("audit-other-key");
+        let mut log = AuditLog::new(vec![Box::new(FileSink::open(&path).unwrap())], b"other-key");
+        log.record(&signed_out("a")).unwrap();
+        drop(log);
+
+        let result = AuditLog::from_config(&AuditConfig {
+            sinks: vec![AuditSinkKind::File],
+            path: Some(path.clone()),
+            key: Some("audit-key".to_string()),
+        });
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err_and(|e| e.contains("audit.key")));
+    }
+
+    const TOKEN: &str = "0123456789abcdef";
+
+    async fn serve(stream: AuditStream) -> AuditClient<tonic::transport::Channel> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(service(stream, TOKEN))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        AuditClient::connect(format!("http://127.0.0.1:{}", port))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_reject_tail_without_admin_token() {
+        let mut client = serve(AuditStream::new(DEFAULT_STREAM_CAPACITY)).await;
+
+        let status = client.tail(proto::TailRequest {}).await.unwrap_err();
+
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+    }
+
+    #[tokio::test]
+    async fn should_stream_entries_to_subscribers() {
+        let stream = AuditStream::new(DEFAULT_STREAM_CAPACITY);
+        let mut log = AuditLog::new(vec![Box::new(stream.sink())], KEY);
+        let mut client = serve(stream).await;
+
+        let mut request = Request::new(proto::TailRequest {});
+        request.metadata_mut().insert(
+            "authorization",
+            format!("Bearer {}", TOKEN).parse().unwrap(),
+        );
+        let mut entries = client.tail(request).await.unwrap().into_inner();
+
+        log.record(&signed_out("a")).unwrap();
+
+        let entry = entries.message().await.unwrap().unwrap();
+        assert_eq!(entry.sequence, 0);
+        assert_eq!(
+            entry.event,
+            serde_json::to_value(signed_out("a")).unwrap().to_string()
+        );
+    }
+}
@@ -1,12 +1,46 @@
-use crate::{sessions::Sessions, users::Users};
+use std::{collections::HashMap, time::Duration};
+
+use tracing::{error, info, instrument, warn};
+
+use crate::{
+    api_keys::{self, ApiKey, ApiKeys, ApiKeysTransient, NewApiKey},
+    audit::AuditLog,
+    events::{self, Event, EventBus},
+    profiles::{self, Profile, ProfileSchema},
+    sessions::Sessions,
+    users::{User, Users},
+    verification::{EmailVerification, EmailVerifier},
+};
 
 pub trait Bound: Send + Sync + 'static {}
 
 impl<T: Send + Sync + 'static> Bound for T {}
 
+/// How often the deletions scheduled with a grace period are checked for.
+pub const DUE_DELETIONS_INTERVAL: Duration = Duration::from_secs(1);
+
 pub struct Authenticator {
     users: Box<dyn Users + Send + Sync>,
     sessions: Box<dyn Sessions + Send + Sync>,
+    api_keys: Box<dyn ApiKeys + Send + Sync>,
+    audit: AuditLog,
+    events: EventBus,
+    deletion_grace_period: Duration,
+    profile_schema: ProfileSchema,
+    email_verification: Option<EmailVerification>,
+    /// Failed sign-ins in a row locking an account, never locked when 0.
+    lockout_threshold: u32,
+    lockout_duration: Duration,
+    /// Failed sign-ins of the accounts, by username.
+    failed_sign_ins: HashMap<String, FailedSignIns>,
+}
+
+#[derive(Debug, Default)]
+struct FailedSignIns {
+    /// In a row since the last successful sign-in or lock.
+    count: u32,
+    /// In milliseconds since the Unix epoch.
+    locked_until_ms: u64,
 }
 
 impl Authenticator {
@@ -14,60 +48,663 @@ impl Authenticator {
         Self {
             users: Box::new(users),
             sessions: Box::new(sessions),
+            api_keys: Box::new(ApiKeysTransient::new()),
+            audit: AuditLog::default(),
+            events: EventBus::default(),
+            deletion_grace_period: Duration::ZERO,
+            profile_schema: ProfileSchema::default(),
+            email_verification: None,
+            lockout_threshold: 0,
+            lockout_duration: Duration::ZERO,
+            failed_sign_ins: HashMap::new(),
+        }
+    }
+
+    pub fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Delays the deletions users ask for, so they can change their mind.
+    pub fn with_deletion_grace_period(mut self, grace_period: Duration) -> Self {
+        self.deletion_grace_period = grace_period;
+        self
+    }
+
+    /// Custom attributes the profiles may carry, none by default.
+    pub fn with_profile_schema(mut self, schema: ProfileSchema) -> Self {
+        self.profile_schema = schema;
+        self
+    }
+
+    /// Emails tokens to verify the email addresses of users.
+    pub fn with_email_verification(mut self, verification: EmailVerification) -> Self {
+        self.email_verification = Some(verification);
+        self
+    }
+
+    /// Refuses the sign-ins of an account for `duration` after `threshold`
+    /// failed ones in a row. The failures are not kept across restarts.
+    pub fn with_lockout(mut self, threshold: u32, duration: Duration) -> Self {
+        self.lockout_threshold = threshold;
+        self.lockout_duration = duration;
+        self
+    }
+
+    /// The bus every event is published on.
+    pub fn events(&self) -> EventBus {
+        self.events.clone()
+    }
+
+    /// Signs up, emailing a verification token to `email` when given and
+    /// email verification is enabled.
+    #[instrument(skip(self, password, email))]
+    pub fn sign_up(&mut self, username: &str, password: &str, email: &str) -> Result<(), String> {
+        match self.create_user(username, password, email) {
+            Ok(user_id) => {
+                info!("user signed up");
+                if !email.is_empty() {
+                    self.send_verification(&user_id, email);
+                }
+                self.emit(Event::SignedUp {
+                    user_id,
+                    username: username.to_string(),
+                });
+                Ok(())
+            }
+            Err(e) => {
+                warn!(error = %e, "sign up failed");
+                self.emit(Event::SignUpFailed {
+                    username: username.to_string(),
+                    reason: e.clone(),
+                });
+                Err(e)
+            }
         }
     }
 
-    pub fn sign_up(&mut self, username: &str, password: &str) -> Result<(), String> {
-        self.users.create_user(username, password)?;
+    fn create_user(
+        &mut self,
+        username: &str,
+        password: &str,
+        email: &str,
+    ) -> Result<String, String> {
+        if !email.is_empty() {
+            profiles::check_email(email)?;
+        } else if self
+            .email_verification
+            .as_ref()
+            .is_some_and(|verification| verification.required_on_sign_up)
+        {
+            return Err("Email is required".to_string());
+        }
+
+        let user_id = self.users.create_user(username, password)?.id().to_string();
+        if !email.is_empty() {
+            let profile = Profile {
+                email: email.to_string(),
+                ..Default::default()
+            };
+            self.users.update_profile(&user_id, profile)?;
+        }
+        Ok(user_id)
+    }
+
+    /// Emails a verification token when email verification is enabled. The
+    /// user can still ask for another one with `resend_verification`, so
+    /// failing to send is only logged.
+    fn send_verification(&self, user_id: &str, email: &str) {
+        if let Some(verification) = &self.email_verification {
+            if let Err(e) = verification.send(user_id, email, events::now_ms()) {
+                error!(error = %e, "failed to send verification email");
+            }
+        }
+    }
+
+    /// Emails another verification token to a user whose address is not
+    /// verified yet. The password stands in for a session, which users cannot
+    /// open before verifying when sign-ins are blocked until then.
+    #[instrument(skip(self, password), fields(user_id))]
+    pub fn resend_verification(&mut self, username: &str, password: &str) -> Result<(), String> {
+        let verification = self
+            .email_verification
+            .as_ref()
+            .ok_or("Email verification is not enabled")?;
+        self.check_lockout(username)?;
+        let Some(user_id) = self.users.find_user_id(username, password) else {
+            self.count_failed_sign_in(username);
+            return Err("User not found".to_string());
+        };
+        tracing::Span::current().record("user_id", &user_id);
+
+        let user = self
+            .users
+            .get_user_by_id(&user_id)
+            .ok_or("User not found")?;
+        if user.is_email_verified() {
+            return Err("Email is already verified".to_string());
+        }
+        let email = &user.profile().email;
+        if email.is_empty() {
+            return Err("No email address to verify".to_string());
+        }
+        verification
+            .send(&user_id, email, events::now_ms())
+            .inspect_err(|e| error!(error = %e, "failed to send verification email"))?;
+        info!("verification email sent again");
         Ok(())
     }
 
-    pub fn sign_out(&mut self, session_token: &str) -> Result<(), String> {
-        self.sessions.delete_session(session_token)?;
+    #[instrument(skip_all, fields(user_id))]
+    pub fn verify_email(&mut self, token: &str) -> Result<(), String> {
+        let result = self.check_verification_token(token);
+        let (user_id, username) = match result {
+            Ok(user) => user,
+            Err(e) => {
+                warn!(error = %e, "email verification failed");
+                return Err(e);
+            }
+        };
+        tracing::Span::current().record("user_id", &user_id);
+
+        self.users.set_email_verified(&user_id)?;
+        info!("email verified");
+        self.emit(Event::EmailVerified { user_id, username });
         Ok(())
     }
 
+    /// The id and username of the user the token was sent to.
+    fn check_verification_token(&self, token: &str) -> Result<(String, String), String> {
+        let verification = self
+            .email_verification
+            .as_ref()
+            .ok_or("Email verification is not enabled")?;
+        let user = EmailVerifier::user_id(token)
+            .and_then(|user_id| self.users.get_user_by_id(user_id))
+            .ok_or("Invalid verification token")?;
+        verification
+            .verifier
+            .verify(token, &user.profile().email, events::now_ms())?;
+        Ok((user.id().to_string(), user.username().to_string()))
+    }
+
+    #[instrument(skip_all)]
+    pub fn sign_out(&mut self, session_token: &str) -> Result<(), String> {
+        match self.sessions.delete_session(session_token) {
+            Ok(user_id) => {
+                info!("user signed out");
+                self.emit(Event::SignedOut { user_id });
+                Ok(())
+            }
+            Err(e) => {
+                warn!(error = %e, "sign out failed");
+                self.emit(Event::SignOutFailed { reason: e.clone() });
+                Err(e)
+            }
+        }
+    }
+
+    /// The user owning a session that has not expired.
+    pub fn session_user(&self, session_token: &str) -> Result<&User, String> {
+        let user_id = self
+            .sessions
+            .get_user_id(session_token)
+            .ok_or("Session not found")?;
+        self.users
+            .get_user_by_id(&user_id)
+            .ok_or_else(|| "User not found".to_string())
+    }
+
+    /// The user a session token or an API key with `scope` belongs to.
+    pub fn authorize(&mut self, token: &str, scope: &str) -> Result<&User, String> {
+        if !token.starts_with(api_keys::KEY_PREFIX) {
+            return self.session_user(token);
+        }
+
+        let key = self.api_keys.use_key(token, events::now_ms())?;
+        if !key.has_scope(scope) {
+            return Err(format!("API key lacks the {} scope", scope));
+        }
+        let user = self
+            .users
+            .get_user_by_id(&key.owner)
+            .ok_or("User not found")?;
+        if user.is_disabled() {
+            return Err("User is disabled".to_string());
+        }
+        Ok(user)
+    }
+
+    #[instrument(skip_all, fields(user_id))]
+    pub fn update_profile(&mut self, token: &str, profile: Profile) -> Result<User, String> {
+        let user = self.authorize(token, api_keys::SCOPE_PROFILE_WRITE)?;
+        let (user_id, previous_email) = (user.id().to_string(), user.profile().email.clone());
+        tracing::Span::current().record("user_id", &user_id);
+        if let Err(e) = self.profile_schema.validate(&profile) {
+            warn!(error = %e, "profile update refused");
+            return Err(e);
+        }
+
+        let user = self.users.update_profile(&user_id, profile)?.clone();
+        info!("profile updated");
+        let email = &user.profile().email;
+        if !email.is_empty() && *email != previous_email {
+            self.send_verification(&user_id, email);
+        }
+        self.emit(Event::ProfileUpdated {
+            user_id,
+            username: user.username().to_string(),
+        });
+        Ok(user)
+    }
+
+    #[instrument(skip(self, password), fields(user_id))]
     pub fn sign_in(&mut self, username: &str, password: &str) -> Result<(String, String), String> {
+        let mut wrong_password = false;
+        let result = self.check_lockout(username).and_then(|()| {
+            let Some(user_id) = self.users.find_user_id(username, password) else {
+                wrong_password = true;
+                return Err("User not found".to_string());
+            };
+            tracing::Span::current().record("user_id", &user_id);
+            let user = self.users.get_user(username).ok_or("User not found")?;
+            if user.is_disabled() {
+                return Err("User is disabled".to_string());
+            }
+            if !user.is_email_verified()
+                && self
+                    .email_verification
+                    .as_ref()
+                    .is_some_and(|verification| verification.block_sign_in)
+            {
+                return Err("Email is not verified".to_string());
+            }
+            let session = self.sessions.create_session(&user_id)?;
+            Ok((session, user_id))
+        });
+
+        match &result {
+            Ok((_, user_id)) => {
+                info!("user signed in");
+                self.failed_sign_ins.remove(username);
+                self.emit(Event::SignedIn {
+                    user_id: user_id.clone(),
+                    username: username.to_string(),
+                });
+            }
+            Err(e) => {
+                warn!(error = %e, "sign in failed");
+                self.emit(Event::SignInFailed {
+                    user_id: self
+                        .users
+                        .get_user(username)
+                        .map(|user| user.id().to_string()),
+                    username: username.to_string(),
+                    reason: e.clone(),
+                });
+                if wrong_password {
+                    self.count_failed_sign_in(username);
+                }
+            }
+        }
+        result
+    }
+
+    fn check_lockout(&self, username: &str) -> Result<(), String> {
+        match self.failed_sign_ins.get(username) {
+            Some(failed) if failed.locked_until_ms > events::now_ms() => {
+                Err("Account is locked".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Locks the account once its failed sign-ins in a row reach the
+    /// threshold.
+    fn count_failed_sign_in(&mut self, username: &str) {
+        if self.lockout_threshold == 0 {
+            return;
+        }
+        // Unknown usernames have no account to lock
+        let Some(user_id) = self
+            .users
+            .get_user(username)
+            .map(|user| user.id().to_string())
+        else {
+            return;
+        };
+
+        let failed = self
+            .failed_sign_ins
+            .entry(username.to_string())
+            .or_default();
+        failed.count += 1;
+        if failed.count < self.lockout_threshold {
+            return;
+        }
+        failed.count = 0;
+        failed.locked_until_ms = events::now_ms() + self.lockout_duration.as_millis() as u64;
+        warn!(
+            user_id,
+            locked_until_ms = failed.locked_until_ms,
+            "account locked"
+        );
+        self.emit(Event::AccountLocked {
+            user_id,
+            username: username.to_string(),
+        });
+    }
+
+    pub fn get_user(&self, username: &str) -> Option<&User> {
+        self.users.get_user(username)
+    }
+
+    pub fn list_users(&self, prefix: &str, after: Option<&str>, limit: usize) -> Vec<&User> {
+        self.users.list_users(prefix, after, limit)
+    }
+
+    /// Disables a user and revokes their sessions, returning the user and the
+    /// number of sessions revoked.
+    #[instrument(skip(self))]
+    pub fn disable_user(&mut self, username: &str) -> Result<(User, usize), String> {
+        let user = self.users.set_disabled(username, true)?.clone();
+        let revoked = self.sessions.delete_user_sessions(user.id());
+        info!(revoked, "user disabled");
+        self.emit(Event::UserDisabled {
+            user_id: user.id().to_string(),
+            username: username.to_string(),
+        });
+        Ok((user, revoked))
+    }
+
+    #[instrument(skip(self))]
+    pub fn enable_user(&mut self, username: &str) -> Result<User, String> {
+        let user = self.users.set_disabled(username, false)?.clone();
+        info!("user enabled");
+        self.emit(Event::UserEnabled {
+            user_id: user.id().to_string(),
+            username: username.to_string(),
+        });
+        Ok(user)
+    }
+
+    /// Deletes a user along with their sessions, returning the number of
+    /// sessions revoked.
+    #[instrument(skip(self))]
+    pub fn delete_user(&mut self, username: &str) -> Result<usize, String> {
         let user_id = self
             .users
-            .find_user_id(username, password)
+            .get_user(username)
+            .ok_or("User not found")?
+            .id()
+            .to_string();
+        self.users.delete_user(username)?;
+        let revoked = self.sessions.delete_user_sessions(&user_id);
+        self.api_keys.revoke_owner_keys(&user_id);
+        self.failed_sign_ins.remove(username);
+        info!(revoked, "user deleted");
+        self.emit(Event::UserDeleted {
+            user_id,
+            username: username.to_string(),
+        });
+        Ok(revoked)
+    }
+
+    #[instrument(skip(self))]
+    pub fn create_service_account(&mut self, username: &str) -> Result<User, String> {
+        let user = self.users.create_service_account(username)?.clone();
+        info!("service account created");
+        self.emit(Event::ServiceAccountCreated {
+            user_id: user.id().to_string(),
+            username: username.to_string(),
+        });
+        Ok(user)
+    }
+
+    fn service_account(&self, username: &str) -> Result<&User, String> {
+        match self.users.get_user(username) {
+            Some(user) if user.is_service_account() => Ok(user),
+            Some(_) => Err("User is not a service account".to_string()),
+            None => Err("User not found".to_string()),
+        }
+    }
+
+    /// Creates an API key for a service account, returning it along with the
+    /// key itself, which is not kept.
+    #[instrument(skip(self, key))]
+    pub fn create_api_key(
+        &mut self,
+        username: &str,
+        key: NewApiKey,
+    ) -> Result<(ApiKey, String), String> {
+        let user_id = self.service_account(username)?.id().to_string();
+        let (api_key, secret) = self.api_keys.create_key(&user_id, key, events::now_ms())?;
+        let api_key = api_key.clone();
+        info!(key_id = %api_key.id, "API key created");
+        self.emit(Event::ApiKeyCreated {
+            user_id,
+            username: username.to_string(),
+            key_id: api_key.id.clone(),
+        });
+        Ok((api_key, secret))
+    }
+
+    pub fn list_api_keys(&self, username: &str) -> Result<Vec<&ApiKey>, String> {
+        let user_id = self.service_account(username)?.id();
+        Ok(self.api_keys.list_keys(user_id))
+    }
+
+    #[instrument(skip(self))]
+    pub fn revoke_api_key(&mut self, username: &str, key_id: &str) -> Result<(), String> {
+        let user_id = self.service_account(username)?.id().to_string();
+        self.api_keys.revoke_key(&user_id, key_id)?;
+        info!("API key revoked");
+        self.emit(Event::ApiKeyRevoked {
+            user_id,
+            username: username.to_string(),
+            key_id: key_id.to_string(),
+        });
+        Ok(())
+    }
+
+    /// Deletes the account of the session's user once the password is
+    /// confirmed, or schedules the deletion when there is a grace period.
+    /// Returns when a scheduled deletion is due.
+    #[instrument(skip_all, fields(user_id))]
+    pub fn delete_account(
+        &mut self,
+        session_token: &str,
+        password: &str,
+    ) -> Result<Option<u64>, String> {
+        let result = self.confirm_account(session_token, password);
+        let (user_id, username) = match result {
+            Ok(user) => user,
+            Err(e) => {
+                warn!(error = %e, "account deletion failed");
+                self.emit(Event::AccountDeletionFailed { reason: e.clone() });
+                return Err(e);
+            }
+        };
+        tracing::Span::current().record("user_id", &user_id);
+
+        if self.deletion_grace_period.is_zero() {
+            self.delete_user(&username)?;
+            return Ok(None);
+        }
+
+        let deletes_at_ms = events::now_ms() + self.deletion_grace_period.as_millis() as u64;
+        self.users.set_deletion_due(&user_id, Some(deletes_at_ms))?;
+        info!(deletes_at_ms, "account deletion scheduled");
+        self.emit(Event::AccountDeletionScheduled { user_id, username });
+        Ok(Some(deletes_at_ms))
+    }
+
+    /// Replaces the password of the session's user once the current one is
+    /// confirmed, and signs them out of their other sessions.
+    #[instrument(skip_all, fields(user_id))]
+    pub fn change_password(
+        &mut self,
+        session_token: &str,
+        password: &str,
+        new_password: &str,
+    ) -> Result<(), String> {
+        let result =
+            self.confirm_account(session_token, password)
+                .and_then(|(user_id, username)| {
+                    self.users.set_password(&user_id, new_password)?;
+                    Ok((user_id, username))
+                });
+
+        match result {
+            Ok((user_id, username)) => {
+                tracing::Span::current().record("user_id", &user_id);
+                let revoked = self.sessions.delete_other_sessions(&user_id, session_token);
+                info!(revoked, "password changed");
+                self.emit(Event::PasswordChanged { user_id, username });
+                Ok(())
+            }
+            Err(e) => {
+                warn!(error = %e, "password change failed");
+                self.emit(Event::PasswordChangeFailed { reason: e.clone() });
+                Err(e)
+            }
+        }
+    }
+
+    /// The id and username of the session's user, if `password` is theirs.
+    /// A wrong password counts towards the lockout like a failed sign-in.
+    fn confirm_account(
+        &mut self,
+        session_token: &str,
+        password: &str,
+    ) -> Result<(String, String), String> {
+        let user = self.session_user(session_token)?;
+        let (user_id, username) = (user.id().to_string(), user.username().to_string());
+        self.check_lockout(&username)?;
+        if self.users.find_user_id(&username, password).as_ref() != Some(&user_id) {
+            self.count_failed_sign_in(&username);
+            return Err("Invalid password".to_string());
+        }
+        self.failed_sign_ins.remove(&username);
+        Ok((user_id, username))
+    }
+
+    #[instrument(skip_all, fields(user_id))]
+    pub fn cancel_account_deletion(&mut self, session_token: &str) -> Result<(), String> {
+        let user_id = self
+            .sessions
+            .get_user_id(session_token)
+            .ok_or("Session not found")?;
+        tracing::Span::current().record("user_id", &user_id);
+        let user = self
+            .users
+            .get_user_by_id(&user_id)
             .ok_or("User not found")?;
-        let session = self.sessions.create_session(&user_id)?;
-        Ok((session, user_id))
+        if user.deletion_due_at_ms().is_none() {
+            return Err("No account deletion is scheduled".to_string());
+        }
+        let username = user.username().to_string();
+        self.users.set_deletion_due(&user_id, None)?;
+        info!("account deletion cancelled");
+        self.emit(Event::AccountDeletionCancelled { user_id, username });
+        Ok(())
+    }
+
+    /// Carries out the scheduled deletions that are due, returning how many
+    /// accounts were deleted.
+    pub fn delete_due_accounts(&mut self) -> usize {
+        let due: Vec<(String, String)> = self
+            .users
+            .deletions_due(events::now_ms())
+            .into_iter()
+            .map(|user| (user.id().to_string(), user.username().to_string()))
+            .collect();
+
+        let mut deleted = 0;
+        for (user_id, username) in due {
+            match self.delete_user(&username) {
+                Ok(_) => deleted += 1,
+                Err(e) => error!(error = %e, user_id, "scheduled account deletion failed"),
+            }
+        }
+        deleted
+    }
+
+    /// Records `event` in the audit log and publishes it. A failing audit
+    /// sink does not fail the operation, which already happened.
+    fn emit(&mut self, event: Event) {
+        if let Err(e) = self.audit.record(&event) {
+            error!(error = %e, "failed to write audit log");
+        }
+        self.events.publish(event);
+    }
+
+    /// Number of registered users and of open sessions.
+    pub fn counts(&self) -> (usize, usize) {
+        (self.users.count(), self.sessions.count())
+    }
+
+    #[instrument(skip_all)]
+    pub fn flush(&mut self) -> Result<(), String> {
+        self.users.flush()?;
+        self.sessions.flush()?;
+        self.audit.flush()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{sessions::SessionsTranstient, users::UsersTransient};
+    use crate::{
+        audit::AuditSink,
+        mailer::{Email, Mailer},
+        sessions::SessionsTranstient,
+        users::UsersTransient,
+    };
+    use common::audit::AuditEntry;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct MemorySink(Arc<Mutex<Vec<AuditEntry>>>);
+
+    impl AuditSink for MemorySink {
+        fn write(&mut self, entry: &AuditEntry) -> Result<(), String> {
+            self.0.lock().unwrap().push(entry.clone());
+            Ok(())
+        }
+    }
 
     #[test]
     fn sign_up_should_succeed_if_user_does_not_exist() {
-        let mut auth = Authenticator::new(UsersTransient::new(), SessionsTranstient::new());
+        let mut auth = Authenticator::new(UsersTransient::fast(), SessionsTranstient::new());
 
-        let response = auth.sign_up("username", "password");
+        let response = auth.sign_up("username", "password", "");
 
         assert!(response.is_ok());
     }
 
     #[test]
     fn sign_up_should_fail_if_username_exists() {
-        let mut auth = Authenticator::new(UsersTransient::new(), SessionsTranstient::new());
+        let mut auth = Authenticator::new(UsersTransient::fast(), SessionsTranstient::new());
 
-        auth.sign_up("username", "password")
+        auth.sign_up("username", "password", "")
             .expect("A user should be signed up");
 
-        let response = auth.sign_up("username", "password");
+        let response = auth.sign_up("username", "password", "");
 
         assert!(response.is_err());
     }
 
     #[test]
     fn sign_in_should_succeed_if_user_exists() {
-        let mut auth = Authenticator::new(UsersTransient::new(), SessionsTranstient::new());
+        let mut auth = Authenticator::new(UsersTransient::fast(), SessionsTranstient::new());
 
-        auth.sign_up("username", "password")
+        auth.sign_up("username", "password", "")
             .expect("A user should be signed up");
 
         let response = auth.sign_in("username", "password");
@@ -77,7 +714,7 @@ mod tests {
 
     #[test]
     fn sign_in_should_fail_if_user_does_not_exist() {
-        let mut auth = Authenticator::new(UsersTransient::new(), SessionsTranstient::new());
+        let mut auth = Authenticator::new(UsersTransient::fast(), SessionsTranstient::new());
 
         let response = auth.sign_in("username", "password");
 
@@ -86,9 +723,9 @@ mod tests {
 
     #[test]
     fn sign_out_should_succeed_if_session_exists() {
-        let mut auth = Authenticator::new(UsersTransient::new(), SessionsTranstient::new());
+        let mut auth = Authenticator::new(UsersTransient::fast(), SessionsTranstient::new());
 
-        auth.sign_up("username", "password")
+        auth.sign_up("username", "password", "")
             .expect("A user should be signed up");
 
         let (session, _) = auth
@@ -102,10 +739,329 @@ mod tests {
 
     #[test]
     fn sign_out_should_fail_if_session_does_not_exist() {
-        let mut auth = Authenticator::new(UsersTransient::new(), SessionsTranstient::new());
+        let mut auth = Authenticator::new(UsersTransient::fast(), SessionsTranstient::new());
 
         let response = auth.sign_out("does-not-exist");
 
         assert!(response.is_err());
     }
+
+    #[test]
+    fn should_audit_every_outcome() {
+        let sink = MemorySink::default();
+        let mut auth = Authenticator::new(UsersTransient::fast(), SessionsTranstient::new())
+            .with_audit(AuditLog::new(vec![Box::new(sink.clone())], b"audit-key"));
+
+        auth.sign_up("username", "password", "").unwrap();
+        let _ = auth.sign_up("username", "password", "");
+        let _ = auth.sign_in("username", "wrong");
+        let (session, user_id) = auth.sign_in("username", "password").unwrap();
+        auth.sign_out(&session).unwrap();
+        let _ = auth.sign_out(&session);
+
+        let entries = sink.0.lock().unwrap();
+        let types: Vec<_> = entries.iter().map(|entry| &entry.event["type"]).collect();
+        assert_eq!(
+            types,
+            [
+                "signed_up",
+                "sign_up_failed",
+                "sign_in_failed",
+                "signed_in",
+                "signed_out",
+                "sign_out_failed"
+            ]
+        );
+        assert_eq!(entries[2].event["user_id"], user_id.as_str());
+        assert_eq!(entries[3].event["user_id"], user_id.as_str());
+        assert_eq!(entries[4].event["user_id"], user_id.as_str());
+        assert!(entries[5].event.get("user_id").is_none());
+        assert!(!entries
+            .iter()
+            .any(|entry| entry.event.to_string().contains("password")
+                || entry.event.to_string().contains(&session)));
+    }
+
+    #[test]
+    fn disabled_user_should_fail_to_sign_in_and_lose_sessions() {
+        let mut auth = Authenticator::new(UsersTransient::fast(), SessionsTranstient::new());
+        auth.sign_up("username", "password", "").unwrap();
+        let (session, _) = auth.sign_in("username", "password").unwrap();
+
+        let (_, revoked) = auth.disable_user("username").unwrap();
+
+        assert_eq!(revoked, 1);
+        assert!(auth.sign_out(&session).is_err());
+        assert_eq!(
+            auth.sign_in("username", "password").unwrap_err(),
+            "User is disabled"
+        );
+
+        auth.enable_user("username").unwrap();
+        assert!(auth.sign_in("username", "password").is_ok());
+    }
+
+    #[test]
+    fn delete_user_should_revoke_sessions() {
+        let mut auth = Authenticator::new(UsersTransient::fast(), SessionsTranstient::new());
+        auth.sign_up("username", "password", "").unwrap();
+        let (session, _) = auth.sign_in("username", "password").unwrap();
+
+        assert_eq!(auth.delete_user("username").unwrap(), 1);
+
+        assert!(auth.sign_out(&session).is_err());
+        assert!(auth.sign_in("username", "password").is_err());
+        assert!(auth.delete_user("username").is_err());
+    }
+
+    /// Keeps the emails in memory, shared with the test.
+    #[derive(Clone, Default)]
+    struct MemoryMailer(Arc<Mutex<Vec<Email>>>);
+
+    impl Mailer for MemoryMailer {
+        fn send(&self, email: Email) -> Result<(), String> {
+            self.0.lock().unwrap().push(email);
+            Ok(())
+        }
+    }
+
+    fn with_verification(mailer: &MemoryMailer) -> Authenticator {
+        Authenticator::new(UsersTransient::fast(), SessionsTranstient::new())
+            .with_email_verification(EmailVerification {
+                verifier: EmailVerifier::new(b"secret", Duration::from_secs(60)),
+                mailer: Box::new(mailer.clone()),
+                required_on_sign_up: true,
+                block_sign_in: true,
+            })
+    }
+
+    #[test]
+    fn should_verify_email_before_sign_in() {
+        let mailer = MemoryMailer::default();
+        let mut auth = with_verification(&mailer);
+
+        assert_eq!(
+            auth.sign_up("username", "password", "").unwrap_err(),
+            "Email is required"
+        );
+        auth.sign_up("username", "password", "jane@example.com")
+            .unwrap();
+        assert_eq!(
+            auth.sign_in("username", "password").unwrap_err(),
+            "Email is not verified"
+        );
+
+        let email = mailer.0.lock().unwrap()[0].clone();
+        assert_eq!(email.to, "jane@example.com");
+        let token = email.body.lines().nth(2).unwrap();
+        assert!(auth.verify_email("garbage.1.00").is_err());
+        auth.verify_email(token).unwrap();
+
+        assert!(auth.sign_in("username", "password").is_ok());
+    }
+
+    #[test]
+    fn should_resend_verification_without_session() {
+        let mailer = MemoryMailer::default();
+        let mut auth = with_verification(&mailer);
+        auth.sign_up("username", "password", "jane@example.com")
+            .unwrap();
+
+        assert!(auth.resend_verification("username", "wrong").is_err());
+        auth.resend_verification("username", "password").unwrap();
+
+        let email = mailer.0.lock().unwrap()[1].clone();
+        assert_eq!(email.to, "jane@example.com");
+        auth.verify_email(email.body.lines().nth(2).unwrap())
+            .unwrap();
+        assert_eq!(
+            auth.resend_verification("username", "password")
+                .unwrap_err(),
+            "Email is already verified"
+        );
+    }
+
+    #[test]
+    fn should_update_profile_of_session_user() {
+        let mut auth = Authenticator::new(UsersTransient::fast(), SessionsTranstient::new());
+        auth.sign_up("username", "password", "").unwrap();
+        let (session, _) = auth.sign_in("username", "password").unwrap();
+        let profile = Profile {
+            display_name: "Jane".to_string(),
+            email: "jane@example.com".to_string(),
+            ..Default::default()
+        };
+
+        auth.update_profile(&session, profile.clone()).unwrap();
+
+        assert_eq!(auth.session_user(&session).unwrap().profile(), &profile);
+        let unknown_attribute = Profile {
+            attributes: [("age".to_string(), "42".to_string())].into(),
+            ..Default::default()
+        };
+        assert!(auth.update_profile(&session, unknown_attribute).is_err());
+        assert!(auth.update_profile("does-not-exist", profile).is_err());
+    }
+
+    #[test]
+    fn api_keys_should_authorize_their_scopes_only() {
+        let mut auth = Authenticator::new(UsersTransient::fast(), SessionsTranstient::new());
+        auth.sign_up("username", "password", "").unwrap();
+        auth.create_service_account("ci-bot").unwrap();
+        let new_key = NewApiKey {
+            name: "ci".to_string(),
+            scopes: vec![api_keys::SCOPE_PROFILE_READ.to_string()],
+            expires_at_ms: None,
+        };
+
+        assert!(auth.create_api_key("username", new_key.clone()).is_err());
+        let (api_key, key) = auth.create_api_key("ci-bot", new_key).unwrap();
+
+        let user = auth.authorize(&key, api_keys::SCOPE_PROFILE_READ).unwrap();
+        assert_eq!(user.username(), "ci-bot");
+        assert!(auth.authorize(&key, api_keys::SCOPE_PROFILE_WRITE).is_err());
+        assert!(auth.list_api_keys("ci-bot").unwrap()[0]
+            .last_used_at_ms
+            .is_some());
+
+        auth.revoke_api_key("ci-bot", &api_key.id).unwrap();
+        assert!(auth.authorize(&key, api_keys::SCOPE_PROFILE_READ).is_err());
+    }
+
+    #[test]
+    fn delete_account_should_confirm_password_and_revoke_sessions() {
+        let mut auth = Authenticator::new(UsersTransient::fast(), SessionsTranstient::new());
+        auth.sign_up("username", "password", "").unwrap();
+        let (session, _) = auth.sign_in("username", "password").unwrap();
+        let (other_session, _) = auth.sign_in("username", "password").unwrap();
+
+        assert_eq!(
+            auth.delete_account(&session, "wrong").unwrap_err(),
+            "Invalid password"
+        );
+        assert_eq!(auth.delete_account(&session, "password").unwrap(), None);
+
+        assert!(auth.sign_out(&other_session).is_err());
+        assert!(auth.get_user("username").is_none());
+        assert!(auth.delete_account("does-not-exist", "password").is_err());
+    }
+
+    #[test]
+    fn delete_account_should_wait_for_grace_period() {
+        let mut auth = Authenticator::new(UsersTransient::fast(), SessionsTranstient::new())
+            .with_deletion_grace_period(Duration::from_millis(1));
+        auth.sign_up("username", "password", "").unwrap();
+        let (session, _) = auth.sign_in("username", "password").unwrap();
+
+        assert!(auth.delete_account(&session, "password").unwrap().is_some());
+        auth.cancel_account_deletion(&session).unwrap();
+        assert!(auth.cancel_account_deletion(&session).is_err());
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(auth.delete_due_accounts(), 0);
+
+        auth.delete_account(&session, "password").unwrap();
+        assert!(auth.get_user("username").is_some());
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(auth.delete_due_accounts(), 1);
+
+        assert!(auth.get_user("username").is_none());
+        assert!(auth.sign_out(&session).is_err());
+    }
+
+    #[test]
+    fn change_password_should_confirm_current_password() {
+        let mut auth = Authenticator::new(UsersTransient::fast(), SessionsTranstient::new());
+        auth.sign_up("username", "password", "").unwrap();
+        let (session, _) = auth.sign_in("username", "password").unwrap();
+        let (other, _) = auth.sign_in("username", "password").unwrap();
+
+        assert_eq!(
+            auth.change_password(&session, "wrong", "new-password")
+                .unwrap_err(),
+            "Invalid password"
+        );
+        auth.change_password(&session, "password", "new-password")
+            .unwrap();
+
+        assert!(auth.sign_in("username", "password").is_err());
+        assert!(auth.sign_in("username", "new-password").is_ok());
+        assert!(auth.session_user(&session).is_ok());
+        assert!(auth.session_user(&other).is_err());
+    }
+
+    #[test]
+    fn confirming_password_should_count_towards_lockout() {
+        let mut auth = Authenticator::new(UsersTransient::fast(), SessionsTranstient::new())
+            .with_lockout(2, Duration::from_secs(60));
+        auth.sign_up("username", "password", "").unwrap();
+        let (session, _) = auth.sign_in("username", "password").unwrap();
+
+        let _ = auth.change_password(&session, "wrong", "new-password");
+        let _ = auth.delete_account(&session, "wrong");
+
+        assert_eq!(
+            auth.change_password(&session, "password", "new-password")
+                .unwrap_err(),
+            "Account is locked"
+        );
+        assert_eq!(
+            auth.delete_account(&session, "password").unwrap_err(),
+            "Account is locked"
+        );
+        assert_eq!(
+            auth.sign_in("username", "password").unwrap_err(),
+            "Account is locked"
+        );
+    }
+
+    #[test]
+    fn should_lock_account_after_failed_sign_ins() {
+        let mut auth = Authenticator::new(UsersTransient::fast(), SessionsTranstient::new())
+            .with_lockout(2, Duration::from_millis(50));
+        auth.sign_up("username", "password", "").unwrap();
+
+        let _ = auth.sign_in("username", "wrong");
+        assert!(auth.sign_in("username", "password").is_ok());
+        let _ = auth.sign_in("username", "wrong");
+        let _ = auth.sign_in("username", "wrong");
+
+        assert_eq!(
+            auth.sign_in("username", "password").unwrap_err(),
+            "Account is locked"
+        );
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(auth.sign_in("username", "password").is_ok());
+    }
+
+    #[test]
+    fn should_audit_password_changes_and_lockouts() {
+        let sink = MemorySink::default();
+        let mut auth = Authenticator::new(UsersTransient::fast(), SessionsTranstient::new())
+            .with_audit(AuditLog::new(vec![Box::new(sink.clone())], b"audit-key"))
+            .with_lockout(1, Duration::from_secs(60));
+        auth.sign_up("username", "password", "").unwrap();
+        let (session, user_id) = auth.sign_in("username", "password").unwrap();
+
+        auth.change_password(&session, "password", "new-password")
+            .unwrap();
+        let _ = auth.change_password(&session, "password", "other-password");
+        let _ = auth.sign_in("username", "password");
+
+        let entries = sink.0.lock().unwrap();
+        let types: Vec<_> = entries[2..]
+            .iter()
+            .map(|entry| &entry.event["type"])
+            .collect();
+        assert_eq!(
+            types,
+            [
+                "password_changed",
+                "account_locked",
+                "password_change_failed",
+                "sign_in_failed"
+            ]
+        );
+        assert_eq!(entries[2].event["user_id"], user_id.as_str());
+        assert_eq!(entries[3].event["user_id"], user_id.as_str());
+    }
 }
@@ -0,0 +1,462 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use common::capture::{self, CapturedCall};
+use http_body::{Body, Frame, SizeHint};
+use http_body_util::{BodyExt, Full};
+use rand_core::{OsRng, RngCore};
+use serde::Serialize;
+use serde_json::Value;
+use tonic::{
+    body::{self as tonic_body, BoxBody},
+    codegen::{http, Bytes, Context, Poll, Service},
+    server::NamedService,
+    Status,
+};
+use tower_layer::Layer;
+
+use crate::service::authentication::{
+    CancelAccountDeletionRequest, CancelAccountDeletionResponse, ChangePasswordRequest,
+    ChangePasswordResponse, DeleteAccountRequest, DeleteAccountResponse, Event, GetProfileRequest,
+    GetProfileResponse, ResendVerificationRequest, ResendVerificationResponse, SignInRequest,
+    SignInResponse, SignOutRequest, SignOutResponse, SignUpRequest, SignUpResponse,
+    SubscribeEventsRequest, UpdateProfileRequest, UpdateProfileResponse, VerifyEmailRequest,
+    VerifyEmailResponse,
+};
+
+/// Records the calls to the Authentication service as JSON lines, for the
+/// `replay` command of the client, with passwords and tokens redacted.
+pub struct Capture {
+    file: Mutex<File>,
+    /// New on every start, so fingerprints only match within a run
+    salt: [u8; 32],
+}
+
+impl Capture {
+    /// Only readable by its owner, since the calls carry usernames and
+    /// emails.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let failed =
+            |e: std::io::Error| format!("Failed to open capture {}: {}", path.display(), e);
+        let mut options = OpenOptions::new();
+        options.create(true).append(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let file = options.open(path).map_err(failed)?;
+        #[cfg(unix)]
+        {
+            // The mode only applies to new files
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(std::fs::Permissions::from_mode(0o600))
+                .map_err(failed)?;
+        }
+        let mut salt = [0; 32];
+        OsRng.fill_bytes(&mut salt);
+        Ok(Self {
+            file: Mutex::new(file),
+            salt,
+        })
+    }
+
+    /// A capture failing does not fail the call, it is only logged.
+    pub fn record<Req: Serialize, Res: Serialize>(&self, rpc: &str, request: &Req, response: &Res) {
+        if let Err(e) = self.write(rpc, request, response) {
+            tracing::warn!(error = %e, rpc, "failed to capture call");
+        }
+    }
+
+    fn write<Req: Serialize, Res: Serialize>(
+        &self,
+        rpc: &str,
+        request: &Req,
+        response: &Res,
+    ) -> Result<(), String> {
+        let mut call = CapturedCall {
+            rpc: rpc.to_string(),
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            request: serde_json::to_value(request).map_err(|e| e.to_string())?,
+            response: serde_json::to_value(response).map_err(|e| e.to_string())?,
+        };
+        capture::redact(&mut call.request, &self.salt);
+        capture::redact(&mut call.response, &self.salt);
+
+        let mut line = serde_json::to_string(&call).map_err(|e| e.to_string())?;
+        line.push('\n');
+        self.file
+            .lock()
+            .unwrap()
+            .write_all(line.as_bytes())
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// The JSON form of an encoded message.
+type Decode = fn(&[u8]) -> Result<Value, String>;
+
+fn decode<M: prost::Message + Default + Serialize>(bytes: &[u8]) -> Result<Value, String> {
+    let message = M::decode(bytes).map_err(|e| e.to_string())?;
+    serde_json::to_value(message).map_err(|e| e.to_string())
+}
+
+/// How to decode the request and the response messages of an RPC of the
+/// Authentication service.
+fn decoders(rpc: &str) -> Option<(Decode, Decode)> {
+    Some(match rpc {
+        "SignUp" => (decode::<SignUpRequest>, decode::<SignUpResponse>),
+        "SignIn" => (decode::<SignInRequest>, decode::<SignInResponse>),
+        "SignOut" => (decode::<SignOutRequest>, decode::<SignOutResponse>),
+        "DeleteAccount" => (
+            decode::<DeleteAccountRequest>,
+            decode::<DeleteAccountResponse>,
+        ),
+        "CancelAccountDeletion" => (
+            decode::<CancelAccountDeletionRequest>,
+            decode::<CancelAccountDeletionResponse>,
+        ),
+        "ChangePassword" => (
+            decode::<ChangePasswordRequest>,
+            decode::<ChangePasswordResponse>,
+        ),
+        "GetProfile" => (decode::<GetProfileRequest>, decode::<GetProfileResponse>),
+        "UpdateProfile" => (
+            decode::<UpdateProfileRequest>,
+            decode::<UpdateProfileResponse>,
+        ),
+        "VerifyEmail" => (decode::<VerifyEmailRequest>, decode::<VerifyEmailResponse>),
+        "ResendVerification" => (
+            decode::<ResendVerificationRequest>,
+            decode::<ResendVerificationResponse>,
+        ),
+        "SubscribeEvents" => (decode::<SubscribeEventsRequest>, decode::<Event>),
+        _ => return None,
+    })
+}
+
+/// Takes the first gRPC message framed in `buffer`, once it is complete:
+/// a compression flag, a big-endian length and the encoded message.
+fn take_message(buffer: &mut Vec<u8>) -> Option<Result<Vec<u8>, String>> {
+    let length = u32::from_be_bytes(buffer.get(1..5)?.try_into().ok()?) as usize;
+    if buffer.len() < 5 + length {
+        return None;
+    }
+    let compressed = buffer[0] != 0;
+    let message = buffer[5..5 + length].to_vec();
+    buffer.drain(..5 + length);
+    Some(if compressed {
+        Err("Compressed messages are not captured".to_string())
+    } else {
+        Ok(message)
+    })
+}
+
+/// Captures the calls to the service it wraps, when given a capture.
+#[derive(Clone)]
+pub struct CaptureLayer {
+    capture: Option<Arc<Capture>>,
+}
+
+impl CaptureLayer {
+    pub fn new(capture: Option<Capture>) -> Self {
+        Self {
+            capture: capture.map(Arc::new),
+        }
+    }
+}
+
+impl<S> Layer<S> for CaptureLayer {
+    type Service = CaptureService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CaptureService {
+            inner,
+            capture: self.capture.clone(),
+        }
+    }
+}
+
+/// Records each message a call answers along with its request, so a
+/// streaming call is recorded once per message.
+#[derive(Clone)]
+pub struct CaptureService<S> {
+    inner: S,
+    capture: Option<Arc<Capture>>,
+}
+
+impl<S: NamedService> NamedService for CaptureService<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S> Service<http::Request<BoxBody>> for CaptureService<S>
+where
+    S: Service<http::Request<BoxBody>, Response = http::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = S::Error;
+    type Future =
+        Pin<Box<dyn std::future::Future<Output = Result<Self::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        let rpc = request.uri().path().rsplit('/').next().unwrap_or_default();
+        let (Some(capture), Some((decode_request, decode_response))) =
+            (self.capture.clone(), decoders(rpc))
+        else {
+            return Box::pin(self.inner.call(request));
+        };
+        let rpc = rpc.to_string();
+        // The service that was polled ready, leaving a clone in its place
+        let ready = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, ready);
+
+        Box::pin(async move {
+            let (parts, body) = request.into_parts();
+            let mut bytes = match body.collect().await {
+                Ok(collected) => collected.to_bytes().to_vec(),
+                Err(status) => return Ok(status.into_http()),
+            };
+            let request = http::Request::from_parts(
+                parts,
+                tonic_body::boxed(Full::new(Bytes::from(bytes.clone()))),
+            );
+            let captured_request = match take_message(&mut bytes) {
+                Some(Ok(message)) => decode_request(&message),
+                Some(Err(e)) => Err(e),
+                None => Err("Incomplete request".to_string()),
+            };
+
+            let response = inner.call(request).await?;
+            let captured_request = match captured_request {
+                Ok(captured_request) => captured_request,
+                Err(e) => {
+                    tracing::warn!(error = %e, rpc, "failed to capture call");
+                    return Ok(response);
+                }
+            };
+            Ok(response.map(|body| {
+                tonic_body::boxed(CapturedBody {
+                    inner: body,
+                    buffer: Vec::new(),
+                    capture,
+                    rpc,
+                    request: captured_request,
+                    decode_response,
+                })
+            }))
+        })
+    }
+}
+
+/// Passes a response on, recording each message as it goes through.
+struct CapturedBody {
+    inner: BoxBody,
+    buffer: Vec<u8>,
+    capture: Arc<Capture>,
+    rpc: String,
+    request: Value,
+    decode_response: Decode,
+}
+
+impl Body for CapturedBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Status>>> {
+        let this = self.get_mut();
+        let frame = std::task::ready!(Pin::new(&mut this.inner).poll_frame(cx));
+        if let Some(data) = frame
+            .as_ref()
+            .and_then(|frame| frame.as_ref().ok())
+            .and_then(Frame::data_ref)
+        {
+            this.buffer.extend_from_slice(data);
+            while let Some(message) = take_message(&mut this.buffer) {
+                match message.and_then(|message| (this.decode_response)(&message)) {
+                    Ok(response) => this.capture.record(&this.rpc, &this.request, &response),
+                    Err(e) => tracing::warn!(error = %e, rpc = this.rpc, "failed to capture call"),
+                }
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::service::{
+        authentication::authentication_client::AuthenticationClient, AuthenticationServer,
+        AuthenticationService, Server,
+    };
+    use tokio::net::TcpListener;
+    use tokio_stream::{wrappers::TcpListenerStream, StreamExt};
+
+    fn temporary_path() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("capture-{}.jsonl", uuid::Uuid::new_v4()))
+    }
+
+    fn read_calls(path: &Path) -> Vec<CapturedCall> {
+        let contents = fs::read_to_string(path).unwrap();
+        fs::remove_file(path).unwrap();
+        contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn should_append_redacted_calls() {
+        let path = temporary_path();
+        let capture = Capture::open(&path).unwrap();
+
+        capture.record(
+            "SignIn",
+            &SignInRequest {
+                username: "jane".to_string(),
+                password: "secret".to_string(),
+            },
+            &SignInResponse {
+                status_code: 1,
+                user_id: "u1".to_string(),
+                session_token: "token".to_string(),
+                profile: None,
+            },
+        );
+        capture.record(
+            "SignOut",
+            &SignInRequest::default(),
+            &SignInResponse::default(),
+        );
+
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let calls: Vec<CapturedCall> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].rpc, "SignIn");
+        assert_eq!(calls[0].request["username"], "jane");
+        assert_eq!(calls[0].response["user_id"], "u1");
+        assert!(!contents.contains("secret"));
+        assert!(capture::is_redacted(
+            calls[0].response["session_token"].as_str().unwrap()
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_only_let_owner_read_capture() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = temporary_path();
+        fs::write(&path, "").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        Capture::open(&path).unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn should_decode_every_rpc() {
+        let proto = include_str!("../../proto/authentication.proto");
+        let rpcs: Vec<&str> = proto
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("rpc "))
+            .filter_map(|rest| rest.split('(').next())
+            .collect();
+
+        assert!(rpcs.contains(&"SignIn"));
+        for rpc in rpcs {
+            assert!(decoders(rpc).is_some(), "{} is not captured", rpc);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_capture_calls_through_layer() {
+        let path = temporary_path();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(
+            Server::builder()
+                .add_service(
+                    CaptureLayer::new(Some(Capture::open(&path).unwrap()))
+                        .layer(AuthenticationServer::new(AuthenticationService::fast())),
+                )
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        let mut client = AuthenticationClient::connect(format!("http://127.0.0.1:{}", port))
+            .await
+            .unwrap();
+
+        client
+            .sign_up(SignUpRequest {
+                username: "jane".to_string(),
+                password: "secret".to_string(),
+                email: "jane@example.com".to_string(),
+            })
+            .await
+            .unwrap();
+        let session_token = client
+            .sign_in(SignInRequest {
+                username: "jane".to_string(),
+                password: "secret".to_string(),
+            })
+            .await
+            .unwrap()
+            .into_inner()
+            .session_token;
+        let mut events = client
+            .subscribe_events(SubscribeEventsRequest {
+                from_sequence: Some(1),
+                session_token,
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        events.next().await.unwrap().unwrap();
+        events.next().await.unwrap().unwrap();
+        drop(events);
+
+        let calls = read_calls(&path);
+        let rpcs: Vec<&str> = calls.iter().map(|call| call.rpc.as_str()).collect();
+        assert_eq!(
+            rpcs,
+            ["SignUp", "SignIn", "SubscribeEvents", "SubscribeEvents"]
+        );
+        assert_eq!(calls[0].request["email"], "jane@example.com");
+        assert!(capture::is_redacted(
+            calls[0].request["password"].as_str().unwrap()
+        ));
+        assert_eq!(calls[2].response["username"], "jane");
+        assert_eq!(calls[3].response["sequence"], 2);
+    }
+}
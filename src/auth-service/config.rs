@@ -0,0 +1,1136 @@
+use std::{fs, net::SocketAddr, path::PathBuf, time::Duration};
+
+use clap::Parser;
+use common::logging::{self, LogFormat};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audit::AuditSinkKind,
+    events::{self, DEFAULT_BUFFER_SIZE},
+    mailer::MailTransport,
+    profiles::{AttributeSchema, ProfileSchema},
+    service::AuthenticationServiceConfig,
+    sessions::DEFAULT_SESSION_LIFETIME,
+    tls::TlsConfig,
+    users::HashingParams,
+    web::{self, AllowedOrigins},
+};
+
+const DEFAULT_LISTEN_ADDR: &str = "[::]:50051";
+const DEFAULT_TLS_RELOAD_INTERVAL_SECS: u64 = 5;
+const DEFAULT_DRAIN_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_METRICS_LISTEN_ADDR: &str = "[::]:9090";
+const DEFAULT_LOG_LEVEL: &str = "info";
+const DEFAULT_SERVICE_NAME: &str = "auth";
+const DEFAULT_WEBHOOK_MAX_ATTEMPTS: u32 = 8;
+const DEFAULT_WEBHOOK_INITIAL_BACKOFF_MS: u64 = 1_000;
+const DEFAULT_WEBHOOK_MAX_BACKOFF_MS: u64 = 5 * 60 * 1_000;
+const DEFAULT_WEBHOOK_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_WEBHOOK_MAX_CONCURRENT_DELIVERIES: usize = 16;
+const DEFAULT_WEBHOOK_MAX_PENDING_DELIVERIES: usize = 1_024;
+const DEFAULT_MAIL_FROM: &str = "no-reply@localhost";
+const DEFAULT_SMTP_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_VERIFICATION_TOKEN_LIFETIME_SECS: u64 = 24 * 60 * 60;
+const MIN_ADMIN_TOKEN_LENGTH: usize = 16;
+const MIN_HASH_ROUNDS: u32 = 1_000;
+const MIN_HASH_OUTPUT_LENGTH: usize = 10;
+const MAX_HASH_OUTPUT_LENGTH: usize = 64;
+/// What the secrets are printed as.
+const REDACTED: &str = "<redacted>";
+
+/// Command line of the auth binary. Every setting can also come from the
+/// environment; both take precedence over the configuration file.
+#[derive(Parser, Debug, Default)]
+#[command(version, about, long_about = None)]
+pub struct Cli {
+    /// Path to a TOML configuration file
+    #[arg(short, long, env = "AUTH_SERVICE_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Print the effective configuration, secrets redacted, and exit
+    #[arg(long)]
+    pub print_config: bool,
+    /// Address the gRPC server listens on
+    #[arg(long, env = "AUTH_SERVICE_LISTEN_ADDR")]
+    pub listen_addr: Option<SocketAddr>,
+    /// Storage backend for users and sessions
+    #[arg(long, env = "AUTH_SERVICE_PERSISTENCE_TYPE")]
+    pub persistence_type: Option<AuthenticationServiceConfig>,
+    /// Lifetime of a session, in seconds
+    #[arg(long, env = "AUTH_SERVICE_SESSION_LIFETIME_SECS")]
+    pub session_lifetime_secs: Option<u64>,
+    /// Number of PBKDF2 rounds used to hash passwords
+    #[arg(long, env = "AUTH_SERVICE_HASH_ROUNDS")]
+    pub hash_rounds: Option<u32>,
+    /// Length in bytes of the password hashes
+    #[arg(long, env = "AUTH_SERVICE_HASH_OUTPUT_LENGTH")]
+    pub hash_output_length: Option<usize>,
+    /// Comma separated list of origins allowed to use gRPC-Web, `*` for any
+    #[arg(long, env = "AUTH_SERVICE_CORS_ALLOWED_ORIGINS", value_delimiter = ',')]
+    pub cors_allowed_origins: Option<Vec<String>>,
+    /// Server certificate (PEM), enables TLS together with --tls-key
+    #[arg(long, env = "AUTH_SERVICE_TLS_CERT")]
+    pub tls_cert: Option<PathBuf>,
+    /// Server private key (PEM)
+    #[arg(long, env = "AUTH_SERVICE_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+    /// CA certificate (PEM) clients must present a certificate from
+    #[arg(long, env = "AUTH_SERVICE_TLS_CLIENT_CA")]
+    pub tls_client_ca: Option<PathBuf>,
+    /// How often the TLS files are checked for changes, in seconds
+    #[arg(long, env = "AUTH_SERVICE_TLS_RELOAD_INTERVAL_SECS")]
+    pub tls_reload_interval_secs: Option<u64>,
+    /// How long in-flight requests may take to finish on shutdown, in seconds
+    #[arg(long, env = "AUTH_SERVICE_DRAIN_TIMEOUT_SECS")]
+    pub drain_timeout_secs: Option<u64>,
+    /// How long the health service reports NOT_SERVING on shutdown before
+    /// the listener closes, for load balancers to stop routing, in seconds
+    #[arg(long, env = "AUTH_SERVICE_PRE_DRAIN_DELAY_SECS")]
+    pub pre_drain_delay_secs: Option<u64>,
+    /// Serve Prometheus metrics
+    #[arg(long, env = "AUTH_SERVICE_METRICS_ENABLED", action = clap::ArgAction::Set)]
+    pub metrics_enabled: Option<bool>,
+    /// Address the `/metrics` HTTP endpoint listens on
+    #[arg(long, env = "AUTH_SERVICE_METRICS_LISTEN_ADDR")]
+    pub metrics_listen_addr: Option<SocketAddr>,
+    /// Log level or filter directives, e.g. `info` or `warn,auth=debug`
+    #[arg(long, env = "AUTH_SERVICE_LOG_LEVEL")]
+    pub log_level: Option<String>,
+    /// Log output format
+    #[arg(long, env = "AUTH_SERVICE_LOG_FORMAT", value_enum)]
+    pub log_format: Option<LogFormat>,
+    /// OTLP/gRPC collector to export traces to, e.g. `http://localhost:4317`
+    #[arg(long, env = "AUTH_SERVICE_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+    /// Comma separated list of sinks the audit log is written to
+    #[arg(
+        long,
+        env = "AUTH_SERVICE_AUDIT_SINKS",
+        value_enum,
+        value_delimiter = ','
+    )]
+    pub audit_sinks: Option<Vec<AuditSinkKind>>,
+    /// File the `file` audit sink appends to
+    #[arg(long, env = "AUTH_SERVICE_AUDIT_PATH")]
+    pub audit_path: Option<PathBuf>,
+    /// Key of the hashes chaining the audit log entries, which verifying the
+    /// log requires
+    #[arg(long, env = "AUTH_SERVICE_AUDIT_KEY", hide_env_values = true)]
+    pub audit_key: Option<String>,
+    /// File the calls are recorded to, with passwords and tokens redacted,
+    /// for the `replay` command of the client
+    #[arg(long, env = "AUTH_SERVICE_CAPTURE_PATH")]
+    pub capture_path: Option<PathBuf>,
+    /// Directory keeping webhook deliveries across restarts, in memory when unset
+    #[arg(long, env = "AUTH_SERVICE_WEBHOOKS_QUEUE_DIR")]
+    pub webhooks_queue_dir: Option<PathBuf>,
+    /// Token callers of the Admin service must present, which is only served
+    /// when set
+    #[arg(long, env = "AUTH_SERVICE_ADMIN_TOKEN", hide_env_values = true)]
+    pub admin_token: Option<String>,
+    /// Seconds during which users can cancel the deletion of their account,
+    /// which is immediate when 0
+    #[arg(long, env = "AUTH_SERVICE_ACCOUNT_DELETION_GRACE_PERIOD_SECS")]
+    pub account_deletion_grace_period_secs: Option<u64>,
+    /// Failed sign-ins in a row after which an account is locked, never
+    /// when 0
+    #[arg(long, env = "AUTH_SERVICE_ACCOUNT_LOCKOUT_THRESHOLD")]
+    pub account_lockout_threshold: Option<u32>,
+    /// Seconds during which a locked account cannot sign in
+    #[arg(long, env = "AUTH_SERVICE_ACCOUNT_LOCKOUT_DURATION_SECS")]
+    pub account_lockout_duration_secs: Option<u64>,
+    /// How emails are sent, no email is sent when unset
+    #[arg(long, env = "AUTH_SERVICE_MAILER_TRANSPORT", value_enum)]
+    pub mailer_transport: Option<MailTransport>,
+    /// Address of the SMTP relay of the smtp mail transport, e.g. `127.0.0.1:25`
+    #[arg(long, env = "AUTH_SERVICE_MAILER_SMTP_ADDR")]
+    pub mailer_smtp_addr: Option<String>,
+    /// Directory the emails are written to by the file mail transport
+    #[arg(long, env = "AUTH_SERVICE_MAILER_OUTBOX_DIR")]
+    pub mailer_outbox_dir: Option<PathBuf>,
+    /// Whether users verify their email address with an emailed token
+    #[arg(long, env = "AUTH_SERVICE_EMAIL_VERIFICATION_ENABLED", action = clap::ArgAction::Set)]
+    pub email_verification_enabled: Option<bool>,
+    /// Key signing the verification tokens, random on every start when unset
+    #[arg(
+        long,
+        env = "AUTH_SERVICE_EMAIL_VERIFICATION_SECRET",
+        hide_env_values = true
+    )]
+    pub email_verification_secret: Option<String>,
+    /// Events kept for subscribers resuming or falling behind
+    #[arg(long, env = "AUTH_SERVICE_EVENTS_BUFFER_SIZE")]
+    pub events_buffer_size: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen_addr: SocketAddr,
+    pub storage: StorageConfig,
+    pub sessions: SessionsConfig,
+    pub hashing: HashingConfig,
+    pub cors: CorsConfig,
+    pub tls: TlsSection,
+    pub shutdown: ShutdownConfig,
+    pub metrics: MetricsConfig,
+    pub logging: LoggingConfig,
+    pub telemetry: TelemetryConfig,
+    pub audit: AuditConfig,
+    pub capture: CaptureConfig,
+    pub events: EventsConfig,
+    pub webhooks: WebhooksConfig,
+    pub admin: AdminConfig,
+    pub accounts: AccountsConfig,
+    pub profiles: ProfilesConfig,
+    pub mailer: MailerConfig,
+    pub email_verification: EmailVerificationConfig,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub backend: AuthenticationServiceConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionsConfig {
+    pub lifetime_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HashingConfig {
+    pub rounds: u32,
+    pub output_length: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsSection {
+    pub cert_path: Option<PathBuf>,
+    pub key_path: Option<PathBuf>,
+    pub client_ca_path: Option<PathBuf>,
+    pub reload_interval_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownConfig {
+    pub drain_timeout_secs: u64,
+    pub pre_drain_delay_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    pub listen_addr: SocketAddr,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub level: String,
+    pub format: LogFormat,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetryConfig {
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    pub sinks: Vec<AuditSinkKind>,
+    pub path: Option<PathBuf>,
+    /// Keys the hashes chaining the entries, required by the sinks
+    pub key: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptureConfig {
+    /// No call is recorded when unset
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EventsConfig {
+    pub buffer_size: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhooksConfig {
+    pub queue_dir: Option<PathBuf>,
+    pub max_attempts: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub timeout_secs: u64,
+    /// Attempts in flight at once, across the endpoints
+    pub max_concurrent_deliveries: usize,
+    /// Deliveries under way at once, backoffs included; as many events may
+    /// wait for them, past which events are dropped
+    pub max_pending_deliveries: usize,
+    pub endpoints: Vec<WebhookEndpointConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookEndpointConfig {
+    pub url: String,
+    /// Key of the HMAC signing the payloads
+    pub secret: String,
+    /// Event types posted to the endpoint, every type when empty
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountsConfig {
+    pub deletion_grace_period_secs: u64,
+    /// Failed sign-ins in a row locking the account, never locked when 0
+    pub lockout_threshold: u32,
+    pub lockout_duration_secs: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProfilesConfig {
+    /// Custom attributes the profiles may carry
+    pub attributes: Vec<AttributeSchema>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MailerConfig {
+    pub transport: Option<MailTransport>,
+    pub from: String,
+    pub smtp_addr: Option<String>,
+    /// Limit on sending an email through SMTP, connecting included
+    pub smtp_timeout_secs: u64,
+    pub outbox_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmailVerificationConfig {
+    pub enabled: bool,
+    /// Sign-up fails without an email address
+    pub required_on_sign_up: bool,
+    /// Sign-in fails until the email address is verified
+    pub block_sign_in: bool,
+    pub token_lifetime_secs: u64,
+    pub secret: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen_addr: DEFAULT_LISTEN_ADDR.parse().unwrap(),
+            storage: StorageConfig::default(),
+            sessions: SessionsConfig::default(),
+            hashing: HashingConfig::default(),
+            cors: CorsConfig::default(),
+            tls: TlsSection::default(),
+            shutdown: ShutdownConfig::default(),
+            metrics: MetricsConfig::default(),
+            logging: LoggingConfig::default(),
+            telemetry: TelemetryConfig::default(),
+            audit: AuditConfig::default(),
+            capture: CaptureConfig::default(),
+            events: EventsConfig::default(),
+            webhooks: WebhooksConfig::default(),
+            admin: AdminConfig::default(),
+            accounts: AccountsConfig::default(),
+            profiles: ProfilesConfig::default(),
+            mailer: MailerConfig::default(),
+            email_verification: EmailVerificationConfig::default(),
+        }
+    }
+}
+
+impl Default for SessionsConfig {
+    fn default() -> Self {
+        Self {
+            lifetime_secs: DEFAULT_SESSION_LIFETIME.as_secs(),
+        }
+    }
+}
+
+impl SessionsConfig {
+    pub fn lifetime(&self) -> Duration {
+        Duration::from_secs(self.lifetime_secs)
+    }
+}
+
+impl Default for AccountsConfig {
+    fn default() -> Self {
+        Self {
+            deletion_grace_period_secs: 0,
+            lockout_threshold: 0,
+            lockout_duration_secs: 900,
+        }
+    }
+}
+
+impl AccountsConfig {
+    pub fn deletion_grace_period(&self) -> Duration {
+        Duration::from_secs(self.deletion_grace_period_secs)
+    }
+
+    pub fn lockout_duration(&self) -> Duration {
+        Duration::from_secs(self.lockout_duration_secs)
+    }
+}
+
+impl ProfilesConfig {
+    pub fn schema(&self) -> Result<ProfileSchema, String> {
+        ProfileSchema::new(self.attributes.clone())
+    }
+}
+
+impl Default for MailerConfig {
+    fn default() -> Self {
+        Self {
+            transport: None,
+            from: DEFAULT_MAIL_FROM.to_string(),
+            smtp_addr: None,
+            smtp_timeout_secs: DEFAULT_SMTP_TIMEOUT_SECS,
+            outbox_dir: None,
+        }
+    }
+}
+
+impl Default for EmailVerificationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            required_on_sign_up: false,
+            block_sign_in: false,
+            token_lifetime_secs: DEFAULT_VERIFICATION_TOKEN_LIFETIME_SECS,
+            secret: None,
+        }
+    }
+}
+
+impl EmailVerificationConfig {
+    pub fn token_lifetime(&self) -> Duration {
+        Duration::from_secs(self.token_lifetime_secs)
+    }
+}
+
+impl Default for HashingConfig {
+    fn default() -> Self {
+        let params = HashingParams::default();
+        Self {
+            rounds: params.rounds,
+            output_length: params.output_length,
+        }
+    }
+}
+
+impl HashingConfig {
+    pub fn params(&self) -> HashingParams {
+        HashingParams {
+            rounds: self.rounds,
+            output_length: self.output_length,
+        }
+    }
+}
+
+impl CorsConfig {
+    pub fn allowed_origins(&self) -> AllowedOrigins {
+        AllowedOrigins::from_list(&self.allowed_origins)
+    }
+}
+
+impl Default for TlsSection {
+    fn default() -> Self {
+        Self {
+            cert_path: None,
+            key_path: None,
+            client_ca_path: None,
+            reload_interval_secs: DEFAULT_TLS_RELOAD_INTERVAL_SECS,
+        }
+    }
+}
+
+impl TlsSection {
+    /// The server TLS configuration, `None` when TLS is disabled.
+    pub fn server_config(&self) -> Option<TlsConfig> {
+        Some(TlsConfig {
+            cert_path: self.cert_path.clone()?,
+            key_path: self.key_path.clone()?,
+            client_ca_path: self.client_ca_path.clone(),
+        })
+    }
+
+    pub fn reload_interval(&self) -> Duration {
+        Duration::from_secs(self.reload_interval_secs)
+    }
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            drain_timeout_secs: DEFAULT_DRAIN_TIMEOUT_SECS,
+            pre_drain_delay_secs: 0,
+        }
+    }
+}
+
+impl ShutdownConfig {
+    pub fn drain_timeout(&self) -> Duration {
+        Duration::from_secs(self.drain_timeout_secs)
+    }
+
+    pub fn pre_drain_delay(&self) -> Duration {
+        Duration::from_secs(self.pre_drain_delay_secs)
+    }
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            listen_addr: DEFAULT_METRICS_LISTEN_ADDR.parse().unwrap(),
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: DEFAULT_LOG_LEVEL.to_string(),
+            format: LogFormat::default(),
+        }
+    }
+}
+
+impl Default for EventsConfig {
+    fn default() -> Self {
+        Self {
+            buffer_size: DEFAULT_BUFFER_SIZE,
+        }
+    }
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        Self {
+            queue_dir: None,
+            max_attempts: DEFAULT_WEBHOOK_MAX_ATTEMPTS,
+            initial_backoff_ms: DEFAULT_WEBHOOK_INITIAL_BACKOFF_MS,
+            max_backoff_ms: DEFAULT_WEBHOOK_MAX_BACKOFF_MS,
+            timeout_secs: DEFAULT_WEBHOOK_TIMEOUT_SECS,
+            max_concurrent_deliveries: DEFAULT_WEBHOOK_MAX_CONCURRENT_DELIVERIES,
+            max_pending_deliveries: DEFAULT_WEBHOOK_MAX_PENDING_DELIVERIES,
+            endpoints: Vec::new(),
+        }
+    }
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            otlp_endpoint: None,
+            service_name: DEFAULT_SERVICE_NAME.to_string(),
+        }
+    }
+}
+
+impl WebhooksConfig {
+    fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if self.max_attempts == 0 {
+            errors.push("webhooks.max_attempts must be greater than 0".to_string());
+        }
+        if self.initial_backoff_ms == 0 || self.max_backoff_ms < self.initial_backoff_ms {
+            errors.push(
+                "webhooks.initial_backoff_ms must be greater than 0 and at most webhooks.max_backoff_ms"
+                    .to_string(),
+            );
+        }
+        if self.timeout_secs == 0 {
+            errors.push("webhooks.timeout_secs must be greater than 0".to_string());
+        }
+        if self.max_concurrent_deliveries == 0 {
+            errors.push("webhooks.max_concurrent_deliveries must be greater than 0".to_string());
+        }
+        if self.max_pending_deliveries == 0 {
+            errors.push("webhooks.max_pending_deliveries must be greater than 0".to_string());
+        }
+        for endpoint in &self.endpoints {
+            if reqwest::Url::parse(&endpoint.url).is_err() {
+                errors.push(format!("webhook url is not a valid URL: {}", endpoint.url));
+            }
+            if endpoint.secret.is_empty() {
+                errors.push(format!("webhook {} has an empty secret", endpoint.url));
+            }
+            for name in &endpoint.events {
+                if events::parse_event_type(name).is_none() {
+                    errors.push(format!(
+                        "webhook {} has an unknown event type: {}",
+                        endpoint.url, name
+                    ));
+                }
+            }
+        }
+
+        errors
+    }
+}
+
+impl Config {
+    /// Builds the configuration from the defaults, the configuration file and
+    /// the command line/environment, in increasing order of precedence, and
+    /// validates the result.
+    pub fn load(cli: &Cli) -> Result<Self, String> {
+        let mut config = match &cli.config {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+
+        config.apply(cli);
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn from_file(path: &PathBuf) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Self::from_toml(&contents).map_err(|e| format!("Invalid {}: {}", path.display(), e))
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Self, String> {
+        toml::from_str(contents).map_err(|e| e.to_string())
+    }
+
+    /// The configuration with its secrets replaced by `<redacted>`, for
+    /// printing.
+    pub fn to_toml(&self) -> Result<String, String> {
+        let mut config = self.clone();
+        for endpoint in &mut config.webhooks.endpoints {
+            endpoint.secret = REDACTED.to_string();
+        }
+        if config.admin.token.is_some() {
+            config.admin.token = Some(REDACTED.to_string());
+        }
+        if config.email_verification.secret.is_some() {
+            config.email_verification.secret = Some(REDACTED.to_string());
+        }
+        if config.audit.key.is_some() {
+            config.audit.key = Some(REDACTED.to_string());
+        }
+        toml::to_string_pretty(&config).map_err(|e| e.to_string())
+    }
+
+    fn apply(&mut self, cli: &Cli) {
+        if let Some(listen_addr) = cli.listen_addr {
+            self.listen_addr = listen_addr;
+        }
+        if let Some(backend) = cli.persistence_type {
+            self.storage.backend = backend;
+        }
+        if let Some(lifetime_secs) = cli.session_lifetime_secs {
+            self.sessions.lifetime_secs = lifetime_secs;
+        }
+        if let Some(rounds) = cli.hash_rounds {
+            self.hashing.rounds = rounds;
+        }
+        if let Some(output_length) = cli.hash_output_length {
+            self.hashing.output_length = output_length;
+        }
+        if let Some(allowed_origins) = &cli.cors_allowed_origins {
+            self.cors.allowed_origins = allowed_origins.clone();
+        }
+        if let Some(cert_path) = &cli.tls_cert {
+            self.tls.cert_path = Some(cert_path.clone());
+        }
+        if let Some(key_path) = &cli.tls_key {
+            self.tls.key_path = Some(key_path.clone());
+        }
+        if let Some(client_ca_path) = &cli.tls_client_ca {
+            self.tls.client_ca_path = Some(client_ca_path.clone());
+        }
+        if let Some(reload_interval_secs) = cli.tls_reload_interval_secs {
+            self.tls.reload_interval_secs = reload_interval_secs;
+        }
+        if let Some(drain_timeout_secs) = cli.drain_timeout_secs {
+            self.shutdown.drain_timeout_secs = drain_timeout_secs;
+        }
+        if let Some(pre_drain_delay_secs) = cli.pre_drain_delay_secs {
+            self.shutdown.pre_drain_delay_secs = pre_drain_delay_secs;
+        }
+        if let Some(enabled) = cli.metrics_enabled {
+            self.metrics.enabled = enabled;
+        }
+        if let Some(listen_addr) = cli.metrics_listen_addr {
+            self.metrics.listen_addr = listen_addr;
+        }
+        if let Some(level) = &cli.log_level {
+            self.logging.level = level.clone();
+        }
+        if let Some(format) = cli.log_format {
+            self.logging.format = format;
+        }
+        if let Some(endpoint) = &cli.otlp_endpoint {
+            self.telemetry.otlp_endpoint = Some(endpoint.clone());
+        }
+        if let Some(sinks) = &cli.audit_sinks {
+            self.audit.sinks = sinks.clone();
+        }
+        if let Some(path) = &cli.audit_path {
+            self.audit.path = Some(path.clone());
+        }
+        if let Some(key) = &cli.audit_key {
+            self.audit.key = Some(key.clone());
+        }
+        if let Some(path) = &cli.capture_path {
+            self.capture.path = Some(path.clone());
+        }
+        if let Some(queue_dir) = &cli.webhooks_queue_dir {
+            self.webhooks.queue_dir = Some(queue_dir.clone());
+        }
+        if let Some(token) = &cli.admin_token {
+            self.admin.token = Some(token.clone());
+        }
+        if let Some(grace_period_secs) = cli.account_deletion_grace_period_secs {
+            self.accounts.deletion_grace_period_secs = grace_period_secs;
+        }
+        if let Some(threshold) = cli.account_lockout_threshold {
+            self.accounts.lockout_threshold = threshold;
+        }
+        if let Some(duration_secs) = cli.account_lockout_duration_secs {
+            self.accounts.lockout_duration_secs = duration_secs;
+        }
+        if let Some(transport) = cli.mailer_transport {
+            self.mailer.transport = Some(transport);
+        }
+        if let Some(smtp_addr) = &cli.mailer_smtp_addr {
+            self.mailer.smtp_addr = Some(smtp_addr.clone());
+        }
+        if let Some(outbox_dir) = &cli.mailer_outbox_dir {
+            self.mailer.outbox_dir = Some(outbox_dir.clone());
+        }
+        if let Some(enabled) = cli.email_verification_enabled {
+            self.email_verification.enabled = enabled;
+        }
+        if let Some(secret) = &cli.email_verification_secret {
+            self.email_verification.secret = Some(secret.clone());
+        }
+        if let Some(buffer_size) = cli.events_buffer_size {
+            self.events.buffer_size = buffer_size;
+        }
+    }
+
+    /// Checks the settings that would otherwise only fail once the server is
+    /// running, reporting every problem at once.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut errors = Vec::new();
+
+        if self.sessions.lifetime_secs == 0 {
+            errors.push("sessions.lifetime_secs must be greater than 0".to_string());
+        }
+        if self.hashing.rounds < MIN_HASH_ROUNDS {
+            errors.push(format!(
+                "hashing.rounds must be at least {}",
+                MIN_HASH_ROUNDS
+            ));
+        }
+        if !(MIN_HASH_OUTPUT_LENGTH..=MAX_HASH_OUTPUT_LENGTH).contains(&self.hashing.output_length)
+        {
+            errors.push(format!(
+                "hashing.output_length must be between {} and {}",
+                MIN_HASH_OUTPUT_LENGTH, MAX_HASH_OUTPUT_LENGTH
+            ));
+        }
+        for origin in &self.cors.allowed_origins {
+            if let Err(e) = web::validate_origin(origin) {
+                errors.push(format!("cors.allowed_origins: {}", e));
+            }
+        }
+        if self.tls.cert_path.is_some() != self.tls.key_path.is_some() {
+            errors.push("tls.cert_path and tls.key_path must be set together".to_string());
+        }
+        if self.tls.client_ca_path.is_some() && self.tls.cert_path.is_none() {
+            errors.push("tls.client_ca_path requires tls.cert_path and tls.key_path".to_string());
+        }
+        if self.metrics.enabled && self.metrics.listen_addr == self.listen_addr {
+            errors.push("metrics.listen_addr must differ from listen_addr".to_string());
+        }
+        if let Err(e) = logging::validate_level(&self.logging.level) {
+            errors.push(format!("logging.level: {}", e));
+        }
+        if let Some(endpoint) = &self.telemetry.otlp_endpoint {
+            if endpoint.parse::<tonic::transport::Uri>().is_err() {
+                errors.push(format!(
+                    "telemetry.otlp_endpoint is not a valid URI: {}",
+                    endpoint
+                ));
+            }
+        }
+        if self.audit.sinks.contains(&AuditSinkKind::File) && self.audit.path.is_none() {
+            errors.push("audit.sinks includes file but audit.path is not set".to_string());
+        }
+        if !self.audit.sinks.is_empty() && self.audit.key.is_none() {
+            errors.push("audit.sinks is set but audit.key is not".to_string());
+        }
+        if self.audit.sinks.contains(&AuditSinkKind::Grpc) && self.admin.token.is_none() {
+            errors.push("audit.sinks includes grpc but admin.token is not set".to_string());
+        }
+        if self.accounts.lockout_threshold > 0 && self.accounts.lockout_duration_secs == 0 {
+            errors.push(
+                "accounts.lockout_duration_secs must be greater than 0 when accounts.lockout_threshold is set"
+                    .to_string(),
+            );
+        }
+        if self.events.buffer_size == 0 {
+            errors.push("events.buffer_size must be greater than 0".to_string());
+        }
+        errors.extend(self.webhooks.validate());
+        match self.mailer.transport {
+            Some(MailTransport::File) if self.mailer.outbox_dir.is_none() => {
+                errors.push("mailer.transport is file but mailer.outbox_dir is not set".to_string())
+            }
+            Some(MailTransport::Smtp) if self.mailer.smtp_addr.is_none() => {
+                errors.push("mailer.transport is smtp but mailer.smtp_addr is not set".to_string())
+            }
+            _ => {}
+        }
+        if self.mailer.smtp_timeout_secs == 0 {
+            errors.push("mailer.smtp_timeout_secs must be greater than 0".to_string());
+        }
+        if self.email_verification.enabled && self.mailer.transport.is_none() {
+            errors.push("email_verification.enabled requires mailer.transport".to_string());
+        }
+        if self.email_verification.token_lifetime_secs == 0 {
+            errors
+                .push("email_verification.token_lifetime_secs must be greater than 0".to_string());
+        }
+        if let Err(e) = self.profiles.schema() {
+            errors.push(format!("profiles.attributes: {}", e));
+        }
+        if self
+            .admin
+            .token
+            .as_ref()
+            .is_some_and(|token| token.len() < MIN_ADMIN_TOKEN_LENGTH)
+        {
+            errors.push(format!(
+                "admin.token must be at least {} characters long",
+                MIN_ADMIN_TOKEN_LENGTH
+            ));
+        }
+        if self.tls.reload_interval_secs == 0 {
+            errors.push("tls.reload_interval_secs must be greater than 0".to_string());
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Invalid configuration: {}", errors.join("; ")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profiles::AttributeType;
+
+    #[test]
+    fn default_config_should_be_valid() {
+        assert!(Config::default().validate().is_ok());
+    }
+
+    #[test]
+    fn should_parse_partial_file() {
+        let config = Config::from_toml(
+            r#"
+            listen_addr = "127.0.0.1:6000"
+
+            [hashing]
+            rounds = 10000
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.listen_addr, "127.0.0.1:6000".parse().unwrap());
+        assert_eq!(config.hashing.rounds, 10_000);
+        assert_eq!(
+            config.hashing.output_length,
+            HashingConfig::default().output_length
+        );
+        assert_eq!(config.sessions, SessionsConfig::default());
+    }
+
+    #[test]
+    fn should_reject_unknown_fields() {
+        assert!(Config::from_toml("listen_address = \"127.0.0.1:6000\"").is_err());
+    }
+
+    #[test]
+    fn command_line_should_override_file() {
+        let mut config = Config::from_toml("[sessions]\nlifetime_secs = 60").unwrap();
+        let cli = Cli::try_parse_from([
+            "auth",
+            "--session-lifetime-secs",
+            "120",
+            "--cors-allowed-origins",
+            "https://a.example,https://b.example",
+        ])
+        .unwrap();
+
+        config.apply(&cli);
+
+        assert_eq!(config.sessions.lifetime_secs, 120);
+        assert_eq!(
+            config.cors.allowed_origins,
+            vec!["https://a.example", "https://b.example"]
+        );
+    }
+
+    #[test]
+    fn should_reject_invalid_allowed_origins() {
+        let mut config = Config::default();
+        config.cors.allowed_origins =
+            vec!["https://a.example".to_string(), "a.example".to_string()];
+
+        let error = config.validate().unwrap_err();
+
+        assert!(error.contains("cors.allowed_origins: a.example"));
+        assert!(!error.contains("https://a.example"));
+    }
+
+    #[test]
+    fn should_fail_to_parse_unknown_persistence_type() {
+        assert!(Cli::try_parse_from(["auth", "--persistence-type", "Unknown"]).is_err());
+    }
+
+    #[test]
+    fn should_report_every_invalid_setting() {
+        let mut config = Config::default();
+        config.sessions.lifetime_secs = 0;
+        config.hashing.rounds = 1;
+        config.tls.cert_path = Some("cert.pem".into());
+
+        let error = config.validate().unwrap_err();
+
+        assert!(error.contains("sessions.lifetime_secs"));
+        assert!(error.contains("hashing.rounds"));
+        assert!(error.contains("tls.cert_path and tls.key_path"));
+    }
+
+    #[test]
+    fn should_reject_invalid_otlp_endpoint() {
+        let mut config = Config::default();
+        config.telemetry.otlp_endpoint = Some("not a uri".to_string());
+
+        assert!(config
+            .validate()
+            .unwrap_err()
+            .contains("telemetry.otlp_endpoint"));
+    }
+
+    #[test]
+    fn should_parse_audit_sinks() {
+        let cli = Cli::try_parse_from([
+            "auth",
+            "--audit-sinks",
+            "file,grpc",
+            "--audit-path",
+            "audit.log",
+            "--audit-key",
+            "audit-key",
+            "--admin-token",
+            "0123456789abcdef",
+        ])
+        .unwrap();
+        let mut config = Config::default();
+
+        config.apply(&cli);
+
+        assert_eq!(
+            config.audit.sinks,
+            vec![AuditSinkKind::File, AuditSinkKind::Grpc]
+        );
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn should_require_admin_token_for_grpc_sink() {
+        let mut config = Config::default();
+        config.audit.sinks = vec![AuditSinkKind::Grpc];
+
+        assert!(config.validate().unwrap_err().contains("admin.token"));
+    }
+
+    #[test]
+    fn should_require_audit_path_for_file_sink() {
+        let mut config = Config::default();
+        config.audit.sinks = vec![AuditSinkKind::File];
+
+        assert!(config.validate().unwrap_err().contains("audit.path"));
+    }
+
+    #[test]
+    fn should_require_lockout_duration() {
+        let mut config = Config::default();
+        config.accounts.lockout_threshold = 5;
+        config.accounts.lockout_duration_secs = 0;
+
+        assert!(config
+            .validate()
+            .unwrap_err()
+            .contains("accounts.lockout_duration_secs"));
+    }
+
+    #[test]
+    fn should_require_audit_key_for_sinks() {
+        let mut config = Config::default();
+        config.audit.sinks = vec![AuditSinkKind::Stdout];
+
+        assert!(config.validate().unwrap_err().contains("audit.key"));
+    }
+
+    #[test]
+    fn should_parse_webhook_endpoints() {
+        let config = Config::from_toml(
+            r#"
+            [webhooks]
+            max_attempts = 3
+
+            [[webhooks.endpoints]]
+            url = "https://hooks.example/auth"
+            secret = "s3cret"
+            events = ["signed_in", "sign_in_failed"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.webhooks.max_attempts, 3);
+        assert_eq!(config.webhooks.endpoints[0].events.len(), 2);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn should_reject_invalid_webhook_endpoint() {
+        let mut config = Config::default();
+        config.webhooks.endpoints.push(WebhookEndpointConfig {
+            url: "not a url".to_string(),
+            secret: String::new(),
+            events: vec!["logged_in".to_string()],
+        });
+
+        let error = config.validate().unwrap_err();
+
+        assert!(error.contains("not a valid URL"));
+        assert!(error.contains("empty secret"));
+        assert!(error.contains("unknown event type: logged_in"));
+    }
+
+    #[test]
+    fn should_reject_short_admin_token() {
+        let mut config = Config::default();
+        config.admin.token = Some("admin".to_string());
+
+        assert!(config.validate().unwrap_err().contains("admin.token"));
+    }
+
+    #[test]
+    fn printed_config_should_round_trip() {
+        let mut config = Config::default();
+        config.tls.cert_path = Some("cert.pem".into());
+        config.tls.key_path = Some("key.pem".into());
+
+        let printed = config.to_toml().unwrap();
+
+        assert_eq!(Config::from_toml(&printed).unwrap(), config);
+    }
+
+    #[test]
+    fn printed_config_should_redact_secrets() {
+        let mut config = Config::default();
+        config.webhooks.endpoints = vec![WebhookEndpointConfig {
+            url: "https://example.com/hook".to_string(),
+            secret: "webhook-s3cret".to_string(),
+            events: Vec::new(),
+        }];
+        config.admin.token = Some("admin-token-s3cret".to_string());
+        config.email_verification.secret = Some("verification-s3cret".to_string());
+        config.audit.key = Some("audit-key-s3cret".to_string());
+
+        let printed = config.to_toml().unwrap();
+
+        assert!(!printed.contains("webhook-s3cret"), "{}", printed);
+        assert!(!printed.contains("admin-token-s3cret"), "{}", printed);
+        assert!(!printed.contains("verification-s3cret"), "{}", printed);
+        assert!(!printed.contains("audit-key-s3cret"), "{}", printed);
+        assert_eq!(
+            Config::from_toml(&printed).unwrap().webhooks.endpoints[0].secret,
+            REDACTED
+        );
+    }
+
+    #[test]
+    fn should_parse_profile_attributes() {
+        let config = Config::from_toml(
+            r#"
+            [[profiles.attributes]]
+            name = "age"
+            type = "integer"
+
+            [[profiles.attributes]]
+            name = "age"
+            type = "boolean"
+            required = true
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.profiles.attributes[0].attribute_type,
+            AttributeType::Integer
+        );
+        assert!(config.profiles.attributes[1].required);
+        assert!(config.validate().unwrap_err().contains("defined twice"));
+    }
+
+    #[test]
+    fn should_require_mailer_for_email_verification() {
+        let config = Config::from_toml(
+            r#"
+            [email_verification]
+            enabled = true
+            "#,
+        )
+        .unwrap();
+
+        assert!(config
+            .validate()
+            .unwrap_err()
+            .contains("email_verification.enabled requires mailer.transport"));
+    }
+}
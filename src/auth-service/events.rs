@@ -0,0 +1,543 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use tokio::sync::{broadcast, mpsc};
+use tonic::Status;
+
+use crate::service::authentication::{self, EventType, SubscribeEventsRequest};
+
+/// Events buffered for replay and for each subscriber before it falls behind.
+pub const DEFAULT_BUFFER_SIZE: usize = 1024;
+
+/// Things that happened in the `Authenticator`. Never carries passwords or
+/// session tokens.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    SignedUp {
+        user_id: String,
+        username: String,
+    },
+    SignUpFailed {
+        username: String,
+        reason: String,
+    },
+    SignedIn {
+        user_id: String,
+        username: String,
+    },
+    SignInFailed {
+        /// When the username is taken, so its owner hears of the attempts
+        #[serde(skip_serializing_if = "Option::is_none")]
+        user_id: Option<String>,
+        username: String,
+        reason: String,
+    },
+    SignedOut {
+        user_id: String,
+    },
+    /// About no user, the session being unknown, so only reaches the admin
+    /// token.
+    SignOutFailed {
+        reason: String,
+    },
+    UserDisabled {
+        user_id: String,
+        username: String,
+    },
+    UserEnabled {
+        user_id: String,
+        username: String,
+    },
+    UserDeleted {
+        user_id: String,
+        username: String,
+    },
+    AccountDeletionScheduled {
+        user_id: String,
+        username: String,
+    },
+    AccountDeletionCancelled {
+        user_id: String,
+        username: String,
+    },
+    AccountDeletionFailed {
+        reason: String,
+    },
+    ProfileUpdated {
+        user_id: String,
+        username: String,
+    },
+    EmailVerified {
+        user_id: String,
+        username: String,
+    },
+    ServiceAccountCreated {
+        user_id: String,
+        username: String,
+    },
+    ApiKeyCreated {
+        user_id: String,
+        username: String,
+        key_id: String,
+    },
+    ApiKeyRevoked {
+        user_id: String,
+        username: String,
+        key_id: String,
+    },
+    PasswordChanged {
+        user_id: String,
+        username: String,
+    },
+    PasswordChangeFailed {
+        reason: String,
+    },
+    AccountLocked {
+        user_id: String,
+        username: String,
+    },
+}
+
+impl Event {
+    pub fn event_type(&self) -> EventType {
+        match self {
+            Event::SignedUp { .. } => EventType::SignedUp,
+            Event::SignUpFailed { .. } => EventType::SignUpFailed,
+            Event::SignedIn { .. } => EventType::SignedIn,
+            Event::SignInFailed { .. } => EventType::SignInFailed,
+            Event::SignedOut { .. } => EventType::SignedOut,
+            Event::SignOutFailed { .. } => EventType::SignOutFailed,
+            Event::UserDisabled { .. } => EventType::UserDisabled,
+            Event::UserEnabled { .. } => EventType::UserEnabled,
+            Event::UserDeleted { .. } => EventType::UserDeleted,
+            Event::AccountDeletionScheduled { .. } => EventType::AccountDeletionScheduled,
+            Event::AccountDeletionCancelled { .. } => EventType::AccountDeletionCancelled,
+            Event::AccountDeletionFailed { .. } => EventType::AccountDeletionFailed,
+            Event::ProfileUpdated { .. } => EventType::ProfileUpdated,
+            Event::EmailVerified { .. } => EventType::EmailVerified,
+            Event::ServiceAccountCreated { .. } => EventType::ServiceAccountCreated,
+            Event::ApiKeyCreated { .. } => EventType::ApiKeyCreated,
+            Event::ApiKeyRevoked { .. } => EventType::ApiKeyRevoked,
+            Event::PasswordChanged { .. } => EventType::PasswordChanged,
+            Event::PasswordChangeFailed { .. } => EventType::PasswordChangeFailed,
+            Event::AccountLocked { .. } => EventType::AccountLocked,
+        }
+    }
+
+    pub fn user_id(&self) -> Option<&str> {
+        match self {
+            Event::SignedUp { user_id, .. }
+            | Event::SignedIn { user_id, .. }
+            | Event::SignedOut { user_id }
+            | Event::UserDisabled { user_id, .. }
+            | Event::UserEnabled { user_id, .. }
+            | Event::UserDeleted { user_id, .. }
+            | Event::AccountDeletionScheduled { user_id, .. }
+            | Event::AccountDeletionCancelled { user_id, .. }
+            | Event::ProfileUpdated { user_id, .. }
+            | Event::EmailVerified { user_id, .. }
+            | Event::ServiceAccountCreated { user_id, .. }
+            | Event::ApiKeyCreated { user_id, .. }
+            | Event::ApiKeyRevoked { user_id, .. }
+            | Event::PasswordChanged { user_id, .. }
+            | Event::AccountLocked { user_id, .. }
+            | Event::SignInFailed {
+                user_id: Some(user_id),
+                ..
+            } => Some(user_id),
+            _ => None,
+        }
+    }
+
+    pub fn username(&self) -> Option<&str> {
+        match self {
+            Event::SignedUp { username, .. }
+            | Event::SignUpFailed { username, .. }
+            | Event::SignedIn { username, .. }
+            | Event::SignInFailed { username, .. }
+            | Event::UserDisabled { username, .. }
+            | Event::UserEnabled { username, .. }
+            | Event::UserDeleted { username, .. }
+            | Event::AccountDeletionScheduled { username, .. }
+            | Event::AccountDeletionCancelled { username, .. }
+            | Event::ProfileUpdated { username, .. }
+            | Event::EmailVerified { username, .. }
+            | Event::ServiceAccountCreated { username, .. }
+            | Event::ApiKeyCreated { username, .. }
+            | Event::ApiKeyRevoked { username, .. }
+            | Event::PasswordChanged { username, .. }
+            | Event::AccountLocked { username, .. } => Some(username),
+            _ => None,
+        }
+    }
+
+    pub fn reason(&self) -> Option<&str> {
+        match self {
+            Event::SignUpFailed { reason, .. }
+            | Event::SignInFailed { reason, .. }
+            | Event::SignOutFailed { reason }
+            | Event::AccountDeletionFailed { reason }
+            | Event::PasswordChangeFailed { reason } => Some(reason),
+            _ => None,
+        }
+    }
+}
+
+/// Parses the name of an event type as in the event payloads, e.g.
+/// `signed_in`, also accepting `signed-in`.
+pub fn parse_event_type(name: &str) -> Option<EventType> {
+    common::events::event_type_str_name(name)
+        .and_then(|str_name| EventType::from_str_name(&str_name))
+}
+
+/// Milliseconds since the Unix epoch.
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// An event as published on the bus.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Published {
+    pub sequence: u64,
+    pub timestamp_ms: u64,
+    pub event: Event,
+}
+
+impl From<&Published> for authentication::Event {
+    fn from(published: &Published) -> Self {
+        let event = &published.event;
+        Self {
+            sequence: published.sequence,
+            timestamp_ms: published.timestamp_ms,
+            r#type: event.event_type().into(),
+            user_id: event.user_id().unwrap_or_default().to_string(),
+            username: event.username().unwrap_or_default().to_string(),
+            reason: event.reason().unwrap_or_default().to_string(),
+        }
+    }
+}
+
+/// Which events a subscriber wants.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EventFilter {
+    pub types: Vec<EventType>,
+    pub user_id: Option<String>,
+    pub username: Option<String>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &Event) -> bool {
+        (self.types.is_empty() || self.types.contains(&event.event_type()))
+            && self
+                .user_id
+                .as_deref()
+                .is_none_or(|user_id| event.user_id() == Some(user_id))
+            && self
+                .username
+                .as_deref()
+                .is_none_or(|username| event.username() == Some(username))
+    }
+}
+
+impl From<&SubscribeEventsRequest> for EventFilter {
+    fn from(request: &SubscribeEventsRequest) -> Self {
+        let non_empty = |value: &str| (!value.is_empty()).then(|| value.to_string());
+        Self {
+            types: request.types().collect(),
+            user_id: non_empty(&request.user_id),
+            username: non_empty(&request.username),
+        }
+    }
+}
+
+/// What a subscriber receives: the replayed events, then the live ones,
+/// starting at `next_sequence`.
+pub struct Subscription {
+    pub replay: Vec<Published>,
+    pub receiver: broadcast::Receiver<Published>,
+    pub next_sequence: u64,
+}
+
+/// Called with every event as it is published.
+type Listener = Box<dyn Fn(&Published) + Send>;
+
+struct History {
+    next_sequence: u64,
+    events: VecDeque<Published>,
+    listeners: Vec<Listener>,
+}
+
+/// In-process publish/subscribe of the [`Event`]s. The most recent events are
+/// kept so subscribers can resume from a sequence number; subscribers that
+/// fall further behind than the buffer are disconnected.
+#[derive(Clone)]
+pub struct EventBus {
+    history: Arc<Mutex<History>>,
+    sender: broadcast::Sender<Published>,
+    buffer_size: usize,
+}
+
+impl EventBus {
+    pub fn new(buffer_size: usize) -> Self {
+        let (sender, _) = broadcast::channel(buffer_size);
+        Self {
+            history: Arc::new(Mutex::new(History {
+                next_sequence: 1,
+                events: VecDeque::with_capacity(buffer_size),
+                listeners: Vec::new(),
+            })),
+            sender,
+            buffer_size,
+        }
+    }
+
+    pub fn publish(&self, event: Event) {
+        let mut history = self.history.lock().unwrap();
+        let published = Published {
+            sequence: history.next_sequence,
+            timestamp_ms: now_ms(),
+            event,
+        };
+        history.next_sequence += 1;
+        if history.events.len() == self.buffer_size {
+            history.events.pop_front();
+        }
+        history.events.push_back(published.clone());
+        for listener in &history.listeners {
+            listener(&published);
+        }
+        // Sent under the lock so that subscribing never misses or repeats one
+        let _ = self.sender.send(published);
+    }
+
+    /// Calls `listener` with every event published from now on, before
+    /// `publish` returns, so unlike subscribers it never misses one. It must
+    /// be quick and must not publish.
+    pub fn listen(&self, listener: impl Fn(&Published) + Send + 'static) {
+        self.history
+            .lock()
+            .unwrap()
+            .listeners
+            .push(Box::new(listener));
+    }
+
+    /// The buffered events from `from_sequence` on, if any, followed by the
+    /// live ones. Fails when some of them are no longer buffered.
+    pub fn subscribe(&self, from_sequence: Option<u64>) -> Result<Subscription, String> {
+        let history = self.history.lock().unwrap();
+        let receiver = self.sender.subscribe();
+
+        let replay: Vec<Published> = match from_sequence {
+            None => Vec::new(),
+            Some(from) => {
+                let oldest = history
+                    .events
+                    .front()
+                    .map_or(history.next_sequence, |published| published.sequence);
+                // Sequences start at 1, nothing is missing until one is dropped
+                if from < oldest && oldest > 1 {
+                    return Err(format!(
+                        "Events before sequence {} are no longer buffered",
+                        oldest
+                    ));
+                }
+                history
+                    .events
+                    .iter()
+                    .filter(|published| published.sequence >= from)
+                    .cloned()
+                    .collect()
+            }
+        };
+
+        let next_sequence = replay
+            .first()
+            .map_or(history.next_sequence, |published| published.sequence);
+        Ok(Subscription {
+            replay,
+            receiver,
+            next_sequence,
+        })
+    }
+
+    /// Streams the events matching `filter` into a channel until the
+    /// subscriber goes away or falls behind.
+    pub fn stream(
+        &self,
+        from_sequence: Option<u64>,
+        filter: EventFilter,
+    ) -> Result<mpsc::Receiver<Result<authentication::Event, Status>>, String> {
+        let Subscription {
+            replay,
+            mut receiver,
+            mut next_sequence,
+        } = self.subscribe(from_sequence)?;
+        let (tx, rx) = mpsc::channel(self.buffer_size.min(128));
+
+        tokio::spawn(async move {
+            for published in replay {
+                next_sequence = published.sequence + 1;
+                if filter.matches(&published.event)
+                    && tx.send(Ok((&published).into())).await.is_err()
+                {
+                    return;
+                }
+            }
+
+            let from_sequence = from_sequence.unwrap_or_default();
+            loop {
+                let published = tokio::select! {
+                    published = receiver.recv() => published,
+                    _ = tx.closed() => return,
+                };
+                match published {
+                    Ok(published) if published.sequence < from_sequence => {}
+                    Ok(published) => {
+                        next_sequence = published.sequence + 1;
+                        if filter.matches(&published.event)
+                            && tx.send(Ok((&published).into())).await.is_err()
+                        {
+                            return;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        let _ = tx
+                            .send(Err(Status::resource_exhausted(format!(
+                                "Subscriber fell behind, resume from sequence {}",
+                                next_sequence
+                            ))))
+                            .await;
+                        return;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        });
+
+        Ok(rx)
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new(DEFAULT_BUFFER_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signed_in(user_id: &str) -> Event {
+        Event::SignedIn {
+            user_id: user_id.to_string(),
+            username: format!("{}-name", user_id),
+        }
+    }
+
+    #[test]
+    fn should_number_events() {
+        let bus = EventBus::default();
+        let mut subscription = bus.subscribe(None).unwrap();
+
+        bus.publish(signed_in("a"));
+        bus.publish(signed_in("b"));
+
+        assert_eq!(subscription.next_sequence, 1);
+        assert_eq!(subscription.receiver.try_recv().unwrap().sequence, 1);
+        assert_eq!(subscription.receiver.try_recv().unwrap().sequence, 2);
+    }
+
+    #[test]
+    fn should_replay_from_sequence() {
+        let bus = EventBus::default();
+        for user_id in ["a", "b", "c"] {
+            bus.publish(signed_in(user_id));
+        }
+
+        let subscription = bus.subscribe(Some(2)).unwrap();
+
+        let sequences: Vec<_> = subscription
+            .replay
+            .iter()
+            .map(|published| published.sequence)
+            .collect();
+        assert_eq!(sequences, [2, 3]);
+    }
+
+    #[test]
+    fn should_resume_from_start_before_any_event() {
+        let bus = EventBus::default();
+
+        assert!(bus.subscribe(Some(0)).is_ok());
+        assert!(bus.subscribe(Some(1)).is_ok());
+    }
+
+    #[test]
+    fn should_refuse_to_resume_from_dropped_events() {
+        let bus = EventBus::new(2);
+        for user_id in ["a", "b", "c"] {
+            bus.publish(signed_in(user_id));
+        }
+
+        let error = bus.subscribe(Some(1)).err().unwrap();
+
+        assert!(error.contains("before sequence 2"));
+    }
+
+    #[test]
+    fn should_parse_event_type_names() {
+        assert_eq!(parse_event_type("signed_in"), Some(EventType::SignedIn));
+        assert_eq!(
+            parse_event_type("sign-out-failed"),
+            Some(EventType::SignOutFailed)
+        );
+        assert_eq!(parse_event_type("unspecified"), None);
+        assert_eq!(parse_event_type("logged_in"), None);
+    }
+
+    #[test]
+    fn should_filter_by_type_and_user() {
+        let filter = EventFilter {
+            types: vec![EventType::SignedIn],
+            user_id: Some("a".to_string()),
+            username: None,
+        };
+
+        assert!(filter.matches(&signed_in("a")));
+        assert!(!filter.matches(&signed_in("b")));
+        assert!(!filter.matches(&Event::SignedOut {
+            user_id: "a".to_string()
+        }));
+        assert!(EventFilter::default().matches(&Event::SignOutFailed {
+            reason: "Session not found".to_string()
+        }));
+    }
+
+    #[tokio::test]
+    async fn should_disconnect_lagging_subscriber() {
+        let bus = EventBus::new(2);
+        let mut events = bus.stream(None, EventFilter::default()).unwrap();
+
+        // Published faster than the stream task, which has not run yet
+        for user_id in ["a", "b", "c", "d"] {
+            bus.publish(signed_in(user_id));
+        }
+
+        let mut last = None;
+        while let Some(event) = events.recv().await {
+            last = Some(event);
+        }
+        let error = last.unwrap().unwrap_err();
+        assert_eq!(error.code(), tonic::Code::ResourceExhausted);
+        assert!(error.message().contains("resume from sequence 1"));
+    }
+}
@@ -0,0 +1,380 @@
+use std::{fs, future::Future, path::PathBuf, time::Duration};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::mpsc,
+};
+
+use crate::config::MailerConfig;
+
+/// How emails leave the service.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MailTransport {
+    /// Written to `mailer.outbox_dir`, one `.eml` file per email
+    File,
+    /// Relayed through the server at `mailer.smtp_addr`
+    Smtp,
+}
+
+/// A plain text email to a single recipient.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl Email {
+    /// The RFC 5322 message, with CRLF line endings.
+    pub fn to_message(&self, from: &str) -> Result<String, String> {
+        for header in [from, &self.to, &self.subject] {
+            if header.contains(['\r', '\n']) {
+                return Err("Email headers cannot contain line breaks".to_string());
+            }
+        }
+        Ok(format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nMessage-ID: <{}@auth>\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+            from,
+            self.to,
+            self.subject,
+            uuid::Uuid::new_v4(),
+            self.body.replace("\r\n", "\n").replace('\n', "\r\n")
+        ))
+    }
+}
+
+pub trait Mailer: Send + Sync {
+    /// Hands the email over for delivery, which may happen later.
+    fn send(&self, email: Email) -> Result<(), String>;
+}
+
+/// Builds the mailer of the configured transport, none when unset.
+pub fn from_config(config: &MailerConfig) -> Result<Option<Box<dyn Mailer>>, String> {
+    let mailer: Box<dyn Mailer> = match config.transport {
+        None => return Ok(None),
+        Some(MailTransport::File) => Box::new(FileMailer::start(
+            config
+                .outbox_dir
+                .clone()
+                .ok_or("The file mail transport requires mailer.outbox_dir")?,
+            &config.from,
+        )?),
+        Some(MailTransport::Smtp) => Box::new(SmtpMailer::start(
+            config
+                .smtp_addr
+                .clone()
+                .ok_or("The smtp mail transport requires mailer.smtp_addr")?,
+            &config.from,
+            Duration::from_secs(config.smtp_timeout_secs),
+        )),
+    };
+    Ok(Some(mailer))
+}
+
+/// Delivers emails one after the other in the background, so that callers,
+/// which hold the `Authenticator` lock, never wait for the disk or network.
+struct Outbox {
+    from: String,
+    sender: mpsc::UnboundedSender<(String, String)>,
+}
+
+impl Outbox {
+    /// Must be called within a Tokio runtime.
+    fn start<F, Fut>(from: &str, transport: &'static str, deliver: F) -> Self
+    where
+        F: Fn(String, String) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), String>> + Send,
+    {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<(String, String)>();
+        tokio::spawn(async move {
+            while let Some((to, message)) = receiver.recv().await {
+                if let Err(e) = deliver(to, message).await {
+                    tracing::error!(error = %e, transport, "failed to send email");
+                }
+            }
+        });
+        Self {
+            from: from.to_string(),
+            sender,
+        }
+    }
+
+    fn send(&self, email: Email) -> Result<(), String> {
+        let message = email.to_message(&self.from)?;
+        self.sender
+            .send((email.to, message))
+            .map_err(|_| "The mailer stopped".to_string())
+    }
+}
+
+/// Writes emails to a directory instead of sending them, for development and
+/// for deployments relaying the outbox themselves.
+pub struct FileMailer {
+    outbox: Outbox,
+}
+
+impl FileMailer {
+    /// Must be called within a Tokio runtime.
+    pub fn start(dir: PathBuf, from: &str) -> Result<Self, String> {
+        fs::create_dir_all(&dir)
+            .map_err(|e| format!("Failed to create outbox {}: {}", dir.display(), e))?;
+        let outbox = Outbox::start(from, "file", move |_, message| {
+            let path = dir.join(format!("{}.eml", uuid::Uuid::new_v4()));
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    fs::write(&path, message)
+                        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+                })
+                .await
+                .map_err(|e| format!("Failed to write an email: {}", e))?
+            }
+        });
+        Ok(Self { outbox })
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, email: Email) -> Result<(), String> {
+        self.outbox.send(email)
+    }
+}
+
+/// Relays emails through an SMTP server.
+pub struct SmtpMailer {
+    outbox: Outbox,
+}
+
+impl SmtpMailer {
+    /// Must be called within a Tokio runtime.
+    pub fn start(addr: String, from: &str, timeout: Duration) -> Self {
+        let envelope_from = from.to_string();
+        let outbox = Outbox::start(from, "smtp", move |to, message| {
+            let (addr, envelope_from) = (addr.clone(), envelope_from.clone());
+            async move { deliver(&addr, &envelope_from, &to, &message, timeout).await }
+        });
+        Self { outbox }
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, email: Email) -> Result<(), String> {
+        self.outbox.send(email)
+    }
+}
+
+/// Sends `message` to `to` with a minimal SMTP exchange: no TLS and no
+/// authentication, as offered by local relays. Gives up after `timeout`, so a
+/// stalled server does not hold up the emails after it.
+pub async fn deliver(
+    addr: &str,
+    from: &str,
+    to: &str,
+    message: &str,
+    timeout: Duration,
+) -> Result<(), String> {
+    tokio::time::timeout(timeout, exchange(addr, from, to, message))
+        .await
+        .map_err(|_| format!("The SMTP exchange with {} timed out", addr))?
+}
+
+async fn exchange(addr: &str, from: &str, to: &str, message: &str) -> Result<(), String> {
+    let stream = TcpStream::connect(addr)
+        .await
+        .map_err(|e| format!("Failed to connect to {}: {}", addr, e))?;
+    let mut smtp = Smtp {
+        stream: BufReader::new(stream),
+    };
+
+    smtp.expect(220).await?;
+    smtp.command("EHLO localhost", 250).await?;
+    smtp.command(&format!("MAIL FROM:<{}>", from), 250).await?;
+    smtp.command(&format!("RCPT TO:<{}>", to), 250).await?;
+    smtp.command("DATA", 354).await?;
+    // Lines starting with a dot are escaped by doubling it
+    let data = message
+        .split("\r\n")
+        .map(|line| match line.starts_with('.') {
+            true => format!(".{}", line),
+            false => line.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\r\n");
+    smtp.write(&data).await?;
+    smtp.command(".", 250).await?;
+    smtp.command("QUIT", 221).await
+}
+
+struct Smtp {
+    stream: BufReader<TcpStream>,
+}
+
+impl Smtp {
+    async fn write(&mut self, data: &str) -> Result<(), String> {
+        self.stream
+            .get_mut()
+            .write_all(data.as_bytes())
+            .await
+            .map_err(|e| format!("Failed to write to the SMTP server: {}", e))
+    }
+
+    async fn command(&mut self, command: &str, code: u16) -> Result<(), String> {
+        self.write(&format!("{}\r\n", command)).await?;
+        self.expect(code).await
+    }
+
+    /// Reads a reply, all of its lines for a multiline one.
+    async fn expect(&mut self, code: u16) -> Result<(), String> {
+        loop {
+            let mut line = String::new();
+            self.stream
+                .read_line(&mut line)
+                .await
+                .map_err(|e| format!("Failed to read from the SMTP server: {}", e))?;
+            let reply_code = line.get(..3).and_then(|code| code.parse::<u16>().ok());
+            if reply_code != Some(code) {
+                return Err(format!("Unexpected SMTP reply: {}", line.trim_end()));
+            }
+            // `250-` continues the reply, `250 ` ends it
+            if line.as_bytes().get(3) != Some(&b'-') {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+
+    /// Accepts every email, keeping the envelope and data of each.
+    async fn smtp_stand_in() -> (String, Arc<Mutex<Vec<(String, String)>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let inbox = received.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let (reader, mut writer) = stream.into_split();
+                let mut lines = BufReader::new(reader).lines();
+                writer.write_all(b"220 stand-in\r\n").await.unwrap();
+                let (mut recipient, mut data, mut in_data) = (String::new(), Vec::new(), false);
+                while let Some(line) = lines.next_line().await.unwrap() {
+                    let reply: &[u8] = if in_data {
+                        if line == "." {
+                            in_data = false;
+                            inbox
+                                .lock()
+                                .unwrap()
+                                .push((recipient.clone(), data.join("\n")));
+                            b"250 queued\r\n"
+                        } else {
+                            data.push(line);
+                            continue;
+                        }
+                    } else if line.starts_with("EHLO") {
+                        b"250-stand-in\r\n250 8BITMIME\r\n"
+                    } else if let Some(to) = line.strip_prefix("RCPT TO:") {
+                        recipient = to.trim_matches(['<', '>']).to_string();
+                        b"250 ok\r\n"
+                    } else if line == "DATA" {
+                        in_data = true;
+                        b"354 go ahead\r\n"
+                    } else if line == "QUIT" {
+                        writer.write_all(b"221 bye\r\n").await.unwrap();
+                        break;
+                    } else {
+                        b"250 ok\r\n"
+                    };
+                    writer.write_all(reply).await.unwrap();
+                }
+            }
+        });
+        (addr, received)
+    }
+
+    fn email() -> Email {
+        Email {
+            to: "jane@example.com".to_string(),
+            subject: "Hello".to_string(),
+            body: "first line\n.second line".to_string(),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn should_send_through_smtp() {
+        let (addr, received) = smtp_stand_in().await;
+        let mailer = SmtpMailer::start(addr, "auth@example.com", Duration::from_secs(10));
+
+        mailer.send(email()).unwrap();
+
+        for _ in 0..500 {
+            if !received.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let received = received.lock().unwrap();
+        let (recipient, data) = &received[0];
+        assert_eq!(recipient, "jane@example.com");
+        assert!(data.contains("Subject: Hello"));
+        assert!(data.ends_with("first line\n..second line"));
+    }
+
+    #[tokio::test]
+    async fn should_give_up_on_stalled_smtp_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let _never_greeted = listener.accept().await.unwrap();
+            std::future::pending::<()>().await
+        });
+
+        let result = deliver(
+            &addr,
+            "auth@example.com",
+            "jane@example.com",
+            "Hello",
+            Duration::from_millis(100),
+        )
+        .await;
+
+        assert!(result.unwrap_err().contains("timed out"));
+    }
+
+    #[tokio::test]
+    async fn should_write_to_outbox() {
+        let dir = std::env::temp_dir().join(format!("outbox-{}", uuid::Uuid::new_v4()));
+        let mailer = FileMailer::start(dir.clone(), "auth@example.com").unwrap();
+
+        mailer.send(email()).unwrap();
+
+        for _ in 0..500 {
+            if fs::read_dir(&dir).unwrap().next().is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let files: Vec<_> = fs::read_dir(&dir).unwrap().collect();
+        let contents = fs::read_to_string(files[0].as_ref().unwrap().path()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(files.len(), 1);
+        assert!(contents.starts_with("From: auth@example.com\r\nTo: jane@example.com\r\n"));
+    }
+
+    #[test]
+    fn should_refuse_header_injection() {
+        let email = Email {
+            subject: "Hello\r\nBcc: someone@example.com".to_string(),
+            ..email()
+        };
+
+        assert!(email.to_message("auth@example.com").is_err());
+    }
+}
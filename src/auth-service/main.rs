@@ -1,33 +1,174 @@
+mod admin;
+mod api_keys;
+mod audit;
 mod auth;
+mod capture;
+mod config;
+mod events;
+mod mailer;
+mod metrics;
+mod profiles;
+mod request_id;
 mod service;
 mod sessions;
+mod shutdown;
+mod tls;
 mod users;
+mod verification;
+mod web;
+mod webhooks;
 
-use std::env;
+use std::{future::Future, pin::Pin, sync::Arc};
 
-use service::{AuthenticationServer, AuthenticationService, AuthenticationServiceConfig, Server};
+use clap::Parser;
+use tower_layer::Layer;
 
-const AUTH_SERVICE_PERSISTENCE_TYPE: &str = "AUTH_SERVICE_PERSISTENCE_TYPE";
+use audit::AuditLog;
+use capture::{Capture, CaptureLayer};
+use config::{Cli, Config};
+use service::{AuthenticationServer, AuthenticationService, Server};
+use shutdown::Shutdown;
+use tls::ReloadingTlsAcceptor;
+use verification::EmailVerification;
+use web::GrpcWebLayer;
+use webhooks::Webhooks;
+
+type ServerFuture = Pin<Box<dyn Future<Output = Result<(), tonic::transport::Error>> + Send>>;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let addr = "[::]:50051".parse()?;
+    let cli = Cli::parse();
+    let config = Config::load(&cli)?;
 
-    let service = build_auth_service();
+    if cli.print_config {
+        print!("{}", config.to_toml()?);
+        return Ok(());
+    }
 
-    Server::builder()
-        .add_service(AuthenticationServer::new(service))
-        .serve(addr)
-        .await?;
+    let telemetry = common::logging::init(
+        &config.logging.level,
+        config.logging.format,
+        config.telemetry.otlp_endpoint.as_deref(),
+        &config.telemetry.service_name,
+    )?;
 
-    Ok(())
-}
+    let (audit, audit_stream) = AuditLog::from_config(&config.audit)?;
+    let email_verification =
+        EmailVerification::from_config(&config.email_verification, &config.mailer)?;
+    let service = Arc::new(AuthenticationService::from_config(
+        &config,
+        audit,
+        email_verification,
+    ));
+    let capture = match &config.capture.path {
+        Some(path) => {
+            tracing::warn!(path = %path.display(), "capturing calls");
+            Some(Capture::open(path)?)
+        }
+        None => None,
+    };
+    if let Some(webhooks) = Webhooks::from_config(&config.webhooks)? {
+        webhooks.start(&service.events())?;
+    }
+
+    if !config.accounts.deletion_grace_period().is_zero() {
+        let service = service.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(auth::DUE_DELETIONS_INTERVAL);
+            loop {
+                interval.tick().await;
+                service.authenticator().delete_due_accounts();
+            }
+        });
+    }
+
+    let (mut health_reporter, health_service) = tonic_health::server::health_reporter();
+    health_reporter
+        .set_serving::<AuthenticationServer<AuthenticationService>>()
+        .await;
+
+    // gRPC-Web clients talk HTTP/1.1, so it has to be accepted alongside HTTP/2
+    let router = Server::builder()
+        .accept_http1(true)
+        .layer(request_id::set_request_id_layer())
+        .layer(request_id::trace_layer())
+        .layer(request_id::propagate_request_id_layer())
+        .layer(web::cors_layer(&config.cors.allowed_origins()))
+        .layer(GrpcWebLayer::new())
+        .add_service(health_service)
+        .add_service(
+            CaptureLayer::new(capture).layer(AuthenticationServer::from_arc(service.clone())),
+        )
+        // Only with the grpc audit sink, which requires the admin token
+        .add_optional_service(
+            audit_stream
+                .zip(config.admin.token.as_deref())
+                .map(|(stream, token)| audit::service(stream, token)),
+        )
+        .add_optional_service(
+            config
+                .admin
+                .token
+                .as_deref()
+                .map(|token| admin::service(service.clone(), token)),
+        );
 
-fn build_auth_service() -> AuthenticationService {
-    if let Ok(string_config) = env::var(AUTH_SERVICE_PERSISTENCE_TYPE) {
-        if let Ok(config) = string_config.parse::<AuthenticationServiceConfig>() {
-            return AuthenticationService::new_with_config(config);
+    let shutdown = Shutdown::new();
+
+    if config.metrics.enabled {
+        let metrics = metrics::serve(
+            config.metrics.listen_addr,
+            service.clone(),
+            shutdown.triggered(),
+        );
+        tracing::info!(addr = %config.metrics.listen_addr, "serving metrics");
+        tokio::spawn(async move {
+            if let Err(e) = metrics.await {
+                tracing::error!(error = %e, "metrics endpoint failed");
+            }
+        });
+    }
+
+    tracing::info!(
+        addr = %config.listen_addr,
+        tls = config.tls.server_config().is_some(),
+        backend = ?config.storage.backend,
+        "serving authentication service"
+    );
+
+    let mut server: ServerFuture = match config.tls.server_config() {
+        Some(tls_config) => {
+            let acceptor = ReloadingTlsAcceptor::new(tls_config)?;
+            acceptor.watch(config.tls.reload_interval());
+            let listener = tokio::net::TcpListener::bind(config.listen_addr).await?;
+            Box::pin(router.serve_with_incoming_shutdown(
+                tls::incoming(listener, acceptor),
+                shutdown.triggered(),
+            ))
+        }
+        None => Box::pin(router.serve_with_shutdown(config.listen_addr, shutdown.triggered())),
+    };
+
+    tokio::select! {
+        result = &mut server => result?,
+        _ = shutdown::signal() => {
+            tracing::info!("shutdown requested, draining in-flight requests");
+            // Load balancers polling the health service stop routing to us
+            // during the pre-drain delay, while requests are still served
+            shutdown::stop_serving(&mut health_reporter).await;
+            let stopped =
+                shutdown::serve_for(&mut server, config.shutdown.pre_drain_delay()).await?;
+            shutdown.trigger();
+
+            if !stopped && !shutdown::serve_for(&mut server, config.shutdown.drain_timeout()).await? {
+                tracing::warn!("shutdown deadline exceeded, abandoning in-flight requests");
+            }
         }
     }
-    AuthenticationService::default()
+
+    service.flush()?;
+    tracing::info!("stopped");
+    telemetry.shutdown().await;
+
+    Ok(())
 }
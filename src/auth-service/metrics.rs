@@ -0,0 +1,204 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, LazyLock},
+    time::{Duration, Instant},
+};
+
+use axum::{extract::State, http::header, routing::get, Router};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+
+use crate::service::AuthenticationService;
+
+const NAMESPACE: &str = "auth";
+
+static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::new);
+
+static REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("requests_total", "Requests handled, by RPC and outcome").namespace(NAMESPACE),
+        &["rpc", "outcome"],
+    ))
+});
+
+static REQUEST_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new(
+            "request_duration_seconds",
+            "Time spent handling requests, by RPC and outcome",
+        )
+        .namespace(NAMESPACE),
+        &["rpc", "outcome"],
+    ))
+});
+
+static SIGN_INS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("sign_ins_total", "Sign in attempts, by result").namespace(NAMESPACE),
+        &["result"],
+    ))
+});
+
+static ACTIVE_SESSIONS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register(IntGauge::with_opts(
+        Opts::new("active_sessions", "Sessions currently open").namespace(NAMESPACE),
+    ))
+});
+
+static USERS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register(IntGauge::with_opts(
+        Opts::new("users", "Registered users").namespace(NAMESPACE),
+    ))
+});
+
+static PASSWORD_HASH_DURATION: LazyLock<Histogram> = LazyLock::new(|| {
+    register(Histogram::with_opts(
+        HistogramOpts::new(
+            "password_hash_duration_seconds",
+            "Time spent hashing or verifying passwords",
+        )
+        .namespace(NAMESPACE)
+        .buckets(vec![0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0]),
+    ))
+});
+
+static LOCK_WAIT: LazyLock<Histogram> = LazyLock::new(|| {
+    register(Histogram::with_opts(
+        HistogramOpts::new(
+            "authenticator_lock_wait_seconds",
+            "Time spent waiting to acquire the authenticator lock",
+        )
+        .namespace(NAMESPACE)
+        .buckets(vec![
+            0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0,
+        ]),
+    ))
+});
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(
+    collector: Result<T, prometheus::Error>,
+) -> T {
+    let collector = collector.expect("Invalid metric definition");
+    REGISTRY
+        .register(Box::new(collector.clone()))
+        .expect("Metric registered twice");
+    collector
+}
+
+pub fn observe_request(rpc: &str, outcome: &str, duration: Duration) {
+    REQUESTS.with_label_values(&[rpc, outcome]).inc();
+    REQUEST_DURATION
+        .with_label_values(&[rpc, outcome])
+        .observe(duration.as_secs_f64());
+}
+
+pub fn observe_sign_in(success: bool) {
+    let result = if success { "success" } else { "failure" };
+    SIGN_INS.with_label_values(&[result]).inc();
+}
+
+pub fn observe_password_hash(duration: Duration) {
+    PASSWORD_HASH_DURATION.observe(duration.as_secs_f64());
+}
+
+pub fn observe_lock_wait(duration: Duration) {
+    LOCK_WAIT.observe(duration.as_secs_f64());
+}
+
+/// Times `f`, reporting the elapsed time as password hashing duration.
+pub fn time_password_hash<T>(f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    observe_password_hash(start.elapsed());
+    result
+}
+
+/// Renders every metric in the Prometheus text format, refreshing the gauges
+/// that are read from the stores rather than tracked incrementally.
+pub fn render(service: &AuthenticationService) -> String {
+    let (users, sessions) = service.counts();
+    USERS.set(users as i64);
+    ACTIVE_SESSIONS.set(sessions as i64);
+
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut buffer)
+        .expect("Failed to encode metrics");
+    String::from_utf8(buffer).expect("Metrics are not valid UTF-8")
+}
+
+async fn metrics(
+    State(service): State<Arc<AuthenticationService>>,
+) -> impl axum::response::IntoResponse {
+    (
+        [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+        render(&service),
+    )
+}
+
+pub fn router(service: Arc<AuthenticationService>) -> Router {
+    Router::new()
+        .route("/metrics", get(metrics))
+        .with_state(service)
+}
+
+/// Serves `/metrics` on `addr` until `shutdown` resolves.
+pub async fn serve(
+    addr: SocketAddr,
+    service: Arc<AuthenticationService>,
+    shutdown: impl std::future::Future<Output = ()> + Send + 'static,
+) -> std::io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    axum::serve(listener, router(service))
+        .with_graceful_shutdown(shutdown)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn should_render_request_metrics() {
+        observe_request("SignUp", "success", Duration::from_millis(5));
+
+        let rendered = render(&AuthenticationService::fast());
+
+        assert!(rendered.contains("auth_requests_total{outcome=\"success\",rpc=\"SignUp\"}"));
+        assert!(rendered.contains("auth_request_duration_seconds_bucket"));
+        assert!(rendered.contains("auth_users 0"));
+        assert!(rendered.contains("auth_active_sessions 0"));
+    }
+
+    #[test]
+    fn should_time_password_hashing() {
+        let before = PASSWORD_HASH_DURATION.get_sample_count();
+
+        time_password_hash(|| ());
+
+        assert!(PASSWORD_HASH_DURATION.get_sample_count() > before);
+    }
+
+    #[tokio::test]
+    async fn should_serve_metrics_over_http() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let service = Arc::new(AuthenticationService::fast());
+        tokio::spawn(async move { axum::serve(listener, router(service)).await });
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.0\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.0 200"));
+        assert!(response.contains("text/plain; version=0.0.4"));
+        assert!(response.contains("auth_users"));
+    }
+}
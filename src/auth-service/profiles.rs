@@ -0,0 +1,223 @@
+use std::collections::{BTreeMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+pub const MAX_DISPLAY_NAME_LENGTH: usize = 100;
+pub const MAX_ATTRIBUTE_LENGTH: usize = 1_000;
+
+/// What users say about themselves, next to their credentials.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    pub display_name: String,
+    pub email: String,
+    /// BCP 47 language tag, e.g. `en-GB`
+    pub locale: String,
+    /// Custom attributes, as defined by the [`ProfileSchema`]
+    pub attributes: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AttributeType {
+    String,
+    Integer,
+    Boolean,
+}
+
+/// A custom attribute profiles may carry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AttributeSchema {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub attribute_type: AttributeType,
+    /// Profiles cannot be updated without it
+    #[serde(default)]
+    pub required: bool,
+}
+
+impl AttributeSchema {
+    fn check(&self, value: &str) -> Result<(), String> {
+        let valid = match self.attribute_type {
+            AttributeType::String => value.len() <= MAX_ATTRIBUTE_LENGTH,
+            AttributeType::Integer => value.parse::<i64>().is_ok(),
+            AttributeType::Boolean => value == "true" || value == "false",
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(format!(
+                "Attribute {} is not a valid {:?}",
+                self.name, self.attribute_type
+            ))
+        }
+    }
+}
+
+/// The custom attributes profiles may carry, any other being rejected.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProfileSchema {
+    attributes: Vec<AttributeSchema>,
+}
+
+impl ProfileSchema {
+    pub fn new(attributes: Vec<AttributeSchema>) -> Result<Self, String> {
+        let mut names = HashSet::new();
+        for attribute in &attributes {
+            if attribute.name.is_empty() {
+                return Err("Profile attributes must have a name".to_string());
+            }
+            if !names.insert(attribute.name.as_str()) {
+                return Err(format!(
+                    "Profile attribute {} is defined twice",
+                    attribute.name
+                ));
+            }
+        }
+        Ok(Self { attributes })
+    }
+
+    pub fn validate(&self, profile: &Profile) -> Result<(), String> {
+        if profile.display_name.chars().count() > MAX_DISPLAY_NAME_LENGTH {
+            return Err(format!(
+                "Display name is longer than {} characters",
+                MAX_DISPLAY_NAME_LENGTH
+            ));
+        }
+        if !profile.email.is_empty() {
+            check_email(&profile.email)?;
+        }
+        if !profile.locale.is_empty() && !is_locale(&profile.locale) {
+            return Err("Locale is not a valid language tag".to_string());
+        }
+
+        for (name, value) in &profile.attributes {
+            self.attributes
+                .iter()
+                .find(|attribute| &attribute.name == name)
+                .ok_or_else(|| format!("Unknown attribute {}", name))?
+                .check(value)?;
+        }
+        if let Some(missing) = self.attributes.iter().find(|attribute| {
+            attribute.required && !profile.attributes.contains_key(&attribute.name)
+        }) {
+            return Err(format!("Attribute {} is required", missing.name));
+        }
+
+        Ok(())
+    }
+}
+
+pub fn check_email(email: &str) -> Result<(), String> {
+    if is_email(email) {
+        Ok(())
+    } else {
+        Err("Email is not valid".to_string())
+    }
+}
+
+/// Only catches the obvious mistakes, the address is proven by using it.
+fn is_email(email: &str) -> bool {
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !email.chars().any(char::is_whitespace)
+        }
+        None => false,
+    }
+}
+
+/// Language, then subtags of letters and digits, e.g. `en`, `pt-BR`.
+fn is_locale(locale: &str) -> bool {
+    let mut subtags = locale.split('-');
+    let language = subtags.next().unwrap_or_default();
+    (2..=3).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_alphabetic())
+        && subtags.all(|subtag| {
+            (1..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema() -> ProfileSchema {
+        ProfileSchema::new(vec![
+            AttributeSchema {
+                name: "age".to_string(),
+                attribute_type: AttributeType::Integer,
+                required: false,
+            },
+            AttributeSchema {
+                name: "newsletter".to_string(),
+                attribute_type: AttributeType::Boolean,
+                required: true,
+            },
+        ])
+        .unwrap()
+    }
+
+    fn profile(attributes: &[(&str, &str)]) -> Profile {
+        Profile {
+            display_name: "Jane".to_string(),
+            email: "jane@example.com".to_string(),
+            locale: "en-GB".to_string(),
+            attributes: attributes
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn should_accept_valid_profile() {
+        assert!(schema()
+            .validate(&profile(&[("age", "42"), ("newsletter", "true")]))
+            .is_ok());
+    }
+
+    #[test]
+    fn should_reject_invalid_attributes() {
+        let schema = schema();
+
+        assert!(schema
+            .validate(&profile(&[("age", "old"), ("newsletter", "true")]))
+            .is_err());
+        assert!(schema
+            .validate(&profile(&[("newsletter", "true"), ("unknown", "x")]))
+            .is_err());
+        assert_eq!(
+            schema.validate(&profile(&[("age", "42")])).unwrap_err(),
+            "Attribute newsletter is required"
+        );
+    }
+
+    #[test]
+    fn should_reject_invalid_fields() {
+        let schema = ProfileSchema::default();
+
+        let mut invalid_email = profile(&[]);
+        invalid_email.email = "jane.example.com".to_string();
+        let mut invalid_locale = profile(&[]);
+        invalid_locale.locale = "english".to_string();
+
+        assert!(schema.validate(&invalid_email).is_err());
+        assert!(schema.validate(&invalid_locale).is_err());
+        assert!(schema.validate(&Profile::default()).is_ok());
+    }
+
+    #[test]
+    fn should_reject_duplicate_attributes() {
+        let attribute = AttributeSchema {
+            name: "age".to_string(),
+            attribute_type: AttributeType::Integer,
+            required: false,
+        };
+
+        assert!(ProfileSchema::new(vec![attribute.clone(), attribute]).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use tonic::codegen::http::{HeaderName, Request};
+use tower_http::{
+    classify::{GrpcErrorsAsFailures, SharedClassifier},
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::{DefaultOnRequest, DefaultOnResponse, MakeSpan, TraceLayer},
+};
+use tracing::{field::Empty, Level, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Metadata key carrying the request id, set by callers or generated here.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Assigns a request id to requests arriving without one.
+pub fn set_request_id_layer() -> SetRequestIdLayer<MakeRequestUuid> {
+    SetRequestIdLayer::new(HeaderName::from_static(REQUEST_ID_HEADER), MakeRequestUuid)
+}
+
+/// Copies the request id onto the response metadata.
+pub fn propagate_request_id_layer() -> PropagateRequestIdLayer {
+    PropagateRequestIdLayer::new(HeaderName::from_static(REQUEST_ID_HEADER))
+}
+
+pub type RequestTraceLayer = TraceLayer<
+    SharedClassifier<GrpcErrorsAsFailures>,
+    RequestSpan,
+    DefaultOnRequest,
+    DefaultOnResponse,
+>;
+
+/// Opens a span per request carrying its id and RPC, so every event logged
+/// while handling it can be correlated.
+pub fn trace_layer() -> RequestTraceLayer {
+    TraceLayer::new_for_grpc()
+        .make_span_with(RequestSpan)
+        .on_request(DefaultOnRequest::new().level(Level::DEBUG))
+        .on_response(DefaultOnResponse::new().level(Level::INFO))
+}
+
+/// Builds the request span, continuing the caller's trace when the request
+/// carries a W3C `traceparent`. Only the path and the request id are
+/// recorded, never the metadata, which may carry credentials. The handler
+/// records the `outcome`.
+#[derive(Debug, Clone, Copy)]
+pub struct RequestSpan;
+
+impl<B> MakeSpan<B> for RequestSpan {
+    fn make_span(&mut self, request: &Request<B>) -> Span {
+        let request_id = request
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        let path = request.uri().path();
+        let (service, method) = path
+            .trim_start_matches('/')
+            .split_once('/')
+            .unwrap_or_default();
+
+        let span = tracing::info_span!(
+            "request",
+            otel.name = %path.trim_start_matches('/'),
+            otel.kind = "server",
+            request_id = %request_id,
+            rpc = %path,
+            rpc.system = "grpc",
+            rpc.service = %service,
+            rpc.method = %method,
+            outcome = Empty,
+        );
+        span.set_parent(common::telemetry::extract(request.headers()));
+        span
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::{
+        authentication::{authentication_client::AuthenticationClient, SignOutRequest},
+        AuthenticationServer, AuthenticationService, Server,
+    };
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+
+    async fn client() -> AuthenticationClient<tonic::transport::Channel> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(
+            Server::builder()
+                .layer(set_request_id_layer())
+                .layer(trace_layer())
+                .layer(propagate_request_id_layer())
+                .add_service(AuthenticationServer::new(AuthenticationService::fast()))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        AuthenticationClient::connect(format!("http://127.0.0.1:{}", port))
+            .await
+            .unwrap()
+    }
+
+    fn sign_out_request() -> tonic::Request<SignOutRequest> {
+        tonic::Request::new(SignOutRequest {
+            session_token: "session_token".to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn should_generate_request_id() {
+        let response = client().await.sign_out(sign_out_request()).await.unwrap();
+
+        let request_id = response.metadata().get(REQUEST_ID_HEADER).unwrap();
+        assert!(uuid::Uuid::parse_str(request_id.to_str().unwrap()).is_ok());
+    }
+
+    #[tokio::test]
+    async fn should_propagate_request_id() {
+        let mut request = sign_out_request();
+        request
+            .metadata_mut()
+            .insert(REQUEST_ID_HEADER, "my-request".parse().unwrap());
+
+        let response = client().await.sign_out(request).await.unwrap();
+
+        assert_eq!(
+            response.metadata().get(REQUEST_ID_HEADER).unwrap(),
+            "my-request"
+        );
+    }
+}
@@ -1,7 +1,21 @@
 use std::str::FromStr;
-use std::sync::Mutex;
-
-use crate::{auth::Authenticator, sessions::SessionsTranstient, users::UsersTransient};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    admin::RequireToken,
+    api_keys,
+    audit::AuditLog,
+    auth::Authenticator,
+    config::Config,
+    events::{EventBus, EventFilter},
+    metrics, profiles,
+    sessions::SessionsTranstient,
+    users::{User, UsersTransient},
+    verification::EmailVerification,
+};
 
 // Re-exporting
 pub use authentication::authentication_server::AuthenticationServer;
@@ -12,46 +26,59 @@ pub mod authentication {
 }
 
 use authentication::{
+    CancelAccountDeletionRequest, CancelAccountDeletionResponse, ChangePasswordRequest,
+    ChangePasswordResponse, DeleteAccountRequest, DeleteAccountResponse, Event, GetProfileRequest,
+    GetProfileResponse, Profile, ResendVerificationRequest, ResendVerificationResponse,
     SignInRequest, SignInResponse, SignOutRequest, SignOutResponse, SignUpRequest, SignUpResponse,
-    StatusCode,
+    StatusCode, SubscribeEventsRequest, UpdateProfileRequest, UpdateProfileResponse,
+    VerifyEmailRequest, VerifyEmailResponse,
 };
 
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
 
 use crate::service::authentication::authentication_server::Authentication;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub enum AuthenticationServiceConfig {
+    #[default]
     InMemory,
 }
 
-impl Default for AuthenticationServiceConfig {
-    fn default() -> Self {
-        Self::InMemory
-    }
-}
-
 impl FromStr for AuthenticationServiceConfig {
-    type Err = ();
+    type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "InMemory" => Ok(AuthenticationServiceConfig::InMemory),
-            _ => Err(()),
+            _ => Err(format!("Unknown persistence type: {}", s)),
         }
     }
 }
 
 pub struct AuthenticationService {
     authenticator: Mutex<Authenticator>,
+    events: EventBus,
+    /// Lets `SubscribeEvents` callers see the events of every user.
+    admin: Option<RequireToken>,
 }
 
 impl AuthenticationService {
-    fn new(authenticator: Authenticator) -> Self {
+    pub fn new(authenticator: Authenticator) -> Self {
         Self {
+            events: authenticator.events(),
             authenticator: Mutex::new(authenticator),
+            admin: None,
         }
     }
 
+    /// Lets the callers presenting `token` subscribe to the events of every
+    /// user.
+    pub fn with_admin_token(mut self, token: &str) -> Self {
+        self.admin = Some(RequireToken::new(token));
+        self
+    }
+
     pub fn new_with_config(config: AuthenticationServiceConfig) -> Self {
         match config {
             AuthenticationServiceConfig::InMemory => Self::new(Authenticator::new(
@@ -60,6 +87,75 @@ impl AuthenticationService {
             )),
         }
     }
+
+    /// Writes any buffered state of the stores, called before exiting.
+    pub fn flush(&self) -> Result<(), String> {
+        self.authenticator().flush()
+    }
+
+    /// The bus the events of the service are published on.
+    pub fn events(&self) -> EventBus {
+        self.events.clone()
+    }
+
+    /// Number of registered users and of open sessions.
+    pub fn counts(&self) -> (usize, usize) {
+        self.authenticator().counts()
+    }
+
+    pub fn authenticator(&self) -> MutexGuard<'_, Authenticator> {
+        let start = Instant::now();
+        let authenticator = self.authenticator.lock().unwrap();
+        metrics::observe_lock_wait(start.elapsed());
+        authenticator
+    }
+
+    pub fn from_config(
+        config: &Config,
+        audit: AuditLog,
+        email_verification: Option<EmailVerification>,
+    ) -> Self {
+        match config.storage.backend {
+            AuthenticationServiceConfig::InMemory => {
+                let mut authenticator = Authenticator::new(
+                    UsersTransient::with_hashing(config.hashing.params()),
+                    SessionsTranstient::with_lifetime(config.sessions.lifetime()),
+                )
+                .with_audit(audit)
+                .with_profile_schema(
+                    config
+                        .profiles
+                        .schema()
+                        .expect("the configuration should have been validated"),
+                )
+                .with_deletion_grace_period(config.accounts.deletion_grace_period())
+                .with_lockout(
+                    config.accounts.lockout_threshold,
+                    config.accounts.lockout_duration(),
+                )
+                .with_events(EventBus::new(config.events.buffer_size));
+                if let Some(email_verification) = email_verification {
+                    authenticator = authenticator.with_email_verification(email_verification);
+                }
+                let service = Self::new(authenticator);
+                match &config.admin.token {
+                    Some(token) => service.with_admin_token(token),
+                    None => service,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+impl AuthenticationService {
+    /// In memory, with `UsersTransient::fast` hashing.
+    pub fn fast() -> Self {
+        Self::new(Authenticator::new(
+            UsersTransient::fast(),
+            SessionsTranstient::new(),
+        ))
+    }
 }
 
 impl Default for AuthenticationService {
@@ -68,19 +164,62 @@ impl Default for AuthenticationService {
     }
 }
 
+impl From<&User> for Profile {
+    fn from(user: &User) -> Self {
+        let profile = user.profile();
+        Self {
+            user_id: user.id().to_string(),
+            username: user.username().to_string(),
+            display_name: profile.display_name.clone(),
+            email: profile.email.clone(),
+            locale: profile.locale.clone(),
+            attributes: profile.attributes.clone().into_iter().collect(),
+            created_at_ms: user.created_at_ms(),
+            updated_at_ms: user.updated_at_ms(),
+            email_verified: user.is_email_verified(),
+        }
+    }
+}
+
+impl From<Profile> for profiles::Profile {
+    fn from(profile: Profile) -> Self {
+        Self {
+            display_name: profile.display_name,
+            email: profile.email,
+            locale: profile.locale,
+            attributes: profile.attributes.into_iter().collect(),
+        }
+    }
+}
+
+fn outcome(status_code: i32) -> &'static str {
+    match StatusCode::try_from(status_code) {
+        Ok(StatusCode::Success) => "success",
+        _ => "failure",
+    }
+}
+
+/// Reports the outcome of a request to the metrics and to its trace span.
+fn record_outcome(rpc: &str, status_code: i32, start: Instant) {
+    let outcome = outcome(status_code);
+    tracing::Span::current().record("outcome", outcome);
+    metrics::observe_request(rpc, outcome, start.elapsed());
+}
+
 #[tonic::async_trait]
 impl Authentication for AuthenticationService {
+    type SubscribeEventsStream = ReceiverStream<Result<Event, Status>>;
+
     async fn sign_up(
         &self,
         request: Request<SignUpRequest>,
     ) -> Result<Response<SignUpResponse>, Status> {
+        let start = Instant::now();
         let req = request.into_inner();
 
         let auth_response = self
-            .authenticator
-            .lock()
-            .unwrap()
-            .sign_up(&req.username, &req.password);
+            .authenticator()
+            .sign_up(&req.username, &req.password, &req.email);
 
         let reply = match auth_response {
             Ok(_) => SignUpResponse {
@@ -91,6 +230,7 @@ impl Authentication for AuthenticationService {
             },
         };
 
+        record_outcome("SignUp", reply.status_code, start);
         Ok(Response::new(reply))
     }
 
@@ -98,27 +238,30 @@ impl Authentication for AuthenticationService {
         &self,
         request: Request<SignInRequest>,
     ) -> Result<Response<SignInResponse>, Status> {
+        let start = Instant::now();
         let req = request.into_inner();
 
-        let auth_response = self
-            .authenticator
-            .lock()
-            .unwrap()
-            .sign_in(&req.username, &req.password);
+        let mut authenticator = self.authenticator();
+        let auth_response = authenticator.sign_in(&req.username, &req.password);
+        metrics::observe_sign_in(auth_response.is_ok());
 
         let reply = match auth_response {
             Ok((session_token, user_id)) => SignInResponse {
                 status_code: StatusCode::Success.into(),
                 session_token,
                 user_id,
+                profile: authenticator.get_user(&req.username).map(Profile::from),
             },
             Err(_) => SignInResponse {
                 status_code: StatusCode::Failure.into(),
                 session_token: "".to_string(),
                 user_id: "".to_string(),
+                profile: None,
             },
         };
+        drop(authenticator);
 
+        record_outcome("SignIn", reply.status_code, start);
         Ok(Response::new(reply))
     }
 
@@ -126,13 +269,10 @@ impl Authentication for AuthenticationService {
         &self,
         request: Request<SignOutRequest>,
     ) -> Result<Response<SignOutResponse>, Status> {
+        let start = Instant::now();
         let req = request.into_inner();
 
-        let auth_response = self
-            .authenticator
-            .lock()
-            .unwrap()
-            .sign_out(&req.session_token);
+        let auth_response = self.authenticator().sign_out(&req.session_token);
 
         let reply = match auth_response {
             Ok(_) => SignOutResponse {
@@ -143,8 +283,221 @@ impl Authentication for AuthenticationService {
             },
         };
 
+        record_outcome("SignOut", reply.status_code, start);
+        Ok(Response::new(reply))
+    }
+
+    async fn delete_account(
+        &self,
+        request: Request<DeleteAccountRequest>,
+    ) -> Result<Response<DeleteAccountResponse>, Status> {
+        let start = Instant::now();
+        let req = request.into_inner();
+
+        let auth_response = self
+            .authenticator()
+            .delete_account(&req.session_token, &req.password);
+
+        let reply = match auth_response {
+            Ok(deletes_at_ms) => DeleteAccountResponse {
+                status_code: StatusCode::Success.into(),
+                deletes_at_ms: deletes_at_ms.unwrap_or_default(),
+            },
+            Err(_) => DeleteAccountResponse {
+                status_code: StatusCode::Failure.into(),
+                deletes_at_ms: 0,
+            },
+        };
+
+        record_outcome("DeleteAccount", reply.status_code, start);
+        Ok(Response::new(reply))
+    }
+
+    async fn cancel_account_deletion(
+        &self,
+        request: Request<CancelAccountDeletionRequest>,
+    ) -> Result<Response<CancelAccountDeletionResponse>, Status> {
+        let start = Instant::now();
+        let req = request.into_inner();
+
+        let auth_response = self
+            .authenticator()
+            .cancel_account_deletion(&req.session_token);
+
+        let reply = match auth_response {
+            Ok(_) => CancelAccountDeletionResponse {
+                status_code: StatusCode::Success.into(),
+            },
+            Err(_) => CancelAccountDeletionResponse {
+                status_code: StatusCode::Failure.into(),
+            },
+        };
+
+        record_outcome("CancelAccountDeletion", reply.status_code, start);
+        Ok(Response::new(reply))
+    }
+
+    async fn change_password(
+        &self,
+        request: Request<ChangePasswordRequest>,
+    ) -> Result<Response<ChangePasswordResponse>, Status> {
+        let start = Instant::now();
+        let req = request.into_inner();
+
+        let auth_response = self.authenticator().change_password(
+            &req.session_token,
+            &req.password,
+            &req.new_password,
+        );
+
+        let reply = match auth_response {
+            Ok(_) => ChangePasswordResponse {
+                status_code: StatusCode::Success.into(),
+            },
+            Err(_) => ChangePasswordResponse {
+                status_code: StatusCode::Failure.into(),
+            },
+        };
+
+        record_outcome("ChangePassword", reply.status_code, start);
+        Ok(Response::new(reply))
+    }
+
+    async fn get_profile(
+        &self,
+        request: Request<GetProfileRequest>,
+    ) -> Result<Response<GetProfileResponse>, Status> {
+        let start = Instant::now();
+        let req = request.into_inner();
+
+        let mut authenticator = self.authenticator();
+        let user = authenticator.authorize(&req.session_token, api_keys::SCOPE_PROFILE_READ);
+        let reply = match user {
+            Ok(user) => GetProfileResponse {
+                status_code: StatusCode::Success.into(),
+                profile: Some(user.into()),
+            },
+            Err(_) => GetProfileResponse {
+                status_code: StatusCode::Failure.into(),
+                profile: None,
+            },
+        };
+
+        record_outcome("GetProfile", reply.status_code, start);
+        Ok(Response::new(reply))
+    }
+
+    async fn update_profile(
+        &self,
+        request: Request<UpdateProfileRequest>,
+    ) -> Result<Response<UpdateProfileResponse>, Status> {
+        let start = Instant::now();
+        let req = request.into_inner();
+
+        let auth_response = self.authenticator().update_profile(
+            &req.session_token,
+            req.profile.clone().map(Into::into).unwrap_or_default(),
+        );
+
+        let reply = match auth_response {
+            Ok(user) => UpdateProfileResponse {
+                status_code: StatusCode::Success.into(),
+                profile: Some((&user).into()),
+                error: String::new(),
+            },
+            Err(error) => UpdateProfileResponse {
+                status_code: StatusCode::Failure.into(),
+                profile: None,
+                error,
+            },
+        };
+
+        record_outcome("UpdateProfile", reply.status_code, start);
+        Ok(Response::new(reply))
+    }
+
+    async fn verify_email(
+        &self,
+        request: Request<VerifyEmailRequest>,
+    ) -> Result<Response<VerifyEmailResponse>, Status> {
+        let start = Instant::now();
+        let req = request.into_inner();
+
+        let auth_response = self.authenticator().verify_email(&req.token);
+
+        let reply = match auth_response {
+            Ok(_) => VerifyEmailResponse {
+                status_code: StatusCode::Success.into(),
+            },
+            Err(_) => VerifyEmailResponse {
+                status_code: StatusCode::Failure.into(),
+            },
+        };
+
+        record_outcome("VerifyEmail", reply.status_code, start);
         Ok(Response::new(reply))
     }
+
+    async fn resend_verification(
+        &self,
+        request: Request<ResendVerificationRequest>,
+    ) -> Result<Response<ResendVerificationResponse>, Status> {
+        let start = Instant::now();
+        let req = request.into_inner();
+
+        let auth_response = self
+            .authenticator()
+            .resend_verification(&req.username, &req.password);
+
+        let reply = match auth_response {
+            Ok(_) => ResendVerificationResponse {
+                status_code: StatusCode::Success.into(),
+            },
+            Err(_) => ResendVerificationResponse {
+                status_code: StatusCode::Failure.into(),
+            },
+        };
+
+        record_outcome("ResendVerification", reply.status_code, start);
+        Ok(Response::new(reply))
+    }
+
+    async fn subscribe_events(
+        &self,
+        request: Request<SubscribeEventsRequest>,
+    ) -> Result<Response<Self::SubscribeEventsStream>, Status> {
+        let is_admin = self
+            .admin
+            .as_ref()
+            .is_some_and(|admin| admin.accepts(request.metadata()));
+        let req = request.into_inner();
+
+        let mut filter = EventFilter::from(&req);
+        if !is_admin {
+            let user_id = self
+                .authenticator()
+                .authorize(&req.session_token, api_keys::SCOPE_EVENTS_READ)
+                .map(|user| user.id().to_string())
+                .map_err(Status::unauthenticated)?;
+            if filter
+                .user_id
+                .as_ref()
+                .is_some_and(|other| *other != user_id)
+            {
+                return Err(Status::permission_denied(
+                    "Only the admin token subscribes to the events of other users",
+                ));
+            }
+            filter.user_id = Some(user_id);
+        }
+
+        let events = self
+            .events
+            .stream(req.from_sequence, filter)
+            .map_err(Status::out_of_range)?;
+
+        Ok(Response::new(ReceiverStream::new(events)))
+    }
 }
 
 #[cfg(test)]
@@ -153,24 +506,25 @@ mod tests {
 
     #[tokio::test]
     async fn sign_up_should_succeed() {
-        let service = AuthenticationService::new_with_config(AuthenticationServiceConfig::InMemory);
+        let service = AuthenticationService::fast();
 
         let request = tonic::Request::new(SignUpRequest {
             username: "username".to_string(),
             password: "password".to_string(),
+            ..Default::default()
         });
 
         let response = service.sign_up(request).await.unwrap();
 
         assert_eq!(
             response.into_inner().status_code,
-            StatusCode::Success.into()
+            i32::from(StatusCode::Success)
         );
     }
 
     #[tokio::test]
     async fn sign_up_shoudl_fail_if_username_exists() {
-        let service = AuthenticationService::new_with_config(AuthenticationServiceConfig::InMemory);
+        let service = AuthenticationService::fast();
 
         let username = "username";
         let password = "password";
@@ -178,6 +532,7 @@ mod tests {
         let request = tonic::Request::new(SignUpRequest {
             username: username.to_string(),
             password: password.to_string(),
+            ..Default::default()
         });
 
         service.sign_up(request).await.unwrap();
@@ -185,19 +540,20 @@ mod tests {
         let request = tonic::Request::new(SignUpRequest {
             username: username.to_string(),
             password: password.to_string(),
+            ..Default::default()
         });
 
         let response = service.sign_up(request).await.unwrap();
 
         assert_eq!(
             response.into_inner().status_code,
-            StatusCode::Failure.into()
+            i32::from(StatusCode::Failure)
         );
     }
 
     #[tokio::test]
     async fn sign_in_should_succeed() {
-        let service = AuthenticationService::new_with_config(AuthenticationServiceConfig::InMemory);
+        let service = AuthenticationService::fast();
 
         let username = "username";
         let password = "password";
@@ -205,6 +561,7 @@ mod tests {
         let request = tonic::Request::new(SignUpRequest {
             username: username.to_string(),
             password: password.to_string(),
+            ..Default::default()
         });
 
         service.sign_up(request).await.unwrap();
@@ -218,13 +575,13 @@ mod tests {
 
         assert_eq!(
             response.into_inner().status_code,
-            StatusCode::Success.into()
+            i32::from(StatusCode::Success)
         );
     }
 
     #[tokio::test]
     async fn sign_in_should_fail_if_user_does_not_exist() {
-        let service = AuthenticationService::new_with_config(AuthenticationServiceConfig::InMemory);
+        let service = AuthenticationService::fast();
 
         let username = "username";
         let password = "password";
@@ -238,13 +595,13 @@ mod tests {
 
         assert_eq!(
             response.into_inner().status_code,
-            StatusCode::Failure.into()
+            i32::from(StatusCode::Failure)
         );
     }
 
     #[tokio::test]
     async fn sign_out_should_succeed() {
-        let service = AuthenticationService::new_with_config(AuthenticationServiceConfig::InMemory);
+        let service = AuthenticationService::fast();
 
         let username = "username";
         let password = "password";
@@ -252,6 +609,7 @@ mod tests {
         let request = tonic::Request::new(SignUpRequest {
             username: username.to_string(),
             password: password.to_string(),
+            ..Default::default()
         });
 
         service.sign_up(request).await.unwrap();
@@ -271,13 +629,13 @@ mod tests {
 
         assert_eq!(
             response.into_inner().status_code,
-            StatusCode::Success.into()
+            i32::from(StatusCode::Success)
         );
     }
 
     #[tokio::test]
     async fn sign_out_should_fail_if_session_does_not_exist() {
-        let service = AuthenticationService::new_with_config(AuthenticationServiceConfig::InMemory);
+        let service = AuthenticationService::fast();
 
         let request = tonic::Request::new(SignOutRequest {
             session_token: "session_token".to_string(),
@@ -287,7 +645,217 @@ mod tests {
 
         assert_eq!(
             response.into_inner().status_code,
-            StatusCode::Failure.into()
+            i32::from(StatusCode::Failure)
+        );
+    }
+
+    const ADMIN_TOKEN: &str = "0123456789abcdef";
+
+    fn as_admin<T>(message: T) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        request.metadata_mut().insert(
+            "authorization",
+            format!("Bearer {}", ADMIN_TOKEN).parse().unwrap(),
         );
+        request
+    }
+
+    async fn sign_up_and_in(service: &AuthenticationService, username: &str) -> SignInResponse {
+        service
+            .sign_up(tonic::Request::new(SignUpRequest {
+                username: username.to_string(),
+                password: "password".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        service
+            .sign_in(tonic::Request::new(SignInRequest {
+                username: username.to_string(),
+                password: "password".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+    }
+
+    #[tokio::test]
+    async fn should_stream_filtered_events() {
+        use authentication::EventType;
+        use tokio_stream::StreamExt;
+
+        let service = AuthenticationService::fast().with_admin_token(ADMIN_TOKEN);
+        let request = SubscribeEventsRequest {
+            types: vec![EventType::SignedIn.into()],
+            ..Default::default()
+        };
+        let mut events = service
+            .subscribe_events(as_admin(request))
+            .await
+            .unwrap()
+            .into_inner();
+
+        service
+            .sign_up(tonic::Request::new(SignUpRequest {
+                username: "username".to_string(),
+                password: "password".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        let user_id = service
+            .sign_in(tonic::Request::new(SignInRequest {
+                username: "username".to_string(),
+                password: "password".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .user_id;
+
+        let event = events.next().await.unwrap().unwrap();
+        assert_eq!(event.r#type(), EventType::SignedIn);
+        assert_eq!(event.sequence, 2);
+        assert_eq!(event.user_id, user_id);
+        assert_eq!(event.username, "username");
+
+        let resumed = SubscribeEventsRequest {
+            from_sequence: Some(1),
+            ..Default::default()
+        };
+        let mut events = service
+            .subscribe_events(as_admin(resumed))
+            .await
+            .unwrap()
+            .into_inner();
+        let event = events.next().await.unwrap().unwrap();
+        assert_eq!(event.r#type(), EventType::SignedUp);
+    }
+
+    #[tokio::test]
+    async fn should_require_session_or_admin_token_to_subscribe() {
+        let service = AuthenticationService::fast().with_admin_token(ADMIN_TOKEN);
+
+        let status = service
+            .subscribe_events(tonic::Request::new(SubscribeEventsRequest::default()))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+    }
+
+    #[tokio::test]
+    async fn should_stream_events_of_session_user_only() {
+        use tokio_stream::StreamExt;
+
+        let service = AuthenticationService::fast();
+        let jane = sign_up_and_in(&service, "jane").await;
+        let john = sign_up_and_in(&service, "john").await;
+
+        let status = service
+            .subscribe_events(tonic::Request::new(SubscribeEventsRequest {
+                session_token: jane.session_token.clone(),
+                user_id: john.user_id.clone(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+        let mut events = service
+            .subscribe_events(tonic::Request::new(SubscribeEventsRequest {
+                session_token: jane.session_token.clone(),
+                from_sequence: Some(1),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let mut user_ids = Vec::new();
+        for _ in 0..2 {
+            user_ids.push(events.next().await.unwrap().unwrap().user_id);
+        }
+        assert_eq!(user_ids, [jane.user_id.clone(), jane.user_id]);
+    }
+
+    #[tokio::test]
+    async fn should_stream_failed_sign_ins_to_account_owner() {
+        use authentication::EventType;
+        use tokio_stream::StreamExt;
+
+        let service = AuthenticationService::fast();
+        let jane = sign_up_and_in(&service, "jane").await;
+        let mut events = service
+            .subscribe_events(tonic::Request::new(SubscribeEventsRequest {
+                session_token: jane.session_token,
+                types: vec![EventType::SignInFailed.into()],
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        for username in ["john", "jane"] {
+            service
+                .sign_in(tonic::Request::new(SignInRequest {
+                    username: username.to_string(),
+                    password: "wrong".to_string(),
+                }))
+                .await
+                .unwrap();
+        }
+
+        let event = events.next().await.unwrap().unwrap();
+        assert_eq!(event.username, "jane");
+        assert_eq!(event.user_id, jane.user_id);
+    }
+
+    #[tokio::test]
+    async fn should_stream_events_to_api_key_with_events_scope() {
+        use crate::api_keys::NewApiKey;
+        use authentication::EventType;
+        use tokio_stream::StreamExt;
+
+        let service = AuthenticationService::fast();
+        let account = service
+            .authenticator()
+            .create_service_account("ci-bot")
+            .unwrap();
+        let create_key = |scope: &str| {
+            let key = NewApiKey {
+                name: scope.to_string(),
+                scopes: vec![scope.to_string()],
+                expires_at_ms: None,
+            };
+            service
+                .authenticator()
+                .create_api_key("ci-bot", key)
+                .unwrap()
+                .1
+        };
+        let profile_key = create_key(api_keys::SCOPE_PROFILE_READ);
+        let events_key = create_key(api_keys::SCOPE_EVENTS_READ);
+
+        let status = service
+            .subscribe_events(tonic::Request::new(SubscribeEventsRequest {
+                session_token: profile_key,
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+
+        let mut events = service
+            .subscribe_events(tonic::Request::new(SubscribeEventsRequest {
+                session_token: events_key,
+                from_sequence: Some(1),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let event = events.next().await.unwrap().unwrap();
+        assert_eq!(event.r#type(), EventType::ServiceAccountCreated);
+        assert_eq!(event.user_id, account.id());
     }
 }
@@ -1,36 +1,139 @@
-use std::collections::HashMap;
+use tracing::instrument;
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+pub const DEFAULT_SESSION_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
 
 pub trait Sessions {
     fn create_session(&mut self, user_id: &str) -> Result<String, String>;
 
-    fn delete_session(&mut self, user_id: &str) -> Result<(), String>;
+    /// Id of the user the session belongs to, unless it does not exist or
+    /// has expired.
+    fn get_user_id(&self, session_token: &str) -> Option<String>;
+
+    /// Deletes the session, returning the id of the user it belonged to.
+    fn delete_session(&mut self, session_token: &str) -> Result<String, String>;
+
+    /// Deletes every session of a user, returning how many there were.
+    fn delete_user_sessions(&mut self, user_id: &str) -> usize;
+
+    /// Deletes every session of a user but `kept`, returning how many were
+    /// deleted.
+    fn delete_other_sessions(&mut self, user_id: &str, kept: &str) -> usize;
+
+    /// Number of sessions that have not expired.
+    fn count(&self) -> usize;
+
+    /// Persists pending changes, a no-op for stores that write through.
+    fn flush(&mut self) -> Result<(), String> {
+        Ok(())
+    }
 }
 
 pub struct SessionsTranstient {
     uuid_to_session: HashMap<String, String>,
+    expirations: HashMap<String, Instant>,
+    lifetime: Duration,
 }
 
 impl SessionsTranstient {
     pub fn new() -> Self {
+        Self::with_lifetime(DEFAULT_SESSION_LIFETIME)
+    }
+
+    pub fn with_lifetime(lifetime: Duration) -> Self {
         Self {
             uuid_to_session: HashMap::new(),
+            expirations: HashMap::new(),
+            lifetime,
+        }
+    }
+
+    fn remove_expired(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<String> = self
+            .expirations
+            .iter()
+            .filter(|(_, expires_at)| **expires_at <= now)
+            .map(|(session, _)| session.clone())
+            .collect();
+
+        for session in expired {
+            self.expirations.remove(&session);
+            self.uuid_to_session.remove(&session);
+        }
+    }
+
+    fn delete_sessions_where(&mut self, matches: impl Fn(&str, &str) -> bool) -> usize {
+        self.remove_expired();
+
+        let sessions: Vec<String> = self
+            .uuid_to_session
+            .iter()
+            .filter(|(session, owner)| matches(session, owner))
+            .map(|(session, _)| session.clone())
+            .collect();
+        for session in &sessions {
+            self.uuid_to_session.remove(session);
+            self.expirations.remove(session);
         }
+        sessions.len()
     }
 }
 
 impl Sessions for SessionsTranstient {
+    #[instrument(skip(self), fields(storage.backend = "InMemory"))]
     fn create_session(&mut self, user_id: &str) -> Result<String, String> {
+        self.remove_expired();
+
         let session = uuid::Uuid::new_v4().to_string();
         self.uuid_to_session.insert(session.clone(), user_id.into());
+        self.expirations
+            .insert(session.clone(), Instant::now() + self.lifetime);
 
         Ok(session)
     }
 
-    fn delete_session(&mut self, session_token: &str) -> Result<(), String> {
-        self.uuid_to_session
+    #[instrument(skip_all, fields(storage.backend = "InMemory"))]
+    fn get_user_id(&self, session_token: &str) -> Option<String> {
+        let expires_at = self.expirations.get(session_token)?;
+        if *expires_at <= Instant::now() {
+            return None;
+        }
+        self.uuid_to_session.get(session_token).cloned()
+    }
+
+    #[instrument(skip_all, fields(storage.backend = "InMemory"))]
+    fn delete_session(&mut self, session_token: &str) -> Result<String, String> {
+        self.remove_expired();
+
+        self.expirations.remove(session_token);
+        let user_id = self
+            .uuid_to_session
             .remove(session_token)
             .ok_or("Session not found")?;
-        Ok(())
+        Ok(user_id)
+    }
+
+    #[instrument(skip(self), fields(storage.backend = "InMemory"))]
+    fn delete_user_sessions(&mut self, user_id: &str) -> usize {
+        self.delete_sessions_where(|_, owner| owner == user_id)
+    }
+
+    #[instrument(skip_all, fields(storage.backend = "InMemory"))]
+    fn delete_other_sessions(&mut self, user_id: &str, kept: &str) -> usize {
+        self.delete_sessions_where(|session, owner| owner == user_id && session != kept)
+    }
+
+    fn count(&self) -> usize {
+        let now = Instant::now();
+        self.expirations
+            .values()
+            .filter(|expires_at| **expires_at > now)
+            .count()
     }
 }
 
@@ -67,4 +170,61 @@ mod tests {
 
         assert!(sessions.delete_session("1235").is_err());
     }
+
+    #[test]
+    fn should_fail_to_delete_expired_session() {
+        let mut sessions = SessionsTranstient::with_lifetime(Duration::ZERO);
+
+        let session = sessions.create_session("1234").unwrap();
+
+        assert!(sessions.delete_session(&session).is_err());
+        assert_eq!(sessions.uuid_to_session.len(), 0);
+    }
+
+    #[test]
+    fn should_delete_every_session_of_user() {
+        let mut sessions = SessionsTranstient::new();
+        sessions.create_session("1234").unwrap();
+        sessions.create_session("1234").unwrap();
+        let other = sessions.create_session("5678").unwrap();
+
+        assert_eq!(sessions.delete_user_sessions("1234"), 2);
+
+        assert_eq!(sessions.uuid_to_session.len(), 1);
+        assert!(sessions.uuid_to_session.contains_key(&other));
+    }
+
+    #[test]
+    fn should_delete_other_sessions_of_user() {
+        let mut sessions = SessionsTranstient::new();
+        let kept = sessions.create_session("1234").unwrap();
+        sessions.create_session("1234").unwrap();
+        let other = sessions.create_session("5678").unwrap();
+
+        assert_eq!(sessions.delete_other_sessions("1234", &kept), 1);
+
+        assert_eq!(sessions.get_user_id(&kept).as_deref(), Some("1234"));
+        assert_eq!(sessions.uuid_to_session.len(), 2);
+        assert!(sessions.uuid_to_session.contains_key(&other));
+    }
+
+    #[test]
+    fn should_get_user_id_of_live_session_only() {
+        let mut sessions = SessionsTranstient::new();
+        let session = sessions.create_session("1234").unwrap();
+        let mut expired_sessions = SessionsTranstient::with_lifetime(Duration::ZERO);
+        let expired = expired_sessions.create_session("1234").unwrap();
+
+        assert_eq!(sessions.get_user_id(&session), Some("1234".to_string()));
+        assert_eq!(sessions.get_user_id("does-not-exist"), None);
+        assert_eq!(expired_sessions.get_user_id(&expired), None);
+    }
+
+    #[test]
+    fn should_not_count_expired_sessions() {
+        let mut sessions = SessionsTranstient::with_lifetime(Duration::ZERO);
+        sessions.create_session("1234").unwrap();
+
+        assert_eq!(sessions.count(), 0);
+    }
 }
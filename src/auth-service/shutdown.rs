@@ -0,0 +1,308 @@
+use std::{future::Future, time::Duration};
+
+use tokio::sync::watch;
+use tonic_health::{server::HealthReporter, ServingStatus};
+
+use crate::service::{AuthenticationServer, AuthenticationService};
+
+/// Resolves when the process is asked to stop, either with SIGINT (Ctrl+C)
+/// or with the SIGTERM sent by container runtimes.
+pub async fn signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for SIGINT");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+/// Lets the server be told to stop accepting connections independently of
+/// the process signal, so the health status can be flipped first.
+pub struct Shutdown {
+    sender: watch::Sender<bool>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self {
+            sender: watch::Sender::new(false),
+        }
+    }
+
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    /// Future to hand to `serve_with_shutdown`, resolving once triggered.
+    pub fn triggered(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut receiver = self.sender.subscribe();
+        async move {
+            let _ = receiver.wait_for(|triggered| *triggered).await;
+        }
+    }
+}
+
+/// Reports NOT_SERVING for the auth service and for the server as a whole,
+/// the `""` service most load balancers and probes ask about.
+pub async fn stop_serving(health_reporter: &mut HealthReporter) {
+    health_reporter
+        .set_not_serving::<AuthenticationServer<AuthenticationService>>()
+        .await;
+    health_reporter
+        .set_service_status("", ServingStatus::NotServing)
+        .await;
+}
+
+/// Keeps a server serving for up to `limit`, e.g. while load balancers
+/// notice it reports NOT_SERVING, or while it finishes its in-flight
+/// requests once told to shut down. Returns `true` if the server stopped
+/// meanwhile.
+pub async fn serve_for<F, E>(server: &mut F, limit: Duration) -> Result<bool, E>
+where
+    F: Future<Output = Result<(), E>> + Unpin,
+{
+    match tokio::time::timeout(limit, server).await {
+        Ok(result) => result.map(|_| true),
+        Err(_) => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::Authenticator,
+        profiles::Profile,
+        service::{
+            authentication::{
+                authentication_client::AuthenticationClient, SignUpRequest, SignUpResponse,
+            },
+            AuthenticationServer, AuthenticationService, Server,
+        },
+        sessions::SessionsTranstient,
+        users::{User, Users, UsersTransient},
+    };
+    use tokio::{net::TcpListener, sync::mpsc};
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic_health::pb::{
+        health_check_response, health_client::HealthClient, HealthCheckRequest,
+    };
+
+    /// Users store whose sign ups take a while and announce when they start,
+    /// so a request can be caught in flight deterministically.
+    struct SlowUsers {
+        inner: UsersTransient,
+        started: mpsc::UnboundedSender<()>,
+    }
+
+    impl Users for SlowUsers {
+        fn create_user(&mut self, username: &str, password: &str) -> Result<&User, String> {
+            let _ = self.started.send(());
+            std::thread::sleep(Duration::from_millis(500));
+            self.inner.create_user(username, password)
+        }
+
+        fn create_service_account(&mut self, username: &str) -> Result<&User, String> {
+            self.inner.create_service_account(username)
+        }
+
+        fn find_user_id(&self, username: &str, password: &str) -> Option<String> {
+            self.inner.find_user_id(username, password)
+        }
+
+        fn get_user(&self, username: &str) -> Option<&User> {
+            self.inner.get_user(username)
+        }
+
+        fn get_user_by_id(&self, user_id: &str) -> Option<&User> {
+            self.inner.get_user_by_id(user_id)
+        }
+
+        fn list_users(&self, prefix: &str, after: Option<&str>, limit: usize) -> Vec<&User> {
+            self.inner.list_users(prefix, after, limit)
+        }
+
+        fn set_disabled(&mut self, username: &str, disabled: bool) -> Result<&User, String> {
+            self.inner.set_disabled(username, disabled)
+        }
+
+        fn update_profile(&mut self, user_id: &str, profile: Profile) -> Result<&User, String> {
+            self.inner.update_profile(user_id, profile)
+        }
+
+        fn set_email_verified(&mut self, user_id: &str) -> Result<&User, String> {
+            self.inner.set_email_verified(user_id)
+        }
+
+        fn set_password(&mut self, user_id: &str, password: &str) -> Result<&User, String> {
+            self.inner.set_password(user_id, password)
+        }
+
+        fn set_deletion_due(
+            &mut self,
+            user_id: &str,
+            due_at_ms: Option<u64>,
+        ) -> Result<&User, String> {
+            self.inner.set_deletion_due(user_id, due_at_ms)
+        }
+
+        fn deletions_due(&self, now_ms: u64) -> Vec<&User> {
+            self.inner.deletions_due(now_ms)
+        }
+
+        fn delete_user(&mut self, username: &str) -> Result<(), String> {
+            self.inner.delete_user(username)
+        }
+
+        fn count(&self) -> usize {
+            self.inner.count()
+        }
+    }
+
+    fn slow_service() -> (AuthenticationService, mpsc::UnboundedReceiver<()>) {
+        let (started, receiver) = mpsc::unbounded_channel();
+        let users = SlowUsers {
+            inner: UsersTransient::fast(),
+            started,
+        };
+
+        let service =
+            AuthenticationService::new(Authenticator::new(users, SessionsTranstient::new()));
+        (service, receiver)
+    }
+
+    async fn start(
+        shutdown: &Shutdown,
+    ) -> (
+        impl Future<Output = Result<(), tonic::transport::Error>> + Unpin,
+        AuthenticationClient<tonic::transport::Channel>,
+        mpsc::UnboundedReceiver<()>,
+    ) {
+        let (service, started) = slow_service();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = Server::builder()
+            .add_service(AuthenticationServer::new(service))
+            .serve_with_incoming_shutdown(TcpListenerStream::new(listener), shutdown.triggered());
+        let server = tokio::spawn(server);
+
+        let client = AuthenticationClient::connect(format!("http://127.0.0.1:{}", port))
+            .await
+            .unwrap();
+
+        (Box::pin(async { server.await.unwrap() }), client, started)
+    }
+
+    fn sign_up(
+        mut client: AuthenticationClient<tonic::transport::Channel>,
+    ) -> tokio::task::JoinHandle<Result<tonic::Response<SignUpResponse>, tonic::Status>> {
+        tokio::spawn(async move {
+            client
+                .sign_up(SignUpRequest {
+                    username: "username".to_string(),
+                    password: "password".to_string(),
+                    ..Default::default()
+                })
+                .await
+        })
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn should_finish_in_flight_requests_before_stopping() {
+        let shutdown = Shutdown::new();
+        let (mut server, client, mut started) = start(&shutdown).await;
+
+        let in_flight = sign_up(client);
+        started.recv().await;
+        shutdown.trigger();
+
+        assert!(serve_for(&mut server, Duration::from_secs(60))
+            .await
+            .unwrap());
+        assert!(in_flight.await.unwrap().is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn should_keep_serving_until_triggered() {
+        let shutdown = Shutdown::new();
+        let (mut server, client, mut started) = start(&shutdown).await;
+
+        let request = sign_up(client);
+        assert!(!serve_for(&mut server, Duration::from_millis(200))
+            .await
+            .unwrap());
+        started.recv().await;
+        shutdown.trigger();
+
+        assert!(serve_for(&mut server, Duration::from_secs(60))
+            .await
+            .unwrap());
+        assert!(request.await.unwrap().is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn should_give_up_draining_after_deadline() {
+        let shutdown = Shutdown::new();
+        let (mut server, client, mut started) = start(&shutdown).await;
+
+        let _in_flight = sign_up(client);
+        started.recv().await;
+        shutdown.trigger();
+
+        assert!(!serve_for(&mut server, Duration::from_millis(1))
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn should_report_server_not_serving() {
+        let (mut health_reporter, health_service) = tonic_health::server::health_reporter();
+        health_reporter
+            .set_serving::<AuthenticationServer<AuthenticationService>>()
+            .await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(
+            Server::builder()
+                .add_service(health_service)
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        let channel = tonic::transport::Endpoint::from_shared(format!("http://127.0.0.1:{}", port))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let mut client = HealthClient::new(channel);
+
+        stop_serving(&mut health_reporter).await;
+
+        for service in ["", "authentication.Authentication"] {
+            let response = client
+                .check(HealthCheckRequest {
+                    service: service.to_string(),
+                })
+                .await
+                .unwrap();
+            assert_eq!(
+                response.into_inner().status(),
+                health_check_response::ServingStatus::NotServing
+            );
+        }
+    }
+}
@@ -0,0 +1,411 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tokio_rustls::{
+    rustls::{
+        crypto::ring::default_provider,
+        pki_types::{CertificateDer, PrivateKeyDer},
+        server::WebPkiClientVerifier,
+        RootCertStore, ServerConfig,
+    },
+    server::TlsStream,
+    TlsAcceptor,
+};
+use tokio_stream::wrappers::ReceiverStream;
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const INCOMING_BUFFER: usize = 64;
+
+/// Paths to the PEM files used to serve TLS, with an optional client CA
+/// that turns on mutual TLS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    pub client_ca_path: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    fn paths(&self) -> Vec<&Path> {
+        let mut paths = vec![self.cert_path.as_path(), self.key_path.as_path()];
+        if let Some(client_ca_path) = &self.client_ca_path {
+            paths.push(client_ca_path);
+        }
+        paths
+    }
+
+    fn load(&self) -> Result<ServerConfig, String> {
+        let certs = load_certs(&self.cert_path)?;
+        let key = load_key(&self.key_path)?;
+        let provider = Arc::new(default_provider());
+
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| format!("Invalid TLS protocol versions: {}", e))?;
+
+        let builder = match &self.client_ca_path {
+            Some(client_ca_path) => {
+                let mut roots = RootCertStore::empty();
+                for cert in load_certs(client_ca_path)? {
+                    roots
+                        .add(cert)
+                        .map_err(|e| format!("Invalid client CA certificate: {}", e))?;
+                }
+                let verifier = WebPkiClientVerifier::builder_with_provider(roots.into(), provider)
+                    .build()
+                    .map_err(|e| format!("Invalid client CA: {}", e))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        let mut config = builder
+            .with_single_cert(certs, key)
+            .map_err(|e| format!("Invalid certificate or key: {}", e))?;
+        // gRPC needs HTTP/2, gRPC-Web clients come in over HTTP/1.1
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        Ok(config)
+    }
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+    let pem = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let certs = rustls_pemfile::certs(&mut pem.as_slice())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+
+    if certs.is_empty() {
+        return Err(format!("No certificates found in {}", path.display()));
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>, String> {
+    let pem = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    rustls_pemfile::private_key(&mut pem.as_slice())
+        .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?
+        .ok_or_else(|| format!("No private key found in {}", path.display()))
+}
+
+fn modified_times(paths: &[&Path]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// TLS acceptor whose certificates are reloaded when the files change on
+/// disk. Connections already established keep the configuration they were
+/// accepted with.
+pub struct ReloadingTlsAcceptor {
+    config: TlsConfig,
+    current: RwLock<Arc<ServerConfig>>,
+    modified: Mutex<Vec<Option<SystemTime>>>,
+}
+
+impl ReloadingTlsAcceptor {
+    pub fn new(config: TlsConfig) -> Result<Arc<Self>, String> {
+        let modified = modified_times(&config.paths());
+        let current = config.load()?;
+
+        Ok(Arc::new(Self {
+            config,
+            current: RwLock::new(Arc::new(current)),
+            modified: Mutex::new(modified),
+        }))
+    }
+
+    pub fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.current.read().unwrap().clone())
+    }
+
+    /// Reloads the certificates if any of the files changed since the last
+    /// load. A failed reload keeps serving the previous certificates.
+    pub fn reload_if_changed(&self) -> Result<bool, String> {
+        let modified = modified_times(&self.config.paths());
+
+        let mut last_modified = self.modified.lock().unwrap();
+        if *last_modified == modified {
+            return Ok(false);
+        }
+
+        // Only remembered once loaded, so that a broken pair is tried again
+        // when fixed, even within the resolution of modification times
+        let config = self.config.load()?;
+        *self.current.write().unwrap() = Arc::new(config);
+        *last_modified = modified;
+        Ok(true)
+    }
+
+    pub fn watch(self: &Arc<Self>, interval: Duration) -> tokio::task::JoinHandle<()> {
+        let acceptor = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                match acceptor.reload_if_changed() {
+                    Ok(true) => tracing::info!("reloaded TLS certificates"),
+                    Ok(false) => {}
+                    Err(e) => tracing::error!(error = %e, "failed to reload TLS certificates"),
+                }
+            }
+        })
+    }
+}
+
+/// Accepts TCP connections and performs the TLS handshake off the accept
+/// loop, yielding only the connections that completed it.
+pub fn incoming(
+    listener: TcpListener,
+    acceptor: Arc<ReloadingTlsAcceptor>,
+) -> ReceiverStream<Result<TlsStream<TcpStream>, io::Error>> {
+    let (tx, rx) = mpsc::channel(INCOMING_BUFFER);
+
+    tokio::spawn(async move {
+        loop {
+            // Stop listening as soon as the server stops consuming connections
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = tx.closed() => return,
+            };
+            let (stream, _) = match accepted {
+                Ok(conn) => conn,
+                Err(e) => {
+                    if tx.send(Err(e)).await.is_err() {
+                        return;
+                    }
+                    continue;
+                }
+            };
+
+            let tls = acceptor.acceptor();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, tls.accept(stream)).await {
+                    Ok(Ok(stream)) => {
+                        let _ = tx.send(Ok(stream)).await;
+                    }
+                    Ok(Err(e)) => tracing::debug!(error = %e, "TLS handshake failed"),
+                    Err(_) => tracing::debug!("TLS handshake timed out"),
+                }
+            });
+        }
+    });
+
+    ReceiverStream::new(rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::{
+        authentication::{authentication_client::AuthenticationClient, SignOutRequest},
+        AuthenticationServer, AuthenticationService, Server,
+    };
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity};
+
+    struct Pki {
+        ca_cert: String,
+        ca: rcgen::Certificate,
+        ca_key: KeyPair,
+    }
+
+    impl Pki {
+        fn new() -> Self {
+            let ca_key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(vec![]).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca = params.self_signed(&ca_key).unwrap();
+
+            Self {
+                ca_cert: ca.pem(),
+                ca,
+                ca_key,
+            }
+        }
+
+        fn issue(&self, name: &str) -> (String, String) {
+            let key = KeyPair::generate().unwrap();
+            let cert = CertificateParams::new(vec![name.to_string()])
+                .unwrap()
+                .signed_by(&key, &self.ca, &self.ca_key)
+                .unwrap();
+            (cert.pem(), key.serialize_pem())
+        }
+    }
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let path = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn write(&self, name: &str, contents: &str) -> PathBuf {
+            let path = self.0.join(name);
+            fs::write(&path, contents).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn server_config(dir: &TempDir, pki: &Pki, client_ca: Option<&Pki>) -> TlsConfig {
+        let (cert, key) = pki.issue("localhost");
+        TlsConfig {
+            cert_path: dir.write("server.pem", &cert),
+            key_path: dir.write("server.key", &key),
+            client_ca_path: client_ca.map(|ca| dir.write("client-ca.pem", &ca.ca_cert)),
+        }
+    }
+
+    async fn serve(acceptor: Arc<ReloadingTlsAcceptor>) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(
+            Server::builder()
+                .add_service(AuthenticationServer::new(AuthenticationService::fast()))
+                .serve_with_incoming(incoming(listener, acceptor)),
+        );
+        port
+    }
+
+    async fn connect(port: u16, tls: ClientTlsConfig) -> Result<Channel, tonic::transport::Error> {
+        Channel::from_shared(format!("https://localhost:{}", port))
+            .unwrap()
+            .tls_config(tls.domain_name("localhost"))?
+            .connect()
+            .await
+    }
+
+    async fn sign_out(channel: Channel) -> Result<(), tonic::Status> {
+        AuthenticationClient::new(channel)
+            .sign_out(SignOutRequest {
+                session_token: "session_token".to_string(),
+            })
+            .await
+            .map(|_| ())
+    }
+
+    #[test]
+    fn should_fail_to_load_missing_files() {
+        let config = TlsConfig {
+            cert_path: "does-not-exist.pem".into(),
+            key_path: "does-not-exist.key".into(),
+            client_ca_path: None,
+        };
+
+        assert!(ReloadingTlsAcceptor::new(config).is_err());
+    }
+
+    #[tokio::test]
+    async fn should_serve_over_tls() {
+        let dir = TempDir::new();
+        let pki = Pki::new();
+        let acceptor = ReloadingTlsAcceptor::new(server_config(&dir, &pki, None)).unwrap();
+        let port = serve(acceptor).await;
+
+        let tls = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(&pki.ca_cert));
+        let channel = connect(port, tls).await.unwrap();
+
+        assert!(sign_out(channel).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn should_require_client_certificate_with_mutual_tls() {
+        let dir = TempDir::new();
+        let pki = Pki::new();
+        let client_pki = Pki::new();
+        let acceptor =
+            ReloadingTlsAcceptor::new(server_config(&dir, &pki, Some(&client_pki))).unwrap();
+        let port = serve(acceptor).await;
+
+        let tls = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(&pki.ca_cert));
+        let without_identity = match connect(port, tls.clone()).await {
+            Ok(channel) => sign_out(channel).await.is_ok(),
+            Err(_) => false,
+        };
+        assert!(!without_identity);
+
+        let (cert, key) = client_pki.issue("client");
+        let channel = connect(port, tls.identity(Identity::from_pem(cert, key)))
+            .await
+            .unwrap();
+        assert!(sign_out(channel).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn should_reload_certificates_when_files_change() {
+        let dir = TempDir::new();
+        let old_pki = Pki::new();
+        let config = server_config(&dir, &old_pki, None);
+        let acceptor = ReloadingTlsAcceptor::new(config.clone()).unwrap();
+        let port = serve(acceptor.clone()).await;
+
+        assert!(!acceptor.reload_if_changed().unwrap());
+
+        let new_pki = Pki::new();
+        let (cert, key) = new_pki.issue("localhost");
+        // Make sure the modification time moves even on coarse filesystems
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        fs::write(&config.cert_path, cert).unwrap();
+        fs::write(&config.key_path, key).unwrap();
+
+        assert!(acceptor.reload_if_changed().unwrap());
+
+        let tls = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(&new_pki.ca_cert));
+        let channel = connect(port, tls).await.unwrap();
+        assert!(sign_out(channel).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn should_retry_failed_reload_until_fixed() {
+        let dir = TempDir::new();
+        let old_pki = Pki::new();
+        let config = server_config(&dir, &old_pki, None);
+        let acceptor = ReloadingTlsAcceptor::new(config.clone()).unwrap();
+        let port = serve(acceptor.clone()).await;
+
+        let new_pki = Pki::new();
+        let (cert, key) = new_pki.issue("localhost");
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        fs::write(&config.cert_path, cert).unwrap();
+        fs::write(&config.key_path, "not a key").unwrap();
+        let broken_at = fs::metadata(&config.key_path).unwrap().modified().unwrap();
+
+        assert!(acceptor.reload_if_changed().is_err());
+        assert!(acceptor.reload_if_changed().is_err());
+
+        // Fixed without the modification time moving
+        fs::write(&config.key_path, key).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&config.key_path)
+            .unwrap()
+            .set_modified(broken_at)
+            .unwrap();
+
+        assert!(acceptor.reload_if_changed().unwrap());
+
+        let tls = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(&new_pki.ca_cert));
+        let channel = connect(port, tls).await.unwrap();
+        assert!(sign_out(channel).await.is_ok());
+    }
+}
@@ -1,19 +1,57 @@
+use tracing::instrument;
+
+use crate::{events, metrics, profiles::Profile};
+
 use pbkdf2::{
     password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
-    Pbkdf2,
+    Params, Pbkdf2,
 };
 
+// Re-exporting
+pub use pbkdf2::Params as HashingParams;
+
 pub trait Users {
     fn create_user(&mut self, username: &str, password: &str) -> Result<&User, String>;
+    /// Creates a user for a machine client, which has no password and
+    /// authenticates with API keys instead.
+    fn create_service_account(&mut self, username: &str) -> Result<&User, String>;
     fn find_user_id(&self, username: &str, password: &str) -> Option<String>;
+    fn get_user(&self, username: &str) -> Option<&User>;
+    fn get_user_by_id(&self, user_id: &str) -> Option<&User>;
+    /// Up to `limit` users whose username starts with `prefix`, ordered by
+    /// username and starting after `after` when given.
+    fn list_users(&self, prefix: &str, after: Option<&str>, limit: usize) -> Vec<&User>;
+    fn set_disabled(&mut self, username: &str, disabled: bool) -> Result<&User, String>;
+    /// Replaces the profile of a user, which is assumed valid. A new email
+    /// address is not verified.
+    fn update_profile(&mut self, user_id: &str, profile: Profile) -> Result<&User, String>;
+    fn set_email_verified(&mut self, user_id: &str) -> Result<&User, String>;
+    fn set_password(&mut self, user_id: &str, password: &str) -> Result<&User, String>;
+    /// Schedules the deletion of a user at `due_at_ms`, or cancels it.
+    fn set_deletion_due(&mut self, user_id: &str, due_at_ms: Option<u64>) -> Result<&User, String>;
+    /// The users whose scheduled deletion is due at `now_ms`.
+    fn deletions_due(&self, now_ms: u64) -> Vec<&User>;
     fn delete_user(&mut self, username: &str) -> Result<(), String>;
+    fn count(&self) -> usize;
+
+    /// Persists pending changes, a no-op for stores that write through.
+    fn flush(&mut self) -> Result<(), String> {
+        Ok(())
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct User {
     username: String,
     password: String,
     uuid: String,
+    disabled: bool,
+    service_account: bool,
+    profile: Profile,
+    email_verified: bool,
+    created_at_ms: u64,
+    updated_at_ms: u64,
+    deletion_due_at_ms: Option<u64>,
 }
 
 impl User {
@@ -24,61 +62,148 @@ impl User {
     pub fn password(&self) -> &str {
         &self.password
     }
+
+    pub fn id(&self) -> &str {
+        &self.uuid
+    }
+
+    /// Disabled users cannot sign in.
+    pub fn is_disabled(&self) -> bool {
+        self.disabled
+    }
+
+    pub fn is_service_account(&self) -> bool {
+        self.service_account
+    }
+
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    pub fn is_email_verified(&self) -> bool {
+        self.email_verified
+    }
+
+    /// Milliseconds since the Unix epoch.
+    pub fn created_at_ms(&self) -> u64 {
+        self.created_at_ms
+    }
+
+    /// Milliseconds since the Unix epoch of the last profile update.
+    pub fn updated_at_ms(&self) -> u64 {
+        self.updated_at_ms
+    }
+
+    /// Milliseconds since the Unix epoch at which the account is deleted,
+    /// when its deletion is scheduled.
+    pub fn deletion_due_at_ms(&self) -> Option<u64> {
+        self.deletion_due_at_ms
+    }
 }
 
 #[derive(Debug, Default)]
 pub struct UsersTransient {
     users: Vec<User>,
+    hashing: Params,
 }
 
 impl UsersTransient {
     pub fn new() -> UsersTransient {
-        UsersTransient { users: Vec::new() }
+        UsersTransient::with_hashing(Params::default())
+    }
+
+    pub fn with_hashing(hashing: Params) -> UsersTransient {
+        UsersTransient {
+            users: Vec::new(),
+            hashing,
+        }
+    }
+
+    /// Hashing with few rounds, so that tests do not take minutes.
+    #[cfg(test)]
+    pub fn fast() -> UsersTransient {
+        UsersTransient::with_hashing(Params {
+            rounds: 1_000,
+            output_length: 32,
+        })
     }
 
     fn find_user_by_username(&self, username: &str) -> Option<&User> {
         self.users.iter().find(|user| user.username == username)
     }
 
-    fn hash_password<T: Into<String>>(password: T) -> Result<String, String> {
+    fn hash_password<T: Into<String>>(&self, password: T) -> Result<String, String> {
         let salt = SaltString::generate(&mut OsRng);
-        let hashed_password = Pbkdf2
-            .hash_password(password.into().as_bytes(), &salt)
-            .map_err(|e| format!("Failed to hash password: {}", e))?
-            .to_string();
+        let hashed_password = metrics::time_password_hash(|| {
+            Pbkdf2.hash_password_customized(
+                password.into().as_bytes(),
+                None,
+                None,
+                self.hashing,
+                &salt,
+            )
+        })
+        .map_err(|e| format!("Failed to hash password: {}", e))?
+        .to_string();
         Ok(hashed_password)
     }
 
+    fn push_user(
+        &mut self,
+        username: &str,
+        password: String,
+        service_account: bool,
+    ) -> Result<&User, String> {
+        if self.find_user_by_username(username).is_some() {
+            return Err("Username already exists".into());
+        }
+
+        let now = events::now_ms();
+        self.users.push(User {
+            username: username.into(),
+            password,
+            uuid: uuid::Uuid::new_v4().to_string(),
+            disabled: false,
+            service_account,
+            profile: Profile::default(),
+            email_verified: false,
+            created_at_ms: now,
+            updated_at_ms: now,
+            deletion_due_at_ms: None,
+        });
+        Ok(self.users.last().unwrap())
+    }
+
     fn verify_password(password: String, user: &User) -> Result<(), String> {
         let parsed_hash =
-            PasswordHash::new(&user.password()).map_err(|_| format!("Error hashing password"))?;
+            PasswordHash::new(user.password()).map_err(|_| "Error hashing password".to_string())?;
 
-        return Pbkdf2
-            .verify_password(password.as_bytes(), &parsed_hash)
-            .map_err(|e| e.to_string());
+        metrics::time_password_hash(|| Pbkdf2.verify_password(password.as_bytes(), &parsed_hash))
+            .map_err(|e| e.to_string())
     }
 }
 
 impl Users for UsersTransient {
+    #[instrument(skip(self, password), fields(storage.backend = "InMemory"))]
     fn create_user(&mut self, username: &str, password: &str) -> Result<&User, String> {
-        if self.find_user_by_username(&username).is_some() {
+        if self.find_user_by_username(username).is_some() {
             return Err("Username already exists".into());
         }
 
-        let hashed_password = Self::hash_password(password)?;
+        let hashed_password = self.hash_password(password)?;
 
-        let user = User {
-            username: username.into(),
-            password: hashed_password,
-            uuid: uuid::Uuid::new_v4().to_string(),
-        };
+        self.push_user(username, hashed_password, false)
+    }
 
-        self.users.push(user);
-        Ok(self.users.last().unwrap())
+    #[instrument(skip(self), fields(storage.backend = "InMemory"))]
+    fn create_service_account(&mut self, username: &str) -> Result<&User, String> {
+        // An empty hash matches no password
+        self.push_user(username, String::new(), true)
     }
 
+    #[instrument(skip(self, password), fields(storage.backend = "InMemory"))]
     fn find_user_id(&self, username: &str, password: &str) -> Option<String> {
-        let user = self.find_user_by_username(&username)?;
+        let user = self.find_user_by_username(username)?;
 
         if Self::verify_password(password.into(), user).is_ok() {
             return Some(user.uuid.clone());
@@ -86,17 +211,111 @@ impl Users for UsersTransient {
         None
     }
 
+    fn get_user(&self, username: &str) -> Option<&User> {
+        self.find_user_by_username(username)
+    }
+
+    fn get_user_by_id(&self, user_id: &str) -> Option<&User> {
+        self.users.iter().find(|user| user.uuid == user_id)
+    }
+
+    #[instrument(skip(self), fields(storage.backend = "InMemory"))]
+    fn list_users(&self, prefix: &str, after: Option<&str>, limit: usize) -> Vec<&User> {
+        let mut users: Vec<&User> = self
+            .users
+            .iter()
+            .filter(|user| user.username.starts_with(prefix))
+            .filter(|user| after.is_none_or(|after| user.username.as_str() > after))
+            .collect();
+        users.sort_by(|a, b| a.username.cmp(&b.username));
+        users.truncate(limit);
+        users
+    }
+
+    #[instrument(skip(self), fields(storage.backend = "InMemory"))]
+    fn set_disabled(&mut self, username: &str, disabled: bool) -> Result<&User, String> {
+        let user = self
+            .users
+            .iter_mut()
+            .find(|user| user.username == username)
+            .ok_or("User not found")?;
+        user.disabled = disabled;
+        Ok(user)
+    }
+
+    #[instrument(skip(self, profile), fields(storage.backend = "InMemory"))]
+    fn update_profile(&mut self, user_id: &str, profile: Profile) -> Result<&User, String> {
+        let user = self
+            .users
+            .iter_mut()
+            .find(|user| user.uuid == user_id)
+            .ok_or("User not found")?;
+        if user.profile.email != profile.email {
+            user.email_verified = false;
+        }
+        user.profile = profile;
+        user.updated_at_ms = events::now_ms();
+        Ok(user)
+    }
+
+    #[instrument(skip(self), fields(storage.backend = "InMemory"))]
+    fn set_email_verified(&mut self, user_id: &str) -> Result<&User, String> {
+        let user = self
+            .users
+            .iter_mut()
+            .find(|user| user.uuid == user_id)
+            .ok_or("User not found")?;
+        user.email_verified = true;
+        Ok(user)
+    }
+
+    #[instrument(skip(self, password), fields(storage.backend = "InMemory"))]
+    fn set_password(&mut self, user_id: &str, password: &str) -> Result<&User, String> {
+        let hashed_password = self.hash_password(password)?;
+        let user = self
+            .users
+            .iter_mut()
+            .find(|user| user.uuid == user_id && !user.service_account)
+            .ok_or("User not found")?;
+        user.password = hashed_password;
+        user.updated_at_ms = events::now_ms();
+        Ok(user)
+    }
+
+    #[instrument(skip(self), fields(storage.backend = "InMemory"))]
+    fn set_deletion_due(&mut self, user_id: &str, due_at_ms: Option<u64>) -> Result<&User, String> {
+        let user = self
+            .users
+            .iter_mut()
+            .find(|user| user.uuid == user_id)
+            .ok_or("User not found")?;
+        user.deletion_due_at_ms = due_at_ms;
+        Ok(user)
+    }
+
+    fn deletions_due(&self, now_ms: u64) -> Vec<&User> {
+        self.users
+            .iter()
+            .filter(|user| user.deletion_due_at_ms.is_some_and(|due| due <= now_ms))
+            .collect()
+    }
+
+    #[instrument(skip(self), fields(storage.backend = "InMemory"))]
     fn delete_user(&mut self, username: &str) -> Result<(), String> {
         let index = self
             .users
             .iter()
             .position(|user| user.username() == username)
-            .ok_or_else(|| "User not found")?;
+            .ok_or("User not found")?;
 
         self.users.remove(index);
 
         Ok(())
     }
+
+    fn count(&self) -> usize {
+        self.users.len()
+    }
 }
 
 #[cfg(test)]
@@ -105,7 +324,7 @@ mod tests {
 
     #[test]
     fn should_create_user() {
-        let mut users = UsersTransient::new();
+        let mut users = UsersTransient::fast();
 
         let user = users.create_user("username", "password");
 
@@ -128,7 +347,7 @@ mod tests {
 
     #[test]
     fn different_users_should_have_different_ids() {
-        let mut users = UsersTransient::new();
+        let mut users = UsersTransient::fast();
 
         users
             .create_user("John", "1234")
@@ -145,7 +364,7 @@ mod tests {
 
     #[test]
     fn should_cannot_create_two_users_with_same_username() {
-        let mut users = UsersTransient::new();
+        let mut users = UsersTransient::fast();
 
         users
             .create_user("John", "1234")
@@ -158,7 +377,7 @@ mod tests {
 
     #[test]
     fn should_fail_to_retreive_user_id_with_incorrect_password() {
-        let mut users = UsersTransient::new();
+        let mut users = UsersTransient::fast();
 
         let username = "username";
         let password = "password";
@@ -172,7 +391,7 @@ mod tests {
 
     #[test]
     fn should_delete_user() {
-        let mut users = UsersTransient::new();
+        let mut users = UsersTransient::fast();
 
         let username = "username";
         let password = "password";
@@ -190,8 +409,155 @@ mod tests {
 
     #[test]
     fn should_fail_to_delete_non_existing_user() {
-        let mut users = UsersTransient::new();
+        let mut users = UsersTransient::fast();
 
         assert!(users.delete_user("username").is_err());
     }
+
+    #[test]
+    fn should_list_users_by_prefix_in_pages() {
+        let mut users = UsersTransient::fast();
+        for username in ["carol", "alice", "bob", "alina"] {
+            users.create_user(username, "password").unwrap();
+        }
+
+        let usernames = |users: Vec<&User>| -> Vec<String> {
+            users
+                .iter()
+                .map(|user| user.username().to_string())
+                .collect()
+        };
+
+        assert_eq!(usernames(users.list_users("", None, 2)), ["alice", "alina"]);
+        assert_eq!(
+            usernames(users.list_users("", Some("alina"), 2)),
+            ["bob", "carol"]
+        );
+        assert_eq!(
+            usernames(users.list_users("al", None, 10)),
+            ["alice", "alina"]
+        );
+    }
+
+    #[test]
+    fn should_disable_user() {
+        let mut users = UsersTransient::fast();
+        users.create_user("username", "password").unwrap();
+
+        users.set_disabled("username", true).unwrap();
+
+        assert!(users.get_user("username").unwrap().is_disabled());
+        assert!(users.set_disabled("nobody", true).is_err());
+    }
+
+    #[test]
+    fn should_get_user_by_id() {
+        let mut users = UsersTransient::fast();
+        let user_id = users
+            .create_user("username", "password")
+            .unwrap()
+            .id()
+            .to_string();
+
+        assert_eq!(
+            users.get_user_by_id(&user_id).unwrap().username(),
+            "username"
+        );
+        assert!(users.get_user_by_id("does-not-exist").is_none());
+    }
+
+    #[test]
+    fn service_account_should_have_no_password() {
+        let mut users = UsersTransient::fast();
+
+        let user = users.create_service_account("ci-bot").unwrap();
+
+        assert!(user.is_service_account());
+        assert!(users.find_user_id("ci-bot", "").is_none());
+        assert!(users.create_service_account("ci-bot").is_err());
+    }
+
+    #[test]
+    fn should_set_password() {
+        let mut users = UsersTransient::fast();
+        let user_id = users
+            .create_user("username", "password")
+            .unwrap()
+            .id()
+            .to_string();
+
+        users.set_password(&user_id, "new-password").unwrap();
+
+        assert!(users.find_user_id("username", "password").is_none());
+        assert_eq!(
+            users.find_user_id("username", "new-password"),
+            Some(user_id)
+        );
+    }
+
+    #[test]
+    fn should_list_due_deletions() {
+        let mut users = UsersTransient::fast();
+        let user_id = users
+            .create_user("username", "password")
+            .unwrap()
+            .id()
+            .to_string();
+        users.create_user("other", "password").unwrap();
+
+        users.set_deletion_due(&user_id, Some(1_000)).unwrap();
+
+        assert!(users.deletions_due(999).is_empty());
+        let due = users.deletions_due(1_000);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].id(), user_id);
+        users.set_deletion_due(&user_id, None).unwrap();
+        assert!(users.deletions_due(1_000).is_empty());
+    }
+
+    #[test]
+    fn should_update_profile() {
+        let mut users = UsersTransient::fast();
+        let user_id = users
+            .create_user("username", "password")
+            .unwrap()
+            .id()
+            .to_string();
+        let profile = Profile {
+            display_name: "Jane".to_string(),
+            ..Default::default()
+        };
+
+        let user = users.update_profile(&user_id, profile.clone()).unwrap();
+
+        assert_eq!(user.profile(), &profile);
+        assert!(user.updated_at_ms() >= user.created_at_ms());
+        users.set_email_verified(&user_id).unwrap();
+        let changed_email = Profile {
+            email: "jane@example.com".to_string(),
+            ..profile.clone()
+        };
+        assert!(!users
+            .update_profile(&user_id, changed_email)
+            .unwrap()
+            .is_email_verified());
+        assert!(users.update_profile("does-not-exist", profile).is_err());
+    }
+
+    #[test]
+    fn should_hash_with_configured_params() {
+        let mut users = UsersTransient::with_hashing(Params {
+            rounds: 1_000,
+            output_length: 16,
+        });
+
+        let user = users
+            .create_user("username", "password")
+            .expect("A user should be created");
+
+        let hash = PasswordHash::new(user.password()).unwrap();
+        assert_eq!(hash.params.get_decimal("i"), Some(1_000));
+        assert_eq!(hash.hash.unwrap().len(), 16);
+        assert!(users.find_user_id("username", "password").is_some());
+    }
 }
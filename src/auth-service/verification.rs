@@ -0,0 +1,202 @@
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use rand_core::{OsRng, RngCore};
+use sha2::Sha256;
+
+use crate::{
+    config::{EmailVerificationConfig, MailerConfig},
+    mailer::{self, Email, Mailer},
+};
+
+/// Issues and checks the tokens proving that a user received an email.
+///
+/// A token is `<user id>.<expiry in ms since the Unix epoch>.<signature>`,
+/// the signature covering the email address too, so that changing the
+/// address voids the tokens sent to the previous one.
+pub struct EmailVerifier {
+    secret: Vec<u8>,
+    lifetime: Duration,
+}
+
+impl EmailVerifier {
+    pub fn new(secret: &[u8], lifetime: Duration) -> Self {
+        Self {
+            secret: secret.to_vec(),
+            lifetime,
+        }
+    }
+
+    /// With a random secret, voiding the tokens on restart.
+    pub fn with_random_secret(lifetime: Duration) -> Self {
+        let mut secret = [0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+        Self::new(&secret, lifetime)
+    }
+
+    fn mac(&self, user_id: &str, email: &str, expires_at_ms: u64) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts any key length");
+        mac.update(user_id.as_bytes());
+        mac.update(b".");
+        mac.update(email.as_bytes());
+        mac.update(b".");
+        mac.update(expires_at_ms.to_string().as_bytes());
+        mac
+    }
+
+    pub fn issue(&self, user_id: &str, email: &str, now_ms: u64) -> String {
+        let expires_at_ms = now_ms + self.lifetime.as_millis() as u64;
+        let signature: String = self
+            .mac(user_id, email, expires_at_ms)
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        format!("{}.{}.{}", user_id, expires_at_ms, signature)
+    }
+
+    /// The user the token was issued to, which is not proven until
+    /// [`EmailVerifier::verify`] succeeds.
+    pub fn user_id(token: &str) -> Option<&str> {
+        token
+            .split('.')
+            .next()
+            .filter(|user_id| !user_id.is_empty())
+    }
+
+    /// Checks that the token was issued for `email` and has not expired.
+    pub fn verify(&self, token: &str, email: &str, now_ms: u64) -> Result<(), String> {
+        let invalid = || "Invalid verification token".to_string();
+        let mut parts = token.split('.');
+        let (Some(user_id), Some(expires_at_ms), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        let expires_at_ms: u64 = expires_at_ms.parse().map_err(|_| invalid())?;
+        let signature = decode_hex(signature).ok_or_else(invalid)?;
+
+        self.mac(user_id, email, expires_at_ms)
+            .verify_slice(&signature)
+            .map_err(|_| invalid())?;
+        if expires_at_ms <= now_ms {
+            return Err("Verification token has expired".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Verification of the email addresses of users, and how strictly it is
+/// enforced.
+pub struct EmailVerification {
+    pub verifier: EmailVerifier,
+    pub mailer: Box<dyn Mailer>,
+    /// Sign-up fails without an email address
+    pub required_on_sign_up: bool,
+    /// Sign-in fails until the email address is verified
+    pub block_sign_in: bool,
+}
+
+impl EmailVerification {
+    /// Built when enabled, which requires a mailer.
+    pub fn from_config(
+        config: &EmailVerificationConfig,
+        mailer: &MailerConfig,
+    ) -> Result<Option<Self>, String> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        let mailer =
+            mailer::from_config(mailer)?.ok_or("Email verification requires mailer.transport")?;
+        let verifier = match &config.secret {
+            Some(secret) => EmailVerifier::new(secret.as_bytes(), config.token_lifetime()),
+            None => EmailVerifier::with_random_secret(config.token_lifetime()),
+        };
+        Ok(Some(Self {
+            verifier,
+            mailer,
+            required_on_sign_up: config.required_on_sign_up,
+            block_sign_in: config.block_sign_in,
+        }))
+    }
+
+    /// Sends a new token to `email`.
+    pub fn send(&self, user_id: &str, email: &str, now_ms: u64) -> Result<(), String> {
+        let token = self.verifier.issue(user_id, email, now_ms);
+        self.mailer.send(Email {
+            to: email.to_string(),
+            subject: "Verify your email address".to_string(),
+            body: format!(
+                "Use this token to verify your email address:\n\n{}\n\nIf you did not sign up, ignore this email.",
+                token
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_000_000;
+
+    fn verifier() -> EmailVerifier {
+        EmailVerifier::new(b"secret", Duration::from_secs(60))
+    }
+
+    #[test]
+    fn should_verify_issued_token() {
+        let token = verifier().issue("1234", "jane@example.com", NOW);
+
+        assert_eq!(EmailVerifier::user_id(&token), Some("1234"));
+        assert!(verifier().verify(&token, "jane@example.com", NOW).is_ok());
+    }
+
+    #[test]
+    fn should_reject_token_for_other_email() {
+        let token = verifier().issue("1234", "jane@example.com", NOW);
+
+        assert!(verifier().verify(&token, "john@example.com", NOW).is_err());
+    }
+
+    #[test]
+    fn should_reject_expired_token() {
+        let token = verifier().issue("1234", "jane@example.com", NOW);
+
+        assert_eq!(
+            verifier()
+                .verify(&token, "jane@example.com", NOW + 60_000)
+                .unwrap_err(),
+            "Verification token has expired"
+        );
+    }
+
+    #[test]
+    fn should_reject_tampered_token() {
+        let token = verifier().issue("1234", "jane@example.com", NOW);
+        let extended = token.replacen(&(NOW + 60_000).to_string(), &(NOW * 2).to_string(), 1);
+        let other_secret = EmailVerifier::new(b"other", Duration::from_secs(60));
+
+        assert!(verifier()
+            .verify(&extended, "jane@example.com", NOW)
+            .is_err());
+        assert!(other_secret
+            .verify(&token, "jane@example.com", NOW)
+            .is_err());
+        assert!(verifier()
+            .verify("garbage", "jane@example.com", NOW)
+            .is_err());
+    }
+}
@@ -0,0 +1,123 @@
+use std::time::Duration;
+
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+// Re-exporting
+pub use tonic_web::GrpcWebLayer;
+
+const DEFAULT_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+const DEFAULT_EXPOSED_HEADERS: [&str; 3] =
+    ["grpc-status", "grpc-message", "grpc-status-details-bin"];
+const DEFAULT_ALLOW_HEADERS: [&str; 4] =
+    ["x-grpc-web", "content-type", "x-user-agent", "grpc-timeout"];
+
+/// Origins allowed to issue gRPC-Web requests from a browser.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum AllowedOrigins {
+    /// No cross-origin requests are allowed.
+    #[default]
+    None,
+    /// Any origin is allowed (`*`).
+    Any,
+    /// Only the listed origins are allowed.
+    List(Vec<String>),
+}
+
+impl AllowedOrigins {
+    /// Builds the allowed origins from a list, `*` meaning any origin.
+    pub fn from_list<I, S>(list: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let origins: Vec<String> = list
+            .into_iter()
+            .map(|origin| origin.as_ref().trim().to_owned())
+            .filter(|origin| !origin.is_empty())
+            .collect();
+
+        if origins.iter().any(|origin| origin == "*") {
+            Self::Any
+        } else if origins.is_empty() {
+            Self::None
+        } else {
+            Self::List(origins)
+        }
+    }
+}
+
+/// Checks that an allowed origin is `*` or a bare `scheme://host[:port]`, as
+/// browsers send it in the `Origin` header; anything else would never match.
+pub fn validate_origin(origin: &str) -> Result<(), String> {
+    let origin = origin.trim();
+    if origin.is_empty() || origin == "*" {
+        return Ok(());
+    }
+    let url = reqwest::Url::parse(origin).map_err(|e| format!("{}: {}", origin, e))?;
+    if !matches!(url.scheme(), "http" | "https") || url.origin().ascii_serialization() != origin {
+        return Err(format!(
+            "{} is not an origin, expected scheme://host[:port]",
+            origin
+        ));
+    }
+    Ok(())
+}
+
+/// Builds the CORS layer that lets browsers call the gRPC-Web endpoint.
+pub fn cors_layer(origins: &AllowedOrigins) -> CorsLayer {
+    let allow_origin = match origins {
+        AllowedOrigins::None => AllowOrigin::list([]),
+        AllowedOrigins::Any => AllowOrigin::any(),
+        // Checked by `validate_origin` when the configuration was loaded
+        AllowedOrigins::List(list) => {
+            AllowOrigin::list(list.iter().filter_map(|origin| origin.parse().ok()))
+        }
+    };
+
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_headers(DEFAULT_ALLOW_HEADERS.map(|h| h.parse().unwrap()))
+        .expose_headers(DEFAULT_EXPOSED_HEADERS.map(|h| h.parse().unwrap()))
+        .allow_methods([tonic::codegen::http::Method::POST])
+        .max_age(DEFAULT_MAX_AGE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_build_empty_origins() {
+        assert_eq!(AllowedOrigins::from_list([""; 0]), AllowedOrigins::None);
+        assert_eq!(AllowedOrigins::from_list([" ", ""]), AllowedOrigins::None);
+    }
+
+    #[test]
+    fn should_build_any_origin() {
+        assert_eq!(
+            AllowedOrigins::from_list(["https://a.example", "*"]),
+            AllowedOrigins::Any
+        );
+    }
+
+    #[test]
+    fn should_validate_origins() {
+        assert!(validate_origin("https://a.example").is_ok());
+        assert!(validate_origin("http://localhost:3000").is_ok());
+        assert!(validate_origin("*").is_ok());
+        assert!(validate_origin("a.example").is_err());
+        assert!(validate_origin("https://a.example/app").is_err());
+        assert!(validate_origin("ftp://a.example").is_err());
+    }
+
+    #[test]
+    fn should_build_origin_list() {
+        assert_eq!(
+            AllowedOrigins::from_list([" https://a.example", "https://b.example "]),
+            AllowedOrigins::List(vec![
+                "https://a.example".to_string(),
+                "https://b.example".to_string()
+            ])
+        );
+    }
+}
@@ -0,0 +1,743 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::sync::{mpsc, Semaphore};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    config::WebhooksConfig,
+    events::{self, EventBus, EventFilter, Published},
+};
+
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+pub const TIMESTAMP_HEADER: &str = "x-webhook-timestamp";
+pub const DELIVERY_ID_HEADER: &str = "x-webhook-id";
+
+/// Signature of a payload as sent in the `X-Webhook-Signature` header:
+/// `sha256=` followed by the hex HMAC-SHA256 of `{timestamp}.{body}` keyed
+/// with the endpoint secret.
+pub fn sign(secret: &str, timestamp: u64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    let signature: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("sha256={}", signature)
+}
+
+/// A payload on its way to an endpoint.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Delivery {
+    pub id: String,
+    /// Index of the endpoint in the configuration, whose URL is `url`
+    pub endpoint: usize,
+    pub url: String,
+    pub payload: String,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+}
+
+/// Deliveries not acknowledged yet, and the ones given up on. With a
+/// directory, each is a JSON file under `pending/` or `dead/` so deliveries
+/// survive restarts; otherwise the dead letters are only kept in memory.
+pub struct DeliveryQueue {
+    dir: Option<PathBuf>,
+    dead_letters: Mutex<Vec<Delivery>>,
+}
+
+impl DeliveryQueue {
+    pub fn in_memory() -> Self {
+        Self {
+            dir: None,
+            dead_letters: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn persistent(dir: &Path) -> Result<Self, String> {
+        for subdir in ["pending", "dead"] {
+            fs::create_dir_all(dir.join(subdir))
+                .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        Ok(Self {
+            dir: Some(dir.to_path_buf()),
+            dead_letters: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn path(dir: &Path, state: &str, id: &str) -> PathBuf {
+        dir.join(state).join(format!("{}.json", id))
+    }
+
+    /// Written to a temporary file first so a crash never leaves half of one.
+    fn write(dir: &Path, state: &str, delivery: &Delivery) -> Result<(), String> {
+        let path = Self::path(dir, state, &delivery.id);
+        let temporary = path.with_extension("tmp");
+        let contents = serde_json::to_vec(delivery).map_err(|e| e.to_string())?;
+        fs::write(&temporary, contents)
+            .and_then(|_| fs::rename(&temporary, &path))
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    fn read_all(dir: &Path, state: &str) -> Result<Vec<Delivery>, String> {
+        let entries = fs::read_dir(dir.join(state))
+            .map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+
+        let mut deliveries = Vec::new();
+        for entry in entries {
+            let path = entry.map_err(|e| e.to_string())?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+            {
+                let contents = fs::read(&path)
+                    .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+                deliveries.push(
+                    serde_json::from_slice(&contents)
+                        .map_err(|e| format!("Invalid delivery {}: {}", path.display(), e))?,
+                );
+            }
+        }
+        Ok(deliveries)
+    }
+
+    pub fn save(&self, delivery: &Delivery) -> Result<(), String> {
+        match &self.dir {
+            Some(dir) => Self::write(dir, "pending", delivery),
+            None => Ok(()),
+        }
+    }
+
+    pub fn complete(&self, delivery: &Delivery) -> Result<(), String> {
+        match &self.dir {
+            Some(dir) => fs::remove_file(Self::path(dir, "pending", &delivery.id))
+                .map_err(|e| format!("Failed to remove delivery {}: {}", delivery.id, e)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn dead_letter(&self, delivery: &Delivery) -> Result<(), String> {
+        match &self.dir {
+            Some(dir) => {
+                Self::write(dir, "dead", delivery)?;
+                self.complete(delivery)
+            }
+            None => {
+                self.dead_letters.lock().unwrap().push(delivery.clone());
+                Ok(())
+            }
+        }
+    }
+
+    /// Deliveries left over by a previous run.
+    pub fn pending(&self) -> Result<Vec<Delivery>, String> {
+        match &self.dir {
+            Some(dir) => Self::read_all(dir, "pending"),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub fn dead_letters(&self) -> Result<Vec<Delivery>, String> {
+        match &self.dir {
+            Some(dir) => Self::read_all(dir, "dead"),
+            None => Ok(self.dead_letters.lock().unwrap().clone()),
+        }
+    }
+}
+
+struct Endpoint {
+    url: String,
+    secret: String,
+    filter: EventFilter,
+}
+
+/// Whether a failed attempt is worth repeating.
+enum Failure {
+    Retry(String),
+    Permanent(String),
+}
+
+/// Posts every event to the configured endpoints, retrying with exponential
+/// backoff and dead-lettering what cannot be delivered. Deliveries to an
+/// endpoint are independent, so they may arrive out of order.
+pub struct Webhooks {
+    endpoints: Vec<Endpoint>,
+    queue: DeliveryQueue,
+    client: reqwest::Client,
+    /// Bounds the attempts in flight.
+    attempts: Semaphore,
+    /// Bounds the deliveries under way, backoffs included.
+    deliveries: Arc<Semaphore>,
+    max_pending_deliveries: usize,
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Webhooks {
+    /// `None` when no endpoint is configured.
+    pub fn from_config(config: &WebhooksConfig) -> Result<Option<Self>, String> {
+        if config.endpoints.is_empty() {
+            return Ok(None);
+        }
+
+        let endpoints = config
+            .endpoints
+            .iter()
+            .map(|endpoint| {
+                let types = endpoint
+                    .events
+                    .iter()
+                    .map(|name| {
+                        events::parse_event_type(name)
+                            .ok_or_else(|| format!("Unknown event type: {}", name))
+                    })
+                    .collect::<Result<_, _>>()?;
+                Ok(Endpoint {
+                    url: endpoint.url.clone(),
+                    secret: endpoint.secret.clone(),
+                    filter: EventFilter {
+                        types,
+                        ..EventFilter::default()
+                    },
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        let queue = match &config.queue_dir {
+            Some(dir) => DeliveryQueue::persistent(dir)?,
+            None => DeliveryQueue::in_memory(),
+        };
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()
+            .map_err(|e| format!("Failed to create webhook client: {}", e))?;
+
+        Ok(Some(Self {
+            endpoints,
+            queue,
+            client,
+            attempts: Semaphore::new(config.max_concurrent_deliveries),
+            deliveries: Arc::new(Semaphore::new(config.max_pending_deliveries)),
+            max_pending_deliveries: config.max_pending_deliveries,
+            max_attempts: config.max_attempts,
+            initial_backoff: Duration::from_millis(config.initial_backoff_ms),
+            max_backoff: Duration::from_millis(config.max_backoff_ms),
+        }))
+    }
+
+    /// Resumes the deliveries of a previous run, then delivers the events
+    /// published on `bus`. Each is handed to a worker as it is published, so
+    /// none is missed unless `max_pending_deliveries` of them are waiting.
+    pub fn start(self, bus: &EventBus) -> Result<Arc<Self>, String> {
+        let webhooks = Arc::new(self);
+
+        let dead_letters = webhooks.queue.dead_letters()?.len();
+        if dead_letters > 0 {
+            warn!(dead_letters, "some webhook deliveries were given up on");
+        }
+        let pending = webhooks.queue.pending()?;
+
+        // Publishing holds locks, so the listener must not wait on the queue
+        let (sender, receiver) = mpsc::channel(webhooks.max_pending_deliveries);
+        bus.listen(move |published| {
+            if sender.try_send(published.clone()).is_err() {
+                error!(
+                    sequence = published.sequence,
+                    "too many pending webhook deliveries, event dropped"
+                );
+            }
+        });
+        tokio::spawn(webhooks.clone().run(pending, receiver));
+
+        Ok(webhooks)
+    }
+
+    async fn run(self: Arc<Self>, pending: Vec<Delivery>, mut events: mpsc::Receiver<Published>) {
+        for delivery in pending {
+            info!(id = %delivery.id, url = %delivery.url, "resuming webhook delivery");
+            self.spawn(delivery).await;
+        }
+        while let Some(published) = events.recv().await {
+            for delivery in self.enqueue(&published) {
+                self.spawn(delivery).await;
+            }
+        }
+    }
+
+    /// Queues a delivery of `published` to each endpoint subscribed to it.
+    fn enqueue(&self, published: &Published) -> Vec<Delivery> {
+        let payload = serde_json::json!({
+            "sequence": published.sequence,
+            "timestamp_ms": published.timestamp_ms,
+            "event": published.event,
+        });
+
+        let mut deliveries = Vec::new();
+        for (index, endpoint) in self.endpoints.iter().enumerate() {
+            if !endpoint.filter.matches(&published.event) {
+                continue;
+            }
+            let delivery = Delivery {
+                id: uuid::Uuid::new_v4().to_string(),
+                endpoint: index,
+                url: endpoint.url.clone(),
+                payload: payload.to_string(),
+                attempts: 0,
+                last_error: None,
+            };
+            if let Err(e) = self.queue.save(&delivery) {
+                error!(error = %e, "failed to queue webhook delivery");
+            }
+            deliveries.push(delivery);
+        }
+        deliveries
+    }
+
+    /// Delivers in the background once fewer than `max_pending_deliveries`
+    /// are under way.
+    async fn spawn(self: &Arc<Self>, delivery: Delivery) {
+        let permit = self
+            .deliveries
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed");
+        let webhooks = self.clone();
+        tokio::spawn(async move {
+            webhooks.deliver(delivery).await;
+            drop(permit);
+        });
+    }
+
+    async fn deliver(self: Arc<Self>, mut delivery: Delivery) {
+        loop {
+            delivery.attempts += 1;
+            let result = {
+                // Not held while backing off
+                let _permit = self
+                    .attempts
+                    .acquire()
+                    .await
+                    .expect("the semaphore is never closed");
+                self.attempt(&delivery).await
+            };
+            let failure = match result {
+                Ok(()) => {
+                    debug!(id = %delivery.id, url = %delivery.url, "webhook delivered");
+                    if let Err(e) = self.queue.complete(&delivery) {
+                        error!(error = %e, "failed to complete webhook delivery");
+                    }
+                    return;
+                }
+                Err(failure) => failure,
+            };
+
+            let (reason, retry) = match failure {
+                Failure::Retry(reason) => (reason, delivery.attempts < self.max_attempts),
+                Failure::Permanent(reason) => (reason, false),
+            };
+            delivery.last_error = Some(reason.clone());
+
+            if !retry {
+                warn!(id = %delivery.id, url = %delivery.url, error = %reason, attempts = delivery.attempts, "webhook dead-lettered");
+                if let Err(e) = self.queue.dead_letter(&delivery) {
+                    error!(error = %e, "failed to dead-letter webhook delivery");
+                }
+                return;
+            }
+
+            debug!(id = %delivery.id, url = %delivery.url, error = %reason, "webhook delivery failed, retrying");
+            if let Err(e) = self.queue.save(&delivery) {
+                error!(error = %e, "failed to update webhook delivery");
+            }
+            tokio::time::sleep(self.backoff(delivery.attempts)).await;
+        }
+    }
+
+    /// Delay before the attempt following attempt number `attempts`.
+    fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+
+    async fn attempt(&self, delivery: &Delivery) -> Result<(), Failure> {
+        // The configuration may have changed since a resumed delivery
+        let endpoint = self
+            .endpoints
+            .get(delivery.endpoint)
+            .filter(|endpoint| endpoint.url == delivery.url)
+            .ok_or_else(|| Failure::Permanent("Endpoint is no longer configured".to_string()))?;
+
+        let timestamp = events::now_ms() / 1000;
+        let response = self
+            .client
+            .post(&endpoint.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(DELIVERY_ID_HEADER, &delivery.id)
+            .header(TIMESTAMP_HEADER, timestamp)
+            .header(
+                SIGNATURE_HEADER,
+                sign(&endpoint.secret, timestamp, &delivery.payload),
+            )
+            .body(delivery.payload.clone())
+            .send()
+            .await
+            .map_err(|e| Failure::Retry(e.to_string()))?;
+
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else if status.is_client_error()
+            && status != reqwest::StatusCode::REQUEST_TIMEOUT
+            && status != reqwest::StatusCode::TOO_MANY_REQUESTS
+        {
+            Err(Failure::Permanent(format!("Endpoint answered {}", status)))
+        } else {
+            Err(Failure::Retry(format!("Endpoint answered {}", status)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::WebhookEndpointConfig, events::Event};
+    use axum::{extract::State, http::HeaderMap, http::StatusCode, routing::post, Router};
+    use std::collections::VecDeque;
+
+    const SECRET: &str = "secret";
+
+    /// Stands in for a webhook receiver, answering with the scripted statuses
+    /// and then 200, after `delay`.
+    #[derive(Clone, Default)]
+    struct Receiver {
+        statuses: Arc<Mutex<VecDeque<StatusCode>>>,
+        received: Arc<Mutex<Vec<(HeaderMap, String)>>>,
+        delay: Duration,
+        /// Requests being answered, and the most there were at once.
+        in_flight: Arc<Mutex<(usize, usize)>>,
+    }
+
+    async fn receive(
+        State(receiver): State<Receiver>,
+        headers: HeaderMap,
+        body: String,
+    ) -> StatusCode {
+        {
+            let mut in_flight = receiver.in_flight.lock().unwrap();
+            in_flight.0 += 1;
+            in_flight.1 = in_flight.1.max(in_flight.0);
+        }
+        tokio::time::sleep(receiver.delay).await;
+        receiver.in_flight.lock().unwrap().0 -= 1;
+
+        receiver.received.lock().unwrap().push((headers, body));
+        receiver
+            .statuses
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or(StatusCode::OK)
+    }
+
+    impl Receiver {
+        async fn start(statuses: &[StatusCode]) -> (Self, String) {
+            Self::start_with_delay(statuses, Duration::ZERO).await
+        }
+
+        async fn start_with_delay(statuses: &[StatusCode], delay: Duration) -> (Self, String) {
+            let receiver = Self {
+                delay,
+                ..Self::default()
+            };
+            receiver.statuses.lock().unwrap().extend(statuses);
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}/hook", listener.local_addr().unwrap());
+            let router = Router::new()
+                .route("/hook", post(receive))
+                .with_state(receiver.clone());
+            tokio::spawn(async move { axum::serve(listener, router).await });
+            (receiver, url)
+        }
+
+        fn received(&self) -> Vec<(HeaderMap, String)> {
+            self.received.lock().unwrap().clone()
+        }
+    }
+
+    fn config(url: &str, events: &[&str]) -> WebhooksConfig {
+        WebhooksConfig {
+            endpoints: vec![WebhookEndpointConfig {
+                url: url.to_string(),
+                secret: SECRET.to_string(),
+                events: events.iter().map(|name| name.to_string()).collect(),
+            }],
+            max_attempts: 3,
+            initial_backoff_ms: 10,
+            max_backoff_ms: 50,
+            ..WebhooksConfig::default()
+        }
+    }
+
+    fn signed_up() -> Event {
+        Event::SignedUp {
+            user_id: "1234".to_string(),
+            username: "username".to_string(),
+        }
+    }
+
+    /// Polls `condition` for up to five seconds.
+    async fn eventually(condition: impl Fn() -> bool) {
+        for _ in 0..500 {
+            if condition() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("condition not met in time");
+    }
+
+    #[test]
+    fn should_sign_timestamp_and_body() {
+        let signature = sign("key", 1700000000, "{}");
+
+        assert!(signature.starts_with("sha256="));
+        assert_eq!(signature.len(), "sha256=".len() + 64);
+        assert_ne!(signature, sign("key", 1700000001, "{}"));
+        assert_ne!(signature, sign("other", 1700000000, "{}"));
+    }
+
+    #[test]
+    fn should_back_off_exponentially() {
+        let webhooks = Webhooks::from_config(&config("http://localhost/", &[]))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(webhooks.backoff(1), Duration::from_millis(10));
+        assert_eq!(webhooks.backoff(2), Duration::from_millis(20));
+        assert_eq!(webhooks.backoff(3), Duration::from_millis(40));
+        assert_eq!(webhooks.backoff(4), Duration::from_millis(50));
+        assert_eq!(webhooks.backoff(100), Duration::from_millis(50));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn should_deliver_signed_payload_after_retrying() {
+        let (receiver, url) = Receiver::start(&[StatusCode::SERVICE_UNAVAILABLE]).await;
+        let bus = EventBus::default();
+        let webhooks = Webhooks::from_config(&config(&url, &[]))
+            .unwrap()
+            .unwrap()
+            .start(&bus)
+            .unwrap();
+
+        bus.publish(signed_up());
+
+        eventually(|| receiver.received().len() == 2).await;
+        let (headers, body) = &receiver.received()[1];
+        let timestamp: u64 = headers[TIMESTAMP_HEADER].to_str().unwrap().parse().unwrap();
+        assert_eq!(headers[SIGNATURE_HEADER], sign(SECRET, timestamp, body));
+        let payload: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(payload["sequence"], 1);
+        assert_eq!(payload["event"]["type"], "signed_up");
+        assert_eq!(payload["event"]["user_id"], "1234");
+        assert!(webhooks.queue.dead_letters().unwrap().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn should_only_deliver_subscribed_events() {
+        let (receiver, url) = Receiver::start(&[]).await;
+        let bus = EventBus::default();
+        let _webhooks = Webhooks::from_config(&config(&url, &["signed_out"]))
+            .unwrap()
+            .unwrap()
+            .start(&bus)
+            .unwrap();
+
+        bus.publish(signed_up());
+        bus.publish(Event::SignedOut {
+            user_id: "1234".to_string(),
+        });
+
+        eventually(|| !receiver.received().is_empty()).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let received = receiver.received();
+        assert_eq!(received.len(), 1);
+        assert!(received[0].1.contains("signed_out"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn should_deliver_events_published_faster_than_buffered() {
+        let (receiver, url) = Receiver::start(&[]).await;
+        let bus = EventBus::new(2);
+        let _webhooks = Webhooks::from_config(&config(&url, &[]))
+            .unwrap()
+            .unwrap()
+            .start(&bus)
+            .unwrap();
+
+        for _ in 0..20 {
+            bus.publish(signed_up());
+        }
+
+        eventually(|| receiver.received().len() == 20).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn should_bound_concurrent_deliveries() {
+        let (receiver, url) = Receiver::start_with_delay(&[], Duration::from_millis(20)).await;
+        let mut config = config(&url, &[]);
+        config.max_concurrent_deliveries = 2;
+        let bus = EventBus::default();
+        let _webhooks = Webhooks::from_config(&config)
+            .unwrap()
+            .unwrap()
+            .start(&bus)
+            .unwrap();
+
+        for _ in 0..10 {
+            bus.publish(signed_up());
+        }
+
+        eventually(|| receiver.received().len() == 10).await;
+        assert_eq!(receiver.in_flight.lock().unwrap().1, 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn should_drop_events_past_max_pending_deliveries() {
+        let (receiver, url) = Receiver::start_with_delay(&[], Duration::from_millis(50)).await;
+        let mut config = config(&url, &[]);
+        config.max_pending_deliveries = 1;
+        let bus = EventBus::default();
+        let _webhooks = Webhooks::from_config(&config)
+            .unwrap()
+            .unwrap()
+            .start(&bus)
+            .unwrap();
+
+        for _ in 0..10 {
+            bus.publish(signed_up());
+        }
+
+        eventually(|| !receiver.received().is_empty()).await;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let received = receiver.received().len();
+        assert!((1..10).contains(&received), "{} received", received);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn should_sign_for_each_endpoint_sharing_url() {
+        let (receiver, url) = Receiver::start(&[]).await;
+        let mut config = config(&url, &[]);
+        config.endpoints.push(WebhookEndpointConfig {
+            url: url.clone(),
+            secret: "other-secret".to_string(),
+            events: vec!["signed_out".to_string()],
+        });
+        let bus = EventBus::default();
+        let _webhooks = Webhooks::from_config(&config)
+            .unwrap()
+            .unwrap()
+            .start(&bus)
+            .unwrap();
+
+        bus.publish(Event::SignedOut {
+            user_id: "1234".to_string(),
+        });
+
+        eventually(|| receiver.received().len() == 2).await;
+        let mut secrets: Vec<_> = receiver
+            .received()
+            .iter()
+            .map(|(headers, body)| {
+                let timestamp: u64 = headers[TIMESTAMP_HEADER].to_str().unwrap().parse().unwrap();
+                [SECRET, "other-secret"]
+                    .into_iter()
+                    .find(|secret| headers[SIGNATURE_HEADER] == sign(secret, timestamp, body))
+                    .unwrap()
+            })
+            .collect();
+        secrets.sort();
+        assert_eq!(secrets, ["other-secret", SECRET]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn should_dead_letter_after_max_attempts() {
+        let (receiver, url) = Receiver::start(&[StatusCode::INTERNAL_SERVER_ERROR; 5]).await;
+        let bus = EventBus::default();
+        let webhooks = Webhooks::from_config(&config(&url, &[]))
+            .unwrap()
+            .unwrap()
+            .start(&bus)
+            .unwrap();
+
+        bus.publish(signed_up());
+
+        eventually(|| !webhooks.queue.dead_letters().unwrap().is_empty()).await;
+        let dead = &webhooks.queue.dead_letters().unwrap()[0];
+        assert_eq!(dead.attempts, 3);
+        assert_eq!(receiver.received().len(), 3);
+        assert!(dead.last_error.as_ref().unwrap().contains("500"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn should_not_retry_rejected_payload() {
+        let (receiver, url) = Receiver::start(&[StatusCode::BAD_REQUEST]).await;
+        let bus = EventBus::default();
+        let webhooks = Webhooks::from_config(&config(&url, &[]))
+            .unwrap()
+            .unwrap()
+            .start(&bus)
+            .unwrap();
+
+        bus.publish(signed_up());
+
+        eventually(|| !webhooks.queue.dead_letters().unwrap().is_empty()).await;
+        assert_eq!(webhooks.queue.dead_letters().unwrap()[0].attempts, 1);
+        assert_eq!(receiver.received().len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn should_resume_persisted_deliveries() {
+        let dir = std::env::temp_dir().join(format!("webhooks-{}", uuid::Uuid::new_v4()));
+        let (receiver, url) = Receiver::start(&[]).await;
+        let mut config = config(&url, &[]);
+        config.queue_dir = Some(dir.clone());
+        let queue = DeliveryQueue::persistent(&dir).unwrap();
+        queue
+            .save(&Delivery {
+                id: "left-over".to_string(),
+                endpoint: 0,
+                url: url.clone(),
+                payload: "{}".to_string(),
+                attempts: 1,
+                last_error: Some("connection refused".to_string()),
+            })
+            .unwrap();
+
+        let webhooks = Webhooks::from_config(&config)
+            .unwrap()
+            .unwrap()
+            .start(&EventBus::default())
+            .unwrap();
+
+        eventually(|| webhooks.queue.pending().unwrap().is_empty()).await;
+        let received = receiver.received();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].0[DELIVERY_ID_HEADER], "left-over");
+    }
+}
@@ -0,0 +1,134 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Name of the profile used when none is given.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// The sessions of the signed in profiles, kept across runs of the client.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Credentials {
+    pub profiles: BTreeMap<String, Session>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Session {
+    /// Host of the auth service the session was opened on
+    pub host: String,
+    pub username: String,
+    pub user_id: String,
+    pub session_token: String,
+    pub signed_in_at_ms: u64,
+}
+
+impl Credentials {
+    /// `auth-client/credentials.toml` in the user's configuration directory.
+    pub fn default_path() -> Result<PathBuf, String> {
+        let config_dir = env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .ok_or("Neither XDG_CONFIG_HOME nor HOME is set, pass --credentials")?;
+        Ok(config_dir.join("auth-client").join("credentials.toml"))
+    }
+
+    /// No credentials when the file does not exist yet.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                toml::from_str(&contents).map_err(|e| format!("Invalid {}: {}", path.display(), e))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+        }
+    }
+
+    /// Replaces the file, readable by the user only since it holds session
+    /// tokens.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let contents = toml::to_string_pretty(self).map_err(|e| e.to_string())?;
+        let failed = |e: io::Error| format!("Failed to write {}: {}", path.display(), e);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(failed)?;
+        }
+
+        // Written aside then renamed, so that an interrupted write does not
+        // lose the other profiles
+        let temporary = path.with_extension("toml.tmp");
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&temporary).map_err(failed)?;
+        #[cfg(unix)]
+        {
+            // The mode only applies to new files
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(fs::Permissions::from_mode(0o600))
+                .map_err(failed)?;
+        }
+        file.write_all(contents.as_bytes()).map_err(failed)?;
+        file.sync_all().map_err(failed)?;
+        fs::rename(&temporary, path).map_err(failed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(username: &str) -> Session {
+        Session {
+            host: "localhost".to_string(),
+            username: username.to_string(),
+            user_id: "u1".to_string(),
+            session_token: "token".to_string(),
+            signed_in_at_ms: 1,
+        }
+    }
+
+    #[test]
+    fn should_save_and_load_profiles() {
+        let dir = env::temp_dir().join(format!("credentials-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("credentials.toml");
+        assert_eq!(Credentials::load(&path).unwrap(), Credentials::default());
+
+        let mut credentials = Credentials::default();
+        credentials
+            .profiles
+            .insert(DEFAULT_PROFILE.to_string(), session("jane"));
+        credentials
+            .profiles
+            .insert("staging".to_string(), session("john"));
+        credentials.save(&path).unwrap();
+        let loaded = Credentials::load(&path);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded.unwrap(), credentials);
+    }
+
+    #[test]
+    fn should_reject_invalid_file() {
+        let path = env::temp_dir().join(format!("credentials-{}.toml", uuid::Uuid::new_v4()));
+        fs::write(&path, "[profiles.default]\nusername = \"jane\"\n").unwrap();
+
+        let loaded = Credentials::load(&path);
+
+        fs::remove_file(&path).unwrap();
+        assert!(loaded.unwrap_err().starts_with("Invalid"));
+    }
+}
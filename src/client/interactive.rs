@@ -0,0 +1,357 @@
+use std::{fs, path::Path};
+
+use clap::{CommandFactory, Parser, Subcommand};
+use rustyline::{
+    completion::{Completer, Pair},
+    error::ReadlineError,
+    highlight::Highlighter,
+    hint::Hinter,
+    history::DefaultHistory,
+    validate::Validator,
+    Editor, Helper,
+};
+
+use crate::{execute, output, Commands, Context};
+
+/// A line typed in the shell.
+#[derive(Parser)]
+#[command(multicall = true)]
+struct Line {
+    #[command(subcommand)]
+    command: ShellCommand,
+}
+
+#[derive(Subcommand)]
+enum ShellCommand {
+    #[command(flatten)]
+    Client(Commands),
+    /// Switch to another instance of the service, by URL or `host[:port]`
+    Connect { host: String },
+    /// Switch to another profile, and so to its session
+    UseProfile { name: String },
+    /// Leave the shell
+    #[command(alias = "quit")]
+    Exit,
+}
+
+/// Runs the commands typed until `exit` or Ctrl-D, over the connection of
+/// `context`. The history is kept next to the credentials.
+pub async fn run(context: &mut Context) -> Result<(), Box<dyn std::error::Error>> {
+    let mut editor = Editor::<Completion, DefaultHistory>::new()?;
+    let mut command = Line::command();
+    command.build();
+    editor.set_helper(Some(Completion(command)));
+
+    let history = context
+        .credentials_path
+        .as_ref()
+        .ok()
+        .map(|path| path.with_file_name("history"));
+    if let Some(path) = &history {
+        // Missing until the first session ends
+        let _ = editor.load_history(path);
+    }
+
+    loop {
+        let line = match editor.readline(&prompt(context)) {
+            Ok(line) => line,
+            // Ctrl-C only drops the line typed so far
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let Some(words) = shlex::split(&line) else {
+            eprintln!("Error: Unterminated quote");
+            continue;
+        };
+        if words.is_empty() {
+            continue;
+        }
+        if !has_secret(&words) {
+            let _ = editor.add_history_entry(line.as_str());
+        }
+
+        let command = match Line::try_parse_from(&words) {
+            Ok(line) => line.command,
+            Err(e) => {
+                // Help included
+                let _ = e.print();
+                continue;
+            }
+        };
+        let result = match command {
+            ShellCommand::Exit => break,
+            ShellCommand::Connect { host } => context.connect(&host).await,
+            ShellCommand::UseProfile { name } => {
+                context.profile = name;
+                Ok(())
+            }
+            // Ctrl-C stops the command, like a `subscribe-events`, not the
+            // shell
+            ShellCommand::Client(command) => tokio::select! {
+                result = execute(context, &command) => result,
+                _ = tokio::signal::ctrl_c() => Err("Interrupted".into()),
+            },
+        };
+        if let Err(e) = result {
+            eprintln!("Error: {}", output::describe(e.as_ref()));
+        }
+    }
+
+    if let Some(path) = &history {
+        save_history(&mut editor, path)?;
+    }
+    Ok(())
+}
+
+/// e.g. `jane@localhost:default> `, without the username when signed out.
+fn prompt(context: &Context) -> String {
+    match context.session() {
+        Some(session) => format!(
+            "{}@{}:{}> ",
+            session.username, context.host, context.profile
+        ),
+        None => format!("{}:{}> ", context.host, context.profile),
+    }
+}
+
+/// Flags passing passwords, tokens or keys, and their short forms.
+const SECRET_SHORT_FLAGS: [char; 3] = ['p', 's', 't'];
+const SECRET_FLAGS: [&str; 6] = [
+    "--password",
+    "--new-password",
+    "--token",
+    "--admin-token",
+    "--session-token",
+    "--key",
+];
+
+/// Whether the words pass a secret, which the history must not keep.
+fn has_secret(words: &[String]) -> bool {
+    words.iter().any(|word| {
+        let flag = word.split('=').next().unwrap_or_default();
+        let short = word
+            .strip_prefix('-')
+            .filter(|rest| !rest.starts_with('-'))
+            .and_then(|rest| rest.chars().next());
+        SECRET_FLAGS.contains(&flag) || short.is_some_and(|flag| SECRET_SHORT_FLAGS.contains(&flag))
+    })
+}
+
+fn save_history(
+    editor: &mut Editor<Completion, DefaultHistory>,
+    path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    editor.save_history(path)?;
+    // Session tokens passed to commands end up in it
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
+
+/// Completes the names of the commands and of their flags.
+struct Completion(clap::Command);
+
+impl Completer for Completion {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let line = &line[..pos];
+        let start = line.rfind(char::is_whitespace).map_or(0, |index| index + 1);
+        let pairs = candidates(&self.0, line[..start].split_whitespace(), &line[start..])
+            .into_iter()
+            .map(|candidate| Pair {
+                replacement: format!("{} ", candidate),
+                display: candidate,
+            })
+            .collect();
+        Ok((start, pairs))
+    }
+}
+
+impl Hinter for Completion {
+    type Hint = String;
+}
+
+impl Highlighter for Completion {}
+
+impl Validator for Completion {}
+
+impl Helper for Completion {}
+
+/// The subcommands, or the flags when `word` starts with `-`, of the command
+/// the typed words lead to.
+fn candidates<'a>(
+    root: &clap::Command,
+    typed: impl Iterator<Item = &'a str>,
+    word: &str,
+) -> Vec<String> {
+    let mut command = root;
+    for typed in typed {
+        if let Some(subcommand) = command.find_subcommand(typed) {
+            command = subcommand;
+        }
+    }
+
+    let names: Vec<String> = if word.starts_with('-') {
+        command
+            .get_arguments()
+            .filter_map(|argument| argument.get_long())
+            .map(|long| format!("--{}", long))
+            .collect()
+    } else {
+        command
+            .get_subcommands()
+            .map(|subcommand| subcommand.get_name().to_string())
+            .collect()
+    };
+    names
+        .into_iter()
+        .filter(|name| name.starts_with(word))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn completions(line: &str) -> Vec<String> {
+        let mut command = Line::command();
+        command.build();
+        let start = line.rfind(' ').map_or(0, |index| index + 1);
+        candidates(&command, line[..start].split_whitespace(), &line[start..])
+    }
+
+    #[test]
+    fn should_complete_commands() {
+        assert_eq!(completions("sign-"), ["sign-in", "sign-up", "sign-out"]);
+        assert_eq!(completions("con"), ["connect"]);
+        assert!(completions("admin list").contains(&"list-users".to_string()));
+    }
+
+    #[test]
+    fn should_complete_flags() {
+        assert_eq!(
+            completions("sign-in --pass"),
+            ["--password", "--password-stdin"]
+        );
+        assert!(completions("admin list-users --").contains(&"--page-size".to_string()));
+    }
+
+    #[test]
+    fn should_parse_client_and_shell_commands() {
+        assert!(matches!(
+            Line::try_parse_from(["get-profile"]).unwrap().command,
+            ShellCommand::Client(Commands::GetProfile { .. })
+        ));
+        assert!(matches!(
+            Line::try_parse_from(["quit"]).unwrap().command,
+            ShellCommand::Exit
+        ));
+        assert!(Line::try_parse_from(["teleport"]).is_err());
+    }
+
+    #[test]
+    fn should_keep_passwords_out_of_history() {
+        let words = |line: &str| shlex::split(line).unwrap();
+
+        assert!(has_secret(&words("sign-in -u jane -p secret")));
+        assert!(has_secret(&words("sign-in -u jane --password=secret")));
+        assert!(has_secret(&words("change-password --new-password secret")));
+        assert!(!has_secret(&words("sign-in -u jane")));
+        assert!(!has_secret(&words("--profile staging whoami")));
+    }
+
+    #[test]
+    fn should_keep_admin_token_out_of_history() {
+        let words = |line: &str| shlex::split(line).unwrap();
+
+        assert!(has_secret(&words("admin --token secret list-users")));
+        assert!(has_secret(&words("admin --token=secret list-users")));
+        assert!(has_secret(&words("tail-audit-log --token secret")));
+    }
+
+    #[test]
+    fn should_keep_subscription_tokens_out_of_history() {
+        let words = |line: &str| shlex::split(line).unwrap();
+
+        assert!(has_secret(&words("subscribe-events --admin-token secret")));
+        assert!(has_secret(&words(
+            "subscribe-events --session-token=secret"
+        )));
+        assert!(has_secret(&words("subscribe-events -s secret")));
+        assert!(!has_secret(&words("subscribe-events --username jane")));
+    }
+
+    #[test]
+    fn should_keep_audit_key_out_of_history() {
+        let words = |line: &str| shlex::split(line).unwrap();
+
+        assert!(has_secret(&words(
+            "verify-audit-log audit.log --key secret"
+        )));
+        assert!(has_secret(&words(
+            "verify-audit-log audit.log --key=secret"
+        )));
+        assert!(!has_secret(&words("verify-audit-log audit.log --partial")));
+    }
+
+    #[tokio::test]
+    async fn should_use_session_only_on_its_host() {
+        let cli = crate::Cli::parse_from(["client"]);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let channel = cli
+            .connection
+            .connect(&address, &cli.requests)
+            .await
+            .unwrap();
+        let mut context = Context {
+            output: cli.output,
+            profile: cli.profile.clone(),
+            credentials_path: Err("No credentials file".to_string()),
+            credentials: Default::default(),
+            connection: cli.connection.clone(),
+            requests: cli.requests.clone(),
+            host: "auth-1.internal".to_string(),
+            client: crate::AuthenticationClient::new(channel.clone()),
+            channel,
+        };
+        context.credentials.profiles.insert(
+            cli.profile.clone(),
+            crate::Session {
+                host: "auth-1.internal".to_string(),
+                username: "jane".to_string(),
+                user_id: "1".to_string(),
+                session_token: "token".to_string(),
+                signed_in_at_ms: 0,
+            },
+        );
+
+        assert_eq!(prompt(&context), "jane@auth-1.internal:default> ");
+        assert_eq!(context.session_token(&None).unwrap(), "token");
+
+        context.host = "auth-2.internal".to_string();
+        assert_eq!(prompt(&context), "auth-2.internal:default> ");
+        assert_eq!(
+            context.session_token(&None).unwrap_err(),
+            "Profile default is signed in to auth-1.internal, not auth-2.internal, \
+             run sign-in or pass --session-token"
+        );
+        assert_eq!(
+            context.session_token(&Some("given".to_string())).unwrap(),
+            "given"
+        );
+    }
+}
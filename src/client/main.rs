@@ -1,76 +1,985 @@
-use std::env;
 use clap::{Parser, Subcommand};
+use common::{
+    audit::Start,
+    capture::{self, CapturedCall, Replacements},
+    connection::{ConnectionOptions, RequestOptions, Retrying},
+    logging::LogOptions,
+    telemetry::TracedChannel,
+};
+use credentials::{Credentials, Session, DEFAULT_PROFILE};
+use output::{Output, Refused};
+use password::PasswordArgs;
+use std::{
+    env,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::PathBuf,
+    process::ExitCode,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::Instrument;
+
+mod credentials;
+mod interactive;
+mod output;
+mod password;
 
 pub mod authentication {
     tonic::include_proto!("authentication");
 }
 
-use authentication::authentication_client::AuthenticationClient;
+pub mod audit {
+    tonic::include_proto!("audit");
+}
+
+pub mod admin {
+    tonic::include_proto!("admin");
+}
 
+use admin::admin_client::AdminClient;
+use audit::audit_client::AuditClient;
+use authentication::authentication_client::AuthenticationClient;
 
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, after_help = output::EXIT_CODES)]
 struct Cli {
     #[command(subcommand)]
-    command: Option<Commands>
+    command: Option<Commands>,
+    /// How responses are printed
+    #[arg(short, long, env = "AUTH_CLIENT_OUTPUT", value_enum, default_value_t = Output::Plain)]
+    output: Output,
+    /// Profile whose session is saved by sign-in and used by the other
+    /// commands, e.g. one per environment
+    #[arg(long, env = "AUTH_CLIENT_PROFILE", default_value = DEFAULT_PROFILE)]
+    profile: String,
+    /// File keeping the sessions of the profiles, by default
+    /// `auth-client/credentials.toml` in the user's configuration directory
+    #[arg(long, env = "AUTH_CLIENT_CREDENTIALS")]
+    credentials: Option<PathBuf>,
+    #[command(flatten)]
+    connection: ConnectionOptions,
+    #[command(flatten)]
+    requests: RequestOptions,
+    #[command(flatten)]
+    log: LogOptions,
 }
 
+/// Channel to the auth service, retrying while it is unavailable.
+type Channel = TracedChannel<Retrying>;
+
 #[derive(Subcommand)]
+#[allow(clippy::enum_variant_names)]
 enum Commands {
+    /// Sign in, saving the session to the profile
     SignIn {
         #[arg(short, long)]
         username: String,
-        #[arg(short, long)]
-        password: String
+        #[command(flatten)]
+        password: PasswordArgs,
     },
     SignUp {
         #[arg(short, long)]
         username: String,
+        #[command(flatten)]
+        password: PasswordArgs,
+        /// Address the verification token is emailed to
+        #[arg(short, long, default_value = "")]
+        email: String,
+    },
+    /// Verify an email address with the token emailed to it
+    VerifyEmail {
+        #[arg(short, long)]
+        token: String,
+    },
+    /// Email another verification token, without signing in
+    ResendVerification {
         #[arg(short, long)]
-        password: String
+        username: String,
+        #[command(flatten)]
+        password: PasswordArgs,
     },
+    /// Sign out, forgetting the session of the profile
     SignOut {
+        /// The session of the profile when not given
+        #[arg(short, long)]
+        session_token: Option<String>,
+    },
+    /// Print who the profile is signed in as
+    Whoami,
+    /// Delete the account of the session's user
+    DeleteAccount {
+        /// The session of the profile when not given
+        #[arg(short, long)]
+        session_token: Option<String>,
+        #[command(flatten)]
+        password: PasswordArgs,
+    },
+    /// Replace the password of the session's user
+    ChangePassword {
+        /// The session of the profile when not given
+        #[arg(short, long)]
+        session_token: Option<String>,
+        /// The current password
+        #[command(flatten)]
+        password: PasswordArgs,
+        /// Ends up in the shell history and process list, prefer the prompt
+        #[arg(long)]
+        new_password: Option<String>,
+    },
+    /// Keep an account whose deletion is scheduled
+    CancelAccountDeletion {
+        /// The session of the profile when not given
+        #[arg(short, long)]
+        session_token: Option<String>,
+    },
+    GetProfile {
+        /// The session of the profile when not given
+        #[arg(short, long)]
+        session_token: Option<String>,
+    },
+    /// Replace the profile of the session's user
+    UpdateProfile {
+        /// The session of the profile when not given
+        #[arg(short, long)]
+        session_token: Option<String>,
+        #[arg(long, default_value = "")]
+        display_name: String,
+        #[arg(long, default_value = "")]
+        email: String,
+        #[arg(long, default_value = "")]
+        locale: String,
+        /// Custom attribute as `name=value`; can be repeated
+        #[arg(long = "attribute", value_parser = parse_attribute)]
+        attributes: Vec<(String, String)>,
+    },
+    /// Manage users through the Admin service
+    Admin {
+        /// Admin token configured on the service
+        #[arg(long, env = "AUTH_ADMIN_TOKEN", hide_env_values = true)]
+        token: String,
+        #[command(subcommand)]
+        command: AdminCommands,
+    },
+    /// Print the events of the service as they happen
+    SubscribeEvents {
+        /// Only events of this type, e.g. `signed-in`; can be repeated
+        #[arg(long = "type")]
+        types: Vec<String>,
+        #[arg(long)]
+        user_id: Option<String>,
+        #[arg(long)]
+        username: Option<String>,
+        /// Replay the buffered events from this sequence number on
+        #[arg(long)]
+        from_sequence: Option<u64>,
+        /// The session of the profile when not given, whose user the events
+        /// are about
+        #[arg(short, long)]
+        session_token: Option<String>,
+        /// Admin token configured on the service, to receive the events of
+        /// every user instead
+        #[arg(long, env = "AUTH_ADMIN_TOKEN", hide_env_values = true)]
+        admin_token: Option<String>,
+    },
+    /// Print the audit log entries as the service writes them, as JSON lines
+    TailAuditLog {
+        /// Admin token configured on the service
+        #[arg(long, env = "AUTH_ADMIN_TOKEN", hide_env_values = true)]
+        token: String,
+    },
+    /// Check that an audit log has no missing or modified entries
+    VerifyAuditLog {
+        /// Audit log file, as written by the file sink or `tail-audit-log`
+        path: PathBuf,
+        /// Key of the hashes, the `audit.key` of the service
+        #[arg(long, env = "AUTH_AUDIT_KEY", hide_env_values = true)]
+        key: String,
+        /// Accept a log starting after the first entry, like the captures of
+        /// `tail-audit-log`
+        #[arg(long)]
+        partial: bool,
+    },
+    /// Run commands one after the other over the same connection
+    Interactive,
+    /// Re-issue the calls of a capture of the service, reporting the
+    /// responses that differ from the captured ones
+    Replay {
+        /// Capture file, as written by the service when `capture.path` is set
+        path: PathBuf,
+        /// Response field left out of the comparison, like those differing
+        /// between instances; can be repeated, replacing the defaults
+        #[arg(
+            long = "ignore-field",
+            default_values = ["user_id", "created_at_ms", "updated_at_ms", "deletes_at_ms"]
+        )]
+        ignored_fields: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum AdminCommands {
+    ListUsers {
+        /// Only users whose username starts with this prefix
+        #[arg(long, default_value = "")]
+        prefix: String,
+        #[arg(long, default_value_t = 0)]
+        page_size: u32,
+        /// `next_page_token` of the previous page
+        #[arg(long, default_value = "")]
+        page_token: String,
+    },
+    GetUser {
+        #[arg(short, long)]
+        username: String,
+    },
+    DisableUser {
+        #[arg(short, long)]
+        username: String,
+    },
+    EnableUser {
+        #[arg(short, long)]
+        username: String,
+    },
+    DeleteUser {
+        #[arg(short, long)]
+        username: String,
+    },
+    /// Create a user signing in with API keys only
+    CreateServiceAccount {
+        #[arg(short, long)]
+        username: String,
+    },
+    /// Create an API key for a service account, printing the key once
+    CreateApiKey {
+        #[arg(short, long)]
+        username: String,
+        #[arg(long, default_value = "")]
+        name: String,
+        /// e.g. `profile:read`; can be repeated
+        #[arg(long = "scope")]
+        scopes: Vec<String>,
+        /// Seconds until the key expires, never when 0
+        #[arg(long, default_value_t = 0)]
+        ttl_secs: u64,
+    },
+    ListApiKeys {
         #[arg(short, long)]
-        session_token: String
+        username: String,
+    },
+    RevokeApiKey {
+        #[arg(short, long)]
+        username: String,
+        #[arg(long)]
+        key_id: String,
     },
 }
 
+/// Host of the auth service from before `--endpoint`, still honoured.
 const AUTH_SERVICE_IP: &str = "AUTH_SERVICE_IP";
-const DEFAULT_AUTH_SERVICE_IP: &str = "[::0]"; 
+const DEFAULT_AUTH_SERVICE_IP: &str = "[::0]";
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let auth_ip = env::var(AUTH_SERVICE_IP).unwrap_or(DEFAULT_AUTH_SERVICE_IP.to_owned());
-    let mut client = AuthenticationClient::connect(format!("http://{}:50051", auth_ip)).await?;
-
+async fn main() -> ExitCode {
     let cli = Cli::parse();
+    let telemetry = match cli.log.init("warn", "client") {
+        Ok(telemetry) => telemetry,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let result = run(&cli).instrument(tracing::info_span!("client")).await;
+
+    telemetry.shutdown().await;
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", output::describe(e.as_ref()));
+            ExitCode::from(output::exit_code(e.as_ref()))
+        }
+    }
+}
+
+async fn run(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(command) = &cli.command else {
+        println!("No command provided");
+        return Ok(());
+    };
+    if let Commands::VerifyAuditLog { path, key, partial } = command {
+        return verify_audit_log(path, key, *partial);
+    }
+
+    let mut context = Context::new(cli).await?;
+    match command {
+        Commands::Interactive => interactive::run(&mut context).await,
+        command => execute(&mut context, command).await,
+    }
+}
+
+/// What the commands run with, kept from one command to the next by
+/// `interactive`.
+struct Context {
+    output: Output,
+    profile: String,
+    /// Missing only matters once a session is saved
+    credentials_path: Result<PathBuf, String>,
+    credentials: Credentials,
+    connection: ConnectionOptions,
+    requests: RequestOptions,
+    /// A URL or `host[:port]`
+    host: String,
+    channel: Channel,
+    client: AuthenticationClient<Channel>,
+}
+
+impl Context {
+    async fn new(cli: &Cli) -> Result<Self, Box<dyn std::error::Error>> {
+        let credentials_path = match &cli.credentials {
+            Some(path) => Ok(path.clone()),
+            None => Credentials::default_path(),
+        };
+        let credentials = match &credentials_path {
+            Ok(path) => Credentials::load(path)?,
+            Err(_) => Credentials::default(),
+        };
+
+        // The host the profile signed in to, unless told otherwise
+        let host = cli
+            .connection
+            .endpoint
+            .clone()
+            .or_else(|| {
+                credentials
+                    .profiles
+                    .get(&cli.profile)
+                    .map(|session| session.host.clone())
+            })
+            .or_else(|| env::var(AUTH_SERVICE_IP).ok())
+            .unwrap_or(DEFAULT_AUTH_SERVICE_IP.to_owned());
+        let channel = connect(&cli.connection, &cli.requests, &host).await?;
+
+        Ok(Self {
+            output: cli.output,
+            profile: cli.profile.clone(),
+            credentials_path,
+            credentials,
+            connection: cli.connection.clone(),
+            requests: cli.requests.clone(),
+            host,
+            client: AuthenticationClient::new(channel.clone()),
+            channel,
+        })
+    }
+
+    /// Switches to another host, keeping the current one when it cannot be
+    /// reached.
+    async fn connect(&mut self, host: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let channel = connect(&self.connection, &self.requests, host).await?;
+        self.client = AuthenticationClient::new(channel.clone());
+        self.channel = channel;
+        self.host = host.to_string();
+        Ok(())
+    }
+
+    /// The session of the profile, unless opened on another host than the
+    /// current one, which must not be sent its token.
+    fn session(&self) -> Option<&Session> {
+        self.credentials
+            .profiles
+            .get(&self.profile)
+            .filter(|session| session.host == self.host)
+    }
+
+    /// Why there is no `session()`.
+    fn not_signed_in(&self) -> String {
+        match self.credentials.profiles.get(&self.profile) {
+            Some(session) => format!(
+                "Profile {} is signed in to {}, not {}",
+                self.profile, session.host, self.host
+            ),
+            None => format!("Profile {} is not signed in", self.profile),
+        }
+    }
+
+    /// The session token given, or else the one of the profile.
+    fn session_token(&self, given: &Option<String>) -> Result<String, String> {
+        given
+            .clone()
+            .or_else(|| self.session().map(|session| session.session_token.clone()))
+            .ok_or_else(|| {
+                format!(
+                    "{}, run sign-in or pass --session-token",
+                    self.not_signed_in()
+                )
+            })
+    }
 
-    match &cli.command {
-        Some(Commands::SignIn { username, password }) => {
+    /// Forgets the session of the profile once it has ended, unless it is not
+    /// the one that did.
+    fn forget(&mut self, session_token: &str) -> Result<(), String> {
+        match self.session() {
+            Some(session) if session.session_token == session_token => {
+                self.credentials.profiles.remove(&self.profile);
+                self.credentials.save(self.credentials_path.as_ref()?)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the service no longer knows a session, e.g. to tell a wrong
+    /// password from an ended session once a request was refused.
+    async fn session_ended(&mut self, session_token: &str) -> Result<bool, tonic::Status> {
+        let request = tonic::Request::new(authentication::GetProfileRequest {
+            session_token: session_token.to_owned(),
+        });
+        let response = self.client.get_profile(request).await?.into_inner();
+        Ok(response.status_code != i32::from(authentication::StatusCode::Success))
+    }
+}
+
+async fn connect(
+    connection: &ConnectionOptions,
+    requests: &RequestOptions,
+    host: &str,
+) -> Result<Channel, Box<dyn std::error::Error>> {
+    tracing::debug!(%host, tls = connection.tls.enabled(), "connecting");
+    connection.connect(host, requests).await
+}
+
+async fn execute(
+    context: &mut Context,
+    command: &Commands,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        Commands::SignIn { username, password } => {
             let request = tonic::Request::new(authentication::SignInRequest {
                 username: username.to_owned(),
-                password: password.to_owned(),
+                password: password.read(false)?,
             });
-            let response = client.sign_in(request).await?;
-            println!("{:#?}", response);
-        },
-        Some(Commands::SignUp { username, password }) => {
+            let response = context.client.sign_in(request).await?.into_inner();
+            context.output.print(&response)?;
+
+            context.credentials.profiles.insert(
+                context.profile.clone(),
+                Session {
+                    host: context.host.clone(),
+                    username: username.to_owned(),
+                    user_id: response.user_id,
+                    session_token: response.session_token,
+                    signed_in_at_ms: now_ms(),
+                },
+            );
+            context
+                .credentials
+                .save(&context.credentials_path.clone()?)?;
+            eprintln!("Signed in as {} (profile {})", username, context.profile);
+        }
+        Commands::SignUp {
+            username,
+            password,
+            email,
+        } => {
             let request = tonic::Request::new(authentication::SignUpRequest {
                 username: username.to_owned(),
-                password: password.to_owned(),
+                password: password.read(true)?,
+                email: email.to_owned(),
             });
-            let response = client.sign_up(request).await?;
-            println!("{:#?}", response);
-        },
-        Some(Commands::SignOut { session_token }) => {
+            let response = context.client.sign_up(request).await?;
+            context.output.print(response.get_ref())?;
+        }
+        Commands::VerifyEmail { token } => {
+            let request = tonic::Request::new(authentication::VerifyEmailRequest {
+                token: token.to_owned(),
+            });
+            let response = context.client.verify_email(request).await?;
+            context.output.print(response.get_ref())?;
+        }
+        Commands::ResendVerification { username, password } => {
+            let request = tonic::Request::new(authentication::ResendVerificationRequest {
+                username: username.to_owned(),
+                password: password.read(false)?,
+            });
+            let response = context.client.resend_verification(request).await?;
+            context.output.print(response.get_ref())?;
+        }
+        Commands::SignOut { session_token } => {
+            let session_token = context.session_token(session_token)?;
             let request = tonic::Request::new(authentication::SignOutRequest {
-                session_token: session_token.to_owned(),
+                session_token: session_token.clone(),
+            });
+            let response = context.client.sign_out(request).await?;
+            // It only fails for sessions the service does not know, which are
+            // as good as ended
+            context.forget(&session_token)?;
+            context.output.print(response.get_ref())?;
+        }
+        Commands::Whoami => {
+            let session = context
+                .session()
+                .cloned()
+                .ok_or_else(|| format!("{}, run sign-in", context.not_signed_in()))?;
+            if context.session_ended(&session.session_token).await? {
+                return Err(Refused(format!(
+                    "The session of profile {} has ended, sign in again",
+                    context.profile
+                ))
+                .into());
+            }
+
+            context.output.print(&serde_json::json!({
+                "profile": context.profile,
+                "host": session.host,
+                "username": session.username,
+                "user_id": session.user_id,
+                "signed_in_at_ms": session.signed_in_at_ms,
+            }))?;
+        }
+        Commands::DeleteAccount {
+            session_token,
+            password,
+        } => {
+            let session_token = context.session_token(session_token)?;
+            let request = tonic::Request::new(authentication::DeleteAccountRequest {
+                session_token: session_token.clone(),
+                password: password.read(false)?,
+            });
+            let response = context.client.delete_account(request).await?;
+            if response.get_ref().status_code == i32::from(authentication::StatusCode::Success)
+                || context.session_ended(&session_token).await?
+            {
+                context.forget(&session_token)?;
+            }
+            context.output.print(response.get_ref())?;
+        }
+        Commands::ChangePassword {
+            session_token,
+            password,
+            new_password,
+        } => {
+            let request = tonic::Request::new(authentication::ChangePasswordRequest {
+                session_token: context.session_token(session_token)?,
+                password: password.read(false)?,
+                new_password: password::read_new(new_password.as_deref())?,
+            });
+            let response = context.client.change_password(request).await?;
+            context.output.print(response.get_ref())?;
+        }
+        Commands::CancelAccountDeletion { session_token } => {
+            let request = tonic::Request::new(authentication::CancelAccountDeletionRequest {
+                session_token: context.session_token(session_token)?,
+            });
+            let response = context.client.cancel_account_deletion(request).await?;
+            context.output.print(response.get_ref())?;
+        }
+        Commands::GetProfile { session_token } => {
+            let request = tonic::Request::new(authentication::GetProfileRequest {
+                session_token: context.session_token(session_token)?,
             });
-            let response = client.sign_out(request).await?;
-            println!("{:#?}", response);
-        },
-        None => println!("No command provided"),
+            let response = context.client.get_profile(request).await?;
+            context.output.print(response.get_ref())?;
+        }
+        Commands::UpdateProfile {
+            session_token,
+            display_name,
+            email,
+            locale,
+            attributes,
+        } => {
+            let request = tonic::Request::new(authentication::UpdateProfileRequest {
+                session_token: context.session_token(session_token)?,
+                profile: Some(authentication::Profile {
+                    display_name: display_name.to_owned(),
+                    email: email.to_owned(),
+                    locale: locale.to_owned(),
+                    attributes: attributes.iter().cloned().collect(),
+                    ..Default::default()
+                }),
+            });
+            let response = context.client.update_profile(request).await?;
+            context.output.print(response.get_ref())?;
+        }
+        Commands::Admin { token, command } => {
+            let mut client = AdminClient::new(context.channel.clone());
+            admin(&mut client, token, command, context.output).await?;
+        }
+        Commands::SubscribeEvents {
+            types,
+            user_id,
+            username,
+            from_sequence,
+            session_token,
+            admin_token,
+        } => {
+            let types = types
+                .iter()
+                .map(|name| {
+                    common::events::event_type_str_name(name)
+                        .and_then(|str_name| authentication::EventType::from_str_name(&str_name))
+                        .map(i32::from)
+                        .ok_or_else(|| format!("Unknown event type: {}", name))
+                })
+                .collect::<Result<_, _>>()?;
+            let mut request = authentication::SubscribeEventsRequest {
+                types,
+                user_id: user_id.clone().unwrap_or_default(),
+                username: username.clone().unwrap_or_default(),
+                from_sequence: *from_sequence,
+                session_token: String::new(),
+            };
+            let request = match admin_token {
+                Some(token) => authorized(&format!("Bearer {}", token).parse()?, request),
+                None => {
+                    request.session_token = context.session_token(session_token)?;
+                    tonic::Request::new(request)
+                }
+            };
+            let mut events = context.client.subscribe_events(request).await?.into_inner();
+            while let Some(event) = events.message().await? {
+                context.output.print(&event)?;
+            }
+        }
+        Commands::TailAuditLog { token } => {
+            let authorization = format!("Bearer {}", token).parse()?;
+            let mut client = AuditClient::new(context.channel.clone());
+            let mut entries = client
+                .tail(authorized(&authorization, audit::TailRequest {}))
+                .await?
+                .into_inner();
+            while let Some(entry) = entries.message().await? {
+                let entry = common::audit::AuditEntry {
+                    sequence: entry.sequence,
+                    timestamp_ms: entry.timestamp_ms,
+                    event: serde_json::from_str(&entry.event)?,
+                    prev_hash: entry.prev_hash,
+                    hash: entry.hash,
+                };
+                println!("{}", serde_json::to_string(&entry)?);
+            }
+        }
+        Commands::VerifyAuditLog { path, key, partial } => verify_audit_log(path, key, *partial)?,
+        Commands::Replay {
+            path,
+            ignored_fields,
+        } => replay(&mut context.client, path, ignored_fields).await?,
+        Commands::Interactive => return Err("Already interactive".into()),
+    }
+
+    Ok(())
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn parse_attribute(attribute: &str) -> Result<(String, String), String> {
+    attribute
+        .split_once('=')
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .ok_or_else(|| format!("Expected name=value, found {}", attribute))
+}
+
+fn authorized<T>(
+    authorization: &tonic::metadata::MetadataValue<tonic::metadata::Ascii>,
+    message: T,
+) -> tonic::Request<T> {
+    let mut request = tonic::Request::new(message);
+    request
+        .metadata_mut()
+        .insert("authorization", authorization.clone());
+    request
+}
+
+async fn admin(
+    client: &mut AdminClient<Channel>,
+    token: &str,
+    command: &AdminCommands,
+    output: Output,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let authorization: tonic::metadata::MetadataValue<_> = format!("Bearer {}", token).parse()?;
+    match command {
+        AdminCommands::ListUsers {
+            prefix,
+            page_size,
+            page_token,
+        } => {
+            let response = client
+                .list_users(authorized(
+                    &authorization,
+                    admin::ListUsersRequest {
+                        username_prefix: prefix.to_owned(),
+                        page_size: *page_size,
+                        page_token: page_token.to_owned(),
+                    },
+                ))
+                .await?;
+            output.print(response.get_ref())?;
+        }
+        AdminCommands::GetUser { username } => {
+            let response = client
+                .get_user(authorized(
+                    &authorization,
+                    admin::GetUserRequest {
+                        username: username.to_owned(),
+                    },
+                ))
+                .await?;
+            output.print(response.get_ref())?;
+        }
+        AdminCommands::DisableUser { username } => {
+            let response = client
+                .disable_user(authorized(
+                    &authorization,
+                    admin::DisableUserRequest {
+                        username: username.to_owned(),
+                    },
+                ))
+                .await?;
+            output.print(response.get_ref())?;
+        }
+        AdminCommands::EnableUser { username } => {
+            let response = client
+                .enable_user(authorized(
+                    &authorization,
+                    admin::EnableUserRequest {
+                        username: username.to_owned(),
+                    },
+                ))
+                .await?;
+            output.print(response.get_ref())?;
+        }
+        AdminCommands::DeleteUser { username } => {
+            let response = client
+                .delete_user(authorized(
+                    &authorization,
+                    admin::DeleteUserRequest {
+                        username: username.to_owned(),
+                    },
+                ))
+                .await?;
+            output.print(response.get_ref())?;
+        }
+        AdminCommands::CreateServiceAccount { username } => {
+            let response = client
+                .create_service_account(authorized(
+                    &authorization,
+                    admin::CreateServiceAccountRequest {
+                        username: username.to_owned(),
+                    },
+                ))
+                .await?;
+            output.print(response.get_ref())?;
+        }
+        AdminCommands::CreateApiKey {
+            username,
+            name,
+            scopes,
+            ttl_secs,
+        } => {
+            let response = client
+                .create_api_key(authorized(
+                    &authorization,
+                    admin::CreateApiKeyRequest {
+                        username: username.to_owned(),
+                        name: name.to_owned(),
+                        scopes: scopes.to_owned(),
+                        ttl_secs: *ttl_secs,
+                    },
+                ))
+                .await?;
+            output.print(response.get_ref())?;
+        }
+        AdminCommands::ListApiKeys { username } => {
+            let response = client
+                .list_api_keys(authorized(
+                    &authorization,
+                    admin::ListApiKeysRequest {
+                        username: username.to_owned(),
+                    },
+                ))
+                .await?;
+            output.print(response.get_ref())?;
+        }
+        AdminCommands::RevokeApiKey { username, key_id } => {
+            let response = client
+                .revoke_api_key(authorized(
+                    &authorization,
+                    admin::RevokeApiKeyRequest {
+                        username: username.to_owned(),
+                        key_id: key_id.to_owned(),
+                    },
+                ))
+                .await?;
+            output.print(response.get_ref())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn verify_audit_log(
+    path: &PathBuf,
+    key: &str,
+    partial: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let start = if partial {
+        Start::Anywhere
+    } else {
+        Start::Genesis
+    };
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let verification = common::audit::verify(BufReader::new(file), key.as_bytes(), start);
+
+    for problem in &verification.problems {
+        eprintln!("{}", problem);
+    }
+    if !verification.is_valid() {
+        return Err(format!(
+            "{} problem(s) found in {} entries",
+            verification.problems.len(),
+            verification.entries
+        )
+        .into());
+    }
+
+    match verification.first_sequence {
+        Some(first) if first > 0 => println!(
+            "{} entries verified, from sequence {}",
+            verification.entries, first
+        ),
+        _ => println!("{} entries verified", verification.entries),
+    }
+    Ok(())
+}
+
+async fn replay(
+    client: &mut AuthenticationClient<Channel>,
+    path: &PathBuf,
+    ignored_fields: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut replacements = Replacements::default();
+    let mut calls = 0;
+    let mut divergences = 0;
+
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let call: CapturedCall = serde_json::from_str(&line)
+            .map_err(|e| format!("Invalid call on line {}: {}", index + 1, e))?;
+        // Streamed events depend on what else was happening, so they are not
+        // replayed
+        if call.rpc == "SubscribeEvents" {
+            continue;
+        }
+
+        let mut request = call.request.clone();
+        replacements.fill(&mut request);
+        let differences = match replay_call(client, &call.rpc, request).await {
+            Ok(response) => {
+                replacements.learn(&call.response, &response);
+                capture::diff(&call.response, &response, ignored_fields)
+            }
+            Err(e) => vec![format!("failed: {}", e)],
+        };
+
+        calls += 1;
+        if !differences.is_empty() {
+            divergences += 1;
+        }
+        for difference in differences {
+            println!("line {} {}: {}", index + 1, call.rpc, difference);
+        }
     }
 
+    println!("{} calls replayed, {} diverged", calls, divergences);
+    if divergences > 0 {
+        return Err(format!("{} of {} calls diverged", divergences, calls).into());
+    }
     Ok(())
 }
+
+/// Issues a captured call, returning the JSON form of the response.
+async fn replay_call(
+    client: &mut AuthenticationClient<Channel>,
+    rpc: &str,
+    request: serde_json::Value,
+) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    use serde_json::{from_value, to_value};
+
+    let response = match rpc {
+        "SignUp" => to_value(
+            client
+                .sign_up(from_value::<authentication::SignUpRequest>(request)?)
+                .await?
+                .into_inner(),
+        ),
+        "SignIn" => to_value(
+            client
+                .sign_in(from_value::<authentication::SignInRequest>(request)?)
+                .await?
+                .into_inner(),
+        ),
+        "SignOut" => to_value(
+            client
+                .sign_out(from_value::<authentication::SignOutRequest>(request)?)
+                .await?
+                .into_inner(),
+        ),
+        "DeleteAccount" => to_value(
+            client
+                .delete_account(from_value::<authentication::DeleteAccountRequest>(request)?)
+                .await?
+                .into_inner(),
+        ),
+        "ChangePassword" => to_value(
+            client
+                .change_password(from_value::<authentication::ChangePasswordRequest>(
+                    request,
+                )?)
+                .await?
+                .into_inner(),
+        ),
+        "CancelAccountDeletion" => to_value(
+            client
+                .cancel_account_deletion(
+                    from_value::<authentication::CancelAccountDeletionRequest>(request)?,
+                )
+                .await?
+                .into_inner(),
+        ),
+        "GetProfile" => to_value(
+            client
+                .get_profile(from_value::<authentication::GetProfileRequest>(request)?)
+                .await?
+                .into_inner(),
+        ),
+        "UpdateProfile" => to_value(
+            client
+                .update_profile(from_value::<authentication::UpdateProfileRequest>(request)?)
+                .await?
+                .into_inner(),
+        ),
+        "VerifyEmail" => to_value(
+            client
+                .verify_email(from_value::<authentication::VerifyEmailRequest>(request)?)
+                .await?
+                .into_inner(),
+        ),
+        "ResendVerification" => to_value(
+            client
+                .resend_verification(from_value::<authentication::ResendVerificationRequest>(
+                    request,
+                )?)
+                .await?
+                .into_inner(),
+        ),
+        _ => return Err(format!("Unknown RPC {}", rpc).into()),
+    };
+    Ok(response?)
+}
@@ -0,0 +1,311 @@
+use std::{error::Error, fmt};
+
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::Value;
+use tonic::Code;
+
+use crate::authentication::{EventType, StatusCode};
+
+/// Exit codes, for the help of the client.
+pub const EXIT_CODES: &str = "Exit codes:
+  0  success
+  1  any other error
+  2  invalid command line
+  3  the service refused the request, e.g. wrong credentials
+  4  not authorized, e.g. a wrong admin token
+  5  not found
+  6  conflicting with the current state, e.g. an existing username
+  7  the service is unreachable or timed out";
+
+/// How responses are printed.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Output {
+    /// A `field: value` line per field, nested ones named by dotted paths
+    #[default]
+    Plain,
+    /// A JSON object per line
+    Json,
+    /// Aligned columns, with a row per item of lists
+    Table,
+}
+
+/// The service answered with a failure status code, and why.
+#[derive(Debug)]
+pub struct Refused(pub String);
+
+impl fmt::Display for Refused {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for Refused {}
+
+impl Output {
+    /// Prints a response, failing with [`Refused`] after printing it when its
+    /// status code is a failure.
+    pub fn print(self, response: &impl Serialize) -> Result<(), Box<dyn Error>> {
+        let mut value = serde_json::to_value(response)?;
+        name_enums(&mut value);
+        print!("{}", self.render(&value));
+
+        if value.get("status_code").and_then(Value::as_str)
+            == Some(StatusCode::Failure.as_str_name())
+        {
+            return Err(Refused("The service refused the request".to_string()).into());
+        }
+        Ok(())
+    }
+
+    pub fn render(self, value: &Value) -> String {
+        match self {
+            Output::Json => format!("{}\n", value),
+            Output::Plain => flatten(value)
+                .into_iter()
+                .map(|(field, value)| format!("{}: {}\n", field, value))
+                .collect(),
+            Output::Table => table(value),
+        }
+    }
+}
+
+/// Names the enums the messages carry as numbers: status codes and event
+/// types, the latter as `--type` of `subscribe-events` takes them.
+fn name_enums(value: &mut Value) {
+    let Value::Object(fields) = value else {
+        return;
+    };
+    for (key, value) in fields.iter_mut() {
+        let number = value.as_i64().and_then(|number| i32::try_from(number).ok());
+        match (key.as_str(), number) {
+            ("status_code", Some(number)) => {
+                if let Ok(status_code) = StatusCode::try_from(number) {
+                    *value = status_code.as_str_name().into();
+                }
+            }
+            ("type", Some(number)) => {
+                if let Ok(event_type) = EventType::try_from(number) {
+                    let name = event_type.as_str_name().trim_start_matches("EVENT_TYPE_");
+                    *value = name.to_lowercase().replace('_', "-").into();
+                }
+            }
+            _ => name_enums(value),
+        }
+    }
+}
+
+/// The leaf fields of a value, by dotted path, items of lists by index.
+fn flatten(value: &Value) -> Vec<(String, String)> {
+    let mut fields = Vec::new();
+    flatten_into("", value, &mut fields);
+    fields
+}
+
+fn flatten_into(path: &str, value: &Value, fields: &mut Vec<(String, String)>) {
+    let child = |key: &str| {
+        if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", path, key)
+        }
+    };
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object {
+                flatten_into(&child(key), value, fields);
+            }
+        }
+        Value::Array(items) => {
+            for (index, item) in items.iter().enumerate() {
+                flatten_into(&child(&index.to_string()), item, fields);
+            }
+        }
+        Value::Null => fields.push((path.to_string(), String::new())),
+        Value::String(text) => fields.push((path.to_string(), text.clone())),
+        other => fields.push((path.to_string(), other.to_string())),
+    }
+}
+
+/// The fields as a two-column table, then the lists of objects, like the
+/// users of `list-users`, as a table each.
+fn table(value: &Value) -> String {
+    let Value::Object(object) = value else {
+        return Output::Plain.render(value);
+    };
+
+    let mut fields = vec![vec!["FIELD".to_string(), "VALUE".to_string()]];
+    let mut lists = Vec::new();
+    for (key, value) in object {
+        match value {
+            Value::Array(items) if !items.is_empty() && items.iter().all(Value::is_object) => {
+                lists.push((key, items))
+            }
+            value => {
+                let mut leaves = Vec::new();
+                flatten_into(key, value, &mut leaves);
+                fields.extend(leaves.into_iter().map(|(field, value)| vec![field, value]));
+            }
+        }
+    }
+
+    let mut sections = Vec::new();
+    if fields.len() > 1 {
+        sections.push(columns(&fields));
+    }
+    for (key, items) in lists {
+        let rows: Vec<Vec<(String, String)>> = items.iter().map(flatten).collect();
+        let mut header: Vec<String> = Vec::new();
+        for (field, _) in rows.iter().flatten() {
+            if !header.contains(field) {
+                header.push(field.clone());
+            }
+        }
+        let mut table = vec![header.iter().map(|field| field.to_uppercase()).collect()];
+        table.extend(rows.iter().map(|row| {
+            header
+                .iter()
+                .map(|field| {
+                    row.iter()
+                        .find(|(name, _)| name == field)
+                        .map(|(_, value)| value.clone())
+                        .unwrap_or_default()
+                })
+                .collect()
+        }));
+        sections.push(format!("{}:\n{}", key, columns(&table)));
+    }
+    sections.join("\n")
+}
+
+/// Rows with their cells padded to the width of their column.
+fn columns(rows: &[Vec<String>]) -> String {
+    let mut widths = Vec::new();
+    for row in rows {
+        for (index, cell) in row.iter().enumerate() {
+            if widths.len() <= index {
+                widths.push(0);
+            }
+            widths[index] = widths[index].max(cell.chars().count());
+        }
+    }
+
+    let mut text = String::new();
+    for row in rows {
+        let cells: Vec<String> = row
+            .iter()
+            .enumerate()
+            .map(|(index, cell)| format!("{:width$}", cell, width = widths[index]))
+            .collect();
+        text.push_str(cells.join("  ").trim_end());
+        text.push('\n');
+    }
+    text
+}
+
+/// The error as printed by the client, gRPC ones without their metadata.
+pub fn describe(error: &(dyn Error + 'static)) -> String {
+    match error.downcast_ref::<tonic::Status>() {
+        Some(status) => format!("{} ({:?})", status.message(), status.code()),
+        None => error.to_string(),
+    }
+}
+
+/// The exit code of the client for an error, see [`EXIT_CODES`].
+pub fn exit_code(error: &(dyn Error + 'static)) -> u8 {
+    if error.is::<Refused>() {
+        return 3;
+    }
+    if error.is::<tonic::transport::Error>() {
+        return 7;
+    }
+    match error
+        .downcast_ref::<tonic::Status>()
+        .map(tonic::Status::code)
+    {
+        Some(Code::Unauthenticated | Code::PermissionDenied) => 4,
+        Some(Code::NotFound) => 5,
+        Some(Code::AlreadyExists | Code::FailedPrecondition) => 6,
+        Some(Code::Unavailable | Code::DeadlineExceeded) => 7,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn sign_in() -> Value {
+        let mut value = json!({
+            "status_code": 1,
+            "session_token": "abc",
+            "profile": {"username": "jane", "attributes": {}},
+        });
+        name_enums(&mut value);
+        value
+    }
+
+    #[test]
+    fn should_name_enums() {
+        let mut event = json!({"sequence": 1, "type": 1});
+
+        name_enums(&mut event);
+
+        assert_eq!(sign_in()["status_code"], "SUCCESS");
+        assert_eq!(event["type"], "signed-up");
+    }
+
+    #[test]
+    fn should_render_plain() {
+        assert_eq!(
+            Output::Plain.render(&sign_in()),
+            "profile.username: jane\nsession_token: abc\nstatus_code: SUCCESS\n"
+        );
+    }
+
+    #[test]
+    fn should_render_json() {
+        let rendered = Output::Json.render(&sign_in());
+
+        assert!(rendered.ends_with("}\n"));
+        assert_eq!(serde_json::from_str::<Value>(&rendered).unwrap(), sign_in());
+    }
+
+    #[test]
+    fn should_render_lists_as_tables() {
+        let users = json!({
+            "next_page_token": "",
+            "users": [
+                {"username": "jane", "disabled": false},
+                {"username": "bartholomew", "disabled": true},
+            ],
+        });
+
+        assert_eq!(
+            Output::Table.render(&users),
+            "FIELD            VALUE\n\
+             next_page_token\n\
+             \n\
+             users:\n\
+             DISABLED  USERNAME\n\
+             false     jane\n\
+             true      bartholomew\n"
+        );
+    }
+
+    #[test]
+    fn should_map_errors_to_exit_codes() {
+        let refused: Box<dyn Error> = Refused("refused".to_string()).into();
+        let not_found: Box<dyn Error> = tonic::Status::not_found("no such user").into();
+        let denied: Box<dyn Error> = tonic::Status::unauthenticated("wrong token").into();
+        let other: Box<dyn Error> = "invalid".into();
+
+        assert_eq!(exit_code(refused.as_ref()), 3);
+        assert_eq!(exit_code(not_found.as_ref()), 5);
+        assert_eq!(exit_code(denied.as_ref()), 4);
+        assert_eq!(exit_code(other.as_ref()), 1);
+        assert_eq!(describe(not_found.as_ref()), "no such user (NotFound)");
+    }
+}
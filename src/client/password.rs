@@ -0,0 +1,148 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use clap::Args;
+
+/// Where a password comes from, a prompt when neither flag is given.
+#[derive(Args, Debug, Clone)]
+pub struct PasswordArgs {
+    /// Ends up in the shell history and process list, prefer
+    /// `--password-stdin` or the prompt
+    #[arg(short, long)]
+    password: Option<String>,
+    /// Read the password from the first line of stdin
+    #[arg(long, conflicts_with = "password")]
+    password_stdin: bool,
+}
+
+impl PasswordArgs {
+    /// `confirm` asks twice when prompting, for new passwords.
+    pub fn read(&self, confirm: bool) -> Result<String, String> {
+        if let Some(password) = &self.password {
+            return Ok(password.clone());
+        }
+        if self.password_stdin {
+            return first_line(io::stdin().lock());
+        }
+        if !io::stdin().is_terminal() {
+            return Err(
+                "No password given, pass --password-stdin or run in a terminal to be prompted"
+                    .to_string(),
+            );
+        }
+
+        let password = prompt("Password: ")?;
+        if confirm && prompt("Confirm password: ")? != password {
+            return Err("The passwords do not match".to_string());
+        }
+        Ok(password)
+    }
+}
+
+/// A new password, prompted for twice when not given.
+pub fn read_new(given: Option<&str>) -> Result<String, String> {
+    if let Some(password) = given {
+        return Ok(password.to_string());
+    }
+    if !io::stdin().is_terminal() {
+        return Err(
+            "No new password given, pass --new-password or run in a terminal to be prompted"
+                .to_string(),
+        );
+    }
+
+    let password = prompt("New password: ")?;
+    if prompt("Confirm new password: ")? != password {
+        return Err("The passwords do not match".to_string());
+    }
+    Ok(password)
+}
+
+/// The first line, without its line ending.
+fn first_line(mut reader: impl BufRead) -> Result<String, String> {
+    let mut line = String::new();
+    reader
+        .read_line(&mut line)
+        .map_err(|e| format!("Failed to read the password: {}", e))?;
+    let line = line.strip_suffix('\n').unwrap_or(&line);
+    Ok(line.strip_suffix('\r').unwrap_or(line).to_string())
+}
+
+/// Reads a line from the terminal without echoing it.
+fn prompt(label: &str) -> Result<String, String> {
+    eprint!("{}", label);
+    let _ = io::stderr().flush();
+
+    let echo_off = EchoOff::new().map_err(|e| format!("Failed to hide the input: {}", e))?;
+    let password = first_line(io::stdin().lock());
+    drop(echo_off);
+    // The newline typed was not echoed either
+    eprintln!();
+    password
+}
+
+/// Turns off the echo of the terminal on stdin until dropped.
+#[cfg(unix)]
+struct EchoOff {
+    original: libc::termios,
+}
+
+#[cfg(unix)]
+impl EchoOff {
+    fn new() -> io::Result<Self> {
+        // SAFETY: termios is plain data, filled in by tcgetattr before use
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+        // SAFETY: stdin is open for the whole run and termios outlives the call
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let original = termios;
+        termios.c_lflag &= !libc::ECHO;
+        // SAFETY: as above, with settings obtained from tcgetattr
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { original })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for EchoOff {
+    fn drop(&mut self) {
+        // SAFETY: restores the settings read in new
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original) };
+    }
+}
+
+/// Elsewhere the input is echoed.
+#[cfg(not(unix))]
+struct EchoOff;
+
+#[cfg(not(unix))]
+impl EchoOff {
+    fn new() -> io::Result<Self> {
+        Ok(Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_read_first_line_only() {
+        assert_eq!(first_line(&b"secret\nrest\n"[..]).unwrap(), "secret");
+        assert_eq!(first_line(&b"secret\r\n"[..]).unwrap(), "secret");
+        assert_eq!(first_line(&b" spaced "[..]).unwrap(), " spaced ");
+        assert_eq!(first_line(&b""[..]).unwrap(), "");
+    }
+
+    #[test]
+    fn should_prefer_given_password() {
+        let args = PasswordArgs {
+            password: Some("secret".to_string()),
+            password_stdin: false,
+        };
+
+        assert_eq!(args.read(true).unwrap(), "secret");
+    }
+}
@@ -0,0 +1,281 @@
+use std::io::BufRead;
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+/// `prev_hash` of the first entry of a chain.
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// One line of the audit log. Every entry carries the hash of the previous
+/// one, so removing, reordering or modifying entries breaks the chain. The
+/// hashes are keyed, so the chain cannot be rewritten without the key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuditEntry {
+    pub sequence: u64,
+    pub timestamp_ms: u64,
+    pub event: serde_json::Value,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl AuditEntry {
+    /// Chains a new entry after `prev`, or starts a chain when there is none.
+    pub fn new(
+        key: &[u8],
+        prev: Option<&AuditEntry>,
+        timestamp_ms: u64,
+        event: serde_json::Value,
+    ) -> Self {
+        let mut entry = Self {
+            sequence: prev.map_or(0, |prev| prev.sequence + 1),
+            timestamp_ms,
+            event,
+            prev_hash: prev.map_or(GENESIS_HASH.to_string(), |prev| prev.hash.clone()),
+            hash: String::new(),
+        };
+        entry.hash = entry.compute_hash(key);
+        entry
+    }
+
+    /// HMAC-SHA256 of every field but `hash`, the event in its compact JSON
+    /// form.
+    pub fn compute_hash(&self, key: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+        mac.update(&self.sequence.to_be_bytes());
+        mac.update(&self.timestamp_ms.to_be_bytes());
+        mac.update(self.event.to_string().as_bytes());
+        mac.update(self.prev_hash.as_bytes());
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+/// Where the log given to [`verify`] is expected to start.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Start {
+    /// At the first entry of the chain, as the file sink writes it.
+    Genesis,
+    /// At any entry, as `Tail` streams it; the entries before are trusted.
+    Anywhere,
+}
+
+/// Outcome of [`verify`].
+#[derive(Debug, Default, PartialEq)]
+pub struct Verification {
+    pub entries: u64,
+    /// Sequence number of the first entry.
+    pub first_sequence: Option<u64>,
+    pub problems: Vec<String>,
+}
+
+impl Verification {
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Checks a JSON lines audit log chained with `key`, reporting entries that
+/// were modified and gaps left by entries that were removed or reordered.
+pub fn verify(log: impl BufRead, key: &[u8], start: Start) -> Verification {
+    let mut verification = Verification::default();
+    let mut prev: Option<AuditEntry> = None;
+
+    for (index, line) in log.lines().enumerate() {
+        let line_number = index + 1;
+        let line = match line {
+            Ok(line) if line.trim().is_empty() => continue,
+            Ok(line) => line,
+            Err(e) => {
+                verification
+                    .problems
+                    .push(format!("line {}: failed to read: {}", line_number, e));
+                break;
+            }
+        };
+        let entry: AuditEntry = match serde_json::from_str(&line) {
+            Ok(entry) => entry,
+            Err(e) => {
+                verification
+                    .problems
+                    .push(format!("line {}: not an audit entry: {}", line_number, e));
+                continue;
+            }
+        };
+        verification.entries += 1;
+
+        let (expected_sequence, expected_prev_hash) = match (&prev, start) {
+            (Some(prev), _) => (prev.sequence + 1, prev.hash.as_str()),
+            (None, Start::Anywhere) if entry.sequence > 0 => {
+                (entry.sequence, entry.prev_hash.as_str())
+            }
+            (None, _) => (0, GENESIS_HASH),
+        };
+        verification.first_sequence.get_or_insert(entry.sequence);
+        if entry.sequence != expected_sequence {
+            verification.problems.push(format!(
+                "line {}: expected sequence {}, found {}",
+                line_number, expected_sequence, entry.sequence
+            ));
+        }
+        if entry.prev_hash != expected_prev_hash {
+            verification.problems.push(format!(
+                "line {}: entry {} does not follow the previous entry",
+                line_number, entry.sequence
+            ));
+        }
+        if entry.hash != entry.compute_hash(key) {
+            verification.problems.push(format!(
+                "line {}: entry {} was modified",
+                line_number, entry.sequence
+            ));
+        }
+
+        prev = Some(entry);
+    }
+
+    verification
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const KEY: &[u8] = b"audit-key";
+
+    fn chain(length: u64) -> Vec<AuditEntry> {
+        let mut entries: Vec<AuditEntry> = Vec::new();
+        for sequence in 0..length {
+            let event = json!({ "type": "signed_in", "user_id": sequence.to_string() });
+            entries.push(AuditEntry::new(
+                KEY,
+                entries.last(),
+                1_000 + sequence,
+                event,
+            ));
+        }
+        entries
+    }
+
+    fn to_log(entries: &[AuditEntry]) -> String {
+        entries
+            .iter()
+            .map(|entry| serde_json::to_string(entry).unwrap() + "\n")
+            .collect()
+    }
+
+    #[test]
+    fn should_link_entries() {
+        let entries = chain(2);
+
+        assert_eq!(entries[0].prev_hash, GENESIS_HASH);
+        assert_eq!(entries[1].prev_hash, entries[0].hash);
+        assert_eq!(entries[1].sequence, 1);
+    }
+
+    #[test]
+    fn should_verify_untouched_log() {
+        let verification = verify(to_log(&chain(3)).as_bytes(), KEY, Start::Genesis);
+
+        assert!(verification.is_valid(), "{:?}", verification.problems);
+        assert_eq!(verification.entries, 3);
+    }
+
+    #[test]
+    fn should_detect_modified_entry() {
+        let mut entries = chain(3);
+        entries[1].event = json!({ "type": "signed_in", "user_id": "someone-else" });
+
+        let verification = verify(to_log(&entries).as_bytes(), KEY, Start::Genesis);
+
+        assert_eq!(verification.problems, vec!["line 2: entry 1 was modified"]);
+    }
+
+    #[test]
+    fn should_detect_rehashed_entry() {
+        let mut entries = chain(3);
+        entries[1].timestamp_ms = 0;
+        entries[1].hash = entries[1].compute_hash(KEY);
+
+        let verification = verify(to_log(&entries).as_bytes(), KEY, Start::Genesis);
+
+        assert_eq!(
+            verification.problems,
+            vec!["line 3: entry 2 does not follow the previous entry"]
+        );
+    }
+
+    #[test]
+    fn should_detect_removed_entry() {
+        let mut entries = chain(3);
+        entries.remove(1);
+
+        let verification = verify(to_log(&entries).as_bytes(), KEY, Start::Genesis);
+
+        assert!(!verification.is_valid());
+        assert!(verification.problems[0].contains("expected sequence 1, found 2"));
+    }
+
+    #[test]
+    fn should_detect_truncated_start() {
+        let entries = chain(3);
+
+        let verification = verify(to_log(&entries[1..]).as_bytes(), KEY, Start::Genesis);
+
+        assert!(verification.problems[0].contains("expected sequence 0, found 1"));
+    }
+
+    #[test]
+    fn should_verify_log_starting_mid_chain() {
+        let entries = chain(4);
+
+        let verification = verify(to_log(&entries[2..]).as_bytes(), KEY, Start::Anywhere);
+
+        assert!(verification.is_valid(), "{:?}", verification.problems);
+        assert_eq!(verification.entries, 2);
+        assert_eq!(verification.first_sequence, Some(2));
+    }
+
+    #[test]
+    fn should_detect_gap_in_log_starting_mid_chain() {
+        let mut entries = chain(4);
+        entries.remove(2);
+
+        let verification = verify(to_log(&entries[1..]).as_bytes(), KEY, Start::Anywhere);
+
+        assert!(verification.problems[0].contains("expected sequence 2, found 3"));
+    }
+
+    #[test]
+    fn should_detect_rewrite_without_key() {
+        let mut entries: Vec<AuditEntry> = Vec::new();
+        for sequence in 0..2 {
+            let event = json!({ "type": "signed_in", "user_id": sequence.to_string() });
+            entries.push(AuditEntry::new(b"guess", entries.last(), 1_000, event));
+        }
+
+        let verification = verify(to_log(&entries).as_bytes(), KEY, Start::Genesis);
+
+        assert_eq!(
+            verification.problems,
+            vec![
+                "line 1: entry 0 was modified",
+                "line 2: entry 1 was modified"
+            ]
+        );
+    }
+
+    #[test]
+    fn should_report_garbage_lines() {
+        let log = to_log(&chain(1)) + "not json\n";
+
+        let verification = verify(log.as_bytes(), KEY, Start::Genesis);
+
+        assert!(verification.problems[0].starts_with("line 2: not an audit entry"));
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+/// Fields holding passwords and tokens, whose values are never captured.
+pub const REDACTED_FIELDS: [&str; 4] = ["password", "new_password", "session_token", "token"];
+
+/// Start of the value standing for a redacted one.
+pub const REDACTED_PREFIX: &str = "redacted:";
+
+/// One line of a traffic capture: a call to the Authentication service and
+/// what it answered, both as the JSON form of their messages.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CapturedCall {
+    /// e.g. `SignIn`
+    pub rpc: String,
+    pub timestamp_ms: u64,
+    pub request: Value,
+    pub response: Value,
+}
+
+/// Replaces the values of the [`REDACTED_FIELDS`] by their fingerprint, the
+/// same for equal values, so that a replay can tell which token a later call
+/// reuses. The salt keeps the fingerprints of weak passwords from being
+/// looked up. Empty values are kept, an empty token meaning none.
+pub fn redact(value: &mut Value, salt: &[u8]) {
+    visit_redacted(value, &mut |text| {
+        if !text.is_empty() {
+            let mut hasher = Sha256::new();
+            hasher.update(salt);
+            hasher.update(text.as_bytes());
+            let fingerprint: String = hasher.finalize()[..8]
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            *text = format!("{}{}", REDACTED_PREFIX, fingerprint);
+        }
+    });
+}
+
+fn visit_redacted(value: &mut Value, visit: &mut impl FnMut(&mut String)) {
+    match value {
+        Value::Object(fields) => {
+            for (key, value) in fields.iter_mut() {
+                match value {
+                    Value::String(text) if REDACTED_FIELDS.contains(&key.as_str()) => visit(text),
+                    value => visit_redacted(value, visit),
+                }
+            }
+        }
+        Value::Array(items) => items
+            .iter_mut()
+            .for_each(|item| visit_redacted(item, visit)),
+        _ => {}
+    }
+}
+
+pub fn is_redacted(text: &str) -> bool {
+    text.starts_with(REDACTED_PREFIX)
+}
+
+/// What the redacted values of a capture stand for during a replay.
+#[derive(Debug, Default)]
+pub struct Replacements {
+    values: HashMap<String, String>,
+}
+
+impl Replacements {
+    /// Puts back the values of the redacted fields of a captured request. A
+    /// value not seen in a response yet, like a password, is made up from
+    /// its fingerprint, so that the calls reusing it get the same one.
+    pub fn fill(&mut self, request: &mut Value) {
+        visit_redacted(request, &mut |text| {
+            if let Some(fingerprint) = text.strip_prefix(REDACTED_PREFIX) {
+                let replacement = format!("replayed-{}", fingerprint);
+                *text = self
+                    .values
+                    .entry(text.clone())
+                    .or_insert(replacement)
+                    .clone();
+            }
+        });
+    }
+
+    /// Learns the values the replayed service gave for redacted ones, e.g.
+    /// the session token of a sign-in.
+    pub fn learn(&mut self, captured: &Value, replayed: &Value) {
+        match (captured, replayed) {
+            (Value::String(captured), Value::String(replayed)) if is_redacted(captured) => {
+                self.values.insert(captured.clone(), replayed.clone());
+            }
+            (Value::Object(captured), Value::Object(replayed)) => {
+                for (key, value) in captured {
+                    if let Some(other) = replayed.get(key) {
+                        self.learn(value, other);
+                    }
+                }
+            }
+            (Value::Array(captured), Value::Array(replayed)) => {
+                for (value, other) in captured.iter().zip(replayed) {
+                    self.learn(value, other);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Where a replayed response differs from the captured one, by dotted path.
+/// Fields named in `ignored` are skipped, and a redacted value matches any
+/// value but an empty one.
+pub fn diff(captured: &Value, replayed: &Value, ignored: &[String]) -> Vec<String> {
+    let mut differences = Vec::new();
+    diff_at("", captured, replayed, ignored, &mut differences);
+    differences
+}
+
+fn diff_at(
+    path: &str,
+    captured: &Value,
+    replayed: &Value,
+    ignored: &[String],
+    differences: &mut Vec<String>,
+) {
+    match (captured, replayed) {
+        (Value::String(captured), Value::String(replayed))
+            if is_redacted(captured) && !replayed.is_empty() => {}
+        (Value::Object(captured), Value::Object(replayed)) => {
+            let mut keys: Vec<_> = captured.keys().chain(replayed.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                if ignored.contains(key) {
+                    continue;
+                }
+                let path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                diff_at(
+                    &path,
+                    captured.get(key).unwrap_or(&Value::Null),
+                    replayed.get(key).unwrap_or(&Value::Null),
+                    ignored,
+                    differences,
+                );
+            }
+        }
+        (captured, replayed) if captured != replayed => differences.push(format!(
+            "{}: captured {}, replayed {}",
+            path, captured, replayed
+        )),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn should_redact_secrets_consistently() {
+        let mut sign_in = json!({"username": "jane", "password": "secret"});
+        let mut delete = json!({"session_token": "abc", "password": "secret"});
+
+        redact(&mut sign_in, b"salt");
+        redact(&mut delete, b"salt");
+
+        assert_eq!(sign_in["username"], "jane");
+        assert!(is_redacted(sign_in["password"].as_str().unwrap()));
+        assert!(!sign_in.to_string().contains("secret"));
+        assert_eq!(sign_in["password"], delete["password"]);
+        assert_ne!(delete["session_token"], delete["password"]);
+
+        let mut other_salt = json!({"password": "secret"});
+        redact(&mut other_salt, b"pepper");
+        assert_ne!(other_salt["password"], sign_in["password"]);
+    }
+
+    #[test]
+    fn should_keep_empty_secrets() {
+        let mut response = json!({"session_token": "", "profile": {"token": "t"}});
+
+        redact(&mut response, b"salt");
+
+        assert_eq!(response["session_token"], "");
+        assert!(is_redacted(response["profile"]["token"].as_str().unwrap()));
+    }
+
+    #[test]
+    fn should_replace_redacted_values() {
+        let mut replacements = Replacements::default();
+        let mut sign_up = json!({"username": "jane", "password": "redacted:01"});
+        let mut sign_in = sign_up.clone();
+
+        replacements.fill(&mut sign_up);
+        replacements.fill(&mut sign_in);
+        assert_eq!(sign_up["password"], "replayed-01");
+        assert_eq!(sign_in, sign_up);
+
+        replacements.learn(
+            &json!({"status_code": 1, "session_token": "redacted:02"}),
+            &json!({"status_code": 1, "session_token": "live"}),
+        );
+        let mut sign_out = json!({"session_token": "redacted:02"});
+        replacements.fill(&mut sign_out);
+        assert_eq!(sign_out["session_token"], "live");
+    }
+
+    #[test]
+    fn should_diff_responses() {
+        let captured = json!({
+            "status_code": 1,
+            "user_id": "u1",
+            "session_token": "redacted:02",
+            "profile": {"username": "jane", "locale": "en"},
+        });
+        let ignored = vec!["user_id".to_string()];
+
+        let same = json!({
+            "status_code": 1,
+            "user_id": "u2",
+            "session_token": "live",
+            "profile": {"username": "jane", "locale": "en"},
+        });
+        assert!(diff(&captured, &same, &ignored).is_empty());
+
+        let different = json!({
+            "status_code": 0,
+            "user_id": "",
+            "session_token": "",
+            "profile": {"username": "jane"},
+        });
+        assert_eq!(
+            diff(&captured, &different, &ignored),
+            vec![
+                "profile.locale: captured \"en\", replayed null",
+                "session_token: captured \"redacted:02\", replayed \"\"",
+                "status_code: captured 1, replayed 0",
+            ]
+        );
+    }
+}
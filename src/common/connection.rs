@@ -0,0 +1,503 @@
+use std::{error::Error, time::Duration};
+
+use clap::Args;
+use http_body_util::{BodyExt, Full};
+use tonic::{
+    body::{self, BoxBody},
+    codegen::{http, BoxFuture, Context, Poll, Service, StdError},
+    metadata::{Ascii, MetadataKey, MetadataMap, MetadataValue},
+    transport::{Channel, Endpoint},
+    Code, ConnectError,
+};
+
+use crate::{
+    telemetry::{self, TracedChannel},
+    tls::ClientTlsOptions,
+};
+
+/// Port of the auth service when the endpoint leaves it out.
+pub const DEFAULT_PORT: u16 = 50051;
+
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Methods that can be sent again after the service may have handled them,
+/// repeating them changing nothing more. The others, e.g. `SignUp`, are only
+/// sent again when they could not be sent at all.
+const IDEMPOTENT_METHODS: [&str; 10] = [
+    "/authentication.Authentication/GetProfile",
+    "/authentication.Authentication/UpdateProfile",
+    "/authentication.Authentication/SubscribeEvents",
+    "/admin.Admin/ListUsers",
+    "/admin.Admin/GetUser",
+    "/admin.Admin/DisableUser",
+    "/admin.Admin/EnableUser",
+    "/admin.Admin/ListApiKeys",
+    "/audit.Audit/Tail",
+    "/grpc.health.v1.Health/Check",
+];
+
+/// Where the auth service is and how to reach it, shared by its clients.
+#[derive(Args, Debug, Clone, Default)]
+pub struct ConnectionOptions {
+    /// URL of the auth service, e.g. `https://auth.example.com:443`, or a
+    /// `host[:port]` reached with the scheme of the TLS options, on port 50051
+    /// unless given
+    #[arg(long, env = "AUTH_ENDPOINT")]
+    pub endpoint: Option<String>,
+    /// gRPC metadata sent with every request, as `key=value`
+    #[arg(
+        long = "header",
+        env = "AUTH_HEADERS",
+        value_delimiter = ',',
+        value_parser = parse_header
+    )]
+    pub headers: Vec<(String, String)>,
+    #[command(flatten)]
+    pub tls: ClientTlsOptions,
+}
+
+/// Timeouts and retries of the requests to the auth service.
+#[derive(Args, Debug, Clone)]
+pub struct RequestOptions {
+    /// Time to wait for the connection to the auth service
+    #[arg(long, env = "AUTH_CONNECT_TIMEOUT_MS", default_value_t = 5_000)]
+    pub connect_timeout_ms: u64,
+    /// Time to wait for the response to a request, 0 for no limit
+    #[arg(long, env = "AUTH_REQUEST_TIMEOUT_MS", default_value_t = 30_000)]
+    pub request_timeout_ms: u64,
+    /// Times a connection or request is attempted again while the auth
+    /// service is unavailable
+    #[arg(long, env = "AUTH_RETRIES", default_value_t = 3)]
+    pub retries: u32,
+    /// Delay before the first retry, doubled for each following one
+    #[arg(long, env = "AUTH_RETRY_BACKOFF_MS", default_value_t = 200)]
+    pub retry_backoff_ms: u64,
+}
+
+fn parse_header(header: &str) -> Result<(String, String), String> {
+    let (key, value) = header
+        .split_once('=')
+        .ok_or_else(|| format!("Header {} is not key=value", header))?;
+    let key = key.trim().to_lowercase();
+    MetadataKey::<Ascii>::from_bytes(key.as_bytes())
+        .map_err(|_| format!("Invalid header name {}", key))?;
+    let value = value.trim();
+    MetadataValue::<Ascii>::try_from(value)
+        .map_err(|_| format!("Invalid value for header {}", key))?;
+    Ok((key, value.to_string()))
+}
+
+impl ConnectionOptions {
+    /// The URL of `address`, either a URL or a `host[:port]`.
+    pub fn url(&self, address: &str) -> Result<String, String> {
+        let Some((scheme, _)) = address.split_once("://") else {
+            // The brackets of IPv6 addresses hold colons of their own
+            let host_end = address.rfind(']').map_or(0, |index| index + 1);
+            return Ok(if address[host_end..].contains(':') {
+                format!("{}://{}", self.tls.scheme(), address)
+            } else {
+                format!("{}://{}:{}", self.tls.scheme(), address, DEFAULT_PORT)
+            });
+        };
+        match scheme {
+            "https" => Ok(address.to_string()),
+            "http" if self.tls.enabled() => {
+                Err(format!("TLS options given for the plaintext {}", address))
+            }
+            "http" => Ok(address.to_string()),
+            _ => Err(format!("Unsupported scheme {} in {}", scheme, address)),
+        }
+    }
+
+    /// The endpoint of `address`, over TLS for `https://` ones even without
+    /// `--tls`.
+    pub fn endpoint(&self, address: &str) -> Result<Endpoint, Box<dyn Error>> {
+        let url = self.url(address)?;
+        let mut endpoint = Endpoint::from_shared(url.clone())?;
+
+        if url.starts_with("https://") {
+            let tls = ClientTlsOptions {
+                tls: true,
+                ..self.tls.clone()
+            };
+            if let Some(config) = tls.client_config()? {
+                endpoint = endpoint.tls_config(config)?;
+            }
+        }
+
+        Ok(endpoint)
+    }
+
+    /// The `--header`s, as sent along every request.
+    pub fn metadata(&self) -> MetadataMap {
+        let mut metadata = MetadataMap::new();
+        for (key, value) in &self.headers {
+            // Both checked when parsed
+            if let (Ok(key), Ok(value)) = (
+                MetadataKey::from_bytes(key.as_bytes()),
+                MetadataValue::try_from(value),
+            ) {
+                metadata.append(key, value);
+            }
+        }
+        metadata
+    }
+
+    /// Wraps a channel to send the trace context and `--header`s.
+    pub fn traced(&self, channel: Channel) -> TracedChannel {
+        telemetry::traced_with(channel, self.metadata())
+    }
+
+    /// Connects to `address`, attempting again while the service is
+    /// unreachable, and returns a channel retrying requests the same way.
+    pub async fn connect(
+        &self,
+        address: &str,
+        options: &RequestOptions,
+    ) -> Result<TracedChannel<Retrying>, Box<dyn Error>> {
+        let mut endpoint = self
+            .endpoint(address)?
+            .connect_timeout(Duration::from_millis(options.connect_timeout_ms));
+        if options.request_timeout_ms > 0 {
+            endpoint = endpoint.timeout(Duration::from_millis(options.request_timeout_ms));
+        }
+
+        let mut attempts = 1;
+        let channel = loop {
+            match endpoint.connect().await {
+                Ok(channel) => break channel,
+                Err(e) if attempts <= options.retries => {
+                    tracing::debug!(%address, attempts, error = %e, "failed to connect, retrying");
+                    tokio::time::sleep(options.backoff(attempts)).await;
+                    attempts += 1;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+
+        let retrying = Retrying {
+            channel,
+            retries: options.retries,
+            initial_backoff: Duration::from_millis(options.retry_backoff_ms),
+        };
+        Ok(telemetry::traced_with(retrying, self.metadata()))
+    }
+}
+
+impl RequestOptions {
+    /// Delay before the attempt following attempt number `attempts`.
+    fn backoff(&self, attempts: u32) -> Duration {
+        backoff(Duration::from_millis(self.retry_backoff_ms), attempts)
+    }
+}
+
+fn backoff(initial: Duration, attempts: u32) -> Duration {
+    let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+    initial.saturating_mul(factor).min(MAX_BACKOFF)
+}
+
+/// Channel sending requests again when the service is unavailable, that is
+/// unreachable or, for the idempotent methods, answering `Unavailable`, e.g.
+/// while restarting. Requests are buffered to be sent again, which suits the
+/// single message ones of the auth service.
+#[derive(Debug, Clone)]
+pub struct Retrying {
+    channel: Channel,
+    retries: u32,
+    initial_backoff: Duration,
+}
+
+impl Service<http::Request<BoxBody>> for Retrying {
+    type Response = http::Response<BoxBody>;
+    type Error = StdError;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.channel.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        // The channel that was polled ready, leaving a clone in its place
+        let ready = self.channel.clone();
+        let mut channel = std::mem::replace(&mut self.channel, ready);
+        let Retrying {
+            retries,
+            initial_backoff,
+            ..
+        } = *self;
+        if retries == 0 {
+            let response = channel.call(request);
+            return Box::pin(async move { Ok(response.await?) });
+        }
+
+        let idempotent = IDEMPOTENT_METHODS.contains(&request.uri().path());
+        Box::pin(async move {
+            let (parts, request_body) = request.into_parts();
+            let request_body = request_body.collect().await?.to_bytes();
+            let request = http::Request::from_parts(parts, ());
+
+            let mut attempts = 1;
+            loop {
+                let attempt = request
+                    .clone()
+                    .map(|()| body::boxed(Full::new(request_body.clone())));
+                let result = channel.call(attempt).await;
+                let unavailable = match &result {
+                    Ok(response) => {
+                        idempotent
+                            && response.headers().get("grpc-status")
+                                == Some(&(Code::Unavailable as i32).into())
+                    }
+                    Err(e) => is_connect_error(e),
+                };
+                if !unavailable || attempts > retries {
+                    return Ok(result?);
+                }
+
+                tracing::debug!(uri = %request.uri(), attempts, "service unavailable, retrying");
+                tokio::time::sleep(backoff(initial_backoff, attempts)).await;
+                attempts += 1;
+                std::future::poll_fn(|cx| channel.poll_ready(cx)).await?;
+            }
+        })
+    }
+}
+
+/// Whether the request failed before being sent, so that sending it again
+/// cannot repeat its effects.
+fn is_connect_error(error: &(dyn Error + 'static)) -> bool {
+    let mut source = Some(error);
+    while let Some(error) = source {
+        if error.is::<ConnectError>() {
+            return true;
+        }
+        source = error.source();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic_health::pb::{health_client::HealthClient, HealthCheckRequest};
+
+    use super::*;
+
+    #[test]
+    fn should_complete_addresses_into_urls() {
+        let plaintext = ConnectionOptions::default();
+        let tls = ConnectionOptions {
+            tls: ClientTlsOptions {
+                tls: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        assert_eq!(
+            plaintext.url("localhost").unwrap(),
+            "http://localhost:50051"
+        );
+        assert_eq!(plaintext.url("[::1]").unwrap(), "http://[::1]:50051");
+        assert_eq!(plaintext.url("[::1]:8080").unwrap(), "http://[::1]:8080");
+        assert_eq!(tls.url("auth:443").unwrap(), "https://auth:443");
+        assert_eq!(
+            plaintext.url("https://auth.example.com").unwrap(),
+            "https://auth.example.com"
+        );
+        assert!(tls.url("http://auth:50051").is_err());
+        assert!(plaintext.url("ftp://auth").is_err());
+    }
+
+    #[test]
+    fn should_parse_headers() {
+        let options = ConnectionOptions {
+            headers: vec![parse_header("X-Tenant = acme").unwrap()],
+            ..Default::default()
+        };
+
+        assert_eq!(options.metadata().get("x-tenant").unwrap(), "acme");
+        assert!(parse_header("x-tenant").is_err());
+        assert!(parse_header("x tenant=acme").is_err());
+        assert!(parse_header("x-tenant=line\nbreak").is_err());
+    }
+
+    #[test]
+    fn should_back_off_exponentially() {
+        let initial = Duration::from_millis(200);
+
+        assert_eq!(backoff(initial, 1), Duration::from_millis(200));
+        assert_eq!(backoff(initial, 3), Duration::from_millis(800));
+        assert_eq!(backoff(initial, 100), MAX_BACKOFF);
+    }
+
+    /// Serves health checks on `port` until `stop` resolves.
+    async fn serve_health(port: u16, stop: impl std::future::Future<Output = ()>) {
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", port))
+            .await
+            .unwrap();
+        let (_, health) = tonic_health::server::health_reporter();
+        tonic::transport::Server::builder()
+            .add_service(health)
+            .serve_with_incoming_shutdown(TcpListenerStream::new(listener), stop)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_retry_until_service_is_up() {
+        // A free port, for the service to start on after the first attempts
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let options = RequestOptions {
+            connect_timeout_ms: 1_000,
+            request_timeout_ms: 1_000,
+            retries: 5,
+            retry_backoff_ms: 100,
+        };
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(250)).await;
+            serve_health(port, std::future::pending()).await
+        });
+
+        let channel = ConnectionOptions::default()
+            .connect(&format!("127.0.0.1:{}", port), &options)
+            .await
+            .unwrap();
+        let response = HealthClient::new(channel)
+            .check(HealthCheckRequest {
+                service: String::new(),
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(response.into_inner().status, 1);
+    }
+
+    #[tokio::test]
+    async fn should_resend_requests_while_service_restarts() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let options = RequestOptions {
+            connect_timeout_ms: 1_000,
+            request_timeout_ms: 1_000,
+            retries: 5,
+            retry_backoff_ms: 100,
+        };
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let first = tokio::spawn(serve_health(port, async {
+            let _ = stopped.await;
+        }));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let channel = ConnectionOptions::default()
+            .connect(&format!("127.0.0.1:{}", port), &options)
+            .await
+            .unwrap();
+        let mut client = HealthClient::new(channel);
+        let check = || HealthCheckRequest {
+            service: String::new(),
+        };
+        client.check(check()).await.unwrap();
+
+        stop.send(()).unwrap();
+        first.await.unwrap();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(250)).await;
+            serve_health(port, std::future::pending()).await
+        });
+
+        assert_eq!(client.check(check()).await.unwrap().into_inner().status, 1);
+    }
+
+    /// Answers every call with `Unavailable`, counting them.
+    #[derive(Clone, Default)]
+    struct Unavailable(std::sync::Arc<std::sync::atomic::AtomicUsize>);
+
+    impl tonic::server::NamedService for Unavailable {
+        const NAME: &'static str = "authentication.Authentication";
+    }
+
+    impl Service<http::Request<BoxBody>> for Unavailable {
+        type Response = http::Response<BoxBody>;
+        type Error = std::convert::Infallible;
+        type Future = std::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: http::Request<BoxBody>) -> Self::Future {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            std::future::ready(Ok(tonic::Status::unavailable("restarting").into_http()))
+        }
+    }
+
+    #[tokio::test]
+    async fn should_only_resend_idempotent_methods_answered_unavailable() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let service = Unavailable::default();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(service.clone())
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        let options = RequestOptions {
+            connect_timeout_ms: 1_000,
+            request_timeout_ms: 1_000,
+            retries: 2,
+            retry_backoff_ms: 10,
+        };
+        let channel = ConnectionOptions::default()
+            .connect(&format!("127.0.0.1:{}", port), &options)
+            .await
+            .unwrap();
+        let mut grpc = tonic::client::Grpc::new(channel);
+
+        let mut calls = Vec::new();
+        for method in ["SignUp", "GetProfile"] {
+            grpc.ready().await.unwrap();
+            let status = grpc
+                .unary::<(), (), _>(
+                    tonic::Request::new(()),
+                    format!("/authentication.Authentication/{}", method)
+                        .parse()
+                        .unwrap(),
+                    tonic::codec::ProstCodec::default(),
+                )
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), Code::Unavailable);
+            calls.push(service.0.swap(0, std::sync::atomic::Ordering::SeqCst));
+        }
+
+        assert_eq!(calls, [1, 3]);
+    }
+
+    #[tokio::test]
+    async fn should_fail_once_out_of_retries() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let options = RequestOptions {
+            connect_timeout_ms: 1_000,
+            request_timeout_ms: 1_000,
+            retries: 1,
+            retry_backoff_ms: 10,
+        };
+
+        let result = ConnectionOptions::default()
+            .connect(&format!("127.0.0.1:{}", port), &options)
+            .await;
+
+        assert!(result.is_err());
+    }
+}
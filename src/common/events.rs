@@ -0,0 +1,28 @@
+//! Event type names shared by the auth service and its clients, which each
+//! generate their own `EventType`.
+
+/// The proto name of an event type named as in the event payloads, e.g.
+/// `EVENT_TYPE_SIGNED_IN` for `signed_in`, also accepting `signed-in`. `None`
+/// for the unspecified type, which names no event.
+pub fn event_type_str_name(name: &str) -> Option<String> {
+    let str_name = format!("EVENT_TYPE_{}", name.to_uppercase().replace('-', "_"));
+    (str_name != "EVENT_TYPE_UNSPECIFIED").then_some(str_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_name_event_types_as_proto() {
+        assert_eq!(
+            event_type_str_name("signed_in").as_deref(),
+            Some("EVENT_TYPE_SIGNED_IN")
+        );
+        assert_eq!(
+            event_type_str_name("sign-out-failed").as_deref(),
+            Some("EVENT_TYPE_SIGN_OUT_FAILED")
+        );
+        assert_eq!(event_type_str_name("unspecified"), None);
+    }
+}
@@ -0,0 +1,10 @@
+//! Code shared by the binaries that talk to the auth service.
+
+pub mod audit;
+pub mod capture;
+pub mod connection;
+pub mod events;
+pub mod logging;
+pub mod secrets;
+pub mod telemetry;
+pub mod tls;
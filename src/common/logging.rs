@@ -0,0 +1,125 @@
+use clap::{Args, ValueEnum};
+use opentelemetry_sdk::trace::TracerProvider;
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+use crate::telemetry;
+
+/// Output format of the logs.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable, multi-line output
+    #[default]
+    Pretty,
+    /// One JSON object per line
+    Json,
+}
+
+/// Logging options shared by the clients of the auth service.
+#[derive(Args, Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogOptions {
+    /// Log level or filter directives, e.g. `info` or `warn,client=debug`
+    #[arg(long, env = "LOG_LEVEL")]
+    pub log_level: Option<String>,
+    /// Log output format
+    #[arg(long, env = "LOG_FORMAT", value_enum)]
+    pub log_format: Option<LogFormat>,
+    /// OTLP/gRPC collector to export traces to, e.g. `http://localhost:4317`
+    #[arg(long, env = "OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+}
+
+impl LogOptions {
+    /// Installs the global subscriber, using `default_level` when no level
+    /// was given.
+    pub fn init(&self, default_level: &str, service_name: &str) -> Result<Telemetry, String> {
+        init(
+            self.log_level.as_deref().unwrap_or(default_level),
+            self.log_format.unwrap_or_default(),
+            self.otlp_endpoint.as_deref(),
+            service_name,
+        )
+    }
+}
+
+/// Keeps the trace exporter of [`init`] alive.
+#[must_use = "dropping the telemetry handle loses the spans not exported yet"]
+pub struct Telemetry {
+    provider: Option<TracerProvider>,
+}
+
+impl Telemetry {
+    /// Flushes the spans not exported yet and stops the exporter.
+    pub async fn shutdown(self) {
+        if let Some(provider) = self.provider {
+            let _ = tokio::task::spawn_blocking(move || provider.shutdown()).await;
+        }
+    }
+}
+
+/// Installs the global `tracing` subscriber, exporting spans to the OTLP
+/// collector at `otlp_endpoint` when given. Logs go to stderr so they never
+/// mix with the output of the command line tools.
+pub fn init(
+    level: &str,
+    format: LogFormat,
+    otlp_endpoint: Option<&str>,
+    service_name: &str,
+) -> Result<Telemetry, String> {
+    let filter =
+        EnvFilter::try_new(level).map_err(|e| format!("Invalid log level {}: {}", level, e))?;
+
+    telemetry::set_propagator();
+    let (provider, otel_layer) = match otlp_endpoint {
+        Some(endpoint) => {
+            let (provider, tracer) = telemetry::tracer(endpoint, service_name)?;
+            let layer = tracing_opentelemetry::layer().with_tracer(tracer);
+            (Some(provider), Some(layer))
+        }
+        None => (None, None),
+    };
+
+    let registry = tracing_subscriber::registry().with(filter).with(otel_layer);
+
+    match format {
+        LogFormat::Pretty => registry
+            .with(fmt::layer().with_writer(std::io::stderr))
+            .try_init(),
+        LogFormat::Json => registry
+            .with(
+                fmt::layer()
+                    .json()
+                    .with_current_span(true)
+                    .with_span_list(true)
+                    .with_writer(std::io::stderr),
+            )
+            .try_init(),
+    }
+    .map_err(|e| format!("Failed to initialize logging: {}", e))?;
+
+    Ok(Telemetry { provider })
+}
+
+/// Validates log filter directives without installing anything.
+pub fn validate_level(level: &str) -> Result<(), String> {
+    EnvFilter::try_new(level)
+        .map(|_| ())
+        .map_err(|e| format!("Invalid log level {}: {}", level, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_accept_filter_directives() {
+        assert!(validate_level("info").is_ok());
+        assert!(validate_level("warn,auth=debug").is_ok());
+    }
+
+    #[test]
+    fn should_reject_invalid_level() {
+        assert!(validate_level("auth=loud").is_err());
+    }
+}
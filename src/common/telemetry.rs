@@ -0,0 +1,225 @@
+use opentelemetry::{
+    global,
+    propagation::{Extractor, Injector},
+    trace::TracerProvider as _,
+    Context, KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    runtime,
+    trace::{Tracer, TracerProvider},
+    Resource,
+};
+use tonic::{
+    codegen::http::HeaderMap,
+    metadata::{KeyAndValueRef, MetadataKey, MetadataMap, MetadataValue},
+    service::{interceptor::InterceptedService, Interceptor},
+    transport::Channel,
+    Request, Status,
+};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Builds a tracer exporting spans in batches to the OTLP/gRPC collector at
+/// `endpoint`. Must be called from within a Tokio runtime.
+pub fn tracer(endpoint: &str, service_name: &str) -> Result<(TracerProvider, Tracer), String> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()
+        .map_err(|e| format!("Failed to create OTLP exporter: {}", e))?;
+
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(Resource::new([KeyValue::new(
+            "service.name",
+            service_name.to_owned(),
+        )]))
+        .build();
+    let tracer = provider.tracer(service_name.to_owned());
+
+    Ok((provider, tracer))
+}
+
+/// Makes W3C `traceparent`/`tracestate` the propagation format.
+pub fn set_propagator() {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+}
+
+struct MetadataInjector<'a>(&'a mut MetadataMap);
+
+impl Injector for MetadataInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(key), Ok(value)) = (
+            MetadataKey::from_bytes(key.as_bytes()),
+            MetadataValue::try_from(value),
+        ) {
+            self.0.insert(key, value);
+        }
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+/// Writes `context` into the gRPC metadata of an outgoing request.
+pub fn inject(context: &Context, metadata: &mut MetadataMap) {
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(context, &mut MetadataInjector(metadata))
+    });
+}
+
+/// Reads the caller's trace context from the headers of an incoming request.
+pub fn extract(headers: &HeaderMap) -> Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)))
+}
+
+/// Client interceptor propagating the trace of the current span to the
+/// server, along with fixed metadata.
+#[derive(Debug, Clone, Default)]
+pub struct PropagateContext {
+    metadata: MetadataMap,
+}
+
+impl Interceptor for PropagateContext {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        for (key, value) in self.metadata.iter().filter_map(|entry| match entry {
+            KeyAndValueRef::Ascii(key, value) => Some((key, value)),
+            KeyAndValueRef::Binary(..) => None,
+        }) {
+            request.metadata_mut().insert(key.clone(), value.clone());
+        }
+        inject(&tracing::Span::current().context(), request.metadata_mut());
+        Ok(request)
+    }
+}
+
+/// Channel whose requests carry the caller's trace context.
+pub type TracedChannel<S = Channel> = InterceptedService<S, PropagateContext>;
+
+pub fn traced(channel: Channel) -> TracedChannel {
+    traced_with(channel, MetadataMap::new())
+}
+
+/// Like [`traced`], also sending `metadata` with every request.
+pub fn traced_with<S>(service: S, metadata: MetadataMap) -> TracedChannel<S> {
+    InterceptedService::new(service, PropagateContext { metadata })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{
+        SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState,
+    };
+    use opentelemetry_proto::tonic::collector::trace::v1::{
+        trace_service_server::{TraceService, TraceServiceServer},
+        ExportTraceServiceRequest, ExportTraceServiceResponse,
+    };
+    use tokio::sync::mpsc;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    /// Stands in for an OTLP collector, forwarding every export.
+    struct Collector(mpsc::UnboundedSender<ExportTraceServiceRequest>);
+
+    #[tonic::async_trait]
+    impl TraceService for Collector {
+        async fn export(
+            &self,
+            request: Request<ExportTraceServiceRequest>,
+        ) -> Result<tonic::Response<ExportTraceServiceResponse>, Status> {
+            let _ = self.0.send(request.into_inner());
+            Ok(tonic::Response::new(ExportTraceServiceResponse {
+                partial_success: None,
+            }))
+        }
+    }
+
+    const TRACEPARENT: &str = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+
+    #[test]
+    fn should_inject_traceparent() {
+        set_propagator();
+        let span_context = SpanContext::new(
+            TraceId::from_hex("0af7651916cd43dd8448eb211c80319c").unwrap(),
+            SpanId::from_hex("b7ad6b7169203331").unwrap(),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        let context = Context::new().with_remote_span_context(span_context);
+
+        let mut metadata = MetadataMap::new();
+        inject(&context, &mut metadata);
+
+        assert_eq!(metadata.get("traceparent").unwrap(), TRACEPARENT);
+    }
+
+    #[test]
+    fn should_extract_traceparent() {
+        set_propagator();
+        let mut headers = HeaderMap::new();
+        headers.insert("traceparent", TRACEPARENT.parse().unwrap());
+
+        let context = extract(&headers);
+
+        assert_eq!(
+            context.span().span_context().trace_id(),
+            TraceId::from_hex("0af7651916cd43dd8448eb211c80319c").unwrap()
+        );
+    }
+
+    #[test]
+    fn should_not_inject_without_trace() {
+        set_propagator();
+        let mut metadata = MetadataMap::new();
+
+        inject(&Context::new(), &mut metadata);
+
+        assert!(metadata.get("traceparent").is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn should_export_spans_to_collector() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(TraceServiceServer::new(Collector(tx)))
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+        );
+
+        let (provider, tracer) =
+            tracer(&format!("http://127.0.0.1:{}", port), "test-service").unwrap();
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("request", rpc.method = "SignIn").in_scope(|| {});
+        });
+        tokio::task::spawn_blocking(move || provider.shutdown())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let export = rx.recv().await.unwrap();
+        let resource_spans = &export.resource_spans[0];
+        let service_name = &resource_spans.resource.as_ref().unwrap().attributes[0];
+        assert_eq!(service_name.key, "service.name");
+        let span = &resource_spans.scope_spans[0].spans[0];
+        assert_eq!(span.name, "request");
+        assert!(span
+            .attributes
+            .iter()
+            .any(|attribute| attribute.key == "rpc.method"));
+    }
+}
@@ -0,0 +1,99 @@
+use std::{fs, path::PathBuf};
+
+use clap::Args;
+use tonic::transport::{Certificate, ClientTlsConfig, Identity};
+
+/// TLS options shared by the clients of the auth service.
+#[derive(Args, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientTlsOptions {
+    /// Connect to the auth service over TLS
+    #[arg(long, env = "AUTH_TLS")]
+    pub tls: bool,
+    /// CA certificate (PEM) used to verify the server, implies --tls
+    #[arg(long, env = "AUTH_TLS_CA_CERT")]
+    pub tls_ca_cert: Option<PathBuf>,
+    /// Client certificate (PEM) for mutual TLS, implies --tls
+    #[arg(long, env = "AUTH_TLS_CERT", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+    /// Client private key (PEM) for mutual TLS
+    #[arg(long, env = "AUTH_TLS_KEY", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+    /// Server name to verify, defaults to the host being connected to
+    #[arg(long, env = "AUTH_TLS_DOMAIN")]
+    pub tls_domain: Option<String>,
+}
+
+impl ClientTlsOptions {
+    pub fn enabled(&self) -> bool {
+        self.tls || self.tls_ca_cert.is_some() || self.tls_cert.is_some()
+    }
+
+    pub fn scheme(&self) -> &'static str {
+        if self.enabled() {
+            "https"
+        } else {
+            "http"
+        }
+    }
+
+    /// Builds the tonic TLS configuration, `None` when TLS is disabled.
+    /// Without a CA certificate the server is verified against the bundled
+    /// web PKI roots.
+    pub fn client_config(&self) -> Result<Option<ClientTlsConfig>, std::io::Error> {
+        if !self.enabled() {
+            return Ok(None);
+        }
+
+        let mut config = ClientTlsConfig::new();
+
+        config = match &self.tls_ca_cert {
+            Some(path) => config.ca_certificate(Certificate::from_pem(fs::read(path)?)),
+            None => config.with_webpki_roots(),
+        };
+
+        if let (Some(cert), Some(key)) = (&self.tls_cert, &self.tls_key) {
+            config = config.identity(Identity::from_pem(fs::read(cert)?, fs::read(key)?));
+        }
+
+        if let Some(domain) = &self.tls_domain {
+            config = config.domain_name(domain);
+        }
+
+        Ok(Some(config))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_be_disabled_by_default() {
+        let options = ClientTlsOptions::default();
+
+        assert!(!options.enabled());
+        assert_eq!(options.scheme(), "http");
+        assert!(options.client_config().unwrap().is_none());
+    }
+
+    #[test]
+    fn should_be_enabled_by_ca_certificate() {
+        let options = ClientTlsOptions {
+            tls_ca_cert: Some("ca.pem".into()),
+            ..Default::default()
+        };
+
+        assert!(options.enabled());
+        assert_eq!(options.scheme(), "https");
+    }
+
+    #[test]
+    fn should_fail_if_ca_certificate_is_missing() {
+        let options = ClientTlsOptions {
+            tls_ca_cert: Some("does-not-exist.pem".into()),
+            ..Default::default()
+        };
+
+        assert!(options.client_config().is_err());
+    }
+}
@@ -0,0 +1,477 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::OpenOptions,
+    io::Write,
+    path::PathBuf,
+    time::Duration,
+};
+
+use serde::Serialize;
+use tokio::{process::Command, sync::mpsc, task::JoinHandle};
+use tracing::{debug, error, warn};
+
+use crate::{
+    config::{AlertingConfig, NotifierConfig},
+    health::Status,
+    status::now_ms,
+};
+
+/// How often flapping targets are checked for having settled.
+const SETTLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A target changed status.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Alert {
+    pub target: String,
+    pub from: Status,
+    pub to: Status,
+    /// In milliseconds since the Unix epoch
+    pub at_ms: u64,
+    /// Of the probe causing the change
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The target changes status too often, further alerts are held back
+    /// until it settles
+    pub flapping: bool,
+}
+
+#[tonic::async_trait]
+pub trait Notifier: Send + Sync {
+    /// Names the notifier in logs.
+    fn describe(&self) -> String;
+
+    async fn notify(&self, alert: &Alert) -> Result<(), String>;
+}
+
+/// POSTs alerts as JSON.
+pub struct WebhookNotifier {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: &str, timeout: Duration) -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|e| format!("Failed to create webhook client: {}", e))?;
+        Ok(Self {
+            url: url.to_string(),
+            client,
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl Notifier for WebhookNotifier {
+    fn describe(&self) -> String {
+        format!("webhook {}", self.url)
+    }
+
+    async fn notify(&self, alert: &Alert) -> Result<(), String> {
+        let response = self
+            .client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_string(alert).map_err(|e| e.to_string())?)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("Webhook answered {}", response.status()))
+        }
+    }
+}
+
+/// Appends alerts to a file, one JSON object per line.
+pub struct LogNotifier {
+    path: PathBuf,
+}
+
+impl LogNotifier {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+#[tonic::async_trait]
+impl Notifier for LogNotifier {
+    fn describe(&self) -> String {
+        format!("log {}", self.path.display())
+    }
+
+    async fn notify(&self, alert: &Alert) -> Result<(), String> {
+        let mut line = serde_json::to_string(alert).map_err(|e| e.to_string())?;
+        line.push('\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .map_err(|e| format!("Failed to write {}: {}", self.path.display(), e))
+    }
+}
+
+/// Runs a program for each alert, passing it in `HEALTH_CHECK_ALERT_*`
+/// environment variables, the whole alert as JSON in `HEALTH_CHECK_ALERT`.
+pub struct CommandNotifier {
+    program: PathBuf,
+    args: Vec<String>,
+    timeout: Duration,
+}
+
+impl CommandNotifier {
+    pub fn new(program: PathBuf, args: Vec<String>, timeout: Duration) -> Self {
+        Self {
+            program,
+            args,
+            timeout,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Notifier for CommandNotifier {
+    fn describe(&self) -> String {
+        format!("command {}", self.program.display())
+    }
+
+    async fn notify(&self, alert: &Alert) -> Result<(), String> {
+        let status = Command::new(&self.program)
+            .args(&self.args)
+            .env(
+                "HEALTH_CHECK_ALERT",
+                serde_json::to_string(alert).map_err(|e| e.to_string())?,
+            )
+            .env("HEALTH_CHECK_ALERT_TARGET", &alert.target)
+            .env("HEALTH_CHECK_ALERT_FROM", alert.from.to_string())
+            .env("HEALTH_CHECK_ALERT_TO", alert.to.to_string())
+            .env(
+                "HEALTH_CHECK_ALERT_ERROR",
+                alert.error.as_deref().unwrap_or_default(),
+            )
+            .env("HEALTH_CHECK_ALERT_FLAPPING", alert.flapping.to_string())
+            .kill_on_drop(true)
+            .status();
+
+        let status = tokio::time::timeout(self.timeout, status)
+            .await
+            .map_err(|_| format!("Timed out after {} s", self.timeout.as_secs()))?
+            .map_err(|e| format!("Failed to run {}: {}", self.program.display(), e))?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(format!("{} exited with {}", self.program.display(), status))
+        }
+    }
+}
+
+/// Recent status changes of a target, to tell when it flaps.
+#[derive(Debug, Default)]
+struct Damper {
+    changes: VecDeque<u64>,
+    /// Status of the target when it started flapping
+    flapping_from: Option<Status>,
+    /// Last change held back while flapping
+    held_back: Option<Alert>,
+}
+
+/// Sends alerts to every notifier, damping the ones of flapping targets.
+pub struct Alerter {
+    notifiers: Vec<Box<dyn Notifier>>,
+    flap_window_ms: u64,
+    flap_threshold: usize,
+    dampers: HashMap<String, Damper>,
+}
+
+impl Alerter {
+    pub fn new(
+        notifiers: Vec<Box<dyn Notifier>>,
+        flap_window: Duration,
+        flap_threshold: usize,
+    ) -> Self {
+        Self {
+            notifiers,
+            flap_window_ms: flap_window.as_millis() as u64,
+            flap_threshold,
+            dampers: HashMap::new(),
+        }
+    }
+
+    pub fn from_config(config: &AlertingConfig) -> Result<Self, String> {
+        let notifiers = config
+            .notifiers
+            .iter()
+            .map(|notifier| {
+                let notifier: Box<dyn Notifier> = match notifier {
+                    NotifierConfig::Webhook { url, timeout_secs } => Box::new(
+                        WebhookNotifier::new(url, Duration::from_secs(*timeout_secs))?,
+                    ),
+                    NotifierConfig::Log { path } => Box::new(LogNotifier::new(path.clone())),
+                    NotifierConfig::Command {
+                        program,
+                        args,
+                        timeout_secs,
+                    } => Box::new(CommandNotifier::new(
+                        program.clone(),
+                        args.clone(),
+                        Duration::from_secs(*timeout_secs),
+                    )),
+                };
+                Ok(notifier)
+            })
+            .collect::<Result<_, String>>()?;
+
+        Ok(Self::new(
+            notifiers,
+            Duration::from_secs(config.flap_window_secs),
+            config.flap_threshold,
+        ))
+    }
+
+    /// The alert to send for a status change, none while the target flaps.
+    fn damp(&mut self, alert: Alert) -> Option<Alert> {
+        let damper = self.dampers.entry(alert.target.clone()).or_default();
+        damper.changes.push_back(alert.at_ms);
+        while damper
+            .changes
+            .front()
+            .is_some_and(|at_ms| *at_ms + self.flap_window_ms <= alert.at_ms)
+        {
+            damper.changes.pop_front();
+        }
+
+        if damper.flapping_from.is_some() {
+            damper.held_back = Some(alert);
+            return None;
+        }
+        if damper.changes.len() > self.flap_threshold {
+            damper.flapping_from = Some(alert.from);
+            return Some(Alert {
+                flapping: true,
+                ..alert
+            });
+        }
+        Some(alert)
+    }
+
+    /// Ends the flapping of the targets without a change for a whole window,
+    /// returning the alerts telling where they settled.
+    fn settle(&mut self, now_ms: u64) -> Vec<Alert> {
+        let mut settled = Vec::new();
+        for damper in self.dampers.values_mut() {
+            let Some(from) = damper.flapping_from else {
+                continue;
+            };
+            if damper
+                .changes
+                .back()
+                .is_some_and(|at_ms| at_ms + self.flap_window_ms > now_ms)
+            {
+                continue;
+            }
+
+            damper.changes.clear();
+            damper.flapping_from = None;
+            if let Some(alert) = damper.held_back.take() {
+                settled.push(Alert {
+                    from,
+                    at_ms: now_ms,
+                    ..alert
+                });
+            }
+        }
+        settled
+    }
+
+    async fn send(&self, alert: &Alert) {
+        debug!(target_name = %alert.target, from = %alert.from, to = %alert.to, "sending alert");
+        for notifier in &self.notifiers {
+            if let Err(e) = notifier.notify(alert).await {
+                error!(error = %e, notifier = %notifier.describe(), "failed to send alert");
+            }
+        }
+    }
+
+    /// Sends the alerts received on the returned channel until it closes.
+    pub fn start(mut self) -> (mpsc::UnboundedSender<Alert>, JoinHandle<()>) {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<Alert>();
+        let task = tokio::spawn(async move {
+            let mut settle_check = tokio::time::interval(SETTLE_CHECK_INTERVAL);
+            loop {
+                tokio::select! {
+                    alert = receiver.recv() => {
+                        let Some(alert) = alert else {
+                            return;
+                        };
+                        if let Some(alert) = self.damp(alert) {
+                            if alert.flapping {
+                                warn!(target_name = %alert.target, "target is flapping, holding back its alerts");
+                            }
+                            self.send(&alert).await;
+                        }
+                    }
+                    _ = settle_check.tick() => {
+                        for alert in self.settle(now_ms()) {
+                            self.send(&alert).await;
+                        }
+                    }
+                }
+            }
+        });
+        (sender, task)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::State, routing::post, Router};
+    use std::sync::{Arc, Mutex};
+
+    fn alert(from: Status, to: Status, at_ms: u64) -> Alert {
+        Alert {
+            target: "auth-1".to_string(),
+            from,
+            to,
+            at_ms,
+            error: None,
+            flapping: false,
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}-{}", name, uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn should_hold_back_alerts_while_flapping() {
+        let mut alerter = Alerter::new(Vec::new(), Duration::from_secs(60), 2);
+
+        assert!(alerter
+            .damp(alert(Status::Healthy, Status::Degraded, 1_000))
+            .is_some());
+        assert!(alerter
+            .damp(alert(Status::Degraded, Status::Healthy, 2_000))
+            .is_some());
+        let flapping = alerter
+            .damp(alert(Status::Healthy, Status::Down, 3_000))
+            .unwrap();
+        assert!(flapping.flapping);
+        assert!(alerter
+            .damp(alert(Status::Down, Status::Healthy, 4_000))
+            .is_none());
+        assert!(alerter
+            .damp(alert(Status::Healthy, Status::Degraded, 5_000))
+            .is_none());
+
+        assert!(alerter.settle(64_000).is_empty());
+        let settled = alerter.settle(65_000);
+        assert_eq!(settled.len(), 1);
+        assert_eq!(settled[0].from, Status::Healthy);
+        assert_eq!(settled[0].to, Status::Degraded);
+        assert!(!settled[0].flapping);
+
+        assert!(alerter
+            .damp(alert(Status::Degraded, Status::Healthy, 70_000))
+            .is_some());
+    }
+
+    #[test]
+    fn should_forget_changes_older_than_window() {
+        let mut alerter = Alerter::new(Vec::new(), Duration::from_secs(1), 1);
+
+        for at_ms in [0, 1_000, 2_000, 3_000] {
+            assert!(
+                !alerter
+                    .damp(alert(Status::Healthy, Status::Down, at_ms))
+                    .unwrap()
+                    .flapping
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn should_append_alerts_to_log() {
+        let path = temp_path("alerts");
+        let notifier = LogNotifier::new(path.clone());
+
+        notifier
+            .notify(&alert(Status::Healthy, Status::Down, 1_000))
+            .await
+            .unwrap();
+        notifier
+            .notify(&alert(Status::Down, Status::Healthy, 2_000))
+            .await
+            .unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<_> = contents.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("\"from\":\"healthy\",\"to\":\"down\""));
+    }
+
+    #[tokio::test]
+    async fn should_run_command_with_alert_in_environment() {
+        let path = temp_path("command");
+        let notifier = CommandNotifier::new(
+            "sh".into(),
+            vec![
+                "-c".to_string(),
+                format!(
+                    "echo \"$HEALTH_CHECK_ALERT_TARGET $HEALTH_CHECK_ALERT_TO\" > {}",
+                    path.display()
+                ),
+            ],
+            Duration::from_secs(10),
+        );
+
+        notifier
+            .notify(&alert(Status::Healthy, Status::Down, 1_000))
+            .await
+            .unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents, "auth-1 down\n");
+
+        let failing = CommandNotifier::new("false".into(), Vec::new(), Duration::from_secs(10));
+        assert!(failing
+            .notify(&alert(Status::Healthy, Status::Down, 1_000))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn should_post_alerts_to_webhook() {
+        let received = Arc::new(Mutex::new(Vec::<String>::new()));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let router = Router::new()
+            .route(
+                "/hook",
+                post(
+                    |State(received): State<Arc<Mutex<Vec<String>>>>, body: String| async move {
+                        received.lock().unwrap().push(body);
+                    },
+                ),
+            )
+            .with_state(received.clone());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        let notifier = WebhookNotifier::new(&url, Duration::from_secs(10)).unwrap();
+
+        notifier
+            .notify(&alert(Status::Healthy, Status::Down, 1_000))
+            .await
+            .unwrap();
+
+        let received = received.lock().unwrap();
+        let alert: serde_json::Value = serde_json::from_str(&received[0]).unwrap();
+        assert_eq!(alert["target"], "auth-1");
+        assert_eq!(alert["to"], "down");
+    }
+}
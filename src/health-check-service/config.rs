@@ -0,0 +1,229 @@
+use std::{fs, path::PathBuf};
+
+use serde::Deserialize;
+
+use crate::probes::Probe;
+
+const DEFAULT_PORT: u16 = 50051;
+const DEFAULT_FLAP_WINDOW_SECS: u64 = 5 * 60;
+const DEFAULT_FLAP_THRESHOLD: usize = 4;
+const DEFAULT_NOTIFIER_TIMEOUT_SECS: u64 = 10;
+
+/// The targets to watch and who to tell when their status changes, read
+/// from the file given with `--config`. How they are probed defaults to the
+/// command line.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub targets: Vec<TargetConfig>,
+    pub alerting: AlertingConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TargetConfig {
+    /// Names the target in alerts and in `/status`, must be unique
+    pub name: String,
+    #[serde(default)]
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    /// URL of the target, in place of `host` and `port`
+    pub endpoint: Option<String>,
+    /// Overrides `--probe`
+    pub probe: Option<Probe>,
+    /// Overrides `--service`
+    pub service: Option<String>,
+}
+
+fn default_port() -> u16 {
+    DEFAULT_PORT
+}
+
+impl TargetConfig {
+    /// `endpoint`, or else `host` and `port`.
+    pub fn address(&self) -> String {
+        match &self.endpoint {
+            Some(endpoint) => endpoint.clone(),
+            None => format!("{}:{}", self.host, self.port),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlertingConfig {
+    /// A target changing status more than `flap_threshold` times within
+    /// this window is flapping: one alert says so, and the next is only sent
+    /// once it kept a status for a whole window.
+    pub flap_window_secs: u64,
+    pub flap_threshold: usize,
+    pub notifiers: Vec<NotifierConfig>,
+}
+
+impl Default for AlertingConfig {
+    fn default() -> Self {
+        Self {
+            flap_window_secs: DEFAULT_FLAP_WINDOW_SECS,
+            flap_threshold: DEFAULT_FLAP_THRESHOLD,
+            notifiers: Vec::new(),
+        }
+    }
+}
+
+/// Where alerts go.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum NotifierConfig {
+    /// POSTs each alert as JSON
+    Webhook {
+        url: String,
+        #[serde(default = "default_notifier_timeout_secs")]
+        timeout_secs: u64,
+    },
+    /// Appends each alert to a file, as a JSON line
+    Log { path: PathBuf },
+    /// Runs a program for each alert, described by `HEALTH_CHECK_ALERT_*`
+    /// environment variables
+    Command {
+        program: PathBuf,
+        #[serde(default)]
+        args: Vec<String>,
+        #[serde(default = "default_notifier_timeout_secs")]
+        timeout_secs: u64,
+    },
+}
+
+fn default_notifier_timeout_secs() -> u64 {
+    DEFAULT_NOTIFIER_TIMEOUT_SECS
+}
+
+impl Config {
+    pub fn from_file(path: &PathBuf) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Self::from_toml(&contents).map_err(|e| format!("Invalid {}: {}", path.display(), e))
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Self, String> {
+        let config: Self = toml::from_str(contents).map_err(|e| e.to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let mut errors = Vec::new();
+
+        for (i, target) in self.targets.iter().enumerate() {
+            if target.name.is_empty() {
+                errors.push(format!("targets[{}].name cannot be empty", i));
+            } else if self.targets[..i]
+                .iter()
+                .any(|other| other.name == target.name)
+            {
+                errors.push(format!("Target {} is defined twice", target.name));
+            }
+            match (&target.endpoint, target.host.is_empty()) {
+                (None, true) => errors.push(format!("targets[{}].host cannot be empty", i)),
+                (Some(_), false) => {
+                    errors.push(format!("targets[{}] takes either a host or an endpoint", i))
+                }
+                _ => {}
+            }
+        }
+        if self.alerting.flap_window_secs == 0 {
+            errors.push("alerting.flap_window_secs must be positive".to_string());
+        }
+        if self.alerting.flap_threshold == 0 {
+            errors.push("alerting.flap_threshold must be positive".to_string());
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_targets_and_notifiers() {
+        let config = Config::from_toml(
+            r#"
+            [[targets]]
+            name = "auth-1"
+            host = "auth-1.internal"
+            probe = "full"
+
+            [[targets]]
+            name = "auth-2"
+            host = "auth-2.internal"
+            port = 50052
+
+            [[targets]]
+            name = "auth-3"
+            endpoint = "https://auth-3.example.com"
+
+            [alerting]
+            flap_threshold = 2
+
+            [[alerting.notifiers]]
+            type = "webhook"
+            url = "http://alerts.internal/hook"
+
+            [[alerting.notifiers]]
+            type = "command"
+            program = "/usr/local/bin/page"
+            args = ["--team", "auth"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.targets[0].port, DEFAULT_PORT);
+        assert_eq!(config.targets[0].probe, Some(Probe::Full));
+        assert_eq!(config.targets[1].address(), "auth-2.internal:50052");
+        assert_eq!(config.targets[2].address(), "https://auth-3.example.com");
+        assert_eq!(config.alerting.flap_threshold, 2);
+        assert_eq!(config.alerting.flap_window_secs, DEFAULT_FLAP_WINDOW_SECS);
+        assert_eq!(
+            config.alerting.notifiers[0],
+            NotifierConfig::Webhook {
+                url: "http://alerts.internal/hook".to_string(),
+                timeout_secs: DEFAULT_NOTIFIER_TIMEOUT_SECS,
+            }
+        );
+    }
+
+    #[test]
+    fn should_reject_duplicate_targets() {
+        let error = Config::from_toml(
+            r#"
+            [[targets]]
+            name = "auth"
+            host = "auth-1"
+
+            [[targets]]
+            name = "auth"
+            host = "auth-2"
+            "#,
+        )
+        .unwrap_err();
+
+        assert_eq!(error, "Target auth is defined twice");
+    }
+
+    #[test]
+    fn should_reject_unknown_notifiers() {
+        assert!(Config::from_toml(
+            r#"
+            [[alerting.notifiers]]
+            type = "carrier-pigeon"
+            "#,
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,149 @@
+use std::fmt;
+
+use serde::Serialize;
+
+/// What the probes say about a target so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// Not enough probes yet to tell
+    Starting,
+    Healthy,
+    /// Failing, though not for long enough to be down
+    Degraded,
+    Down,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Status::Starting => "starting",
+            Status::Healthy => "healthy",
+            Status::Degraded => "degraded",
+            Status::Down => "down",
+        })
+    }
+}
+
+/// Consecutive probe outcomes it takes to change the status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Thresholds {
+    /// Successes before a target is healthy
+    pub healthy: u32,
+    /// Failures before a healthy target is degraded
+    pub degraded: u32,
+    /// Failures before a target is down
+    pub down: u32,
+}
+
+/// Turns probe outcomes into a [`Status`], only changing it after enough
+/// consecutive outcomes agree, so that a single slow or dropped call does not
+/// flap it.
+#[derive(Debug, Clone)]
+pub struct Tracker {
+    thresholds: Thresholds,
+    successes: u32,
+    failures: u32,
+    status: Status,
+}
+
+impl Tracker {
+    /// Thresholds of 0 are taken as 1, and a degraded threshold above the
+    /// down one as the down one.
+    pub fn new(thresholds: Thresholds) -> Self {
+        let down = thresholds.down.max(1);
+        Self {
+            thresholds: Thresholds {
+                healthy: thresholds.healthy.max(1),
+                degraded: thresholds.degraded.clamp(1, down),
+                down,
+            },
+            successes: 0,
+            failures: 0,
+            status: Status::Starting,
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    /// Consecutive failed probes, 0 after a success.
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Records the outcome of a probe, returning the new status when it
+    /// changed.
+    pub fn record(&mut self, success: bool) -> Option<Status> {
+        let next = if success {
+            self.successes += 1;
+            self.failures = 0;
+            (self.successes >= self.thresholds.healthy).then_some(Status::Healthy)
+        } else {
+            self.failures += 1;
+            self.successes = 0;
+            if self.failures >= self.thresholds.down {
+                Some(Status::Down)
+            } else if self.failures >= self.thresholds.degraded && self.status == Status::Healthy {
+                Some(Status::Degraded)
+            } else {
+                None
+            }
+        };
+
+        match next {
+            Some(status) if status != self.status => {
+                self.status = status;
+                Some(status)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn thresholds(healthy: u32, degraded: u32, down: u32) -> Thresholds {
+        Thresholds {
+            healthy,
+            degraded,
+            down,
+        }
+    }
+
+    #[test]
+    fn should_degrade_then_go_down() {
+        let mut tracker = Tracker::new(thresholds(1, 1, 3));
+
+        assert_eq!(tracker.record(true), Some(Status::Healthy));
+        assert_eq!(tracker.record(false), Some(Status::Degraded));
+        assert_eq!(tracker.record(false), None);
+        assert_eq!(tracker.record(false), Some(Status::Down));
+        assert_eq!(tracker.record(false), None);
+        assert_eq!(tracker.failures(), 4);
+    }
+
+    #[test]
+    fn should_reset_failures_on_success() {
+        let mut tracker = Tracker::new(thresholds(2, 2, 2));
+
+        tracker.record(false);
+        tracker.record(true);
+        assert_eq!(tracker.record(false), None);
+        assert_eq!(tracker.status(), Status::Starting);
+        assert_eq!(tracker.record(false), Some(Status::Down));
+        assert_eq!(tracker.record(true), None);
+        assert_eq!(tracker.record(true), Some(Status::Healthy));
+    }
+
+    #[test]
+    fn should_not_degrade_while_starting() {
+        let mut tracker = Tracker::new(thresholds(1, 1, 2));
+
+        assert_eq!(tracker.record(false), None);
+        assert_eq!(tracker.record(false), Some(Status::Down));
+    }
+}
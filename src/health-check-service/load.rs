@@ -0,0 +1,600 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use clap::{Args, ValueEnum};
+use common::telemetry::TracedChannel;
+use rand_core::{OsRng, RngCore};
+use serde::Serialize;
+use tokio::{task::JoinSet, time};
+use uuid::Uuid;
+
+use crate::authentication::{
+    authentication_client::AuthenticationClient, DeleteAccountRequest, GetProfileRequest,
+    SignInRequest, SignOutRequest, SignUpRequest, StatusCode,
+};
+
+/// Sessions a worker keeps at most, the oldest being dropped, so a mix
+/// signing in more than out does not grow without bounds.
+const MAX_SESSIONS_PER_WORKER: usize = 100;
+
+/// Options of the `load` mode.
+#[derive(Args, Debug, Clone)]
+pub struct LoadOptions {
+    /// Requests in flight at once
+    #[arg(long, default_value_t = 10)]
+    pub concurrency: usize,
+    /// Requests per second across all workers, as fast as possible when 0
+    #[arg(long, default_value_t = 0)]
+    pub rps: u32,
+    /// Calls before the measurement, e.g. to fill caches and pools
+    #[arg(long, default_value_t = 5)]
+    pub warm_up_secs: u64,
+    /// Length of the measurement
+    #[arg(long, default_value_t = 30)]
+    pub duration_secs: u64,
+    /// Weighted RPCs to call, e.g. `sign-in=8,get-profile=2`. RPCs needing a
+    /// user or a session call SignUp or SignIn first when there is none.
+    #[arg(long, default_value = "sign-up=1,sign-in=1,sign-out=1")]
+    pub mix: Mix,
+    /// Write the report to this file as JSON
+    #[arg(long)]
+    pub report: Option<PathBuf>,
+    /// Leave the users signed up by the test instead of deleting them
+    #[arg(long)]
+    pub keep_users: bool,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Rpc {
+    SignUp,
+    SignIn,
+    SignOut,
+    GetProfile,
+}
+
+impl fmt::Display for Rpc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Rpc::SignUp => "SignUp",
+            Rpc::SignIn => "SignIn",
+            Rpc::SignOut => "SignOut",
+            Rpc::GetProfile => "GetProfile",
+        })
+    }
+}
+
+/// RPCs and how often each is picked relative to the others.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mix {
+    weights: Vec<(Rpc, u32)>,
+    total: u32,
+}
+
+impl FromStr for Mix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let weights = s
+            .split(',')
+            .map(|entry| {
+                let (name, weight) = entry
+                    .split_once('=')
+                    .ok_or_else(|| format!("Expected rpc=weight, got {}", entry))?;
+                let rpc = Rpc::from_str(name.trim(), true)
+                    .map_err(|_| format!("Unknown RPC {}", name))?;
+                let weight = weight
+                    .trim()
+                    .parse::<u32>()
+                    .map_err(|_| format!("Invalid weight {}", weight))?;
+                Ok((rpc, weight))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let total = weights.iter().map(|(_, weight)| weight).sum();
+        if total == 0 {
+            return Err("The mix needs a positive weight".to_string());
+        }
+        Ok(Self { weights, total })
+    }
+}
+
+impl Mix {
+    /// The RPC `roll` falls on, taken modulo the total weight.
+    fn pick(&self, roll: u32) -> Rpc {
+        let mut roll = roll % self.total;
+        for (rpc, weight) in &self.weights {
+            if roll < *weight {
+                return *rpc;
+            }
+            roll -= weight;
+        }
+        unreachable!("the roll is below the total weight")
+    }
+}
+
+/// Spaces the calls of all workers to keep to the target rate.
+struct Pacer {
+    interval: Option<Duration>,
+    next: Mutex<time::Instant>,
+}
+
+impl Pacer {
+    fn new(rps: u32) -> Self {
+        Self {
+            interval: (rps > 0).then(|| Duration::from_secs(1) / rps),
+            next: Mutex::new(time::Instant::now()),
+        }
+    }
+
+    async fn wait(&self) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+        let slot = {
+            let mut next = self.next.lock().unwrap();
+            let slot = (*next).max(time::Instant::now());
+            *next = slot + interval;
+            slot
+        };
+        time::sleep_until(slot).await;
+    }
+}
+
+/// Outcomes of the calls to one RPC.
+#[derive(Debug, Clone, Default)]
+struct Samples {
+    latencies: Vec<Duration>,
+    errors: u64,
+}
+
+/// Nearest-rank percentile of sorted latencies.
+fn percentile(sorted: &[Duration], percent: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = ((percent / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1_000.0
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Latencies {
+    pub min: f64,
+    pub mean: f64,
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RpcReport {
+    pub rpc: String,
+    pub requests: u64,
+    pub errors: u64,
+    pub throughput_rps: f64,
+    pub error_rate: f64,
+    /// Of every call, failed ones included
+    pub latency_ms: Latencies,
+}
+
+/// What a load test measured.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Report {
+    pub target: String,
+    pub concurrency: usize,
+    /// None when unlimited
+    pub target_rps: Option<u32>,
+    pub duration_secs: f64,
+    pub requests: u64,
+    pub errors: u64,
+    pub throughput_rps: f64,
+    pub error_rate: f64,
+    pub rpcs: Vec<RpcReport>,
+}
+
+impl Report {
+    fn new(
+        target: &str,
+        options: &LoadOptions,
+        elapsed: Duration,
+        samples: BTreeMap<Rpc, Samples>,
+    ) -> Self {
+        let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+        let rate = |errors: u64, requests: u64| match requests {
+            0 => 0.0,
+            requests => errors as f64 / requests as f64,
+        };
+
+        let rpcs: Vec<RpcReport> = samples
+            .into_iter()
+            .map(|(rpc, mut samples)| {
+                samples.latencies.sort();
+                let sorted = &samples.latencies;
+                let requests = sorted.len() as u64;
+                let total: Duration = sorted.iter().sum();
+                RpcReport {
+                    rpc: rpc.to_string(),
+                    requests,
+                    errors: samples.errors,
+                    throughput_rps: requests as f64 / seconds,
+                    error_rate: rate(samples.errors, requests),
+                    latency_ms: Latencies {
+                        min: millis(sorted.first().copied().unwrap_or_default()),
+                        mean: millis(total / (requests.max(1) as u32)),
+                        p50: millis(percentile(sorted, 50.0)),
+                        p95: millis(percentile(sorted, 95.0)),
+                        p99: millis(percentile(sorted, 99.0)),
+                        max: millis(sorted.last().copied().unwrap_or_default()),
+                    },
+                }
+            })
+            .collect();
+
+        let requests = rpcs.iter().map(|rpc| rpc.requests).sum();
+        let errors = rpcs.iter().map(|rpc| rpc.errors).sum();
+        Self {
+            target: target.to_string(),
+            concurrency: options.concurrency,
+            target_rps: (options.rps > 0).then_some(options.rps),
+            duration_secs: elapsed.as_secs_f64(),
+            requests,
+            errors,
+            throughput_rps: requests as f64 / seconds,
+            error_rate: rate(errors, requests),
+            rpcs,
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} requests to {} in {:.1} s: {:.1} req/s, {:.2}% errors",
+            self.requests,
+            self.target,
+            self.duration_secs,
+            self.throughput_rps,
+            self.error_rate * 100.0
+        )?;
+        writeln!(
+            f,
+            "{:<12} {:>9} {:>8} {:>9} {:>9} {:>9} {:>9} {:>9}",
+            "rpc", "requests", "errors", "req/s", "p50 ms", "p95 ms", "p99 ms", "max ms"
+        )?;
+        for rpc in &self.rpcs {
+            writeln!(
+                f,
+                "{:<12} {:>9} {:>8} {:>9.1} {:>9.2} {:>9.2} {:>9.2} {:>9.2}",
+                rpc.rpc,
+                rpc.requests,
+                rpc.errors,
+                rpc.throughput_rps,
+                rpc.latency_ms.p50,
+                rpc.latency_ms.p95,
+                rpc.latency_ms.p99,
+                rpc.latency_ms.max
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A synthetic client, keeping the users and sessions it created.
+struct Worker {
+    client: AuthenticationClient<TracedChannel>,
+    users: Vec<(String, String)>,
+    sessions: Vec<String>,
+    samples: BTreeMap<Rpc, Samples>,
+    /// Calls starting earlier are the warm-up, not recorded
+    measure_from: Instant,
+}
+
+fn succeeded(status_code: i32) -> Result<(), String> {
+    if status_code == StatusCode::Success as i32 {
+        Ok(())
+    } else {
+        Err("Refused".to_string())
+    }
+}
+
+fn pick<T>(items: &[T]) -> &T {
+    &items[OsRng.next_u32() as usize % items.len()]
+}
+
+impl Worker {
+    fn record(&mut self, rpc: Rpc, start: Instant, result: &Result<(), String>) {
+        if start < self.measure_from {
+            return;
+        }
+        let samples = self.samples.entry(rpc).or_default();
+        samples.latencies.push(start.elapsed());
+        if result.is_err() {
+            samples.errors += 1;
+        }
+    }
+
+    async fn call(&mut self, rpc: Rpc) {
+        if matches!(rpc, Rpc::SignIn) && self.users.is_empty() {
+            Box::pin(self.call(Rpc::SignUp)).await;
+        }
+        if matches!(rpc, Rpc::SignOut | Rpc::GetProfile) && self.sessions.is_empty() {
+            Box::pin(self.call(Rpc::SignIn)).await;
+        }
+
+        let start = Instant::now();
+        let result = match rpc {
+            Rpc::SignUp => self.sign_up().await,
+            Rpc::SignIn => self.sign_in().await,
+            Rpc::SignOut => self.sign_out().await,
+            Rpc::GetProfile => self.get_profile().await,
+        };
+        self.record(rpc, start, &result);
+    }
+
+    async fn sign_up(&mut self) -> Result<(), String> {
+        let username = format!("load-{}", Uuid::new_v4());
+        let password = Uuid::new_v4().to_string();
+        let response = self
+            .client
+            .sign_up(SignUpRequest {
+                username: username.clone(),
+                password: password.clone(),
+                email: String::new(),
+            })
+            .await
+            .map_err(|e| e.message().to_string())?
+            .into_inner();
+        succeeded(response.status_code)?;
+        self.users.push((username, password));
+        Ok(())
+    }
+
+    async fn sign_in(&mut self) -> Result<(), String> {
+        if self.users.is_empty() {
+            return Err("No user to sign in".to_string());
+        }
+        let (username, password) = pick(&self.users).clone();
+        let response = self
+            .client
+            .sign_in(SignInRequest { username, password })
+            .await
+            .map_err(|e| e.message().to_string())?
+            .into_inner();
+        succeeded(response.status_code)?;
+        if self.sessions.len() >= MAX_SESSIONS_PER_WORKER {
+            self.sessions.remove(0);
+        }
+        self.sessions.push(response.session_token);
+        Ok(())
+    }
+
+    async fn sign_out(&mut self) -> Result<(), String> {
+        let session_token = self.sessions.pop().ok_or("No session to sign out")?;
+        let response = self
+            .client
+            .sign_out(SignOutRequest { session_token })
+            .await
+            .map_err(|e| e.message().to_string())?
+            .into_inner();
+        succeeded(response.status_code)
+    }
+
+    async fn get_profile(&mut self) -> Result<(), String> {
+        let session_token = self.sessions.last().cloned().ok_or("No session")?;
+        let response = self
+            .client
+            .get_profile(GetProfileRequest { session_token })
+            .await
+            .map_err(|e| e.message().to_string())?
+            .into_inner();
+        succeeded(response.status_code)
+    }
+
+    /// Deletes the users this worker signed up.
+    async fn clean_up(&mut self) -> usize {
+        let mut failures = 0;
+        for (username, password) in std::mem::take(&mut self.users) {
+            let deleted = async {
+                let session = self
+                    .client
+                    .sign_in(SignInRequest {
+                        username,
+                        password: password.clone(),
+                    })
+                    .await
+                    .map_err(|e| e.message().to_string())?
+                    .into_inner();
+                succeeded(session.status_code)?;
+                let response = self
+                    .client
+                    .delete_account(DeleteAccountRequest {
+                        session_token: session.session_token,
+                        password,
+                    })
+                    .await
+                    .map_err(|e| e.message().to_string())?
+                    .into_inner();
+                succeeded(response.status_code)
+            };
+            if deleted.await.is_err() {
+                failures += 1;
+            }
+        }
+        failures
+    }
+}
+
+/// Calls the target with `options.concurrency` workers for the warm-up and
+/// the duration, then reports on the calls of the latter.
+pub async fn run(
+    target: &str,
+    options: &LoadOptions,
+    connect: impl Fn() -> Result<TracedChannel, String>,
+) -> Result<Report, String> {
+    if options.concurrency == 0 {
+        return Err("--concurrency must be positive".to_string());
+    }
+    if options.duration_secs == 0 {
+        return Err("--duration-secs must be positive".to_string());
+    }
+
+    let pacer = Arc::new(Pacer::new(options.rps));
+    let measure_from = Instant::now() + Duration::from_secs(options.warm_up_secs);
+    let end = measure_from + Duration::from_secs(options.duration_secs);
+
+    let mut workers = JoinSet::new();
+    for _ in 0..options.concurrency {
+        let mut worker = Worker {
+            client: AuthenticationClient::new(connect()?),
+            users: Vec::new(),
+            sessions: Vec::new(),
+            samples: BTreeMap::new(),
+            measure_from,
+        };
+        let pacer = pacer.clone();
+        let mix = options.mix.clone();
+        let keep_users = options.keep_users;
+        workers.spawn(async move {
+            while Instant::now() < end {
+                pacer.wait().await;
+                if Instant::now() >= end {
+                    break;
+                }
+                worker.call(mix.pick(OsRng.next_u32())).await;
+            }
+            let samples = std::mem::take(&mut worker.samples);
+            let cleanup_failures = if keep_users {
+                0
+            } else {
+                worker.clean_up().await
+            };
+            (samples, cleanup_failures)
+        });
+    }
+
+    let mut samples: BTreeMap<Rpc, Samples> = BTreeMap::new();
+    let mut cleanup_failures = 0;
+    while let Some(result) = workers.join_next().await {
+        let (worker_samples, failures) = result.map_err(|e| e.to_string())?;
+        for (rpc, worker_samples) in worker_samples {
+            let merged = samples.entry(rpc).or_default();
+            merged.latencies.extend(worker_samples.latencies);
+            merged.errors += worker_samples.errors;
+        }
+        cleanup_failures += failures;
+    }
+    if cleanup_failures > 0 {
+        tracing::warn!(users = cleanup_failures, "failed to delete some test users");
+    }
+
+    Ok(Report::new(
+        target,
+        options,
+        Duration::from_secs(options.duration_secs),
+        samples,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(mix: &str) -> LoadOptions {
+        LoadOptions {
+            concurrency: 4,
+            rps: 0,
+            warm_up_secs: 0,
+            duration_secs: 10,
+            mix: mix.parse().unwrap(),
+            report: None,
+            keep_users: false,
+        }
+    }
+
+    #[test]
+    fn should_parse_and_pick_from_mix() {
+        let mix: Mix = "sign-in=3, get-profile=1".parse().unwrap();
+
+        assert_eq!(mix.pick(0), Rpc::SignIn);
+        assert_eq!(mix.pick(2), Rpc::SignIn);
+        assert_eq!(mix.pick(3), Rpc::GetProfile);
+        assert_eq!(mix.pick(4), Rpc::SignIn);
+    }
+
+    #[test]
+    fn should_reject_invalid_mix() {
+        assert!("sign-in".parse::<Mix>().is_err());
+        assert!("delete-everything=1".parse::<Mix>().is_err());
+        assert!("sign-in=0".parse::<Mix>().is_err());
+    }
+
+    #[test]
+    fn should_compute_percentiles() {
+        let sorted: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+
+        assert_eq!(percentile(&sorted, 50.0), Duration::from_millis(50));
+        assert_eq!(percentile(&sorted, 99.0), Duration::from_millis(99));
+        assert_eq!(percentile(&sorted[..1], 95.0), Duration::from_millis(1));
+        assert_eq!(percentile(&[], 50.0), Duration::ZERO);
+    }
+
+    #[test]
+    fn should_report_per_rpc() {
+        let mut samples = BTreeMap::new();
+        samples.insert(
+            Rpc::SignIn,
+            Samples {
+                latencies: (1..=10).rev().map(Duration::from_millis).collect(),
+                errors: 1,
+            },
+        );
+        samples.insert(
+            Rpc::SignUp,
+            Samples {
+                latencies: vec![Duration::from_millis(20); 10],
+                errors: 0,
+            },
+        );
+
+        let report = Report::new(
+            "auth:50051",
+            &options("sign-in=1"),
+            Duration::from_secs(10),
+            samples,
+        );
+
+        assert_eq!(report.requests, 20);
+        assert_eq!(report.throughput_rps, 2.0);
+        assert_eq!(report.error_rate, 0.05);
+        let sign_in = &report.rpcs[1];
+        assert_eq!(sign_in.rpc, "SignIn");
+        assert_eq!(sign_in.error_rate, 0.1);
+        assert_eq!(sign_in.latency_ms.p50, 5.0);
+        assert_eq!(sign_in.latency_ms.max, 10.0);
+        let json = serde_json::to_string(&report).unwrap();
+        assert!(json.contains("\"p95\":10.0"));
+    }
+
+    #[tokio::test]
+    async fn should_pace_calls() {
+        let pacer = Pacer::new(10);
+        let start = Instant::now();
+
+        for _ in 0..5 {
+            pacer.wait().await;
+        }
+
+        assert!(start.elapsed() >= Duration::from_millis(400));
+    }
+}
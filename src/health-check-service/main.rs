@@ -1,82 +1,381 @@
-use std::env;
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use clap::{Parser, Subcommand};
+use common::{connection::ConnectionOptions, logging::LogOptions};
+use tokio::{sync::mpsc, task::JoinSet};
+use tracing::Instrument;
+
+mod alerts;
+mod config;
+mod health;
+mod load;
+mod probes;
+mod scenario;
+mod status;
 
 mod authentication {
     tonic::include_proto!("authentication");
 }
 
-use authentication::{
-    authentication_client::AuthenticationClient, SignInRequest, SignInResponse, SignOutRequest,
-    SignOutResponse, SignUpRequest, SignUpResponse,
-};
-use tonic::transport::Channel;
-use uuid::Uuid;
+mod admin {
+    tonic::include_proto!("admin");
+}
+
+use alerts::{Alert, Alerter};
+use config::{Config, TargetConfig};
+use health::{Status, Thresholds, Tracker};
+use load::LoadOptions;
+use probes::{Probe, Prober};
+use scenario::{Runner, Scenario, ScenarioOptions};
+use status::{Observer, Observers};
+
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// TOML file listing the targets to watch and the alert notifiers;
+    /// without one, only `--endpoint` is watched
+    #[arg(short, long, env = "HEALTH_CHECK_CONFIG")]
+    config: Option<PathBuf>,
+    /// Host of the auth service, when `--endpoint` is not given; its
+    /// `AUTH_HOSTNAME` is from before `AUTH_ENDPOINT`, still honoured
+    #[arg(long, env = "AUTH_HOSTNAME", default_value = "[::0]")]
+    host: String,
+    #[arg(long, env = "AUTH_PORT", default_value_t = 50051)]
+    port: u16,
+    #[arg(long, env = "HEALTH_CHECK_PROBE", value_enum, default_value_t = Probe::GrpcHealth)]
+    probe: Probe,
+    /// Admin token configured on the service, with which the full probe
+    /// deletes its users
+    #[arg(long, env = "AUTH_ADMIN_TOKEN", hide_env_values = true)]
+    admin_token: Option<String>,
+    /// Name of the service checked by the grpc-health probe
+    #[arg(
+        long,
+        env = "HEALTH_CHECK_SERVICE",
+        default_value = "authentication.Authentication"
+    )]
+    service: String,
+    /// Time between the start of two probes
+    #[arg(
+        long,
+        env = "HEALTH_CHECK_INTERVAL_MS",
+        default_value_t = 1000,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    interval_ms: u64,
+    /// Time after which a probe fails
+    #[arg(long, env = "HEALTH_CHECK_TIMEOUT_MS", default_value_t = 5000)]
+    timeout_ms: u64,
+    /// Consecutive successful probes before a target is healthy
+    #[arg(long, env = "HEALTH_CHECK_HEALTHY_THRESHOLD", default_value_t = 1)]
+    healthy_threshold: u32,
+    /// Consecutive failed probes before a healthy target is degraded
+    #[arg(long, env = "HEALTH_CHECK_DEGRADED_THRESHOLD", default_value_t = 1)]
+    degraded_threshold: u32,
+    /// Consecutive failed probes before a target is down
+    #[arg(long, env = "HEALTH_CHECK_UNHEALTHY_THRESHOLD", default_value_t = 3)]
+    unhealthy_threshold: u32,
+    /// Exit with an error once a target is down
+    #[arg(long, env = "HEALTH_CHECK_EXIT_ON_UNHEALTHY")]
+    exit_on_unhealthy: bool,
+    /// Address of the `/healthz`, `/readyz` and `/status` endpoints
+    #[arg(long, env = "HEALTH_CHECK_LISTEN_ADDR", default_value = "[::]:8080")]
+    listen_addr: SocketAddr,
+    #[command(flatten)]
+    connection: ConnectionOptions,
+    #[command(flatten)]
+    log: LogOptions,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Load-test `--endpoint` instead of watching it, then print a report
+    Load(LoadOptions),
+    /// Run scripted scenarios against `--endpoint`, then print which passed
+    Scenario(ScenarioOptions),
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let auth_hostname = env::var("AUTH_HOSTNAME").unwrap_or("[::0]".to_owned());
+    let cli = Cli::parse();
+    let telemetry = cli.log.init("info", "health-check")?;
+
+    let result = match &cli.command {
+        Some(Command::Load(options)) => load(&cli, options).await,
+        Some(Command::Scenario(options)) => scenarios(&cli, options).await,
+        None => run(&cli).await,
+    };
+
+    telemetry.shutdown().await;
+    result
+}
 
-    let mut client =
-        AuthenticationClient::connect(format!("http://{}:50051", auth_hostname)).await?;
+impl Cli {
+    /// `--endpoint`, or else `--host` and `--port`.
+    fn address(&self) -> String {
+        match &self.connection.endpoint {
+            Some(endpoint) => endpoint.clone(),
+            None => format!("{}:{}", self.host, self.port),
+        }
+    }
+}
 
-    loop {
-        let username = Uuid::new_v4().to_string();
-        let password = Uuid::new_v4().to_string();
+async fn load(cli: &Cli, options: &LoadOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let target = cli.address();
+    tracing::info!(
+        %target,
+        concurrency = options.concurrency,
+        rps = options.rps,
+        warm_up_secs = options.warm_up_secs,
+        duration_secs = options.duration_secs,
+        "starting load test"
+    );
 
-        // SignUp
-        let response = sign_up(&mut client, &username, &password).await?;
-        println!("SignUp Status Code: {}", response.status_code);
+    let timeout = Duration::from_millis(cli.timeout_ms);
+    let report = load::run(&target, options, || {
+        let endpoint = cli
+            .connection
+            .endpoint(&target)
+            .map_err(|e| e.to_string())?;
+        Ok(cli.connection.traced(
+            endpoint
+                .timeout(timeout)
+                .connect_timeout(timeout)
+                .connect_lazy(),
+        ))
+    })
+    .await?;
+
+    print!("{}", report);
+    if let Some(path) = &options.report {
+        let json = serde_json::to_string_pretty(&report)?;
+        std::fs::write(path, json)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
 
-        // SignIn
-        let response = sign_in(&mut client, &username, &password).await?;
-        println!("SignIn Status Code: {}", response.status_code);
+async fn scenarios(cli: &Cli, options: &ScenarioOptions) -> Result<(), Box<dyn std::error::Error>> {
+    // All parsed first, so that a broken file fails before anything runs
+    let scenarios = options
+        .files
+        .iter()
+        .map(|path| Scenario::from_file(path))
+        .collect::<Result<Vec<_>, _>>()?;
 
-        // SignOut
-        let session_token = response.session_token;
-        let response = sign_out(&mut client, &session_token).await?;
+    let timeout = Duration::from_millis(cli.timeout_ms);
+    let channel = cli
+        .connection
+        .endpoint(&cli.address())?
+        .timeout(timeout)
+        .connect_timeout(timeout)
+        .connect_lazy();
+    let mut runner = Runner::new(cli.connection.traced(channel));
 
-        println!("SignOut Status Code: {}", response.status_code);
+    let mut outcomes = Vec::new();
+    for scenario in &scenarios {
+        let outcome = runner
+            .run(scenario)
+            .instrument(tracing::info_span!("scenario", name = %scenario.name))
+            .await;
+        match &outcome.failure {
+            Some((step, reason)) => println!("FAIL {}: {}: {}", outcome.scenario, step, reason),
+            None => println!("PASS {} ({} steps)", outcome.scenario, outcome.steps),
+        }
+        outcomes.push(outcome);
+    }
 
-        // Wait
-        tokio::time::sleep(std::time::Duration::from_millis(1000)).await;
+    let failed = outcomes
+        .iter()
+        .filter(|outcome| outcome.failure.is_some())
+        .count();
+    println!("{} passed, {} failed", outcomes.len() - failed, failed);
+    if let Some(path) = &options.junit {
+        std::fs::write(path, scenario::junit(&outcomes))
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} scenarios failed", failed, outcomes.len()).into());
     }
+    Ok(())
 }
 
-async fn sign_up(
-    client: &mut AuthenticationClient<Channel>,
-    username: &str,
-    password: &str,
-) -> Result<SignUpResponse, Box<dyn std::error::Error>> {
-    // SignUp
-    let request = tonic::Request::new(SignUpRequest {
-        username: username.to_owned(),
-        password: password.to_owned(),
+async fn run(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let mut config = match &cli.config {
+        Some(path) => Config::from_file(path)?,
+        None => Config::default(),
+    };
+    if config.targets.is_empty() {
+        config.targets.push(TargetConfig {
+            name: cli.address(),
+            host: cli.host.clone(),
+            port: cli.port,
+            endpoint: cli.connection.endpoint.clone(),
+            probe: None,
+            service: None,
+        });
+    }
+
+    let thresholds = Thresholds {
+        healthy: cli.healthy_threshold,
+        degraded: cli.degraded_threshold,
+        down: cli.unhealthy_threshold,
+    };
+    let observers: Observers = Arc::new(
+        config
+            .targets
+            .iter()
+            .map(|target| Mutex::new(Observer::new(&target.name, Tracker::new(thresholds))))
+            .collect(),
+    );
+
+    let listener = tokio::net::TcpListener::bind(cli.listen_addr).await?;
+    tracing::info!(listen_addr = %cli.listen_addr, "serving status");
+    let endpoints = status::serve(listener, observers.clone());
+    tokio::spawn(async move {
+        if let Err(e) = endpoints.await {
+            tracing::error!(error = %e, "status endpoints stopped");
+        }
     });
 
-    Ok(client.sign_up(request).await?.into_inner())
+    let (alerts, alerter) = Alerter::from_config(&config.alerting)?.start();
+
+    let timeout = Duration::from_millis(cli.timeout_ms);
+    let mut watches = JoinSet::new();
+    for (index, target) in config.targets.iter().enumerate() {
+        let probe = target.probe.unwrap_or(cli.probe);
+        if probe == Probe::Full && cli.admin_token.is_none() {
+            return Err(format!(
+                "The full probe of {} needs --admin-token to delete its users",
+                target.name
+            )
+            .into());
+        }
+        tracing::info!(
+            target_name = %target.name,
+            address = %target.address(),
+            ?probe,
+            tls = cli.connection.tls.enabled(),
+            "starting health check"
+        );
+
+        // Lazily, so that a target down at startup is a failed probe like any
+        // other
+        let endpoint = cli
+            .connection
+            .endpoint(&target.address())?
+            .connect_timeout(timeout);
+        let channel = endpoint.connect_lazy();
+        let watch = Watch {
+            prober: Prober::new(
+                probe,
+                probes::socket_address(endpoint.uri()),
+                target.service.clone().unwrap_or(cli.service.clone()),
+                cli.connection.traced(channel),
+            )
+            .with_admin_token(cli.admin_token.clone()),
+            observers: observers.clone(),
+            index,
+            alerts: alerts.clone(),
+            interval: Duration::from_millis(cli.interval_ms),
+            timeout,
+            exit_on_down: cli.exit_on_unhealthy,
+        };
+        watches.spawn(
+            watch
+                .run()
+                .instrument(tracing::info_span!("watch", target_name = %target.name)),
+        );
+    }
+    drop(alerts);
+
+    let result = match watches.join_next().await {
+        Some(Ok(result)) => result,
+        Some(Err(e)) => Err(e.to_string()),
+        None => Ok(()),
+    };
+    // The alerter stops once every watch is gone, after sending what they
+    // queued
+    watches.shutdown().await;
+    alerter.await?;
+    Ok(result?)
 }
 
-async fn sign_in(
-    client: &mut AuthenticationClient<Channel>,
-    username: &str,
-    password: &str,
-) -> Result<SignInResponse, Box<dyn std::error::Error>> {
-    // SignUp
-    let request = tonic::Request::new(SignInRequest {
-        username: username.to_owned(),
-        password: password.to_owned(),
-    });
+/// Probes a target for as long as it runs.
+struct Watch {
+    prober: Prober,
+    observers: Observers,
+    /// Of the target's observer
+    index: usize,
+    alerts: mpsc::UnboundedSender<Alert>,
+    interval: Duration,
+    timeout: Duration,
+    exit_on_down: bool,
+}
+
+impl Watch {
+    /// Only returns when `exit_on_down` is set and the target went down.
+    async fn run(mut self) -> Result<(), String> {
+        let mut interval = tokio::time::interval(self.interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
 
-    Ok(client.sign_in(request).await?.into_inner())
+            let result = self.prober.check(self.timeout).await;
+            let at_ms = status::now_ms();
+            let mut observer = status::lock(&self.observers[self.index]);
+            let previous = observer.tracker().status();
+            let changed = observer.record(&result, self.prober.steps(), at_ms);
+            let tracker = observer.tracker();
+            if let Err(e) = &result {
+                tracing::warn!(
+                    error = %e,
+                    failures = tracker.failures(),
+                    status = %tracker.status(),
+                    "probe failed"
+                );
+            }
+
+            let Some(status) = changed else {
+                continue;
+            };
+            match status {
+                Status::Down => tracing::error!(failures = tracker.failures(), "target is down"),
+                _ => tracing::info!(%status, "target status changed"),
+            }
+            // Coming up is only news after having been down
+            if previous != Status::Starting || status != Status::Healthy {
+                let _ = self.alerts.send(Alert {
+                    target: observer.name().to_string(),
+                    from: previous,
+                    to: status,
+                    at_ms,
+                    error: result.err(),
+                    flapping: false,
+                });
+            }
+            if status == Status::Down && self.exit_on_down {
+                return Err(format!("Target {} is down", observer.name()));
+            }
+        }
+    }
 }
 
-async fn sign_out(
-    client: &mut AuthenticationClient<Channel>,
-    session_token: &str,
-) -> Result<SignOutResponse, Box<dyn std::error::Error>> {
-    // SignUp
-    let request = tonic::Request::new(SignOutRequest {
-        session_token: session_token.to_owned(),
-    });
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    Ok(client.sign_out(request).await?.into_inner())
+    #[test]
+    fn should_reject_zero_interval() {
+        assert!(Cli::try_parse_from(["health-check", "--interval-ms", "0"]).is_err());
+        let cli = Cli::try_parse_from(["health-check", "--interval-ms", "1"]).unwrap();
+        assert_eq!(cli.interval_ms, 1);
+    }
 }
@@ -0,0 +1,620 @@
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use common::telemetry::TracedChannel;
+use serde::Deserialize;
+use tokio::net::TcpStream;
+use tonic::transport::Uri;
+use tonic_health::pb::{
+    health_check_response::ServingStatus, health_client::HealthClient, HealthCheckRequest,
+};
+use tracing::{instrument, Instrument};
+use uuid::Uuid;
+
+use crate::{
+    admin::{admin_client::AdminClient, DeleteUserRequest},
+    authentication::{
+        authentication_client::AuthenticationClient, GetProfileRequest, SignInRequest,
+        SignInResponse, SignOutRequest, SignUpRequest, StatusCode,
+    },
+};
+
+/// How the target is checked.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Probe {
+    /// Asks the `grpc.health.v1` service for the status of the auth service
+    GrpcHealth,
+    /// Only opens a TCP connection
+    Tcp,
+    /// Calls GetProfile with an invalid token, which changes nothing and must
+    /// be refused
+    Validate,
+    /// Signs a fresh user up, in and out, then deletes it through the Admin
+    /// service, so it needs the admin token
+    Full,
+}
+
+/// A call made by a probe, and how long it took.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub name: &'static str,
+    pub latency: Duration,
+}
+
+/// Checks a target with one kind of probe.
+pub struct Prober {
+    probe: Probe,
+    /// `host:port`, for the TCP probe
+    addr: String,
+    /// Name the auth service is registered under in the health service
+    service: String,
+    health: HealthClient<TracedChannel>,
+    authentication: AuthenticationClient<TracedChannel>,
+    admin: AdminClient<TracedChannel>,
+    /// Deletes the users of the full probe
+    admin_token: Option<String>,
+    steps: Vec<Step>,
+    /// Username of the user the full probe signed up and has not deleted
+    /// yet.
+    created: Option<String>,
+}
+
+/// `host:port` of `uri`, the port defaulting to the scheme's, for the TCP
+/// probe.
+pub fn socket_address(uri: &Uri) -> String {
+    let host = uri.host().unwrap_or_default();
+    let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+        Some("https") => 443,
+        _ => 80,
+    });
+    format!("{}:{}", host, port)
+}
+
+impl Prober {
+    pub fn new(probe: Probe, addr: String, service: String, channel: TracedChannel) -> Self {
+        Self {
+            probe,
+            addr,
+            service,
+            health: HealthClient::new(channel.clone()),
+            authentication: AuthenticationClient::new(channel.clone()),
+            admin: AdminClient::new(channel),
+            admin_token: None,
+            steps: Vec::new(),
+            created: None,
+        }
+    }
+
+    pub fn with_admin_token(mut self, admin_token: Option<String>) -> Self {
+        self.admin_token = admin_token;
+        self
+    }
+
+    /// The calls of the last check, up to the one failing it.
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    fn step(&mut self, name: &'static str, start: Instant) {
+        self.steps.push(Step {
+            name,
+            latency: start.elapsed(),
+        });
+    }
+
+    /// Runs the probe, failing when it does not complete within `timeout`.
+    /// The user of a full probe cut short is deleted in the background.
+    #[instrument(skip(self), fields(probe = ?self.probe))]
+    pub async fn check(&mut self, timeout: Duration) -> Result<(), String> {
+        self.steps.clear();
+        let probe = async {
+            match self.probe {
+                Probe::GrpcHealth => self.grpc_health().await,
+                Probe::Tcp => self.tcp().await,
+                Probe::Validate => self.validate().await,
+                Probe::Full => self.full().await,
+            }
+        };
+        let result = match tokio::time::timeout(timeout, probe).await {
+            Ok(result) => result,
+            Err(_) => Err(format!("Timed out after {} ms", timeout.as_millis())),
+        };
+
+        if let Some(username) = self.created.take() {
+            let delete = delete_user(
+                self.admin.clone(),
+                self.admin_token.clone().unwrap_or_default(),
+                username.clone(),
+            );
+            tokio::spawn(
+                async move {
+                    match tokio::time::timeout(timeout, delete).await {
+                        Ok(Ok(())) => tracing::info!(username, "deleted the user of a timed out probe"),
+                        Ok(Err(e)) => tracing::warn!(username, error = %e, "failed to delete the user of a timed out probe"),
+                        Err(_) => tracing::warn!(username, "timed out deleting the user of a timed out probe"),
+                    }
+                }
+                .in_current_span(),
+            );
+        }
+        result
+    }
+
+    async fn grpc_health(&mut self) -> Result<(), String> {
+        let start = Instant::now();
+        let response = self
+            .health
+            .check(HealthCheckRequest {
+                service: self.service.clone(),
+            })
+            .await;
+        self.step("Check", start);
+        let response = response
+            .map_err(|e| format!("Health check failed: {}", e.message()))?
+            .into_inner();
+
+        match response.status() {
+            ServingStatus::Serving => Ok(()),
+            status => Err(format!("{} is {}", self.service, status.as_str_name())),
+        }
+    }
+
+    async fn tcp(&mut self) -> Result<(), String> {
+        let start = Instant::now();
+        let connected = TcpStream::connect(&self.addr).await;
+        self.step("Connect", start);
+        connected
+            .map(drop)
+            .map_err(|e| format!("Failed to connect to {}: {}", self.addr, e))
+    }
+
+    /// The service must look the invalid token up and refuse it.
+    async fn validate(&mut self) -> Result<(), String> {
+        let start = Instant::now();
+        let response = self
+            .authentication
+            .get_profile(GetProfileRequest {
+                session_token: String::new(),
+            })
+            .await;
+        self.step("GetProfile", start);
+        let response = response
+            .map_err(|e| format!("GetProfile failed: {}", e.message()))?
+            .into_inner();
+        if response.status_code == StatusCode::Success as i32 {
+            return Err("GetProfile accepted an invalid token".to_string());
+        }
+        Ok(())
+    }
+
+    async fn full(&mut self) -> Result<(), String> {
+        let Some(admin_token) = self.admin_token.clone() else {
+            return Err("The full probe needs the admin token to delete its users".to_string());
+        };
+        let username = format!("health-check-{}", Uuid::new_v4());
+        let password = Uuid::new_v4().to_string();
+        // Before signing up, which may complete even if the reply never comes
+        self.created = Some(username.clone());
+
+        let start = Instant::now();
+        let response = self
+            .authentication
+            .sign_up(SignUpRequest {
+                username: username.clone(),
+                password: password.clone(),
+                email: String::new(),
+            })
+            .await;
+        self.step("SignUp", start);
+        let response = response
+            .map_err(|e| format!("SignUp failed: {}", e.message()))?
+            .into_inner();
+        tracing::info!(status_code = response.status_code, "SignUp");
+        if let Err(e) = succeeded("SignUp", response.status_code) {
+            self.created = None;
+            return Err(e);
+        }
+
+        let flow = self.sign_in_and_out(&username, &password).await;
+        // The user is deleted even when the flow failed half-way, e.g. when
+        // its email must be verified first
+        let start = Instant::now();
+        let cleanup = delete_user(self.admin.clone(), admin_token, username.clone()).await;
+        self.step("DeleteUser", start);
+        self.created = None;
+        flow.and(cleanup)
+    }
+
+    async fn sign_in_and_out(&mut self, username: &str, password: &str) -> Result<(), String> {
+        let response = self.sign_in(username, password).await?;
+
+        let start = Instant::now();
+        let response = self
+            .authentication
+            .sign_out(SignOutRequest {
+                session_token: response.session_token,
+            })
+            .await;
+        self.step("SignOut", start);
+        let response = response
+            .map_err(|e| format!("SignOut failed: {}", e.message()))?
+            .into_inner();
+        tracing::info!(status_code = response.status_code, "SignOut");
+        succeeded("SignOut", response.status_code)
+    }
+
+    async fn sign_in(&mut self, username: &str, password: &str) -> Result<SignInResponse, String> {
+        let start = Instant::now();
+        let response = self
+            .authentication
+            .sign_in(SignInRequest {
+                username: username.to_owned(),
+                password: password.to_owned(),
+            })
+            .await;
+        self.step("SignIn", start);
+        let response = response
+            .map_err(|e| format!("SignIn failed: {}", e.message()))?
+            .into_inner();
+        tracing::info!(status_code = response.status_code, "SignIn");
+        succeeded("SignIn", response.status_code)?;
+        Ok(response)
+    }
+}
+
+/// Deletes a user of the full probe at once, whether it could sign in or
+/// not, and whatever the deletion grace period of the accounts.
+async fn delete_user(
+    mut admin: AdminClient<TracedChannel>,
+    admin_token: String,
+    username: String,
+) -> Result<(), String> {
+    let mut request = tonic::Request::new(DeleteUserRequest { username });
+    let authorization = format!("Bearer {}", admin_token)
+        .parse()
+        .map_err(|_| "Invalid admin token".to_string())?;
+    request
+        .metadata_mut()
+        .insert("authorization", authorization);
+    let response = admin
+        .delete_user(request)
+        .await
+        .map_err(|e| format!("DeleteUser failed: {}", e.message()))?
+        .into_inner();
+    tracing::info!(revoked_sessions = response.revoked_sessions, "DeleteUser");
+    Ok(())
+}
+
+fn succeeded(rpc: &str, status_code: i32) -> Result<(), String> {
+    if status_code == StatusCode::Success as i32 {
+        Ok(())
+    } else {
+        Err(format!("{} was refused", rpc))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        admin::{
+            admin_server::{Admin, AdminServer},
+            CreateApiKeyRequest, CreateApiKeyResponse, CreateServiceAccountRequest,
+            DeleteUserResponse, DisableUserRequest, DisableUserResponse, EnableUserRequest,
+            GetUserRequest, ListApiKeysRequest, ListApiKeysResponse, ListUsersRequest,
+            ListUsersResponse, RevokeApiKeyRequest, RevokeApiKeyResponse, User,
+        },
+        authentication::{
+            authentication_server::{Authentication, AuthenticationServer},
+            CancelAccountDeletionRequest, CancelAccountDeletionResponse, ChangePasswordRequest,
+            ChangePasswordResponse, DeleteAccountRequest, DeleteAccountResponse, Event,
+            GetProfileResponse, ResendVerificationRequest, ResendVerificationResponse,
+            SignOutResponse, SignUpResponse, SubscribeEventsRequest, UpdateProfileRequest,
+            UpdateProfileResponse, VerifyEmailRequest, VerifyEmailResponse,
+        },
+    };
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::{Request, Response, Status};
+
+    const ADMIN_TOKEN: &str = "admin-token";
+
+    /// Auth service signing everyone up, in and out unless told otherwise,
+    /// and recording the users deleted through the Admin service.
+    #[derive(Clone, Default)]
+    struct FakeService {
+        refuse_sign_in: bool,
+        stall_sign_out: bool,
+        deleted: Arc<Mutex<Vec<String>>>,
+    }
+
+    type Reply<T> = Result<Response<T>, Status>;
+
+    fn status_code(success: bool) -> i32 {
+        if success {
+            StatusCode::Success.into()
+        } else {
+            StatusCode::Failure.into()
+        }
+    }
+
+    #[tonic::async_trait]
+    impl Authentication for FakeService {
+        type SubscribeEventsStream = tokio_stream::Empty<Result<Event, Status>>;
+
+        async fn sign_up(&self, _: Request<SignUpRequest>) -> Reply<SignUpResponse> {
+            Ok(Response::new(SignUpResponse {
+                status_code: status_code(true),
+            }))
+        }
+
+        async fn sign_in(&self, request: Request<SignInRequest>) -> Reply<SignInResponse> {
+            Ok(Response::new(SignInResponse {
+                status_code: status_code(!self.refuse_sign_in),
+                session_token: request.into_inner().username,
+                ..Default::default()
+            }))
+        }
+
+        async fn sign_out(&self, _: Request<SignOutRequest>) -> Reply<SignOutResponse> {
+            if self.stall_sign_out {
+                std::future::pending::<()>().await;
+            }
+            Ok(Response::new(SignOutResponse {
+                status_code: status_code(true),
+            }))
+        }
+
+        async fn delete_account(
+            &self,
+            _: Request<DeleteAccountRequest>,
+        ) -> Reply<DeleteAccountResponse> {
+            Err(Status::unimplemented("DeleteAccount"))
+        }
+
+        async fn cancel_account_deletion(
+            &self,
+            _: Request<CancelAccountDeletionRequest>,
+        ) -> Reply<CancelAccountDeletionResponse> {
+            Err(Status::unimplemented("CancelAccountDeletion"))
+        }
+
+        async fn change_password(
+            &self,
+            _: Request<ChangePasswordRequest>,
+        ) -> Reply<ChangePasswordResponse> {
+            Err(Status::unimplemented("ChangePassword"))
+        }
+
+        async fn get_profile(
+            &self,
+            request: Request<GetProfileRequest>,
+        ) -> Reply<GetProfileResponse> {
+            Ok(Response::new(GetProfileResponse {
+                status_code: status_code(!request.into_inner().session_token.is_empty()),
+                profile: None,
+            }))
+        }
+
+        async fn update_profile(
+            &self,
+            _: Request<UpdateProfileRequest>,
+        ) -> Reply<UpdateProfileResponse> {
+            Err(Status::unimplemented("UpdateProfile"))
+        }
+
+        async fn verify_email(&self, _: Request<VerifyEmailRequest>) -> Reply<VerifyEmailResponse> {
+            Err(Status::unimplemented("VerifyEmail"))
+        }
+
+        async fn resend_verification(
+            &self,
+            _: Request<ResendVerificationRequest>,
+        ) -> Reply<ResendVerificationResponse> {
+            Err(Status::unimplemented("ResendVerification"))
+        }
+
+        async fn subscribe_events(
+            &self,
+            _: Request<SubscribeEventsRequest>,
+        ) -> Reply<Self::SubscribeEventsStream> {
+            Err(Status::unimplemented("SubscribeEvents"))
+        }
+    }
+
+    #[tonic::async_trait]
+    impl Admin for FakeService {
+        async fn list_users(&self, _: Request<ListUsersRequest>) -> Reply<ListUsersResponse> {
+            Err(Status::unimplemented("ListUsers"))
+        }
+
+        async fn get_user(&self, _: Request<GetUserRequest>) -> Reply<User> {
+            Err(Status::unimplemented("GetUser"))
+        }
+
+        async fn disable_user(&self, _: Request<DisableUserRequest>) -> Reply<DisableUserResponse> {
+            Err(Status::unimplemented("DisableUser"))
+        }
+
+        async fn enable_user(&self, _: Request<EnableUserRequest>) -> Reply<User> {
+            Err(Status::unimplemented("EnableUser"))
+        }
+
+        async fn delete_user(
+            &self,
+            request: Request<DeleteUserRequest>,
+        ) -> Reply<DeleteUserResponse> {
+            let authorization = format!("Bearer {}", ADMIN_TOKEN);
+            if request
+                .metadata()
+                .get("authorization")
+                .map(|value| value.as_bytes())
+                != Some(authorization.as_bytes())
+            {
+                return Err(Status::unauthenticated("Invalid admin token"));
+            }
+            self.deleted
+                .lock()
+                .unwrap()
+                .push(request.into_inner().username);
+            Ok(Response::new(DeleteUserResponse {
+                revoked_sessions: 0,
+            }))
+        }
+
+        async fn create_service_account(
+            &self,
+            _: Request<CreateServiceAccountRequest>,
+        ) -> Reply<User> {
+            Err(Status::unimplemented("CreateServiceAccount"))
+        }
+
+        async fn create_api_key(
+            &self,
+            _: Request<CreateApiKeyRequest>,
+        ) -> Reply<CreateApiKeyResponse> {
+            Err(Status::unimplemented("CreateApiKey"))
+        }
+
+        async fn list_api_keys(
+            &self,
+            _: Request<ListApiKeysRequest>,
+        ) -> Reply<ListApiKeysResponse> {
+            Err(Status::unimplemented("ListApiKeys"))
+        }
+
+        async fn revoke_api_key(
+            &self,
+            _: Request<RevokeApiKeyRequest>,
+        ) -> Reply<RevokeApiKeyResponse> {
+            Err(Status::unimplemented("RevokeApiKey"))
+        }
+    }
+
+    impl FakeService {
+        async fn start(&self, probe: Probe) -> Prober {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap().to_string();
+            tokio::spawn(
+                tonic::transport::Server::builder()
+                    .add_service(AuthenticationServer::new(self.clone()))
+                    .add_service(AdminServer::new(self.clone()))
+                    .serve_with_incoming(TcpListenerStream::new(listener)),
+            );
+            let channel = tonic::transport::Endpoint::from_shared(format!("http://{}", addr))
+                .unwrap()
+                .connect_lazy();
+            Prober::new(
+                probe,
+                addr,
+                String::new(),
+                common::telemetry::traced(channel),
+            )
+            .with_admin_token(Some(ADMIN_TOKEN.to_string()))
+        }
+
+        /// Waits up to a second for a user to be deleted.
+        async fn deleted(&self) -> Vec<String> {
+            for _ in 0..100 {
+                if !self.deleted.lock().unwrap().is_empty() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            self.deleted.lock().unwrap().clone()
+        }
+    }
+
+    fn step_names(prober: &Prober) -> Vec<&'static str> {
+        prober.steps().iter().map(|step| step.name).collect()
+    }
+
+    #[test]
+    fn should_take_socket_address_of_uri() {
+        let address = |uri: &str| socket_address(&uri.parse().unwrap());
+        assert_eq!(address("http://auth.internal:50051"), "auth.internal:50051");
+        assert_eq!(address("https://auth.example.com"), "auth.example.com:443");
+        assert_eq!(address("http://[::1]:50051/"), "[::1]:50051");
+    }
+
+    #[tokio::test]
+    async fn should_connect_to_url_target_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let endpoint = tonic::transport::Endpoint::from_shared(url).unwrap();
+        let mut prober = Prober::new(
+            Probe::Tcp,
+            socket_address(endpoint.uri()),
+            String::new(),
+            common::telemetry::traced(endpoint.connect_lazy()),
+        );
+
+        assert_eq!(prober.check(Duration::from_secs(1)).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn validate_should_expect_invalid_token_refused() {
+        let mut prober = FakeService::default().start(Probe::Validate).await;
+
+        assert_eq!(prober.check(Duration::from_secs(1)).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn full_probe_should_delete_its_user_through_admin_service() {
+        let service = FakeService::default();
+        let mut prober = service.start(Probe::Full).await;
+
+        assert_eq!(prober.check(Duration::from_secs(1)).await, Ok(()));
+
+        assert_eq!(
+            step_names(&prober),
+            ["SignUp", "SignIn", "SignOut", "DeleteUser"]
+        );
+        let deleted = service.deleted.lock().unwrap();
+        assert_eq!(deleted.len(), 1);
+        assert!(deleted[0].starts_with("health-check-"));
+    }
+
+    #[tokio::test]
+    async fn full_probe_should_delete_user_refused_sign_in() {
+        let service = FakeService {
+            refuse_sign_in: true,
+            ..FakeService::default()
+        };
+        let mut prober = service.start(Probe::Full).await;
+
+        let result = prober.check(Duration::from_secs(1)).await;
+
+        assert_eq!(result.unwrap_err(), "SignIn was refused");
+        assert_eq!(step_names(&prober), ["SignUp", "SignIn", "DeleteUser"]);
+        assert_eq!(service.deleted.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn full_probe_should_need_admin_token() {
+        let service = FakeService::default();
+        let mut prober = service.start(Probe::Full).await.with_admin_token(None);
+
+        assert!(prober.check(Duration::from_secs(1)).await.is_err());
+        assert!(prober.steps().is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_delete_user_of_timed_out_full_probe() {
+        let service = FakeService {
+            stall_sign_out: true,
+            ..FakeService::default()
+        };
+        let mut prober = service.start(Probe::Full).await;
+
+        let result = prober.check(Duration::from_millis(500)).await;
+
+        assert!(result.unwrap_err().starts_with("Timed out"));
+        assert_eq!(step_names(&prober), ["SignUp", "SignIn"]);
+        let deleted = service.deleted().await;
+        assert_eq!(deleted.len(), 1);
+        assert!(deleted[0].starts_with("health-check-"));
+    }
+}
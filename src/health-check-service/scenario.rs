@@ -0,0 +1,594 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use clap::Args;
+use common::telemetry::TracedChannel;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Map, Value};
+use uuid::Uuid;
+
+use crate::authentication::{
+    authentication_client::AuthenticationClient, DeleteAccountRequest, GetProfileRequest, Profile,
+    SignInRequest, SignOutRequest, SignUpRequest, StatusCode,
+};
+
+/// Options of the `scenario` mode.
+#[derive(Args, Debug, Clone)]
+pub struct ScenarioOptions {
+    /// Scenario files, JSON lines with one step each
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+    /// Write a JUnit XML report to this file
+    #[arg(long)]
+    pub junit: Option<PathBuf>,
+}
+
+/// What a step does. Its strings may hold `${variable}` placeholders,
+/// `${run_id}` being unique to each run of the scenario.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Action {
+    SignUp {
+        username: String,
+        password: String,
+        #[serde(default)]
+        email: String,
+    },
+    SignIn {
+        username: String,
+        password: String,
+    },
+    SignOut {
+        session_token: String,
+    },
+    /// Checks a session token by getting its user's profile
+    Validate {
+        session_token: String,
+    },
+    DeleteAccount {
+        session_token: String,
+        password: String,
+    },
+    /// Sets variables, e.g. to reuse a generated username
+    Set {
+        vars: BTreeMap<String, String>,
+    },
+}
+
+impl Action {
+    fn kind(&self) -> &'static str {
+        match self {
+            Action::SignUp { .. } => "sign_up",
+            Action::SignIn { .. } => "sign_in",
+            Action::SignOut { .. } => "sign_out",
+            Action::Validate { .. } => "validate",
+            Action::DeleteAccount { .. } => "delete_account",
+            Action::Set { .. } => "set",
+        }
+    }
+}
+
+/// The status code a step expects in its response.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Expect {
+    #[default]
+    Success,
+    Failure,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub name: Option<String>,
+    /// The action as written, placeholders included
+    action: Map<String, Value>,
+    pub expect: Expect,
+    /// Variables to set from the response, by dotted path, e.g.
+    /// `{"token": "session_token"}`
+    pub capture: BTreeMap<String, String>,
+    /// Values the response must have, by dotted path, e.g.
+    /// `{"profile.username": "${username}"}`
+    pub assert: BTreeMap<String, String>,
+}
+
+impl Step {
+    fn parse(line: &str) -> Result<Self, String> {
+        let Value::Object(mut action) = serde_json::from_str(line).map_err(|e| e.to_string())?
+        else {
+            return Err("A step must be a JSON object".to_string());
+        };
+        // What is left once these are taken out is the action
+        let name = take(&mut action, "name")?;
+        let expect = take(&mut action, "expect")?;
+        let capture = take(&mut action, "capture")?;
+        let assert = take(&mut action, "assert")?;
+
+        // Checked now, so that a mistyped step fails before anything runs
+        serde_json::from_value::<Action>(Value::Object(action.clone()))
+            .map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            name,
+            action,
+            expect: expect.unwrap_or_default(),
+            capture: capture.unwrap_or_default(),
+            assert: assert.unwrap_or_default(),
+        })
+    }
+
+    /// The action, placeholders replaced.
+    fn action(&self, vars: &BTreeMap<String, String>) -> Result<Action, String> {
+        let substituted = substitute_value(&Value::Object(self.action.clone()), vars)?;
+        serde_json::from_value(substituted).map_err(|e| e.to_string())
+    }
+
+    fn describe(&self, index: usize) -> String {
+        let kind = self
+            .action
+            .get("step")
+            .and_then(Value::as_str)
+            .unwrap_or("step");
+        match &self.name {
+            Some(name) => format!("step {} ({}: {})", index + 1, kind, name),
+            None => format!("step {} ({})", index + 1, kind),
+        }
+    }
+}
+
+fn take<T: DeserializeOwned>(
+    fields: &mut Map<String, Value>,
+    key: &str,
+) -> Result<Option<T>, String> {
+    fields
+        .remove(key)
+        .map(serde_json::from_value)
+        .transpose()
+        .map_err(|e| format!("Invalid {}: {}", key, e))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scenario {
+    pub name: String,
+    pub steps: Vec<Step>,
+}
+
+impl Scenario {
+    /// Blank lines and lines starting with `#` are skipped.
+    pub fn parse(name: &str, contents: &str) -> Result<Self, String> {
+        let steps = contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            .map(|(i, line)| Step::parse(line).map_err(|e| format!("Line {}: {}", i + 1, e)))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            name: name.to_string(),
+            steps,
+        })
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.display().to_string());
+        Self::parse(&name, &contents).map_err(|e| format!("Invalid {}: {}", path.display(), e))
+    }
+}
+
+/// Replaces the `${variable}` placeholders of `text`.
+pub fn substitute(text: &str, vars: &BTreeMap<String, String>) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("Unterminated placeholder in {}", text))?;
+        let name = &rest[start + 2..start + end];
+        result.push_str(
+            vars.get(name)
+                .ok_or_else(|| format!("Unknown variable {}", name))?,
+        );
+        rest = &rest[start + end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+fn substitute_value(value: &Value, vars: &BTreeMap<String, String>) -> Result<Value, String> {
+    Ok(match value {
+        Value::String(text) => Value::String(substitute(text, vars)?),
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(|item| substitute_value(item, vars))
+                .collect::<Result<_, _>>()?,
+        ),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), substitute_value(value, vars)?)))
+                .collect::<Result<_, String>>()?,
+        ),
+        other => other.clone(),
+    })
+}
+
+/// The value at a dotted path, e.g. `profile.username`, as text.
+fn lookup(response: &Value, path: &str) -> Option<String> {
+    let value = path
+        .split('.')
+        .try_fold(response, |value, key| value.get(key))?;
+    Some(match value {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    })
+}
+
+/// Checks the response of a step, then captures its variables.
+fn evaluate(
+    step: &Step,
+    response: &Value,
+    vars: &mut BTreeMap<String, String>,
+) -> Result<(), String> {
+    let expected = match step.expect {
+        Expect::Success => StatusCode::Success,
+        Expect::Failure => StatusCode::Failure,
+    };
+    if let Some(status_code) = response.get("status_code").and_then(Value::as_str) {
+        if status_code != expected.as_str_name() {
+            return Err(format!(
+                "Expected {}, got {}",
+                expected.as_str_name(),
+                status_code
+            ));
+        }
+    }
+
+    for (path, expected) in &step.assert {
+        let expected = substitute(expected, vars)?;
+        match lookup(response, path) {
+            Some(actual) if actual == expected => {}
+            Some(actual) => {
+                return Err(format!(
+                    "Expected {} to be {:?}, got {:?}",
+                    path, expected, actual
+                ))
+            }
+            None => return Err(format!("Response has no {}", path)),
+        }
+    }
+
+    for (name, path) in &step.capture {
+        let value = lookup(response, path).ok_or_else(|| format!("Response has no {}", path))?;
+        vars.insert(name.clone(), value);
+    }
+    Ok(())
+}
+
+fn status_name(status_code: i32) -> &'static str {
+    StatusCode::try_from(status_code)
+        .unwrap_or(StatusCode::Failure)
+        .as_str_name()
+}
+
+fn profile_json(profile: Option<Profile>) -> Value {
+    match profile {
+        Some(profile) => json!({
+            "user_id": profile.user_id,
+            "username": profile.username,
+            "display_name": profile.display_name,
+            "email": profile.email,
+            "locale": profile.locale,
+            "attributes": profile.attributes,
+            "email_verified": profile.email_verified,
+        }),
+        None => Value::Null,
+    }
+}
+
+/// How a scenario went.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub scenario: String,
+    pub steps: usize,
+    /// The failing step and why
+    pub failure: Option<(String, String)>,
+    pub elapsed: Duration,
+}
+
+/// Runs scenarios against a target.
+pub struct Runner {
+    client: AuthenticationClient<TracedChannel>,
+}
+
+impl Runner {
+    pub fn new(channel: TracedChannel) -> Self {
+        Self {
+            client: AuthenticationClient::new(channel),
+        }
+    }
+
+    /// Runs the steps in order, stopping at the first failing one.
+    pub async fn run(&mut self, scenario: &Scenario) -> Outcome {
+        let start = Instant::now();
+        let mut vars = BTreeMap::from([("run_id".to_string(), Uuid::new_v4().to_string())]);
+
+        let mut failure = None;
+        for (i, step) in scenario.steps.iter().enumerate() {
+            let result = match step.action(&vars) {
+                Ok(action) => self.call(action, &mut vars).await,
+                Err(e) => Err(e),
+            }
+            .and_then(|response| evaluate(step, &response, &mut vars));
+            if let Err(e) = result {
+                failure = Some((step.describe(i), e));
+                break;
+            }
+        }
+
+        Outcome {
+            scenario: scenario.name.clone(),
+            steps: scenario.steps.len(),
+            failure,
+            elapsed: start.elapsed(),
+        }
+    }
+
+    /// The response of the action as JSON, for captures and assertions.
+    async fn call(
+        &mut self,
+        action: Action,
+        vars: &mut BTreeMap<String, String>,
+    ) -> Result<Value, String> {
+        let kind = action.kind();
+        let failed = |e: tonic::Status| format!("{} failed: {}", kind, e.message());
+        Ok(match action {
+            Action::SignUp {
+                username,
+                password,
+                email,
+            } => {
+                let response = self
+                    .client
+                    .sign_up(SignUpRequest {
+                        username,
+                        password,
+                        email,
+                    })
+                    .await
+                    .map_err(failed)?
+                    .into_inner();
+                json!({ "status_code": status_name(response.status_code) })
+            }
+            Action::SignIn { username, password } => {
+                let response = self
+                    .client
+                    .sign_in(SignInRequest { username, password })
+                    .await
+                    .map_err(failed)?
+                    .into_inner();
+                json!({
+                    "status_code": status_name(response.status_code),
+                    "user_id": response.user_id,
+                    "session_token": response.session_token,
+                    "profile": profile_json(response.profile),
+                })
+            }
+            Action::SignOut { session_token } => {
+                let response = self
+                    .client
+                    .sign_out(SignOutRequest { session_token })
+                    .await
+                    .map_err(failed)?
+                    .into_inner();
+                json!({ "status_code": status_name(response.status_code) })
+            }
+            Action::Validate { session_token } => {
+                let response = self
+                    .client
+                    .get_profile(GetProfileRequest { session_token })
+                    .await
+                    .map_err(failed)?
+                    .into_inner();
+                json!({
+                    "status_code": status_name(response.status_code),
+                    "profile": profile_json(response.profile),
+                })
+            }
+            Action::DeleteAccount {
+                session_token,
+                password,
+            } => {
+                let response = self
+                    .client
+                    .delete_account(DeleteAccountRequest {
+                        session_token,
+                        password,
+                    })
+                    .await
+                    .map_err(failed)?
+                    .into_inner();
+                json!({
+                    "status_code": status_name(response.status_code),
+                    "deletes_at_ms": response.deletes_at_ms,
+                })
+            }
+            Action::Set { vars: values } => {
+                vars.extend(values);
+                json!({})
+            }
+        })
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// A JUnit XML report, a test case per scenario.
+pub fn junit(outcomes: &[Outcome]) -> String {
+    let failures = outcomes
+        .iter()
+        .filter(|outcome| outcome.failure.is_some())
+        .count();
+    let total: Duration = outcomes.iter().map(|outcome| outcome.elapsed).sum();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuite name=\"scenarios\" tests=\"{}\" failures=\"{}\" errors=\"0\" time=\"{:.3}\">",
+        outcomes.len(),
+        failures,
+        total.as_secs_f64()
+    );
+    for outcome in outcomes {
+        let _ = write!(
+            xml,
+            "  <testcase classname=\"scenarios\" name=\"{}\" time=\"{:.3}\"",
+            escape_xml(&outcome.scenario),
+            outcome.elapsed.as_secs_f64()
+        );
+        match &outcome.failure {
+            Some((step, reason)) => {
+                let _ = writeln!(
+                    xml,
+                    ">\n    <failure message=\"{}\">{}</failure>\n  </testcase>",
+                    escape_xml(&format!("{}: {}", step, reason)),
+                    escape_xml(reason)
+                );
+            }
+            None => xml.push_str("/>\n"),
+        }
+    }
+    xml.push_str("</testsuite>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENARIO: &str = r#"
+# Signing out ends the session
+{"step": "set", "vars": {"username": "qa-${run_id}"}}
+{"step": "sign_up", "username": "${username}", "password": "secret"}
+{"step": "sign_in", "username": "${username}", "password": "secret", "capture": {"token": "session_token"}, "assert": {"profile.username": "${username}"}}
+{"step": "sign_out", "session_token": "${token}"}
+{"step": "validate", "session_token": "${token}", "expect": "failure", "name": "session is gone"}
+"#;
+
+    fn vars(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn should_parse_scenario() {
+        let scenario = Scenario::parse("sign-out", SCENARIO).unwrap();
+
+        assert_eq!(scenario.steps.len(), 5);
+        assert_eq!(scenario.steps[2].capture["token"], "session_token");
+        assert_eq!(scenario.steps[4].expect, Expect::Failure);
+        assert_eq!(
+            scenario.steps[4].describe(4),
+            "step 5 (validate: session is gone)"
+        );
+        assert_eq!(
+            scenario.steps[3]
+                .action(&vars(&[("token", "abc")]))
+                .unwrap(),
+            Action::SignOut {
+                session_token: "abc".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn should_reject_invalid_steps() {
+        assert!(Scenario::parse("s", r#"{"step": "sign_in", "username": "a"}"#).is_err());
+        assert!(Scenario::parse("s", r#"{"step": "teleport"}"#).is_err());
+        let error = Scenario::parse(
+            "s",
+            "\n{\"step\": \"sign_out\", \"session_token\": \"t\", \"expect\": \"maybe\"}",
+        )
+        .unwrap_err();
+        assert!(error.starts_with("Line 2: Invalid expect"));
+    }
+
+    #[test]
+    fn should_substitute_variables() {
+        let vars = vars(&[("user", "jane"), ("run_id", "42")]);
+
+        assert_eq!(substitute("${user}-${run_id}!", &vars).unwrap(), "jane-42!");
+        assert_eq!(substitute("plain", &vars).unwrap(), "plain");
+        assert!(substitute("${nobody}", &vars).is_err());
+        assert!(substitute("${user", &vars).is_err());
+    }
+
+    #[test]
+    fn should_check_status_assert_and_capture() {
+        let scenario = Scenario::parse("s", SCENARIO).unwrap();
+        let sign_in = &scenario.steps[2];
+        let mut vars = vars(&[("username", "jane")]);
+        let response = json!({
+            "status_code": "SUCCESS",
+            "session_token": "abc",
+            "profile": {"username": "jane"},
+        });
+
+        evaluate(sign_in, &response, &mut vars).unwrap();
+        assert_eq!(vars["token"], "abc");
+
+        let refused = json!({"status_code": "FAILURE"});
+        assert_eq!(
+            evaluate(sign_in, &refused, &mut vars).unwrap_err(),
+            "Expected SUCCESS, got FAILURE"
+        );
+        let other_user = json!({"status_code": "SUCCESS", "profile": {"username": "john"}});
+        assert!(evaluate(sign_in, &other_user, &mut vars).is_err());
+    }
+
+    #[test]
+    fn should_write_junit() {
+        let outcomes = [
+            Outcome {
+                scenario: "sign-out".to_string(),
+                steps: 5,
+                failure: None,
+                elapsed: Duration::from_millis(1500),
+            },
+            Outcome {
+                scenario: "<lockout>".to_string(),
+                steps: 3,
+                failure: Some(("step 2 (sign_in)".to_string(), "Expected \"x\"".to_string())),
+                elapsed: Duration::from_millis(500),
+            },
+        ];
+
+        let xml = junit(&outcomes);
+
+        assert!(xml.contains("tests=\"2\" failures=\"1\" errors=\"0\" time=\"2.000\""));
+        assert!(
+            xml.contains("<testcase classname=\"scenarios\" name=\"sign-out\" time=\"1.500\"/>")
+        );
+        assert!(xml.contains("name=\"&lt;lockout&gt;\""));
+        assert!(xml.contains(
+            "<failure message=\"step 2 (sign_in): Expected &quot;x&quot;\">Expected &quot;x&quot;</failure>"
+        ));
+    }
+}
@@ -0,0 +1,270 @@
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+use serde::Serialize;
+use tokio::net::TcpListener;
+
+use crate::{
+    health::{Status, Tracker},
+    probes::Step,
+};
+
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StepReport {
+    pub name: &'static str,
+    pub latency_ms: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProbeReport {
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// When the probe ended, in milliseconds since the Unix epoch
+    pub at_ms: u64,
+    pub steps: Vec<StepReport>,
+}
+
+/// What `/status` answers about a target.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StatusReport {
+    pub name: String,
+    pub status: Status,
+    pub last_probe: Option<ProbeReport>,
+    pub consecutive_failures: u32,
+    /// Since the first success after the target was last down
+    pub target_up_since_ms: Option<u64>,
+    pub target_uptime_ms: Option<u64>,
+}
+
+/// What `/status` answers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Report {
+    pub targets: Vec<StatusReport>,
+}
+
+/// What the health check observed of a target, shared with the HTTP
+/// endpoints.
+#[derive(Debug)]
+pub struct Observer {
+    name: String,
+    tracker: Tracker,
+    last_probe: Option<ProbeReport>,
+    up_since_ms: Option<u64>,
+}
+
+impl Observer {
+    pub fn new(name: &str, tracker: Tracker) -> Self {
+        Self {
+            name: name.to_string(),
+            tracker,
+            last_probe: None,
+            up_since_ms: None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn tracker(&self) -> &Tracker {
+        &self.tracker
+    }
+
+    /// Records a probe ending at `at_ms`, returning the new status of the
+    /// target when it changed.
+    pub fn record(
+        &mut self,
+        result: &Result<(), String>,
+        steps: &[Step],
+        at_ms: u64,
+    ) -> Option<Status> {
+        let changed = self.tracker.record(result.is_ok());
+        match (result, changed) {
+            (Ok(()), _) => {
+                self.up_since_ms.get_or_insert(at_ms);
+            }
+            (Err(_), Some(Status::Down)) => self.up_since_ms = None,
+            (Err(_), _) => {}
+        }
+
+        self.last_probe = Some(ProbeReport {
+            success: result.is_ok(),
+            error: result.as_ref().err().cloned(),
+            at_ms,
+            steps: steps
+                .iter()
+                .map(|step| StepReport {
+                    name: step.name,
+                    latency_ms: step.latency.as_millis() as u64,
+                })
+                .collect(),
+        });
+        changed
+    }
+
+    pub fn report(&self, now_ms: u64) -> StatusReport {
+        StatusReport {
+            name: self.name.clone(),
+            status: self.tracker.status(),
+            last_probe: self.last_probe.clone(),
+            consecutive_failures: self.tracker.failures(),
+            target_up_since_ms: self.up_since_ms,
+            target_uptime_ms: self.up_since_ms.map(|since| now_ms.saturating_sub(since)),
+        }
+    }
+}
+
+/// The observers of every target.
+pub type Observers = Arc<Vec<Mutex<Observer>>>;
+
+pub fn lock(observer: &Mutex<Observer>) -> MutexGuard<'_, Observer> {
+    observer.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn statuses(observers: &Observers) -> Vec<Status> {
+    observers
+        .iter()
+        .map(|observer| lock(observer).tracker.status())
+        .collect()
+}
+
+/// Fails once a target is down, a target still starting is alive.
+async fn healthz(State(observers): State<Observers>) -> (StatusCode, &'static str) {
+    if statuses(&observers).contains(&Status::Down) {
+        (StatusCode::SERVICE_UNAVAILABLE, "down")
+    } else {
+        (StatusCode::OK, "ok")
+    }
+}
+
+/// Only succeeds once every target is healthy, or merely degraded.
+async fn readyz(State(observers): State<Observers>) -> (StatusCode, &'static str) {
+    if statuses(&observers)
+        .iter()
+        .all(|status| matches!(status, Status::Healthy | Status::Degraded))
+    {
+        (StatusCode::OK, "ready")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "not ready")
+    }
+}
+
+async fn status(State(observers): State<Observers>) -> Json<Report> {
+    let now_ms = now_ms();
+    Json(Report {
+        targets: observers
+            .iter()
+            .map(|observer| lock(observer).report(now_ms))
+            .collect(),
+    })
+}
+
+fn router(observers: Observers) -> Router {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/status", get(status))
+        .with_state(observers)
+}
+
+/// Serves `/healthz`, `/readyz` and `/status` on `listener`.
+pub async fn serve(listener: TcpListener, observers: Observers) -> std::io::Result<()> {
+    axum::serve(listener, router(observers)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::Thresholds;
+    use std::{net::SocketAddr, time::Duration};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn tracker(down: u32) -> Tracker {
+        Tracker::new(Thresholds {
+            healthy: 1,
+            degraded: 1,
+            down,
+        })
+    }
+
+    fn steps() -> Vec<Step> {
+        vec![
+            Step {
+                name: "SignUp",
+                latency: Duration::from_millis(12),
+            },
+            Step {
+                name: "SignIn",
+                latency: Duration::from_millis(8),
+            },
+        ]
+    }
+
+    #[test]
+    fn should_report_last_probe_and_uptime() {
+        let mut observer = Observer::new("auth-1", tracker(2));
+
+        observer.record(&Ok(()), &steps(), 1_000);
+        observer.record(&Err("SignOut failed".to_string()), &steps(), 2_000);
+        let report = observer.report(3_000);
+
+        assert_eq!(report.status, Status::Degraded);
+        assert_eq!(report.consecutive_failures, 1);
+        assert_eq!(report.target_uptime_ms, Some(2_000));
+        let last_probe = report.last_probe.unwrap();
+        assert_eq!(last_probe.error.as_deref(), Some("SignOut failed"));
+        assert_eq!(last_probe.steps[0].latency_ms, 12);
+
+        observer.record(&Err("SignOut failed".to_string()), &[], 4_000);
+        let report = observer.report(5_000);
+        assert_eq!(report.status, Status::Down);
+        assert_eq!(report.target_uptime_ms, None);
+    }
+
+    async fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(format!("GET {} HTTP/1.0\r\nHost: localhost\r\n\r\n", path).as_bytes())
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn should_serve_observed_status() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let observers: Observers = Arc::new(vec![
+            Mutex::new(Observer::new("auth-1", tracker(1))),
+            Mutex::new(Observer::new("auth-2", tracker(1))),
+        ]);
+        tokio::spawn(serve(listener, observers.clone()));
+
+        assert!(get(addr, "/healthz").await.starts_with("HTTP/1.0 200"));
+        assert!(get(addr, "/readyz").await.starts_with("HTTP/1.0 503"));
+
+        for observer in observers.iter() {
+            lock(observer).record(&Ok(()), &steps(), now_ms());
+        }
+        assert!(get(addr, "/readyz").await.starts_with("HTTP/1.0 200"));
+        let status = get(addr, "/status").await;
+        assert!(status.contains("\"name\":\"auth-2\",\"status\":\"healthy\""));
+        assert!(status.contains("{\"name\":\"SignUp\",\"latency_ms\":12}"));
+
+        lock(&observers[1]).record(&Err("down".to_string()), &[], now_ms());
+        assert!(get(addr, "/healthz").await.starts_with("HTTP/1.0 503"));
+    }
+}